@@ -0,0 +1,27 @@
+extern crate mco_http;
+extern crate fast_log;
+
+use std::io;
+use fast_log::config::Config;
+
+use mco_http::Client;
+use mco_http::http::h2::Http2Protocol;
+
+// run http_server.rs first, the server speaks HTTP/2 to clients starting with its preface (h2c)
+fn main() {
+    let _ = fast_log::init(Config::new().level(log::LevelFilter::Info).console());
+
+    let url = "http://127.0.0.1:3000".to_string();
+
+    // requests to the same host share one connection
+    let client = Client::with_protocol(Http2Protocol::new());
+
+    for _ in 0..2 {
+        let mut res = client.get(&url).send().unwrap();
+
+        println!("Response: {} {}", res.version, res.status);
+        println!("Headers:\n{}", res.headers);
+        io::copy(&mut res, &mut io::stdout()).unwrap();
+        println!();
+    }
+}
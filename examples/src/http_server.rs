@@ -11,7 +11,8 @@ fn hello(_req: Request, res: Response) {
 
 fn main() {
     let _ = fast_log::init(Config::new().console());
-    let _listening = mco_http::Server::http("0.0.0.0:3000").unwrap()
-        .handle(hello);
+    let mut listening = mco_http::Server::http("0.0.0.0:3000").unwrap()
+        .handle(hello).unwrap();
     println!("Listening on http://127.0.0.1:3000");
+    listening.join().unwrap();
 }
@@ -0,0 +1,23 @@
+use mco_http::compress::Compression;
+use mco_http::route::Route;
+use mco_http::server::{Request, Response};
+use fast_log::config::Config;
+
+fn main() {
+    let _ = fast_log::init(Config::new().level(log::LevelFilter::Info).console());
+
+    let route = Route::new();
+    // bodies of 1KB and more are compressed for clients sending `Accept-Encoding`,
+    // try `curl --compressed -v http://127.0.0.1:3000/`
+    let mut compression = Compression::new();
+    compression.min_size(1024);
+    route.add_around(compression);
+    route.get_fn("/", |req: Request, res: Response| {
+        res.text(&"Hello World! ".repeat(1000)).unwrap();
+    });
+
+    let mut listening = mco_http::Server::http("0.0.0.0:3000").unwrap()
+        .handle(route).unwrap();
+    println!("Listening on http://127.0.0.1:3000");
+    listening.join().unwrap();
+}
@@ -71,8 +71,8 @@ fn main() {
     route.handle_fn("/", move |req: Request, res: Response| {
         route_clone.find_all(req, res);
     });
-    let _listening = mco_http::Server::http("0.0.0.0:3000").unwrap()
-        .handle(route);
+    let mut listening = mco_http::Server::http("0.0.0.0:3000").unwrap()
+        .handle(route).unwrap();
     println!("Listening on http://127.0.0.1:3000");
 }
 
@@ -86,5 +86,6 @@ fn make_sqlite() -> cdbc::Result<SqlitePool> {
     conn.execute("CREATE TABLE biz_activity(  id string, name string,age int, delete_flag int) ");
     conn.execute("INSERT INTO biz_activity (id,name,age,delete_flag) values (\"1\",\"1\",1,0)");
     Ok(pool)
+    listening.join().unwrap();
 }
 
@@ -0,0 +1,28 @@
+use std::collections::BTreeMap;
+use fast_log::config::Config;
+use mco_http::extract::{extract, Json, PathParams, Query, TypedHeader};
+use mco_http::header::UserAgent;
+use mco_http::route::Route;
+use mco_http::server::Response;
+
+fn main() {
+    let _ = fast_log::init(Config::new().level(log::LevelFilter::Info).console());
+
+    let route = Route::new();
+    // curl http://127.0.0.1:3000/user/1?name=joe
+    route.get_fn("/user/{id}", extract(|PathParams(path): PathParams<BTreeMap<String, u64>>,
+                                        Query(query): Query<BTreeMap<String, String>>,
+                                        TypedHeader(agent): TypedHeader<UserAgent>,
+                                        res: Response| {
+        res.send(format!("path: {:?}, query: {:?}, agent: {}", path, query, agent).as_bytes()).unwrap();
+    }));
+    // request header Content-Type: application/json, other types get 415
+    route.post_fn("/json", extract(|Json(body): Json<serde_json::Value>, res: Response| {
+        res.send(body.to_string().as_bytes()).unwrap();
+    }));
+
+    let mut listening = mco_http::Server::http("0.0.0.0:3000").unwrap()
+        .handle(route).unwrap();
+    println!("Listening on http://127.0.0.1:3000/user/1?name=joe");
+    listening.join().unwrap();
+}
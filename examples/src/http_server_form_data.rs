@@ -16,7 +16,8 @@ fn hello(mut req: Request, res: Response) {
 
 fn main() {
     let _ = fast_log::init(Config::new().level(log::LevelFilter::Info).console());
-    let _listening = mco_http::Server::http("0.0.0.0:3000").unwrap()
+    let mut listening = mco_http::Server::http("0.0.0.0:3000").unwrap()
         .handle(hello).unwrap();
     println!("Listening on http://127.0.0.1:3000");
+    listening.join().unwrap();
 }
@@ -1,15 +1,13 @@
 use std::sync::Arc;
 use std::time::Duration;
+use mco_http::auth::{Auth, BearerError};
+use mco_http::extract::{reply, Extra};
 use mco_http::route::Route;
-use mco_http::server::{Request, Response};
-use mco_http::route::MiddleWare;
 
 use serde::{Serialize, Deserialize};
 use jsonwebtoken::{encode, decode, Header, Algorithm, Validation, EncodingKey, DecodingKey};
 use jsonwebtoken::errors::ErrorKind;
 use mco::std::time::Time;
-use mco_http::query::read_query;
-use mco_http::uri::RequestUri;
 use fast_log::config::Config;
 
 /// JWT Token.
@@ -61,59 +59,20 @@ impl JWTToken {
     }
 }
 
-// MiddleWare
-#[derive(Debug)]
-pub struct MyMiddleWare {
-    secret: String,
-}
-
-impl MiddleWare for MyMiddleWare {
-    fn handle(&self, req: &mut Request, resp: &mut Option<Response>) {
-        //not check login api
-        match &req.uri {
-            RequestUri::AbsolutePath(p) => {
-                if p.eq("/login") {
-                    return;
-                }
-            }
-            RequestUri::AbsoluteUri(_) => {}
-            RequestUri::Authority(_) => {}
-            RequestUri::Star => {}
-        }
-        let querys = read_query(&req.uri.to_string());
-        let token_value = querys.get("token");
-        if token_value.is_none() {
-            resp.take().unwrap().send("token must be in path.for example: http://127.0.0.1:3000?token=xxxxx".as_bytes());
-            return;
-        }
-        let token = token_value.unwrap();
-        if token.is_empty() {
-            resp.take().unwrap().send("token must be in path.for example: http://127.0.0.1:3000?token=xxxxx".as_bytes());
-            return;
-        }
-        match JWTToken::verify(&self.secret, token) {
-            Ok(v) => {
-                req.extra.insert(v);
-            }
-            Err(e) => {
-                resp.take().unwrap().send(format!("Token is invalid! {}", e).as_bytes());
-            }
-        }
-    }
-}
-
-
 fn main() {
     let _ = fast_log::init(Config::new().level(log::LevelFilter::Info).console());
 
-    let mut route = Route::new();
-    let middle = Arc::new(MyMiddleWare { secret: "123456".to_string() });
-    route.add_middleware(middle.clone());
-    route.handle_fn("/", |req: Request, mut res: Response| {
-        //Note that the token is obtained from path, which is not rigorous. In the actual system, the token should be obtained from the header
-        let login_user_data = req.extra.get::<JWTToken>().unwrap();
-        res.send(format!("read from middleware: {:?}", login_user_data).as_bytes());
+    let secret = "123456";
+    let route = Route::new();
+    let mut auth = Auth::bearer("jwt", move |token: &str| {
+        JWTToken::verify(secret, token).map_err(BearerError::InvalidToken)
     });
+    //not check login api
+    auth.skip("/login");
+    route.add_middleware(auth);
+    route.handle_fn("/", reply(|Extra(login_user_data): Extra<JWTToken>| {
+        format!("read from middleware: {:?}", login_user_data)
+    }));
     let login_fn = move || -> String {
         let jwt = JWTToken {
             id: "111".to_string(),
@@ -127,17 +86,15 @@ fn main() {
                     .unix_timestamp() as usize
             },
         };
-        let token = jwt.create_token(middle.secret.as_str()).unwrap();
-        return token;
+        jwt.create_token(secret).unwrap()
     };
     let token = login_fn();
-    route.handle_fn("/login", move |req: Request, mut res: Response| {
-        res.send(format!("{}", login_fn()).as_bytes());
-    });
+    route.handle_fn("/login", reply(login_fn));
 
     let route = Arc::new(route);
-    let _listening = mco_http::Server::http("0.0.0.0:3000").unwrap()
-        .handle(route.clone());
+    let mut listening = mco_http::Server::http("0.0.0.0:3000").unwrap()
+        .handle(route.clone()).unwrap();
     println!("Listening on http://127.0.0.1:3000/login");
-    println!("then click http://127.0.0.1:3000?token={}", token);
-}
\ No newline at end of file
+    println!("then try curl -H \"Authorization: Bearer {}\" http://127.0.0.1:3000/", token);
+    listening.join().unwrap();
+}
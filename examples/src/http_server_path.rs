@@ -3,21 +3,34 @@ extern crate mco_http;
 extern crate fast_log;
 
 use fast_log::config::Config;
-use mco_http::path::Path;
-use mco_http::query::read_query;
+use mco_http::route::{Params, Route};
 use mco_http::server::{Request, Response};
 
-// http://127.0.0.1:3000/1/2/3
-fn hello(req: Request, res: Response) {
-    let p = Path::new("/{a}/{b}/");
-    let param = p.read_path(&req.uri.to_string());
-    res.send(format!("{:?}", param).as_bytes()).unwrap();
+// http://127.0.0.1:3000/user/1
+fn user(req: Request, res: Response) {
+    let params = req.extra.get::<Params>().unwrap();
+    res.send(format!("user id = {}", params.get("id").unwrap_or_default()).as_bytes()).unwrap();
+}
+
+// http://127.0.0.1:3000/static/css/site.css
+fn file(req: Request, res: Response) {
+    let params = req.extra.get::<Params>().unwrap();
+    res.send(format!("file = {}", params.get("path").unwrap_or_default()).as_bytes()).unwrap();
 }
 
 fn main() {
     let _ = fast_log::init(Config::new().level(log::LevelFilter::Info).console());
-    let _listening = mco_http::Server::http("0.0.0.0:3000").unwrap()
-        .handle(hello);
+    let route = Route::new();
+    route.get_fn("/user/{id}", user);
+    // DELETE /user/1 is routed here, POST /user/1 gets 405 Method Not Allowed
+    route.delete_fn("/user/{id}", |req: Request, res: Response| {
+        let params = req.extra.get::<Params>().unwrap();
+        res.send(format!("deleted user {}", params.get("id").unwrap_or_default()).as_bytes()).unwrap();
+    });
+    route.get_fn("/static/*path", file);
+    let mut listening = mco_http::Server::http("0.0.0.0:3000").unwrap()
+        .handle(route).unwrap();
     println!("Listening on http://127.0.0.1:3000");
-    println!("please click http://127.0.0.1:3000/1/2/3");
+    println!("please click http://127.0.0.1:3000/user/1");
+    listening.join().unwrap();
 }
@@ -20,11 +20,12 @@ fn main() {
     });
 
     let route = Arc::new(route);
-    let _listening = mco_http::Server::http("0.0.0.0:3000").unwrap()
-        .handle(route.clone());
+    let mut listening = mco_http::Server::http("0.0.0.0:3000").unwrap()
+        .handle(route.clone()).unwrap();
     println!("Listening on http://127.0.0.1:3000");
 
-    for x in &route.handlers {
-        println!("please click http://127.0.0.1:3000{}", x.0);
+    for x in route.urls() {
+        println!("please click http://127.0.0.1:3000{}", x);
     }
+    listening.join().unwrap();
 }
\ No newline at end of file
@@ -30,7 +30,8 @@ fn main() {
     });
 
 
-    let _listening = mco_http::Server::http("0.0.0.0:3000").unwrap()
-        .handle(route);
+    let mut listening = mco_http::Server::http("0.0.0.0:3000").unwrap()
+        .handle(route).unwrap();
     println!("Listening on http://127.0.0.1:3000");
+    listening.join().unwrap();
 }
\ No newline at end of file
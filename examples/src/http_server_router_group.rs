@@ -0,0 +1,34 @@
+use mco_http::route::{Params, Route};
+use mco_http::server::{Request, Response};
+use fast_log::config::Config;
+
+fn main() {
+    let _ = fast_log::init(Config::new().level(log::LevelFilter::Info).console());
+
+    // /api/v1 group, the middleware only runs for /api/v1/*
+    let api = Route::new();
+    api.add_middleware(|req: &mut Request, res: &mut Option<Response>| {
+        println!("api middleware: {}", req.uri);
+    });
+    api.get_fn("/user/{id}", |req: Request, res: Response| {
+        let id = req.extra.get::<Params>().unwrap().get("id").unwrap_or_default().to_string();
+        res.send(format!("user {}", id).as_bytes()).unwrap();
+    });
+
+    let admin = Route::new();
+    admin.get_fn("/", |req: Request, res: Response| {
+        res.send(b"admin").unwrap();
+    });
+
+    let route = Route::new();
+    route.get_fn("/", |req: Request, res: Response| {
+        res.send(b"Hello World!").unwrap();
+    });
+    route.mount("/api/v1", api);
+    route.mount("/admin", admin);
+
+    let mut listening = mco_http::Server::http("0.0.0.0:3000").unwrap()
+        .handle(route).unwrap();
+    println!("Listening on http://127.0.0.1:3000/api/v1/user/1");
+    listening.join().unwrap();
+}
@@ -1,7 +1,7 @@
 use std::sync::Arc;
 use mco_http::route::Route;
 use mco_http::server::{Request, Response};
-use mco_http::route::MiddleWare;
+use mco_http::route::{MiddleWare, Next};
 use fast_log::config::Config;
 
 // MiddleWare
@@ -25,11 +25,22 @@ fn main() {
     let mut route = Arc::new(Route::new());
     route.add_middleware(MyMiddleWare { route: route.clone() });
     route.add_middleware(|req: &mut Request, res: &mut Option<Response>| {});
+    // around MiddleWare, runs code after the handler
+    route.add_around_fn(|req: Request, mut res: Response, next: Next| {
+        let start = std::time::Instant::now();
+        let uri = req.uri.to_string();
+        res.on_head(|_status, headers| {
+            headers.set_raw("X-Powered-By", vec![b"mco-http".to_vec()]);
+        });
+        let status = next.run(req, res);
+        println!("{} {:?} use {:?}", uri, status, start.elapsed());
+    });
     route.handle_fn("/", |req: Request, res: Response| {
         res.send(format!("read from middleware: {:?}", req.extra.get::<String>()).as_bytes());
     });
 
-    let _listening = mco_http::Server::http("0.0.0.0:3000").unwrap()
-        .handle(route);
+    let mut listening = mco_http::Server::http("0.0.0.0:3000").unwrap()
+        .handle(route).unwrap();
     println!("Listening on http://127.0.0.1:3000");
+    listening.join().unwrap();
 }
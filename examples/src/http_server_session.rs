@@ -0,0 +1,46 @@
+use std::time::Duration;
+
+use mco_http::extract::{reply, Query};
+use mco_http::route::Route;
+use mco_http::session::{MemoryStore, Session, Sessions};
+use mco_http::status::StatusCode;
+use fast_log::config::Config;
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct Login {
+    name: String,
+}
+
+fn main() {
+    let _ = fast_log::init(Config::new().level(log::LevelFilter::Info).console());
+
+    let route = Route::new();
+    let mut sessions = Sessions::new(MemoryStore::new());
+    sessions.idle_timeout(Duration::from_secs(10 * 60))
+        .absolute_timeout(Some(Duration::from_secs(8 * 3600)));
+    route.add_around(sessions);
+
+    // try `curl -c jar -b jar -X POST "http://127.0.0.1:3000/login?name=joe"`, then `/me` and `/logout`
+    route.post_fn("/login", reply(|session: Session, Query(login): Query<Login>| {
+        // a new session id on login, so an id known before can't be used
+        session.rotate();
+        session.insert("user", login.name);
+        "logged in"
+    }));
+    route.get_fn("/me", reply(|session: Session| {
+        match session.get("user") {
+            Some(user) => Ok(format!("hello {}", user)),
+            None => Err((StatusCode::Unauthorized, "log in first")),
+        }
+    }));
+    route.post_fn("/logout", reply(|session: Session| {
+        session.destroy();
+        "logged out"
+    }));
+
+    let mut listening = mco_http::Server::http("0.0.0.0:3000").unwrap()
+        .handle(route).unwrap();
+    println!("Listening on http://127.0.0.1:3000");
+    listening.join().unwrap();
+}
@@ -0,0 +1,31 @@
+use std::time::Duration;
+
+use mco_http::route::Route;
+use mco_http::server::{Request, Response};
+use mco_http::sse::{self, Event, EventStream};
+use fast_log::config::Config;
+
+fn main() {
+    let _ = fast_log::init(Config::new().level(log::LevelFilter::Info).console());
+
+    let route = Route::new();
+    // try `curl -N http://127.0.0.1:3000/events`, or `-H "Last-Event-ID: 10"` to resume
+    route.get_fn("/events", |req: Request, res: Response| {
+        let mut n: u64 = sse::last_event_id(&req).and_then(|id| id.parse().ok()).unwrap_or(0);
+        let mut events = EventStream::new(res).unwrap();
+        loop {
+            n += 1;
+            let event = Event::data(format!("tick {}", n)).event("tick").id(n.to_string());
+            if events.send(&event).is_err() {
+                println!("client gone after {} events", n - 1);
+                return;
+            }
+            mco::coroutine::sleep(Duration::from_secs(1));
+        }
+    });
+
+    let mut listening = mco_http::Server::http("0.0.0.0:3000").unwrap()
+        .handle(route).unwrap();
+    println!("Listening on http://127.0.0.1:3000/events");
+    listening.join().unwrap();
+}
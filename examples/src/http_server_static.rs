@@ -0,0 +1,23 @@
+use mco_http::route::Route;
+use mco_http::server::{Request, Response};
+use mco_http::static_files::StaticFiles;
+use fast_log::config::Config;
+
+fn main() {
+    let _ = fast_log::init(Config::new().level(log::LevelFilter::Info).console());
+
+    let route = Route::new();
+    route.get_fn("/", |req: Request, res: Response| {
+        res.send(b"see /static/").unwrap();
+    });
+    // GET /static/src/http_server_static.rs serves ./src/http_server_static.rs,
+    // try it with `Range: bytes=0-99` or `If-None-Match`
+    let mut files = StaticFiles::new("/static", ".");
+    files.index_files(&["index.html", "index.htm"]);
+    route.get_fn("/static/{*}", files);
+
+    let mut listening = mco_http::Server::http("0.0.0.0:3000").unwrap()
+        .handle(route).unwrap();
+    println!("Listening on http://127.0.0.1:3000/static/");
+    listening.join().unwrap();
+}
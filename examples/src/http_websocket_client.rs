@@ -1,29 +1,13 @@
-extern crate mco_http;
-extern crate fast_log;
-
-use std::time::Duration;
-use tungstenite::{connect, Message};
-use tungstenite::protocol::Role;
-use tungstenite::stream::MaybeTlsStream;
+use mco_http::Client;
+use mco_http::websocket::Message;
 
 fn main() {
-    let (mut s, _response) =
-        connect(url::Url::parse("ws://127.0.0.1:3000").unwrap()).expect("Can't connect");
-
-    //replace TcpStream
-    let v = s.get_mut();
-    let stream = match v {
-        MaybeTlsStream::Plain(v) => {
-            v.try_clone().expect("clone stream fail")
-        }
-        _ => {
-            panic!("unknown stream")
-        }
-    };
-    let mco_tcp = mco::net::TcpStream::new(stream).unwrap();
-    let mut socket = tungstenite::WebSocket::from_raw_socket(mco_tcp, Role::Client, Option::from(s.get_config().clone()));
-    socket.send(Message::Text("Hello mco WebSocket".to_string())).unwrap();
-    let msg = socket.read().expect("Error reading message");
-    mco::coroutine::sleep(Duration::from_secs(1));
-    println!("Received: {}", msg);
+    let client = Client::new();
+    let mut ws = client.websocket("ws://127.0.0.1:3000").expect("Can't connect");
+    ws.send(Message::Text("Hello mco WebSocket".to_string())).unwrap();
+    let msg = ws.read().expect("Error reading message");
+    println!("Received: {:?}", msg);
+    ws.close(None).unwrap();
+    // the server's close reply
+    let _ = ws.read();
 }
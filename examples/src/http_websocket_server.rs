@@ -1,29 +1,38 @@
-#[deny(unused_variables)]
-extern crate mco_http;
-extern crate fast_log;
-
+use mco_http::route::Route;
+use mco_http::server::{Request, Response};
+use mco_http::websocket::Message;
 use fast_log::config::Config;
-use tungstenite::{accept_hdr, handshake};
 
 fn main() {
-    let _ = fast_log::init(Config::new().console());
-    let _listening = mco_http::Server::http("0.0.0.0:3000").unwrap()
-        .handle_accept(|stream| {
-            let callback = |req: &handshake::server::Request, response: handshake::server::Response| {
-                println!("Received a new ws handshake");
-                println!("The request's path is: {}", req.uri().to_string());
-                println!("The request's headers are:");
-                Ok(response)
-            };
-            let mut websocket = accept_hdr(stream, callback).unwrap();
-            println!("start reading websocket....................");
-            loop {
-                let msg = websocket.read().unwrap();
-                if msg.is_binary() || msg.is_text() {
-                    println!("read msg={}", msg);
-                    websocket.send(msg).unwrap();
+    let _ = fast_log::init(Config::new().level(log::LevelFilter::Info).console());
+
+    let route = Route::new();
+    // echoes text and binary messages, run the http_websocket_client example to try it
+    route.get_fn("/", |req: Request, res: Response| {
+        println!("ws handshake for {}", req.uri);
+        let mut ws = match res.upgrade_websocket(req) {
+            Ok(ws) => ws,
+            Err(e) => {
+                println!("not a websocket handshake: {}", e);
+                return;
+            }
+        };
+        while let Ok(msg) = ws.read() {
+            match msg {
+                Message::Text(_) | Message::Binary(_) => {
+                    println!("read msg={:?}", msg);
+                    if ws.send(msg).is_err() {
+                        break;
+                    }
                 }
+                Message::Close(_) => break,
+                _ => {}
             }
-        });
-    println!("Listening on http://127.0.0.1:3000");
+        }
+    });
+
+    let mut listening = mco_http::Server::http("0.0.0.0:3000").unwrap()
+        .handle(route).unwrap();
+    println!("Listening on ws://127.0.0.1:3000");
+    listening.join().unwrap();
 }
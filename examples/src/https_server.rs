@@ -22,9 +22,10 @@ fn main() {
 
     let ssl = TlsServer::new(vec![buf], buf2);
 
-    let _listening = mco_http::Server::https("0.0.0.0:3000", ssl).unwrap()
+    let mut listening = mco_http::Server::https("0.0.0.0:3000", ssl).unwrap()
         .handle(|_req: Request, resp: Response| {
             resp.send(b"Hello World!").unwrap();
-        });
+        }).unwrap();
     println!("Listening on https://localhost:3000");
+    listening.join().unwrap();
 }
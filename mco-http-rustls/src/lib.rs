@@ -1,183 +1,226 @@
 extern crate mco_http;
 extern crate rustls;
+extern crate webpki;
 extern crate webpki_roots;
+#[cfg(test)]
+extern crate rcgen;
 
 use mco_http::net::{HttpStream, NetworkStream};
-use std::convert::{TryInto};
+use std::convert::{TryFrom, TryInto};
 
-use std::{io};
+use std::{fs, io};
+use std::collections::HashMap;
 use std::fmt::{Debug, Display, Formatter};
-use std::io::{BufReader, Cursor, Read, Write};
+use std::io::{BufReader, Cursor, Read};
 use std::net::{Shutdown, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
-use rustls::{ClientConfig, ClientConnection, ConfigBuilder, RootCertStore, ServerConfig, ServerConnection, StreamOwned, WantsVerifier};
-use rustls::client::WantsClientCert;
+use rustls::{ClientConfig, ClientConnection, ConfigBuilder, Connection, RootCertStore, ServerConfig, ServerConnection, WantsVerifier};
+use rustls::client::{ResolvesClientCert, WantsClientCert};
+use rustls::crypto::ring::sign::any_supported_type;
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::SignatureScheme;
+use rustls::pki_types::CertificateDer;
 use mco_http::runtime::Mutex;
 
 
-pub enum Connection {
-    Client(StreamOwned<ClientConnection, HttpStream>),
-    Server(StreamOwned<ServerConnection, HttpStream>),
+/// A TLS connection and the socket its records are written to.
+pub struct TlsStream {
+    conn: Connection,
+    sock: HttpStream,
 }
 
-impl Read for Connection {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        match self {
-            Connection::Client(c) => {
-                c.read(buf)
-            }
-            Connection::Server(c) => {
-                c.read(buf)
-            }
-        }
+impl TlsStream {
+    fn new(conn: Connection, sock: HttpStream) -> TlsStream {
+        TlsStream { conn, sock }
     }
-}
 
-impl Write for Connection {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        match self {
-            Connection::Client(c) => {
-                c.write(buf)
-            }
-            Connection::Server(c) => {
-                c.write(buf)
-            }
+    /// Send the records that are ready, such as handshake messages and
+    /// written data.
+    fn write_pending(&mut self) -> io::Result<()> {
+        while self.conn.wants_write() {
+            self.conn.write_tls(&mut self.sock)?;
         }
+        Ok(())
     }
 
-    fn flush(&mut self) -> io::Result<()> {
-        match self {
-            Connection::Client(c) => {
-                c.flush()
+    /// Process records read off the socket, returning how many bytes were used.
+    fn read_records(&mut self, mut records: &[u8]) -> io::Result<usize> {
+        let len = records.len();
+        while !records.is_empty() {
+            // the buffer of received data is full until it's read
+            if self.conn.read_tls(&mut records).is_err() {
+                break;
             }
-            Connection::Server(c) => {
-                c.flush()
+            if let Err(e) = self.conn.process_new_packets() {
+                // tell the peer, if we can
+                let _ = self.write_pending();
+                return Err(io::Error::new(io::ErrorKind::InvalidData, e));
             }
         }
+        Ok(len - records.len())
     }
 }
 
-
-pub struct TlsStream {
-    sess: Box<Connection>,
-    tls_error: Option<rustls::Error>,
+/// What was read off the socket, but not yet processed.
+struct Reading {
+    sock: HttpStream,
+    records: Vec<u8>,
+    eof: bool,
 }
 
-impl TlsStream {
-    fn promote_tls_error(&mut self) -> io::Result<()> {
-        match self.tls_error.take() {
-            Some(err) => {
-                return Err(io::Error::new(io::ErrorKind::ConnectionAborted, err));
-            }
-            None => return Ok(()),
-        };
-    }
+/// A TLS stream that can be cloned, to read from one clone while writing to
+/// another.
+///
+/// Reading off the socket doesn't keep the TLS connection locked, so a read
+/// waiting for data doesn't hold up writes.
+#[derive(Clone)]
+pub struct WrappedStream {
+    tls: Arc<Mutex<TlsStream>>,
+    reading: Arc<Mutex<Reading>>,
+    sock: HttpStream,
+    /// The host a client connected to.
+    host: Option<String>,
 }
 
-impl NetworkStream for TlsStream {
-    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
-        match self.sess.as_mut() {
-            Connection::Client(c) => {
-                c.sock.peer_addr()
-            }
-            Connection::Server(c) => {
-                c.sock.peer_addr()
-            }
+impl WrappedStream {
+    fn new(conn: Connection, sock: HttpStream, host: Option<String>) -> WrappedStream {
+        let reading = Reading {
+            sock: sock.clone(),
+            records: Vec::new(),
+            eof: false,
+        };
+        WrappedStream {
+            tls: Arc::new(Mutex::new(TlsStream::new(conn, sock.clone()))),
+            reading: Arc::new(Mutex::new(reading)),
+            sock,
+            host,
         }
     }
 
-    fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
-        match self.sess.as_ref() {
-            Connection::Client(c) => {
-                c.sock.set_read_timeout(dur)
-            }
-            Connection::Server(c) => {
-                c.sock.set_read_timeout(dur)
-            }
-        }
+    /// The protocol both sides agreed on with ALPN, such as `h2`, once the
+    /// handshake is done.
+    pub fn alpn_protocol(&self) -> Option<Vec<u8>> {
+        self.lock().conn.alpn_protocol().map(|p| p.to_vec())
     }
 
-    fn set_write_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
-        match self.sess.as_ref() {
-            Connection::Client(c) => {
-                c.sock.set_write_timeout(dur)
-            }
-            Connection::Server(c) => {
-                c.sock.set_write_timeout(dur)
-            }
-        }
+    /// The certificate chain the peer presented, end-entity first, once the
+    /// handshake verified it. On a server, clients only present one when asked
+    /// to, see `SSLServer::require_client_cert`.
+    pub fn peer_certificates(&self) -> Option<Vec<CertificateDer<'static>>> {
+        self.lock().conn.peer_certificates()
+            .map(|certs| certs.iter().map(|cert| cert.clone().into_owned()).collect())
     }
-}
 
-impl io::Read for TlsStream {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        loop {
-            self.promote_tls_error()?;
-            match self.sess.as_mut().read(buf) {
-                Ok(0) => continue,
-                Ok(n) => return Ok(n),
-                Err(e) => return Err(e),
-            }
+    /// The server name of the connection: the one the client sent with SNI on
+    /// a server, the host it connected to on a client.
+    pub fn server_name(&self) -> Option<String> {
+        match self.lock().conn {
+            Connection::Server(ref c) => c.server_name().map(|name| name.to_owned()),
+            Connection::Client(_) => self.host.clone(),
         }
     }
-}
 
-impl io::Write for TlsStream {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        let len = self.sess.write(buf)?;
-        self.promote_tls_error()?;
-        Ok(len)
+    // when both are held, `reading` is locked first
+    fn lock(&self) -> mco_http::runtime::MutexGuard<'_, TlsStream> {
+        self.tls.lock().unwrap_or_else(|e| e.into_inner())
     }
 
-    fn flush(&mut self) -> io::Result<()> {
-        let rc = self.sess.flush();
-        self.promote_tls_error()?;
-        rc
+    fn lock_reading(&self) -> mco_http::runtime::MutexGuard<'_, Reading> {
+        self.reading.lock().unwrap_or_else(|e| e.into_inner())
     }
-}
 
-#[derive(Clone)]
-pub struct WrappedStream(Arc<Mutex<TlsStream>>);
+    /// Process the records read so far, or read more off the socket.
+    fn read_more(&self, reading: &mut Reading) -> io::Result<()> {
+        if !reading.records.is_empty() || reading.eof {
+            let mut tls = self.lock();
+            let used = tls.read_records(&reading.records)?;
+            reading.records.drain(..used);
+            if reading.eof && reading.records.is_empty() {
+                tls.conn.read_tls(&mut io::empty())?;
+            }
+            return tls.write_pending();
+        }
+        self.lock().write_pending()?;
+        let mut buf = [0; 16 * 1024];
+        let n = reading.sock.read(&mut buf)?;
+        reading.eof = n == 0;
+        reading.records.extend_from_slice(&buf[..n]);
+        Ok(())
+    }
 
-impl WrappedStream {
-    fn lock(&self) -> mco_http::runtime::MutexGuard<TlsStream> {
-        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    /// Finish the handshake, if it's not done yet.
+    fn handshake(&self) -> io::Result<()> {
+        if !self.lock().conn.is_handshaking() {
+            return Ok(());
+        }
+        let mut reading = self.lock_reading();
+        loop {
+            let eof = reading.eof;
+            self.read_more(&mut reading)?;
+            if !self.lock().conn.is_handshaking() {
+                return Ok(());
+            }
+            if eof {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "tls handshake eof"));
+            }
+        }
     }
 }
 
 impl io::Read for WrappedStream {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.lock().read(buf)
+        let mut reading = self.lock_reading();
+        loop {
+            match self.lock().conn.reader().read(buf) {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                rc => return rc,
+            }
+            self.read_more(&mut reading)?;
+        }
     }
 }
 
 impl io::Write for WrappedStream {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.lock().write(buf)
+        self.handshake()?;
+        let mut tls = self.lock();
+        let len = tls.conn.writer().write(buf)?;
+        tls.write_pending()?;
+        Ok(len)
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        self.lock().flush()
+        let mut tls = self.lock();
+        tls.conn.writer().flush()?;
+        tls.write_pending()?;
+        tls.sock.flush()
     }
 }
 
 impl NetworkStream for WrappedStream {
     fn peer_addr(&mut self) -> io::Result<SocketAddr> {
-        self.lock().peer_addr()
+        self.sock.peer_addr()
     }
 
     fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
-        self.lock().set_read_timeout(dur)
+        self.sock.set_read_timeout(dur)
     }
 
     fn set_write_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
-        self.lock().set_write_timeout(dur)
+        self.sock.set_write_timeout(dur)
     }
 
     fn close(&mut self, how: Shutdown) -> io::Result<()> {
-        self.lock().close(how)
+        if how != Shutdown::Read {
+            let mut tls = self.lock();
+            tls.conn.send_close_notify();
+            let _ = tls.write_pending();
+        }
+        self.sock.close(how)
     }
 }
 
@@ -212,6 +255,39 @@ impl TlsClient {
             cfg: Arc::new(tls),
         })
     }
+
+    /// Offer `protocols` with ALPN, in order of preference, such as `h2` for an
+    /// `Http2Protocol`.
+    pub fn with_alpn(self, protocols: Vec<Vec<u8>>) -> TlsClient {
+        let mut cfg = (*self.cfg).clone();
+        cfg.alpn_protocols = protocols;
+        TlsClient {
+            cfg: Arc::new(cfg),
+        }
+    }
+
+    /// Present this certificate chain and private key, both PEM encoded, to
+    /// servers asking for a client certificate.
+    pub fn with_client_cert(self, certs: &[u8], key: &[u8]) -> io::Result<TlsClient> {
+        let mut cfg = (*self.cfg).clone();
+        cfg.client_auth_cert_resolver = Arc::new(ClientCert(Arc::new(certified_key(certs, key)?)));
+        Ok(TlsClient {
+            cfg: Arc::new(cfg),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct ClientCert(Arc<CertifiedKey>);
+
+impl ResolvesClientCert for ClientCert {
+    fn resolve(&self, _root_hint_subjects: &[&[u8]], _sigschemes: &[SignatureScheme]) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.clone())
+    }
+
+    fn has_certs(&self) -> bool {
+        true
+    }
 }
 
 
@@ -305,12 +381,7 @@ impl mco_http::net::SslClient for TlsClient {
             self.cfg.clone(),
             host.to_string().try_into().unwrap(),
         ).map_err(|e| mco_http::Error::Ssl(Box::new(e)))?;
-        let tls = TlsStream {
-            sess: Box::new(Connection::Client(StreamOwned::new(c, stream))),
-            tls_error: None,
-        };
-
-        Ok(WrappedStream(Arc::new(Mutex::new(tls))))
+        Ok(WrappedStream::new(Connection::Client(c), stream, Some(host.to_owned())))
     }
 }
 
@@ -324,7 +395,7 @@ pub struct SSLServer {
 
 impl SSLServer {
 
-    /// new with with_single_cert
+    /// new with with_single_cert, offering HTTP/2 and HTTP/1.1 with ALPN
     pub fn new(certs: Vec<Vec<u8>>, key: Vec<u8>) -> SSLServer {
         let flattened_data: Vec<u8> = certs.into_iter().flatten().collect();
         let mut reader = BufReader::new(Cursor::new(flattened_data));
@@ -334,9 +405,35 @@ impl SSLServer {
         if private_key.is_none() {
             panic!("load keys is empty")
         }
-        let config = rustls::ServerConfig::builder()
+        let mut config = rustls::ServerConfig::builder()
             .with_no_client_auth()
             .with_single_cert(certs, private_key.unwrap()).unwrap();
+        config.alpn_protocols = default_alpn();
+
+        SSLServer {
+            cfg: Arc::new(config),
+        }
+    }
+
+    /// new with a certificate for each server name, offering HTTP/2 and HTTP/1.1 with ALPN
+    pub fn new_sni(resolver: SniResolver) -> SSLServer {
+        let mut config = rustls::ServerConfig::builder()
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(resolver));
+        config.alpn_protocols = default_alpn();
+
+        SSLServer {
+            cfg: Arc::new(config),
+        }
+    }
+
+    /// new with a certificate that can be replaced while serving, offering
+    /// HTTP/2 and HTTP/1.1 with ALPN
+    pub fn new_reloadable(reloader: CertReloader) -> SSLServer {
+        let mut config = rustls::ServerConfig::builder()
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(reloader));
+        config.alpn_protocols = default_alpn();
 
         SSLServer {
             cfg: Arc::new(config),
@@ -350,6 +447,275 @@ impl SSLServer {
         }
     }
 
+    /// Ask clients for a certificate issued by one of the CAs of `ca`, a PEM
+    /// bundle, failing the handshake of those without one.
+    ///
+    /// This builds a new `ServerConfig`: its public fields are kept, but the
+    /// crypto provider and protocol versions of one passed to
+    /// `with_tls_config` go back to the rustls defaults.
+    pub fn require_client_cert(self, ca: &[u8]) -> io::Result<SSLServer> {
+        self.client_auth(ca, true)
+    }
+
+    /// Ask clients for a certificate issued by one of the CAs of `ca`, a PEM
+    /// bundle, still accepting those without one.
+    ///
+    /// Like `require_client_cert`, this keeps the public fields of the
+    /// `ServerConfig` only.
+    pub fn request_client_cert(self, ca: &[u8]) -> io::Result<SSLServer> {
+        self.client_auth(ca, false)
+    }
+
+    // the verifier can only be set building a config, to which the public
+    // fields of the current one are copied
+    fn client_auth(self, ca: &[u8], required: bool) -> io::Result<SSLServer> {
+        let mut roots = RootCertStore::empty();
+        for cert in rustls_pemfile::certs(&mut BufReader::new(ca)) {
+            roots.add(cert?).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        }
+        let mut verifier = WebPkiClientVerifier::builder(Arc::new(roots));
+        if !required {
+            verifier = verifier.allow_unauthenticated();
+        }
+        let verifier = verifier.build().map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let old = &*self.cfg;
+        let mut config = rustls::ServerConfig::builder()
+            .with_client_cert_verifier(verifier)
+            .with_cert_resolver(old.cert_resolver.clone());
+        config.ignore_client_order = old.ignore_client_order;
+        config.max_fragment_size = old.max_fragment_size;
+        config.session_storage = old.session_storage.clone();
+        config.ticketer = old.ticketer.clone();
+        config.alpn_protocols = old.alpn_protocols.clone();
+        config.key_log = old.key_log.clone();
+        config.enable_secret_extraction = old.enable_secret_extraction;
+        config.max_early_data_size = old.max_early_data_size;
+        config.send_half_rtt_data = old.send_half_rtt_data;
+        config.send_tls13_tickets = old.send_tls13_tickets;
+
+        Ok(SSLServer {
+            cfg: Arc::new(config),
+        })
+    }
+
+    /// Accept `protocols` with ALPN, in order of preference. With none, a client
+    /// speaks HTTP/1.1 unless it knows the server speaks HTTP/2.
+    pub fn with_alpn(self, protocols: Vec<Vec<u8>>) -> SSLServer {
+        let mut config = (*self.cfg).clone();
+        config.alpn_protocols = protocols;
+        SSLServer {
+            cfg: Arc::new(config),
+        }
+    }
+
+}
+
+fn default_alpn() -> Vec<Vec<u8>> {
+    vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+}
+
+/// Picks the certificate of a connection by the server name its client sent
+/// with SNI.
+///
+/// A name may start with a `*.` wildcard, which matches one more label.
+/// Clients sending no name, or one without a certificate, get the default
+/// certificate if there is one, and fail the handshake otherwise.
+#[derive(Debug, Default)]
+pub struct SniResolver {
+    certs: HashMap<String, Arc<CertifiedKey>>,
+    default: Option<Arc<CertifiedKey>>,
+}
+
+impl SniResolver {
+    pub fn new() -> SniResolver {
+        SniResolver::default()
+    }
+
+    /// Add the certificate chain and private key of `name`, both PEM encoded.
+    pub fn add(&mut self, name: &str, certs: &[u8], key: &[u8]) -> io::Result<()> {
+        let key = certified_key(certs, key)?;
+        self.certs.insert(name.to_ascii_lowercase(), Arc::new(key));
+        Ok(())
+    }
+
+    /// Add the certificate chain and private key of `name` from PEM files.
+    pub fn add_pem_files<P: AsRef<Path>>(&mut self, name: &str, certs: P, key: P) -> io::Result<()> {
+        self.add(name, &fs::read(certs)?, &fs::read(key)?)
+    }
+
+    /// Use this certificate chain and private key, both PEM encoded, when no
+    /// other matches.
+    pub fn set_default(&mut self, certs: &[u8], key: &[u8]) -> io::Result<()> {
+        self.default = Some(Arc::new(certified_key(certs, key)?));
+        Ok(())
+    }
+
+    fn find(&self, name: &str) -> Option<&Arc<CertifiedKey>> {
+        let name = name.to_ascii_lowercase();
+        if let Some(key) = self.certs.get(&name) {
+            return Some(key);
+        }
+        let parent = name.split_once('.')?.1;
+        self.certs.get(&format!("*.{}", parent))
+    }
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        client_hello.server_name()
+            .and_then(|name| self.find(name))
+            .or(self.default.as_ref())
+            .cloned()
+    }
+}
+
+fn certified_key(certs: &[u8], key: &[u8]) -> io::Result<CertifiedKey> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(certs)).collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "no certificates found"));
+    }
+    let key = rustls_pemfile::private_key(&mut BufReader::new(key))?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no private key found"))?;
+    let key = any_supported_type(&key).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let certified = CertifiedKey::new(certs, key);
+    check_key(&certified)?;
+    Ok(certified)
+}
+
+/// Fails unless the private key of `certified` is the one of its first
+/// certificate, by signing a probe and verifying it with the certificate.
+fn check_key(certified: &CertifiedKey) -> io::Result<()> {
+    let mismatch = || io::Error::new(io::ErrorKind::InvalidInput, "private key doesn't match the certificate");
+    let algorithms = rustls::crypto::ring::default_provider().signature_verification_algorithms;
+    let signer = certified.key.choose_scheme(&algorithms.supported_schemes()).ok_or_else(mismatch)?;
+    let probe = b"mco-http-rustls key check";
+    let signature = signer.sign(probe).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let cert = webpki::EndEntityCert::try_from(&certified.cert[0])
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{:?}", e)))?;
+    let verified = algorithms.mapping.iter()
+        .filter(|(scheme, _)| *scheme == signer.scheme())
+        .flat_map(|(_, algs)| algs.iter())
+        .any(|alg| cert.verify_signature(*alg, probe, &signature).is_ok());
+    if verified {
+        Ok(())
+    } else {
+        Err(mismatch())
+    }
+}
+
+/// A certificate chain and private key that can be replaced while serving,
+/// for a server made with `SSLServer::new_reloadable`.
+///
+/// Clones are handles to the same certificate. Handshakes started after a
+/// reload present the new certificate, while connections already established
+/// keep theirs.
+#[derive(Clone)]
+pub struct CertReloader {
+    inner: Arc<Reloadable>,
+}
+
+struct Reloadable {
+    key: Mutex<Arc<CertifiedKey>>,
+    files: Option<(PathBuf, PathBuf)>,
+    // of the files, when last loaded
+    modified: Mutex<Option<(SystemTime, SystemTime)>>,
+}
+
+impl CertReloader {
+    /// Serve this certificate chain and private key, both PEM encoded.
+    pub fn new(certs: &[u8], key: &[u8]) -> io::Result<CertReloader> {
+        Ok(CertReloader::with_key(certified_key(certs, key)?, None, None))
+    }
+
+    /// Serve the certificate chain and private key of these PEM files, which
+    /// `reload` reads again when they change.
+    pub fn from_pem_files<P: AsRef<Path>>(certs: P, key: P) -> io::Result<CertReloader> {
+        let (certs, key) = (certs.as_ref().to_path_buf(), key.as_ref().to_path_buf());
+        let modified = modified(&certs, &key)?;
+        let certified = certified_key(&fs::read(&certs)?, &fs::read(&key)?)?;
+        Ok(CertReloader::with_key(certified, Some((certs, key)), Some(modified)))
+    }
+
+    fn with_key(key: CertifiedKey, files: Option<(PathBuf, PathBuf)>, modified: Option<(SystemTime, SystemTime)>) -> CertReloader {
+        CertReloader {
+            inner: Arc::new(Reloadable {
+                key: Mutex::new(Arc::new(key)),
+                files,
+                modified: Mutex::new(modified),
+            }),
+        }
+    }
+
+    /// Replace the certificate chain and private key, both PEM encoded. On an
+    /// error, such as a key that isn't the one of the certificate, the current
+    /// ones are kept.
+    pub fn set(&self, certs: &[u8], key: &[u8]) -> io::Result<()> {
+        let key = certified_key(certs, key)?;
+        *self.inner.key.lock().unwrap_or_else(|e| e.into_inner()) = Arc::new(key);
+        Ok(())
+    }
+
+    /// Read the PEM files again if either was modified since they were last
+    /// loaded, returning whether the certificate was replaced. On an error the
+    /// current certificate is kept, and the files are read again next time.
+    pub fn reload(&self) -> io::Result<bool> {
+        let (certs, key) = match self.inner.files {
+            Some(ref files) => files,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "certificate not loaded from files")),
+        };
+        let mut last = self.inner.modified.lock().unwrap_or_else(|e| e.into_inner());
+        let modified = modified(certs, key)?;
+        if *last == Some(modified) {
+            return Ok(false);
+        }
+        self.set(&fs::read(certs)?, &fs::read(key)?)?;
+        *last = Some(modified);
+        Ok(true)
+    }
+
+    /// Call `reload` every `interval` in a background coroutine, until every
+    /// handle and the servers using it are dropped. Replace each file by
+    /// renaming, so a poll never reads one half written. A poll between the
+    /// two renames reads a certificate and key that don't match, which `reload`
+    /// rejects, keeping the current pair until the next poll.
+    pub fn watch(&self, interval: Duration) {
+        let inner = Arc::downgrade(&self.inner);
+        mco_http::runtime::spawn(move || loop {
+            mco_http::runtime::sleep(interval);
+            let reloader = match inner.upgrade() {
+                Some(inner) => CertReloader { inner },
+                None => return,
+            };
+            match reloader.reload() {
+                Ok(true) => log::debug!("reloaded certificate"),
+                Ok(false) => {}
+                Err(e) => log::warn!("certificate reload failed: {}", e),
+            }
+        });
+    }
+
+    /// The certificate chain and key served now.
+    pub fn current(&self) -> Arc<CertifiedKey> {
+        self.inner.key.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+fn modified(certs: &Path, key: &Path) -> io::Result<(SystemTime, SystemTime)> {
+    Ok((fs::metadata(certs)?.modified()?, fs::metadata(key)?.modified()?))
+}
+
+impl Debug for CertReloader {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CertReloader")
+            .field("files", &self.inner.files)
+            .finish()
+    }
+}
+
+impl ResolvesServerCert for CertReloader {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current())
+    }
 }
 
 impl mco_http::net::SslServer for SSLServer {
@@ -357,13 +723,309 @@ impl mco_http::net::SslServer for SSLServer {
 
     fn wrap_server(&self, stream: HttpStream) -> mco_http::Result<WrappedStream> {
         let conn = ServerConnection::new(self.cfg.clone()).unwrap();
-        let stream = rustls::StreamOwned::new(conn, stream);
+        Ok(WrappedStream::new(Connection::Server(conn), stream, None))
+    }
+}
 
-        let tls = TlsStream {
-            sess: Box::new(Connection::Server(stream)),
-            tls_error: None,
-        };
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::io::{self, Read, Write};
+    use std::net::{SocketAddr, TcpStream};
+    use std::path::Path;
+    use std::time::{Duration, SystemTime};
+
+    use mco_http::net::{HttpStream, SslClient};
+    use mco_http::server::{Handler, Listening, Request, Response, Server};
+    use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, IsCa};
+    use rustls::pki_types::CertificateDer;
+
+    use super::{CertReloader, SniResolver, SSLServer, TlsClient, WrappedStream};
+
+    fn ca() -> Certificate {
+        let mut params = CertificateParams::new(Vec::new());
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.distinguished_name.push(DnType::CommonName, "mco-http test CA");
+        Certificate::from_params(params).unwrap()
+    }
+
+    /// A certificate for `names` issued by `ca`, and its key, PEM encoded.
+    fn issue(ca: &Certificate, names: &[&str]) -> (Vec<u8>, Vec<u8>) {
+        let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        let cert = Certificate::from_params(CertificateParams::new(names)).unwrap();
+        (cert.serialize_pem_with_signer(ca).unwrap().into_bytes(), cert.serialize_private_key_pem().into_bytes())
+    }
+
+    fn der(pem: &[u8]) -> CertificateDer<'static> {
+        rustls_pemfile::certs(&mut &pem[..]).next().unwrap().unwrap()
+    }
+
+    fn client(ca: &Certificate) -> TlsClient {
+        let pem = ca.serialize_pem().unwrap();
+        TlsClient::new_ca(Some(&mut pem.as_bytes())).unwrap()
+    }
+
+    fn serve<H: Handler + 'static>(ssl: SSLServer, handler: H) -> Listening {
+        Server::https("127.0.0.1:0", ssl).unwrap().handle(handler).unwrap()
+    }
+
+    /// Answers with the server name and ALPN protocol of the connection.
+    fn describe(req: Request, res: Response) {
+        let tls = req.ssl::<WrappedStream>().unwrap();
+        let alpn = tls.alpn_protocol().map(|p| String::from_utf8(p).unwrap());
+        res.send(format!("{:?} {:?}", tls.server_name(), alpn).as_bytes()).unwrap();
+    }
+
+    /// Answers whether the client presented `expected`, or `None` without a
+    /// certificate.
+    fn presented(expected: CertificateDer<'static>) -> impl Handler {
+        move |req: Request, res: Response| {
+            let certs = req.ssl::<WrappedStream>().unwrap().peer_certificates();
+            res.send(format!("{:?}", certs.map(|certs| certs[0] == expected)).as_bytes()).unwrap();
+        }
+    }
+
+    /// Connect to `addr` with `name` as server name, and send a first request.
+    fn get(client: &TlsClient, addr: SocketAddr, name: &str) -> mco_http::Result<(WrappedStream, String)> {
+        let mut tls = client.wrap_client(HttpStream(TcpStream::connect(addr)?), name)?;
+        let body = request(&mut tls)?;
+        Ok((tls, body))
+    }
+
+    /// Send a request on a keep-alive connection, returning the body of the response.
+    fn request(tls: &mut WrappedStream) -> io::Result<String> {
+        tls.write_all(b"GET / HTTP/1.1\r\nHost: example\r\n\r\n")?;
+        tls.flush()?;
+        let mut buf = Vec::new();
+        let mut part = [0; 1024];
+        loop {
+            let n = tls.read(&mut part)?;
+            if n == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            buf.extend_from_slice(&part[..n]);
+            let text = String::from_utf8_lossy(&buf);
+            if let Some(end) = text.find("\r\n\r\n") {
+                let len: usize = text[..end].lines()
+                    .find_map(|line| line.strip_prefix("Content-Length: "))
+                    .unwrap().parse().unwrap();
+                if buf.len() >= end + 4 + len {
+                    return Ok(text[end + 4..].to_string());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_sni_resolver_find() {
+        let ca = ca();
+        let (cert, key) = issue(&ca, &["example.com"]);
+        let mut certs = SniResolver::new();
+        certs.add("Example.com", &cert, &key).unwrap();
+        certs.add("*.example.org", &cert, &key).unwrap();
+        assert!(certs.find("EXAMPLE.COM").is_some());
+        assert!(certs.find("www.example.com").is_none());
+        assert!(certs.find("www.example.org").is_some());
+        // a wildcard matches one more label
+        assert!(certs.find("example.org").is_none());
+        assert!(certs.find("a.www.example.org").is_none());
+        assert!(certs.add("example.net", b"", &key).is_err());
+        assert!(certs.add("example.net", &cert, b"").is_err());
+    }
+
+    #[test]
+    fn test_sni_handshake() {
+        let ca = ca();
+        let com = issue(&ca, &["example.com"]);
+        let org = issue(&ca, &["*.example.org"]);
+        let fallback = issue(&ca, &["fallback.test"]);
+        let client = client(&ca);
+
+        let mut certs = SniResolver::new();
+        certs.add("example.com", &com.0, &com.1).unwrap();
+        certs.add("*.example.org", &org.0, &org.1).unwrap();
+        let mut listening = serve(SSLServer::new_sni(certs), describe);
+        let (tls, body) = get(&client, listening.socket, "example.com").unwrap();
+        assert_eq!(tls.peer_certificates().unwrap()[0], der(&com.0));
+        assert_eq!(body, "Some(\"example.com\") None");
+        let (tls, body) = get(&client, listening.socket, "www.example.org").unwrap();
+        assert_eq!(tls.peer_certificates().unwrap()[0], der(&org.0));
+        assert_eq!(body, "Some(\"www.example.org\") None");
+        // no certificate for the name, and no default
+        assert!(get(&client, listening.socket, "fallback.test").is_err());
+        listening.close().unwrap();
+
+        let mut certs = SniResolver::new();
+        certs.add("example.com", &com.0, &com.1).unwrap();
+        certs.set_default(&fallback.0, &fallback.1).unwrap();
+        let mut listening = serve(SSLServer::new_sni(certs), describe);
+        let (tls, body) = get(&client, listening.socket, "fallback.test").unwrap();
+        assert_eq!(tls.peer_certificates().unwrap()[0], der(&fallback.0));
+        assert_eq!(body, "Some(\"fallback.test\") None");
+        listening.close().unwrap();
+    }
+
+    #[test]
+    fn test_alpn() {
+        let ca = ca();
+        let (cert, key) = issue(&ca, &["localhost"]);
+        let client = client(&ca).with_alpn(vec![b"http/1.1".to_vec()]);
+
+        let mut listening = serve(SSLServer::new(vec![cert.clone()], key.clone()), describe);
+        let (tls, body) = get(&client, listening.socket, "localhost").unwrap();
+        assert_eq!(tls.alpn_protocol(), Some(b"http/1.1".to_vec()));
+        assert_eq!(tls.server_name(), Some("localhost".to_string()));
+        assert_eq!(body, "Some(\"localhost\") Some(\"http/1.1\")");
+        listening.close().unwrap();
+
+        // a server without ALPN picks no protocol
+        let ssl = SSLServer::new(vec![cert.clone()], key.clone()).with_alpn(Vec::new());
+        let mut listening = serve(ssl, describe);
+        let (tls, body) = get(&client, listening.socket, "localhost").unwrap();
+        assert_eq!(tls.alpn_protocol(), None);
+        assert_eq!(body, "Some(\"localhost\") None");
+        listening.close().unwrap();
+
+        // nor one the client doesn't offer
+        let mut listening = serve(SSLServer::new(vec![cert], key).with_alpn(vec![b"h2".to_vec()]), describe);
+        assert!(get(&client, listening.socket, "localhost").is_err());
+        listening.close().unwrap();
+    }
+
+    #[test]
+    fn test_client_cert_required() {
+        let other = issue(&ca(), &["client"]);
+        let ca = ca();
+        let (cert, key) = issue(&ca, &["localhost"]);
+        let (client_cert, client_key) = issue(&ca, &["client"]);
+        let ssl = SSLServer::new(vec![cert.clone()], key)
+            .require_client_cert(ca.serialize_pem().unwrap().as_bytes()).unwrap();
+        let mut listening = serve(ssl, presented(der(&client_cert)));
+
+        let tls_client = client(&ca).with_client_cert(&client_cert, &client_key).unwrap();
+        let (tls, body) = get(&tls_client, listening.socket, "localhost").unwrap();
+        assert_eq!(body, "Some(true)");
+        assert_eq!(tls.peer_certificates().unwrap()[0], der(&cert));
+        // without a certificate, or with one of another CA
+        assert!(get(&client(&ca), listening.socket, "localhost").is_err());
+        let tls_client = client(&ca).with_client_cert(&other.0, &other.1).unwrap();
+        assert!(get(&tls_client, listening.socket, "localhost").is_err());
+        listening.close().unwrap();
+
+        assert!(SSLServer::new(vec![cert], other.1).require_client_cert(b"").is_err());
+    }
+
+    #[test]
+    fn test_client_cert_requested() {
+        let other = issue(&ca(), &["client"]);
+        let ca = ca();
+        let (cert, key) = issue(&ca, &["localhost"]);
+        let (client_cert, client_key) = issue(&ca, &["client"]);
+        let ssl = SSLServer::new(vec![cert], key)
+            .request_client_cert(ca.serialize_pem().unwrap().as_bytes()).unwrap();
+        let mut listening = serve(ssl, presented(der(&client_cert)));
+
+        let tls_client = client(&ca).with_client_cert(&client_cert, &client_key).unwrap();
+        let (_, body) = get(&tls_client, listening.socket, "localhost").unwrap();
+        assert_eq!(body, "Some(true)");
+        let (_, body) = get(&client(&ca), listening.socket, "localhost").unwrap();
+        assert_eq!(body, "None");
+        // a certificate that doesn't verify still fails
+        let tls_client = client(&ca).with_client_cert(&other.0, &other.1).unwrap();
+        assert!(get(&tls_client, listening.socket, "localhost").is_err());
+        listening.close().unwrap();
+    }
+
+    #[test]
+    fn test_client_auth_keeps_config() {
+        let ca = ca();
+        let (cert, key) = issue(&ca, &["localhost"]);
+        let ssl = SSLServer::new(vec![cert], key);
+        let mut config = (*ssl.cfg).clone();
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        config.ignore_client_order = true;
+        config.max_fragment_size = Some(1024);
+        config.send_tls13_tickets = 1;
+        let ssl = ssl.with_tls_config(config)
+            .request_client_cert(ca.serialize_pem().unwrap().as_bytes()).unwrap();
+        assert_eq!(ssl.cfg.alpn_protocols, vec![b"http/1.1".to_vec()]);
+        assert!(ssl.cfg.ignore_client_order);
+        assert_eq!(ssl.cfg.max_fragment_size, Some(1024));
+        assert_eq!(ssl.cfg.send_tls13_tickets, 1);
+    }
+
+    #[test]
+    fn test_cert_reloader_handshake() {
+        let ca = ca();
+        let first = issue(&ca, &["localhost"]);
+        let second = issue(&ca, &["localhost"]);
+        let reloader = CertReloader::new(&first.0, &first.1).unwrap();
+        let mut listening = serve(SSLServer::new_reloadable(reloader.clone()), describe);
+
+        let (mut old, _) = get(&client(&ca), listening.socket, "localhost").unwrap();
+        assert_eq!(old.peer_certificates().unwrap()[0], der(&first.0));
+        reloader.set(&second.0, &second.1).unwrap();
+        // a new client, which can't resume the session of the first
+        let (new, _) = get(&client(&ca), listening.socket, "localhost").unwrap();
+        assert_eq!(new.peer_certificates().unwrap()[0], der(&second.0));
+        // the connection made before goes on with the first certificate
+        assert_eq!(request(&mut old).unwrap(), "Some(\"localhost\") None");
+        assert_eq!(old.peer_certificates().unwrap()[0], der(&first.0));
+
+        // a certificate or key that doesn't parse keeps the current one
+        assert!(reloader.set(b"", &first.1).is_err());
+        assert!(reloader.set(&first.0, b"").is_err());
+        // and so does a key that isn't the one of the certificate
+        assert!(reloader.set(&first.0, &second.1).is_err());
+        assert_eq!(reloader.current().cert[0], der(&second.0));
+        let (tls, _) = get(&client(&ca), listening.socket, "localhost").unwrap();
+        assert_eq!(tls.peer_certificates().unwrap()[0], der(&second.0));
+        listening.close().unwrap();
+    }
+
+    #[test]
+    fn test_cert_reloader_files() {
+        // the modification time is what tells a reload to read the files again
+        fn write(path: &Path, contents: &[u8], secs: u64) {
+            fs::write(path, contents).unwrap();
+            let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+            File::options().write(true).open(path).unwrap().set_modified(modified).unwrap();
+        }
+
+        let ca = ca();
+        let first = issue(&ca, &["localhost"]);
+        let second = issue(&ca, &["localhost"]);
+        let dir = std::env::temp_dir().join(format!("mco_http_rustls_reload_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+        write(&cert_path, &first.0, 1);
+        write(&key_path, &first.1, 1);
+
+        let reloader = CertReloader::from_pem_files(&cert_path, &key_path).unwrap();
+        assert!(!reloader.reload().unwrap());
+        write(&cert_path, &second.0, 2);
+        write(&key_path, &second.1, 2);
+        assert!(reloader.reload().unwrap());
+        assert_eq!(reloader.current().cert[0], der(&second.0));
+        assert!(!reloader.reload().unwrap());
+
+        // a broken file keeps the current certificate, and is read again
+        write(&key_path, b"not a key", 3);
+        assert!(reloader.reload().is_err());
+        assert!(reloader.reload().is_err());
+        assert_eq!(reloader.current().cert[0], der(&second.0));
+        write(&key_path, &second.1, 4);
+        assert!(reloader.reload().unwrap());
+
+        // read between replacing the certificate and the key, the pair doesn't match
+        write(&cert_path, &first.0, 5);
+        assert!(reloader.reload().is_err());
+        assert_eq!(reloader.current().cert[0], der(&second.0));
+        write(&key_path, &first.1, 5);
+        assert!(reloader.reload().unwrap());
+        assert_eq!(reloader.current().cert[0], der(&first.0));
 
-        Ok(WrappedStream(Arc::new(Mutex::new(tls))))
+        assert!(CertReloader::new(&first.0, &first.1).unwrap().reload().is_err());
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,440 @@
+//! HTTP authentication.
+//!
+//! `Auth` is a `MiddleWare` checking the `Authorization` header of each
+//! request with a verifier fn, for the Basic, Bearer or Digest scheme. The
+//! principal the verifier returns is inserted into `Request::extra`, where
+//! handlers get it with `Extra`. Requests failing are answered with `401
+//! Unauthorized` and the `WWW-Authenticate` challenge of the scheme.
+//!
+//! ```rust
+//! use mco_http::auth::{Auth, BearerError};
+//! use mco_http::extract::{reply, Extra};
+//! use mco_http::route::Route;
+//!
+//! #[derive(Clone)]
+//! struct User(String);
+//!
+//! let route = Route::new();
+//! let mut auth = Auth::bearer("api", |token: &str| match token {
+//!     "secret" => Ok(User("joe".to_owned())),
+//!     _ => Err(BearerError::InvalidToken("unknown token".to_owned())),
+//! });
+//! // no token needed to log in
+//! auth.skip("/login");
+//! route.add_middleware(auth);
+//! route.get_fn("/me", reply(|Extra(user): Extra<User>| user.0));
+//! route.post_fn("/login", reply(|| "secret"));
+//! ```
+//!
+//! To protect only part of the urls, `skip` the others or add the middleware
+//! to a sub route given to `Route::mount`.
+use std::fmt::{self, Debug, Formatter};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use md5::Md5;
+use sha2::Sha256;
+
+use crate::extract::Rejection;
+use crate::header::{Authorization, Basic, Bearer, Digest};
+use crate::route::{self, Match, MiddleWare, Tree};
+use crate::server::{Request, Response};
+use crate::status::StatusCode;
+
+/// Why a verifier refused a bearer token, defined in
+/// [RFC6750](https://tools.ietf.org/html/rfc6750#section-3.1).
+///
+/// Each carries the `error_description` sent to the client.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BearerError {
+    /// The request is malformed, answered with `400 Bad Request`.
+    InvalidRequest(String),
+    /// The token is unknown, expired or revoked, answered with `401 Unauthorized`.
+    InvalidToken(String),
+    /// The token doesn't allow the request, answered with `403 Forbidden`.
+    InsufficientScope(String),
+}
+
+impl BearerError {
+    /// The `error` code sent to the client.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            BearerError::InvalidRequest(_) => "invalid_request",
+            BearerError::InvalidToken(_) => "invalid_token",
+            BearerError::InsufficientScope(_) => "insufficient_scope",
+        }
+    }
+
+    /// The `error_description` sent to the client.
+    pub fn description(&self) -> &str {
+        match *self {
+            BearerError::InvalidRequest(ref d) |
+            BearerError::InvalidToken(ref d) |
+            BearerError::InsufficientScope(ref d) => d,
+        }
+    }
+
+    /// The status of the response.
+    pub fn status(&self) -> StatusCode {
+        match *self {
+            BearerError::InvalidRequest(_) => StatusCode::BadRequest,
+            BearerError::InvalidToken(_) => StatusCode::Unauthorized,
+            BearerError::InsufficientScope(_) => StatusCode::Forbidden,
+        }
+    }
+}
+
+type BasicVerifier<P> = Box<dyn Fn(&str, &str) -> Option<P> + Send + Sync>;
+type BearerVerifier<P> = Box<dyn Fn(&str) -> Result<P, BearerError> + Send + Sync>;
+type DigestVerifier<P> = Box<dyn Fn(&str) -> Option<(String, P)> + Send + Sync>;
+
+enum Verifier<P> {
+    Basic(BasicVerifier<P>),
+    Bearer(BearerVerifier<P>),
+    Digest(DigestVerifier<P>),
+}
+
+enum Failure {
+    // no credentials of the scheme
+    Missing,
+    Invalid,
+    // a digest that was right, for an expired nonce
+    Stale,
+    Bearer(BearerError),
+}
+
+/// A `MiddleWare` authenticating requests, see the module docs.
+///
+/// `P` is the principal a verifier returns for valid credentials, such as
+/// a user, inserted into `Request::extra` before the handler runs.
+pub struct Auth<P> {
+    realm: String,
+    verifier: Verifier<P>,
+    skip: Tree<()>,
+    // signs digest nonces, so they needn't be kept
+    nonce_key: [u8; 32],
+    nonce_timeout: Duration,
+}
+
+impl<P: Send + Sync + 'static> Auth<P> {
+    fn new(realm: &str, verifier: Verifier<P>) -> Auth<P> {
+        Auth {
+            realm: realm.to_owned(),
+            verifier,
+            skip: Tree::new(),
+            nonce_key: rand::random(),
+            nonce_timeout: Duration::from_secs(5 * 60),
+        }
+    }
+
+    /// Basic authentication, calling `verifier` with the username and
+    /// password. Only use it over https, the password is sent in clear.
+    pub fn basic<F>(realm: &str, verifier: F) -> Auth<P>
+        where F: Fn(&str, &str) -> Option<P> + Send + Sync + 'static {
+        Auth::new(realm, Verifier::Basic(Box::new(verifier)))
+    }
+
+    /// Bearer token authentication, calling `verifier` with the token.
+    pub fn bearer<F>(realm: &str, verifier: F) -> Auth<P>
+        where F: Fn(&str) -> Result<P, BearerError> + Send + Sync + 'static {
+        Auth::new(realm, Verifier::Bearer(Box::new(verifier)))
+    }
+
+    /// Digest authentication with `qop=auth`, over SHA-256 or MD5, calling
+    /// `verifier` with the username for its password and principal.
+    ///
+    /// Nonces are not kept, so a request can be replayed until its nonce
+    /// expires, see `nonce_timeout`.
+    pub fn digest<F>(realm: &str, verifier: F) -> Auth<P>
+        where F: Fn(&str) -> Option<(String, P)> + Send + Sync + 'static {
+        Auth::new(realm, Verifier::Digest(Box::new(verifier)))
+    }
+
+    /// Let requests for `url` through without credentials. The url may
+    /// contain `{param}` and `*wildcard` segments, as for `Route`.
+    pub fn skip(&mut self, url: &str) -> &mut Auth<P> {
+        self.skip.insert(None, url, ());
+        self
+    }
+
+    /// Sets how long a digest nonce is accepted, 5 minutes by default.
+    /// Clients then retry with a new one, without asking the user again.
+    pub fn nonce_timeout(&mut self, timeout: Duration) -> &mut Auth<P> {
+        self.nonce_timeout = timeout;
+        self
+    }
+
+    fn skipped(&self, req: &Request) -> bool {
+        match route::request_path(req) {
+            Some(path) => matches!(self.skip.find(&req.method, path), Match::Found(..)),
+            None => false,
+        }
+    }
+
+    fn authenticate(&self, req: &Request) -> Result<P, Failure> {
+        match self.verifier {
+            Verifier::Basic(ref verifier) => {
+                let Authorization(basic) = req.headers.get::<Authorization<Basic>>().ok_or(Failure::Missing)?;
+                verifier(&basic.username, basic.password.as_deref().unwrap_or("")).ok_or(Failure::Invalid)
+            }
+            Verifier::Bearer(ref verifier) => {
+                let Authorization(bearer) = req.headers.get::<Authorization<Bearer>>().ok_or(Failure::Missing)?;
+                verifier(&bearer.token).map_err(Failure::Bearer)
+            }
+            Verifier::Digest(ref verifier) => {
+                let Authorization(digest) = req.headers.get::<Authorization<Digest>>().ok_or(Failure::Missing)?;
+                self.verify_digest(req, digest, verifier)
+            }
+        }
+    }
+
+    fn verify_digest(&self, req: &Request, digest: &Digest, verifier: &DigestVerifier<P>) -> Result<P, Failure> {
+        let hash = match digest.algorithm.as_deref() {
+            None => hex::<Md5>,
+            Some(a) if a.eq_ignore_ascii_case("MD5") => hex::<Md5>,
+            Some(a) if a.eq_ignore_ascii_case("SHA-256") => hex::<Sha256>,
+            Some(_) => return Err(Failure::Invalid),
+        };
+        let (nc, cnonce) = match (digest.qop.as_deref(), &digest.nc, &digest.cnonce) {
+            (Some("auth"), Some(nc), Some(cnonce)) => (nc, cnonce),
+            _ => return Err(Failure::Invalid),
+        };
+        if digest.realm != self.realm || digest.uri != req.uri.to_string() {
+            return Err(Failure::Invalid);
+        }
+        let issued = self.nonce_issued(&digest.nonce).ok_or(Failure::Invalid)?;
+        let (password, principal) = verifier(&digest.username).ok_or(Failure::Invalid)?;
+        let ha1 = hash(&format!("{}:{}:{}", digest.username, self.realm, password));
+        let ha2 = hash(&format!("{}:{}", req.method, digest.uri));
+        let expected = hash(&format!("{}:{}:{}:{}:auth:{}", ha1, digest.nonce, nc, cnonce, ha2));
+        if !constant_time_eq(expected.as_bytes(), digest.response.to_ascii_lowercase().as_bytes()) {
+            return Err(Failure::Invalid);
+        }
+        if unix_time().saturating_sub(issued) > self.nonce_timeout.as_secs() {
+            return Err(Failure::Stale);
+        }
+        Ok(principal)
+    }
+
+    fn nonce_mac(&self, issued: &str) -> Hmac<Sha256> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.nonce_key).expect("HMAC takes keys of any size");
+        mac.update(issued.as_bytes());
+        mac
+    }
+
+    // the time it was issued at, signed so a client can't make up a fresh one
+    fn nonce(&self, issued: u64) -> String {
+        let issued = format!("{:x}", issued);
+        let tag = BASE64.encode(self.nonce_mac(&issued).finalize().into_bytes());
+        format!("{}.{}", issued, tag)
+    }
+
+    // when the nonce was issued, if it was by us
+    fn nonce_issued(&self, nonce: &str) -> Option<u64> {
+        let (issued, tag) = nonce.split_once('.')?;
+        let tag = BASE64.decode(tag).ok()?;
+        self.nonce_mac(issued).verify_slice(&tag).ok()?;
+        u64::from_str_radix(issued, 16).ok()
+    }
+
+    fn challenges(&self, failure: &Failure) -> Vec<Vec<u8>> {
+        let realm = quote(&self.realm);
+        let challenges = match self.verifier {
+            Verifier::Basic(_) => vec![format!("Basic realm={}, charset=\"UTF-8\"", realm)],
+            Verifier::Bearer(_) => match *failure {
+                Failure::Bearer(ref e) => vec![format!("Bearer realm={}, error=\"{}\", error_description={}",
+                                                      realm, e.code(), quote(e.description()))],
+                _ => vec![format!("Bearer realm={}", realm)],
+            },
+            Verifier::Digest(_) => {
+                let nonce = self.nonce(unix_time());
+                let stale = if let Failure::Stale = *failure { ", stale=true" } else { "" };
+                ["SHA-256", "MD5"].iter()
+                    .map(|algorithm| format!("Digest realm={}, qop=\"auth\", algorithm={}, nonce=\"{}\"{}",
+                                             realm, algorithm, nonce, stale))
+                    .collect()
+            }
+        };
+        challenges.into_iter().map(String::into_bytes).collect()
+    }
+
+    fn reject(&self, res: Response, failure: Failure) {
+        res.headers.set_raw("WWW-Authenticate", self.challenges(&failure));
+        let rejection = match failure {
+            Failure::Bearer(e) => Rejection::new(e.status(), e.description()),
+            _ => Rejection::new(StatusCode::Unauthorized, "unauthorized"),
+        };
+        rejection.write(res);
+    }
+}
+
+impl<P> Debug for Auth<P> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let scheme = match self.verifier {
+            Verifier::Basic(_) => "Basic",
+            Verifier::Bearer(_) => "Bearer",
+            Verifier::Digest(_) => "Digest",
+        };
+        f.debug_struct("Auth")
+            .field("scheme", &scheme)
+            .field("realm", &self.realm)
+            .field("skip", &self.skip)
+            .finish()
+    }
+}
+
+impl<P: Send + Sync + 'static> MiddleWare for Auth<P> {
+    fn handle(&self, req: &mut Request, res: &mut Option<Response>) {
+        if self.skipped(req) {
+            return;
+        }
+        match self.authenticate(req) {
+            Ok(principal) => {
+                req.extra.insert(principal);
+            }
+            Err(failure) => {
+                if let Some(res) = res.take() {
+                    self.reject(res, failure);
+                }
+            }
+        }
+    }
+}
+
+fn hex<D: sha2::Digest>(data: &str) -> String {
+    D::digest(data.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use md5::Md5;
+    use crate::extract::{reply, Extra};
+    use crate::mock::serve;
+    use crate::route::Route;
+    use super::{hex, unix_time, Auth, BearerError};
+
+    #[derive(Clone)]
+    struct User(String);
+
+    fn route(auth: Auth<User>) -> Arc<Route> {
+        let route = Route::new();
+        route.add_middleware(auth);
+        route.get_fn("/me", reply(|Extra(user): Extra<User>| user.0));
+        route.get_fn("/public/{name}", reply(|| "public"));
+        Arc::new(route)
+    }
+
+    fn send(route: &Arc<Route>, path: &str, authorization: Option<&str>) -> String {
+        let authorization = authorization.map(|a| format!("Authorization: {}\r\n", a)).unwrap_or_default();
+        let input = format!("GET {} HTTP/1.1\r\n{}Connection: close\r\n\r\n", path, authorization);
+        serve(route.clone(), input)
+    }
+
+    #[test]
+    fn test_basic() {
+        let mut auth = Auth::basic("site", |user: &str, password: &str| {
+            if user == "Aladdin" && password == "open sesame" { Some(User(user.to_owned())) } else { None }
+        });
+        auth.skip("/public/*");
+        let route = route(auth);
+
+        let s = send(&route, "/me", Some("Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ=="));
+        assert!(s.starts_with("HTTP/1.1 200 OK\r\n"), "{}", s);
+        assert!(s.ends_with("\r\n\r\nAladdin"), "{}", s);
+
+        let s = send(&route, "/me", Some("Basic QWxhZGRpbjpvcGVu"));
+        assert!(s.starts_with("HTTP/1.1 401 Unauthorized\r\n"), "{}", s);
+        assert!(s.contains("\r\nWWW-Authenticate: Basic realm=\"site\", charset=\"UTF-8\"\r\n"), "{}", s);
+
+        let s = send(&route, "/me", None);
+        assert!(s.starts_with("HTTP/1.1 401 Unauthorized\r\n"), "{}", s);
+
+        let s = send(&route, "/public/a", None);
+        assert!(s.starts_with("HTTP/1.1 200 OK\r\n"), "{}", s);
+        assert!(s.ends_with("\r\n\r\npublic"), "{}", s);
+    }
+
+    #[test]
+    fn test_bearer() {
+        let route = route(Auth::bearer("api", |token: &str| match token {
+            "good" => Ok(User("joe".to_owned())),
+            "reader" => Err(BearerError::InsufficientScope("read only".to_owned())),
+            _ => Err(BearerError::InvalidToken("the \"token\" expired".to_owned())),
+        }));
+
+        let s = send(&route, "/me", Some("Bearer good"));
+        assert!(s.ends_with("\r\n\r\njoe"), "{}", s);
+
+        let s = send(&route, "/me", None);
+        assert!(s.starts_with("HTTP/1.1 401 Unauthorized\r\n"), "{}", s);
+        assert!(s.contains("\r\nWWW-Authenticate: Bearer realm=\"api\"\r\n"), "{}", s);
+
+        let s = send(&route, "/me", Some("Bearer old"));
+        assert!(s.starts_with("HTTP/1.1 401 Unauthorized\r\n"), "{}", s);
+        assert!(s.contains("\r\nWWW-Authenticate: Bearer realm=\"api\", error=\"invalid_token\", \
+            error_description=\"the \\\"token\\\" expired\"\r\n"), "{}", s);
+
+        let s = send(&route, "/me", Some("Bearer reader"));
+        assert!(s.starts_with("HTTP/1.1 403 Forbidden\r\n"), "{}", s);
+        assert!(s.contains("error=\"insufficient_scope\""), "{}", s);
+        assert!(s.ends_with("\r\n\r\nread only"), "{}", s);
+    }
+
+    fn digest_header(nonce: &str, uri: &str, password: &str) -> String {
+        let ha1 = hex::<Md5>(&format!("Mufasa:test:{}", password));
+        let ha2 = hex::<Md5>(&format!("GET:{}", uri));
+        let response = hex::<Md5>(&format!("{}:{}:00000001:abc:auth:{}", ha1, nonce, ha2));
+        format!("Digest username=\"Mufasa\", realm=\"test\", nonce=\"{}\", uri=\"{}\", response=\"{}\", \
+                 algorithm=MD5, qop=auth, nc=00000001, cnonce=\"abc\"", nonce, uri, response)
+    }
+
+    #[test]
+    fn test_digest() {
+        let auth = Auth::digest("test", |user: &str| {
+            if user == "Mufasa" { Some(("Circle of Life".to_owned(), User(user.to_owned()))) } else { None }
+        });
+        let fresh = auth.nonce(unix_time());
+        let old = auth.nonce(unix_time() - 3600);
+        let route = route(auth);
+
+        let s = send(&route, "/me", None);
+        assert!(s.starts_with("HTTP/1.1 401 Unauthorized\r\n"), "{}", s);
+        assert!(s.contains("\r\nWWW-Authenticate: Digest realm=\"test\", qop=\"auth\", algorithm=SHA-256, nonce=\""), "{}", s);
+        assert!(s.contains("\r\nWWW-Authenticate: Digest realm=\"test\", qop=\"auth\", algorithm=MD5, nonce=\""), "{}", s);
+
+        let s = send(&route, "/me", Some(&digest_header(&fresh, "/me", "Circle of Life")));
+        assert!(s.starts_with("HTTP/1.1 200 OK\r\n"), "{}", s);
+        assert!(s.ends_with("\r\n\r\nMufasa"), "{}", s);
+
+        let s = send(&route, "/me", Some(&digest_header(&fresh, "/me", "wrong")));
+        assert!(s.starts_with("HTTP/1.1 401 Unauthorized\r\n"), "{}", s);
+        assert!(!s.contains("stale"), "{}", s);
+
+        // signed for another url
+        let s = send(&route, "/me", Some(&digest_header(&fresh, "/other", "Circle of Life")));
+        assert!(s.starts_with("HTTP/1.1 401 Unauthorized\r\n"), "{}", s);
+
+        // a nonce not issued by the middleware
+        let s = send(&route, "/me", Some(&digest_header("1.bm9wZQ", "/me", "Circle of Life")));
+        assert!(s.starts_with("HTTP/1.1 401 Unauthorized\r\n"), "{}", s);
+
+        let s = send(&route, "/me", Some(&digest_header(&old, "/me", "Circle of Life")));
+        assert!(s.starts_with("HTTP/1.1 401 Unauthorized\r\n"), "{}", s);
+        assert!(s.contains(", stale=true\r\n"), "{}", s);
+    }
+}
@@ -0,0 +1,559 @@
+//! Client Cookie Storage
+//!
+//! A `CookieStore` set on a `Client` keeps the cookies of `Set-Cookie`
+//! response headers, and sends those matching a request with its `Cookie`
+//! header, following redirects too.
+//!
+//! ```no_run
+//! # use mco_http::Client;
+//! use std::sync::Arc;
+//! use mco_http::client::cookie::{CookieStore, CookieFile};
+//!
+//! let store = Arc::new(CookieStore::with_persistence(CookieFile::new("cookies.txt")).unwrap());
+//! let mut client = Client::new();
+//! client.set_cookie_store(store.clone());
+//!
+//! client.get("http://example.domain/login").send().unwrap();
+//! store.save().unwrap();
+//! ```
+use std::cmp::Reverse;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use url::{Host, Url};
+
+use crate::header::{Cookie, SetCookie};
+
+/// The `SameSite` attribute of a cookie.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SameSite {
+    /// `SameSite=Strict`
+    Strict,
+    /// `SameSite=Lax`
+    Lax,
+    /// `SameSite=None`
+    None,
+}
+
+impl SameSite {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match *self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+
+    fn parse(s: &str) -> Option<SameSite> {
+        if s.eq_ignore_ascii_case("strict") {
+            Some(SameSite::Strict)
+        } else if s.eq_ignore_ascii_case("lax") {
+            Some(SameSite::Lax)
+        } else if s.eq_ignore_ascii_case("none") {
+            Some(SameSite::None)
+        } else {
+            None
+        }
+    }
+}
+
+/// A cookie kept by a `CookieStore`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StoredCookie {
+    /// The cookie name.
+    pub name: String,
+    /// The cookie value.
+    pub value: String,
+    /// The domain the cookie is sent to, lowercase.
+    pub domain: String,
+    /// Whether the cookie is only sent to `domain` itself, and not its
+    /// subdomains, as when it was set without a `Domain` attribute.
+    pub host_only: bool,
+    /// The path the cookie is sent to, and the paths below it.
+    pub path: String,
+    /// When the cookie expires, `None` for a session cookie.
+    pub expires: Option<SystemTime>,
+    /// Whether the cookie is only sent over `https`.
+    pub secure: bool,
+    /// Whether the cookie is hidden from scripts, kept for persistence.
+    pub http_only: bool,
+    /// The `SameSite` attribute, kept for persistence.
+    pub same_site: Option<SameSite>,
+}
+
+impl StoredCookie {
+    /// Parse a `Set-Cookie` value received from `url`, `None` if it is
+    /// malformed or its `Domain` doesn't cover `url`.
+    ///
+    /// A `Domain` of a single label, such as `com`, is taken for a public
+    /// suffix, and so is one on a host that is an IP address: the cookie is
+    /// only kept if it names the host itself, and then is host-only.
+    pub fn parse(set_cookie: &str, url: &Url) -> Option<StoredCookie> {
+        let host = url.host_str()?.to_ascii_lowercase();
+        let mut parts = set_cookie.split(';');
+        let (name, value) = parts.next()?.split_once('=')?;
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+        let mut cookie = StoredCookie {
+            name: name.to_owned(),
+            value: value.trim().to_owned(),
+            domain: host.clone(),
+            host_only: true,
+            path: default_path(url),
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        };
+        // Max-Age wins over Expires, wherever it is
+        let mut max_age = None;
+        for attr in parts {
+            let (key, val) = match attr.split_once('=') {
+                Some((key, val)) => (key.trim(), val.trim()),
+                None => (attr.trim(), ""),
+            };
+            if key.eq_ignore_ascii_case("domain") {
+                let domain = val.trim_start_matches('.').to_ascii_lowercase();
+                if !domain.is_empty() {
+                    cookie.domain = domain;
+                    cookie.host_only = false;
+                }
+            } else if key.eq_ignore_ascii_case("path") {
+                if val.starts_with('/') {
+                    cookie.path = val.to_owned();
+                }
+            } else if key.eq_ignore_ascii_case("expires") {
+                if let Some(expires) = parse_date(val) {
+                    cookie.expires = Some(expires);
+                }
+            } else if key.eq_ignore_ascii_case("max-age") {
+                if let Ok(secs) = val.parse::<i64>() {
+                    max_age = Some(secs);
+                }
+            } else if key.eq_ignore_ascii_case("secure") {
+                cookie.secure = true;
+            } else if key.eq_ignore_ascii_case("httponly") {
+                cookie.http_only = true;
+            } else if key.eq_ignore_ascii_case("samesite") {
+                cookie.same_site = SameSite::parse(val);
+            }
+        }
+        if let Some(secs) = max_age {
+            cookie.expires = Some(if secs > 0 {
+                expiry(SystemTime::now(), secs as u64)
+            } else {
+                UNIX_EPOCH
+            });
+        }
+        if !cookie.host_only && !domain_match(url, &cookie.domain) {
+            return None;
+        }
+        let is_ip = !matches!(url.host(), Some(Host::Domain(_)));
+        cookie.restrict_public_suffix(url, &|domain: &str| is_ip || !domain.contains('.'))
+    }
+
+    // RFC 6265 5.3 step 5: a cookie for a public suffix is only kept as a
+    // host-only cookie of that host
+    fn restrict_public_suffix(mut self, url: &Url, is_public_suffix: &dyn Fn(&str) -> bool) -> Option<StoredCookie> {
+        if !self.host_only && is_public_suffix(&self.domain) {
+            if !url.host_str().is_some_and(|host| host.eq_ignore_ascii_case(&self.domain)) {
+                return None;
+            }
+            self.host_only = true;
+        }
+        Some(self)
+    }
+
+    /// Whether the cookie has expired.
+    pub fn is_expired(&self) -> bool {
+        self.expires.is_some_and(|expires| expires <= SystemTime::now())
+    }
+
+    /// Whether the cookie is sent with a request to `url`.
+    pub fn matches(&self, url: &Url) -> bool {
+        if self.secure && !matches!(url.scheme(), "https" | "wss") {
+            return false;
+        }
+        let host_matches = if self.host_only {
+            url.host_str().is_some_and(|host| host.eq_ignore_ascii_case(&self.domain))
+        } else {
+            domain_match(url, &self.domain)
+        };
+        host_matches && path_match(url.path(), &self.path)
+    }
+
+    fn same_cookie(&self, other: &StoredCookie) -> bool {
+        self.name == other.name && self.domain == other.domain && self.path == other.path
+    }
+}
+
+/// Where a `CookieStore` loads its cookies from and saves them to.
+pub trait CookiePersistence: Send + Sync {
+    /// Load the saved cookies.
+    fn load(&self) -> io::Result<Vec<StoredCookie>>;
+    /// Save `cookies`, replacing those saved before.
+    fn save(&self, cookies: &[StoredCookie]) -> io::Result<()>;
+}
+
+/// Persists cookies to a file, one per line with tab separated fields.
+///
+/// A missing file loads no cookies. Session cookies are not saved.
+#[derive(Debug)]
+pub struct CookieFile {
+    path: PathBuf,
+}
+
+impl CookieFile {
+    /// Persist cookies to the file at `path`.
+    pub fn new<P: Into<PathBuf>>(path: P) -> CookieFile {
+        CookieFile {
+            path: path.into(),
+        }
+    }
+}
+
+impl CookiePersistence for CookieFile {
+    fn load(&self) -> io::Result<Vec<StoredCookie>> {
+        let file = match fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut cookies = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.splitn(9, '\t').collect();
+            if fields.len() != 9 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "malformed cookie line"));
+            }
+            let expires = fields[6].parse::<u64>()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            cookies.push(StoredCookie {
+                domain: fields[0].to_owned(),
+                host_only: fields[1] == "TRUE",
+                path: fields[2].to_owned(),
+                secure: fields[3] == "TRUE",
+                http_only: fields[4] == "TRUE",
+                same_site: SameSite::parse(fields[5]),
+                expires: Some(expiry(UNIX_EPOCH, expires)),
+                name: fields[7].to_owned(),
+                value: fields[8].to_owned(),
+            });
+        }
+        Ok(cookies)
+    }
+
+    fn save(&self, cookies: &[StoredCookie]) -> io::Result<()> {
+        let mut out = Vec::new();
+        for cookie in cookies {
+            let expires = match cookie.expires {
+                Some(expires) => expires.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+                None => continue,
+            };
+            let flag = |b: bool| if b { "TRUE" } else { "FALSE" };
+            writeln!(out, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                     cookie.domain, flag(cookie.host_only), cookie.path, flag(cookie.secure),
+                     flag(cookie.http_only), cookie.same_site.map_or("", |s| s.as_str()),
+                     expires, cookie.name, cookie.value)?;
+        }
+        // written whole and renamed, so a crash never leaves half a file
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, out)?;
+        fs::rename(tmp, &self.path)
+    }
+}
+
+/// The cookies a `Client` received, sent back with its later requests.
+///
+/// Shared behind an `Arc`, one store can serve several clients.
+#[derive(Default)]
+pub struct CookieStore {
+    cookies: Mutex<Vec<StoredCookie>>,
+    persistence: Option<Box<dyn CookiePersistence>>,
+    public_suffix: Option<Box<IsPublicSuffix>>,
+}
+
+type IsPublicSuffix = dyn Fn(&str) -> bool + Send + Sync;
+
+impl fmt::Debug for CookieStore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CookieStore")
+            .field("cookies", &self.cookies().len())
+            .field("persistent", &self.persistence.is_some())
+            .finish()
+    }
+}
+
+impl CookieStore {
+    /// An empty store, kept in memory only.
+    pub fn new() -> CookieStore {
+        CookieStore::default()
+    }
+
+    /// A store with the cookies loaded from `persistence`, where `save` writes
+    /// them back.
+    pub fn with_persistence<P: CookiePersistence + 'static>(persistence: P) -> io::Result<CookieStore> {
+        let mut cookies = persistence.load()?;
+        cookies.retain(|cookie| !cookie.is_expired());
+        Ok(CookieStore {
+            cookies: Mutex::new(cookies),
+            persistence: Some(Box::new(persistence)),
+            public_suffix: None,
+        })
+    }
+
+    /// Also take the domains for which `is_public_suffix` is true, such as
+    /// `co.uk`, for public suffixes, which no cookie is shared across. Single
+    /// labels always are, see `StoredCookie::parse`, the others take a list
+    /// such as the one of the `publicsuffix` crate.
+    pub fn with_public_suffixes<F>(mut self, is_public_suffix: F) -> CookieStore
+        where F: Fn(&str) -> bool + Send + Sync + 'static {
+        self.public_suffix = Some(Box::new(is_public_suffix));
+        self
+    }
+
+    /// Save the cookies that have not expired, if the store has persistence.
+    pub fn save(&self) -> io::Result<()> {
+        match self.persistence {
+            Some(ref persistence) => persistence.save(&self.cookies()),
+            None => Ok(()),
+        }
+    }
+
+    /// Keep the cookies of a response from `url`, replacing those of the same
+    /// name, domain and path. An expired cookie removes the one it replaces.
+    pub fn store(&self, url: &Url, set_cookie: &SetCookie) {
+        let mut cookies = self.lock();
+        for cookie in set_cookie.iter().filter_map(|s| self.parse(s, url)) {
+            match cookies.iter().position(|c| c.same_cookie(&cookie)) {
+                Some(i) if cookie.is_expired() => {
+                    cookies.remove(i);
+                }
+                // in place, so it keeps its order
+                Some(i) => cookies[i] = cookie,
+                None if cookie.is_expired() => (),
+                None => cookies.push(cookie),
+            }
+        }
+    }
+
+    /// The `Cookie` header for a request to `url`, `None` without a cookie to
+    /// send. Cookies with longer paths come first.
+    pub fn cookie_header(&self, url: &Url) -> Option<Cookie> {
+        let mut cookies = self.lock();
+        cookies.retain(|cookie| !cookie.is_expired());
+        let mut matching: Vec<&StoredCookie> = cookies.iter().filter(|c| c.matches(url)).collect();
+        if matching.is_empty() {
+            return None;
+        }
+        matching.sort_by_key(|c| Reverse(c.path.len()));
+        Some(Cookie(matching.iter().map(|c| format!("{}={}", c.name, c.value)).collect()))
+    }
+
+    /// The cookies that have not expired.
+    pub fn cookies(&self) -> Vec<StoredCookie> {
+        self.lock().iter().filter(|cookie| !cookie.is_expired()).cloned().collect()
+    }
+
+    /// Remove every cookie.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    fn parse(&self, set_cookie: &str, url: &Url) -> Option<StoredCookie> {
+        let cookie = StoredCookie::parse(set_cookie, url)?;
+        match self.public_suffix {
+            Some(ref is_public_suffix) => cookie.restrict_public_suffix(url, &**is_public_suffix),
+            None => Some(cookie),
+        }
+    }
+
+    fn lock(&self) -> ::std::sync::MutexGuard<'_, Vec<StoredCookie>> {
+        self.cookies.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// RFC 6265 5.1.3, an IP address only matching itself
+fn domain_match(url: &Url, domain: &str) -> bool {
+    match url.host() {
+        Some(Host::Domain(host)) => {
+            let host = host.to_ascii_lowercase();
+            // no subdomains of what looks like an IP address
+            let numeric = domain.rsplit('.').next().is_some_and(|label| label.bytes().all(|b| b.is_ascii_digit()));
+            host == domain || !numeric && host.ends_with(domain) && host[..host.len() - domain.len()].ends_with('.')
+        }
+        Some(_) => url.host_str() == Some(domain),
+        None => false,
+    }
+}
+
+// RFC 6265 5.1.4
+fn default_path(url: &Url) -> String {
+    let path = url.path();
+    match path.rfind('/') {
+        Some(0) | None => "/".to_owned(),
+        Some(i) => path[..i].to_owned(),
+    }
+}
+
+fn path_match(path: &str, cookie_path: &str) -> bool {
+    path == cookie_path || path.starts_with(cookie_path)
+        && (cookie_path.ends_with('/') || path[cookie_path.len()..].starts_with('/'))
+}
+
+fn parse_date(s: &str) -> Option<SystemTime> {
+    if let Ok(date) = httpdate::parse_http_date(s) {
+        return Some(date);
+    }
+    // the dashed dates of the original Netscape spec
+    let tm = time::strptime(s, "%a, %d-%b-%Y %T %Z").ok()?;
+    let secs = tm.to_timespec().sec;
+    Some(if secs > 0 { expiry(UNIX_EPOCH, secs as u64) } else { UNIX_EPOCH })
+}
+
+/// `secs` seconds after `start`, but no later than the end of year 9999, the
+/// last date a cookie can name, so a huge `Max-Age` doesn't overflow.
+fn expiry(start: SystemTime, secs: u64) -> SystemTime {
+    let latest = UNIX_EPOCH + Duration::from_secs(253_402_300_799);
+    start.checked_add(Duration::from_secs(secs)).map_or(latest, |time| time.min(latest))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    use url::Url;
+    use crate::header::SetCookie;
+    use super::{CookieFile, CookiePersistence, CookieStore, SameSite, StoredCookie};
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    fn header(store: &CookieStore, u: &str) -> Option<String> {
+        store.cookie_header(&url(u)).map(|c| c.join("; "))
+    }
+
+    #[test]
+    fn test_parse_attributes() {
+        let c = StoredCookie::parse("id=a3fWa; Domain=.Example.com; Path=/docs; Secure; HttpOnly; SameSite=Lax; \
+                                     Expires=Wed, 21 Oct 2099 07:28:00 GMT", &url("https://www.example.com/")).unwrap();
+        assert_eq!(c.name, "id");
+        assert_eq!(c.value, "a3fWa");
+        assert_eq!(c.domain, "example.com");
+        assert!(!c.host_only);
+        assert_eq!(c.path, "/docs");
+        assert!(c.secure && c.http_only);
+        assert_eq!(c.same_site, Some(SameSite::Lax));
+        assert!(c.expires.unwrap() > SystemTime::now());
+
+        let c = StoredCookie::parse("a=b; Max-Age=60; Expires=Thu, 01-Jan-1970 00:00:01 GMT", &url("http://example.com/a/b")).unwrap();
+        assert!(!c.is_expired());
+        assert!(c.host_only);
+        assert_eq!(c.path, "/a");
+
+        let c = StoredCookie::parse("a=b; Expires=Thu, 01-Jan-1970 00:00:01 GMT", &url("http://example.com/")).unwrap();
+        assert!(c.is_expired());
+
+        // capped, instead of overflowing
+        let c = StoredCookie::parse(&format!("a=b; Max-Age={}", i64::MAX), &url("http://example.com/")).unwrap();
+        let end_of_9999 = UNIX_EPOCH + Duration::from_secs(253_402_300_799);
+        assert_eq!(c.expires, Some(end_of_9999));
+
+        assert_eq!(StoredCookie::parse("novalue", &url("http://example.com/")), None);
+        assert_eq!(StoredCookie::parse("a=b; Domain=other.com", &url("http://example.com/")), None);
+        assert_eq!(StoredCookie::parse("a=b; Domain=ample.com", &url("http://example.com/")), None);
+    }
+
+    #[test]
+    fn test_public_suffix() {
+        assert_eq!(StoredCookie::parse("a=b; Domain=com", &url("http://example.com/")), None);
+        assert_eq!(StoredCookie::parse("a=b; Domain=.COM", &url("http://example.com/")), None);
+        let c = StoredCookie::parse("a=b; Domain=localhost", &url("http://localhost/")).unwrap();
+        assert!(c.host_only);
+
+        // an IP address has no domain to share cookies with
+        let c = StoredCookie::parse("a=b; Domain=10.0.0.1", &url("http://10.0.0.1/")).unwrap();
+        assert!(c.host_only);
+        assert_eq!(StoredCookie::parse("a=b; Domain=0.0.1", &url("http://10.0.0.1/")), None);
+        assert_eq!(StoredCookie::parse("a=b; Domain=::1", &url("http://[::1]/")), None);
+        assert_eq!(StoredCookie::parse("a=b; Domain=1.2.3.4", &url("http://a.1.2.3.4/")), None);
+
+        let store = CookieStore::new().with_public_suffixes(|domain| domain == "co.uk");
+        store.store(&url("http://www.example.co.uk/"), &SetCookie(vec![
+            "super=1; Domain=co.uk".to_owned(),
+            "site=2; Domain=example.co.uk".to_owned(),
+        ]));
+        assert_eq!(header(&store, "http://www.example.co.uk/").unwrap(), "site=2");
+        assert_eq!(header(&store, "http://other.co.uk/"), None);
+        store.store(&url("http://co.uk/"), &SetCookie(vec!["own=3; Domain=co.uk".to_owned()]));
+        assert_eq!(header(&store, "http://co.uk/").unwrap(), "own=3");
+        assert_eq!(header(&store, "http://www.co.uk/"), None);
+    }
+
+    #[test]
+    fn test_matching() {
+        let store = CookieStore::new();
+        store.store(&url("http://www.example.com/app/login"), &SetCookie(vec![
+            "host=1".to_owned(),
+            "domain=2; Domain=example.com".to_owned(),
+            "root=3; Path=/".to_owned(),
+            "secure=4; Path=/; Secure".to_owned(),
+        ]));
+        assert_eq!(header(&store, "http://www.example.com/app/x").unwrap(), "host=1; domain=2; root=3");
+        assert_eq!(header(&store, "https://www.example.com/").unwrap(), "root=3; secure=4");
+        assert_eq!(header(&store, "http://api.example.com/app").unwrap(), "domain=2");
+        assert_eq!(header(&store, "http://www.example.com/application"), Some("root=3".to_owned()));
+        assert_eq!(header(&store, "http://example.org/"), None);
+    }
+
+    #[test]
+    fn test_replace_and_expire() {
+        let store = CookieStore::new();
+        let u = url("http://example.com/");
+        store.store(&u, &SetCookie(vec!["a=1".to_owned(), "b=2".to_owned()]));
+        store.store(&u, &SetCookie(vec!["a=3".to_owned()]));
+        assert_eq!(header(&store, "http://example.com/").unwrap(), "a=3; b=2");
+        store.store(&u, &SetCookie(vec!["a=; Max-Age=0".to_owned()]));
+        assert_eq!(header(&store, "http://example.com/").unwrap(), "b=2");
+        store.clear();
+        assert!(store.cookies().is_empty());
+    }
+
+    #[test]
+    fn test_cookie_file() {
+        let path = std::env::temp_dir().join(format!("mco_http_cookies_{}", std::process::id()));
+        let file = CookieFile::new(&path);
+        assert_eq!(file.load().unwrap(), vec![]);
+
+        let store = CookieStore::with_persistence(CookieFile::new(&path)).unwrap();
+        store.store(&url("https://example.com/"), &SetCookie(vec![
+            "session=1".to_owned(),
+            "kept=2; Max-Age=3600; Secure; HttpOnly; SameSite=Strict".to_owned(),
+        ]));
+        store.save().unwrap();
+
+        let loaded = file.load().unwrap();
+        assert_eq!(loaded.len(), 1);
+        let mut expected = store.cookies().into_iter().find(|c| c.name == "kept").unwrap();
+        // saved in whole seconds
+        let secs = expected.expires.unwrap().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+        expected.expires = Some(std::time::UNIX_EPOCH + Duration::from_secs(secs));
+        assert_eq!(loaded[0], expected);
+
+        let store = CookieStore::with_persistence(CookieFile::new(&path)).unwrap();
+        assert_eq!(header(&store, "https://example.com/").unwrap(), "kept=2");
+
+        std::fs::write(&path, format!("example.com\tTRUE\t/\tFALSE\tFALSE\t\t{}\tbig\t1\n", u64::MAX)).unwrap();
+        assert!(!file.load().unwrap()[0].is_expired());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
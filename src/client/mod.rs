@@ -59,6 +59,7 @@ use std::borrow::Cow;
 use std::default::Default;
 use std::io::{self, copy, Read};
 use std::fmt;
+use std::sync::Arc;
 
 use std::time::Duration;
 
@@ -66,24 +67,31 @@ use url::Url;
 use url::ParseError as UrlError;
 
 use crate::header::{Headers, Header, HeaderFormat};
-use crate::header::{ContentLength, Host, Location};
+use crate::header::{ContentLength, Cookie, Host, Location, RetryAfter, SetCookie};
+#[cfg(feature = "compression")]
+use crate::header::{qitem, AcceptEncoding, Encoding};
 use crate::method::Method;
 use crate::net::{NetworkConnector, NetworkStream, SslClient};
+use crate::runtime;
+use crate::status::StatusCode;
 use crate::Error;
 
 use self::proxy::{Proxy, tunnel};
 use self::scheme::Scheme;
+use self::cookie::CookieStore;
 pub use self::pool::Pool;
 pub use self::request::Request;
 pub use self::response::Response;
 
 mod proxy;
+pub mod cookie;
 pub mod pool;
 pub mod request;
 pub mod response;
 
 use crate::http::Protocol;
-use crate::http::h1::Http11Protocol;
+use crate::http::h1::{Http11Message, Http11Protocol};
+use crate::websocket::{ClientStream, WebSocket};
 
 
 /// A Client to use additional features with Requests.
@@ -92,18 +100,24 @@ use crate::http::h1::Http11Protocol;
 pub struct Client {
     protocol: Box<dyn Protocol + Send + Sync>,
     redirect_policy: RedirectPolicy,
+    retry_policy: RetryPolicy,
     read_timeout: Option<Duration>,
     write_timeout: Option<Duration>,
-    proxy: Option<(Scheme, Cow<'static, str>, u16)>
+    proxy: Option<(Scheme, Cow<'static, str>, u16)>,
+    cookie_store: Option<Arc<CookieStore>>,
+    #[cfg(feature = "compression")]
+    decompress: bool,
 }
 
 impl fmt::Debug for Client {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         fmt.debug_struct("Client")
            .field("redirect_policy", &self.redirect_policy)
+           .field("retry_policy", &self.retry_policy)
            .field("read_timeout", &self.read_timeout)
            .field("write_timeout", &self.write_timeout)
            .field("proxy", &self.proxy)
+           .field("cookie_store", &self.cookie_store)
            .finish()
     }
 }
@@ -164,9 +178,13 @@ impl Client {
         Client {
             protocol: Box::new(protocol),
             redirect_policy: Default::default(),
+            retry_policy: RetryPolicy::never(),
             read_timeout: None,
             write_timeout: None,
             proxy: None,
+            cookie_store: None,
+            #[cfg(feature = "compression")]
+            decompress: false,
         }
     }
 
@@ -175,6 +193,22 @@ impl Client {
         self.redirect_policy = policy;
     }
 
+    /// Set the RetryPolicy, by default requests are never retried.
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+    }
+
+    /// Keep the cookies of responses in `store`, sending them back with later
+    /// requests and redirects. Without a store cookies are ignored.
+    pub fn set_cookie_store(&mut self, store: Arc<CookieStore>) {
+        self.cookie_store = Some(store);
+    }
+
+    /// The store set with `set_cookie_store`.
+    pub fn cookie_store(&self) -> Option<&Arc<CookieStore>> {
+        self.cookie_store.as_ref()
+    }
+
     /// Set the read timeout value for all requests.
     pub fn set_read_timeout(&mut self, dur: Option<Duration>) {
         self.read_timeout = dur;
@@ -185,6 +219,14 @@ impl Client {
         self.write_timeout = dur;
     }
 
+    /// Set whether to ask for compressed responses and decode them, off by default.
+    ///
+    /// Requests that set their own `Accept-Encoding` get the body as it was sent.
+    #[cfg(feature = "compression")]
+    pub fn set_decompress(&mut self, decompress: bool) {
+        self.decompress = decompress;
+    }
+
     /// Build a Get request.
     pub fn get<U: IntoUrl>(&self, url: U) -> RequestBuilder {
         self.request(Method::Get, url)
@@ -226,6 +268,63 @@ impl Client {
             headers: None,
         }
     }
+
+    /// Open a WebSocket to a `ws://` or `wss://` url.
+    pub fn websocket<U: IntoUrl>(&self, url: U) -> crate::Result<WebSocket<ClientStream>> {
+        self.websocket_with_headers(url, Headers::new())
+    }
+
+    /// Open a WebSocket, sending `headers` with the opening handshake, such as
+    /// `Sec-WebSocket-Protocol` or `Origin`.
+    ///
+    /// The connection is not pooled, and redirects are not followed.
+    pub fn websocket_with_headers<U: IntoUrl>(&self, url: U, mut headers: Headers)
+                                              -> crate::Result<WebSocket<ClientStream>> {
+        let url = url.into_url()?;
+        let scheme = match url.scheme() {
+            "ws" => "http",
+            "wss" => "https",
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid scheme for WebSocket").into()),
+        };
+        let (host, port) = get_host_and_port(&url)?;
+        let message = self.protocol.new_message(host, port, scheme)?;
+        let stream = match message.downcast::<Http11Message>() {
+            Ok(message) => message.into_inner(),
+            Err(_) => return Err(Error::Other("websocket needs an HTTP/1.1 connection".to_owned())),
+        };
+        stream.set_read_timeout(self.read_timeout)?;
+        stream.set_write_timeout(self.write_timeout)?;
+
+        if !headers.has::<Host>() {
+            headers.set(Host {
+                hostname: host.to_owned(),
+                port: url.port(),
+            });
+        }
+        self.add_cookies(&url, &mut headers);
+        let mut path = url.path().to_owned();
+        if let Some(query) = url.query() {
+            path.push('?');
+            path.push_str(query);
+        }
+        if scheme == "http" && self.proxy.is_some() {
+            // a proxy takes the absolute url
+            path = format!("http://{}:{}{}", host, port, path);
+        }
+        crate::websocket::connect(stream, &path, headers)
+    }
+
+    // append the stored cookies for `url` to those already in `headers`
+    fn add_cookies(&self, url: &Url, headers: &mut Headers) {
+        let stored = match self.cookie_store.as_ref().and_then(|store| store.cookie_header(url)) {
+            Some(Cookie(cookies)) => cookies,
+            None => return,
+        };
+        match headers.get_mut::<Cookie>() {
+            Some(&mut Cookie(ref mut cookies)) => cookies.extend(stored),
+            None => headers.set(Cookie(stored)),
+        }
+    }
 }
 
 impl Default for Client {
@@ -284,6 +383,8 @@ impl<'a> RequestBuilder<'a> {
     }
 
     /// Execute this request and receive a Response back.
+    ///
+    /// Failed attempts are retried following the Client's `RetryPolicy`.
     pub fn send(self) -> crate::Result<Response> {
         let RequestBuilder { client, method, url, headers, body } = self;
         let mut url = url?;
@@ -300,44 +401,43 @@ impl<'a> RequestBuilder<'a> {
             None
         };
 
-        loop {
-            let mut req = {
-                let (host, port) = get_host_and_port(&url)?;
-                let mut message = client.protocol.new_message(&host, port, url.scheme())?;
-                if url.scheme() == "http" && client.proxy.is_some() {
-                    message.set_proxied(true);
-                }
-
-                let mut h = Headers::new();
-                h.set(Host {
-                    hostname: host.to_owned(),
-                    port: Some(port),
-                });
-                if let Some(ref headers) = headers {
-                    h.extend(headers.iter());
-                }
-                let headers = h;
-                Request::with_headers_and_message(method.clone(), url.clone(), headers, message)
-            };
+        #[cfg(feature = "compression")]
+        let decompress = client.decompress && !headers.as_ref().is_some_and(|h| h.has::<AcceptEncoding>());
 
-            req.set_write_timeout(client.write_timeout)?;
-            req.set_read_timeout(client.read_timeout)?;
+        let mut headers = headers.unwrap_or_else(Headers::new);
+        #[cfg(feature = "compression")]
+        if decompress {
+            headers.set(AcceptEncoding(vec![qitem(Encoding::Gzip), qitem(Encoding::Deflate),
+                                            qitem(Encoding::Brotli)]));
+        }
 
-            match (can_have_body, body.as_ref()) {
-                (true, Some(body)) => match body.size() {
-                    Some(size) => req.headers_mut().set(ContentLength(size)),
-                    None => (), // chunked, Request will add it automatically
-                },
-                (true, None) => req.headers_mut().set(ContentLength(0)),
-                _ => () // neither
-            }
-            let mut streaming = req.start()?;
-            if let Some(mut rdr) = body.take() {
-                copy(&mut rdr, &mut streaming)?;
-            }
-            let res = streaming.send()?;
+        #[allow(unused_mut)]
+        let mut res = loop {
+            let mut attempt = 1;
+            let res = loop {
+                // a buffered body can be sent again, a reader only until it is read
+                let replayable = body.as_ref().is_none_or(Body::is_replayable);
+                let mut written = false;
+                let wait = match send_once(client, &method, &url, &headers, &mut body, &mut written) {
+                    Ok(res) => match client.retry_policy.retry_after(&res) {
+                        Some(wait) if attempt < client.retry_policy.max_attempts
+                            && method.idempotent() && replayable => wait,
+                        _ => break res,
+                    },
+                    Err(Error::Io(e)) if attempt < client.retry_policy.max_attempts
+                        && (!written || method.idempotent() && replayable) => {
+                        debug!("attempt {} for {} failed: {}", attempt, url, e);
+                        client.retry_policy.backoff(attempt)
+                    }
+                    Err(e) => return Err(e),
+                };
+                attempt += 1;
+                runtime::sleep(wait);
+            };
+            // the body was sent with the first request
+            body = None;
             if !res.status.is_redirection() {
-                return Ok(res)
+                break res;
             }
             debug!("redirect code {:?} for {}", res.status, url);
 
@@ -355,24 +455,76 @@ impl<'a> RequestBuilder<'a> {
                 };
                 match loc {
                     Some(r) => r,
-                    None => return Ok(res)
+                    None => break res
                 }
             };
             url = match loc {
                 Ok(u) => u,
                 Err(e) => {
                     debug!("Location header had invalid URI: {:?}", e);
-                    return Ok(res);
+                    break res;
                 }
             };
             match client.redirect_policy {
                 // separate branches because they can't be one
                 RedirectPolicy::FollowAll => (), //continue
                 RedirectPolicy::FollowIf(cond) if cond(&url) => (), //continue
-                _ => return Ok(res),
+                _ => break res,
             }
+        };
+        #[cfg(feature = "compression")]
+        if decompress {
+            res.decompress();
+        }
+        Ok(res)
+    }
+}
+
+// Send a request once, taking `body` unless it can be sent again, and setting
+// `written` once the request may have reached the server.
+fn send_once(client: &Client, method: &Method, url: &Url, headers: &Headers,
+             body: &mut Option<Body>, written: &mut bool) -> crate::Result<Response> {
+    let can_have_body = !matches!(*method, Method::Get | Method::Head);
+
+    let mut req = {
+        let (host, port) = get_host_and_port(url)?;
+        let mut message = client.protocol.new_message(host, port, url.scheme())?;
+        if url.scheme() == "http" && client.proxy.is_some() {
+            message.set_proxied(true);
         }
+
+        let mut h = Headers::new();
+        h.set(Host {
+            hostname: host.to_owned(),
+            port: Some(port),
+        });
+        h.extend(headers.iter());
+        client.add_cookies(url, &mut h);
+        Request::with_headers_and_message(method.clone(), url.clone(), h, message)
+    };
+
+    req.set_write_timeout(client.write_timeout)?;
+    req.set_read_timeout(client.read_timeout)?;
+
+    match (can_have_body, body.as_ref()) {
+        // without a size it's chunked, Request will add it automatically
+        (true, Some(body)) => if let Some(size) = body.size() {
+            req.headers_mut().set(ContentLength(size));
+        },
+        (true, None) => req.headers_mut().set(ContentLength(0)),
+        _ => () // neither
     }
+    *written = true;
+    let mut streaming = req.start()?;
+    let replay = body.as_ref().and_then(Body::replay);
+    if let Some(mut rdr) = replay.or_else(|| body.take()) {
+        copy(&mut rdr, &mut streaming)?;
+    }
+    let res = streaming.send()?;
+    if let (Some(store), Some(set_cookie)) = (client.cookie_store.as_ref(), res.headers.get::<SetCookie>()) {
+        store.store(url, set_cookie);
+    }
+    Ok(res)
 }
 
 /// An enum of possible body types for a Request.
@@ -393,6 +545,18 @@ impl<'a> Body<'a> {
             _ => None
         }
     }
+
+    fn is_replayable(&self) -> bool {
+        matches!(*self, Body::BufBody(..))
+    }
+
+    // the same body from its start, if it is buffered
+    fn replay(&self) -> Option<Body<'a>> {
+        match *self {
+            Body::BufBody(buf, len) => Some(Body::BufBody(buf, len)),
+            _ => None
+        }
+    }
 }
 
 impl<'a> Read for Body<'a> {
@@ -533,6 +697,64 @@ impl Default for RedirectPolicy {
     }
 }
 
+/// How a Client retries a request that failed.
+///
+/// A request is sent again after an IO error if it never reached the server,
+/// or if its method is idempotent, and after a 429 or 503 response with a
+/// `Retry-After` header if its method is idempotent. A body read from a
+/// reader can't be sent twice, so such requests are only retried if the
+/// failure came before they were written.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// The most times a request is sent, 1 to never retry.
+    pub max_attempts: u32,
+    /// The wait before the first retry, doubling for each retry after it,
+    /// then randomly shortened by up to half.
+    pub base_delay: Duration,
+    /// The longest wait before a retry. A `Retry-After` asking for longer is
+    /// not waited for, and its response is returned.
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// A policy sending each request once.
+    pub fn never() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    // the wait after the failed attempt number `attempt`, counting from 1
+    fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self.base_delay
+            .checked_mul(1 << (attempt - 1).min(31))
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay));
+        delay - delay.mul_f64(rand::random::<f64>() / 2.0)
+    }
+
+    // the wait asked for by a response to retry its request
+    fn retry_after(&self, res: &Response) -> Option<Duration> {
+        match res.status {
+            StatusCode::TooManyRequests | StatusCode::ServiceUnavailable => (),
+            _ => return None,
+        }
+        res.headers.get::<RetryAfter>()
+            .map(RetryAfter::delay)
+            .filter(|wait| *wait <= self.max_delay)
+    }
+}
+
+impl Default for RetryPolicy {
+    /// Three attempts, waiting up to 100ms and then up to 200ms, at most 10s.
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(10),
+        }
+    }
+}
 
 fn get_host_and_port(url: &Url) -> crate::Result<(&str, u16)> {
     let host = match url.host_str() {
@@ -581,11 +803,15 @@ mod scheme {
 
 #[cfg(test)]
 mod tests {
-    use std::io::Read;
+    use std::io::{Cursor, Read};
     use crate::header::Server;
     use crate::http::h1::Http11Message;
     use crate::mock::{MockStream, MockSsl};
-    use super::{Client, RedirectPolicy};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use crate::status::StatusCode;
+    use super::{Client, RedirectPolicy, RetryPolicy};
     use super::scheme::Scheme;
     use super::proxy::Proxy;
     use super::pool::Pool;
@@ -743,4 +969,209 @@ mod tests {
         let err = client.post("http://127.0.0.1").body(&mut BadBody).send().unwrap_err();
         assert_eq!(err.to_string(), "BadBody read");
     }
+
+    // fails the first `fail` connections, then answers with `responses` in turn
+    struct FlakyConnector {
+        fail: usize,
+        responses: Vec<&'static [u8]>,
+        connects: Arc<AtomicUsize>,
+    }
+
+    impl FlakyConnector {
+        fn new(fail: usize, responses: Vec<&'static [u8]>) -> FlakyConnector {
+            FlakyConnector { fail, responses, connects: Default::default() }
+        }
+    }
+
+    impl crate::net::NetworkConnector for FlakyConnector {
+        type Stream = MockStream;
+        fn connect(&self, _: &str, _: u16, _: &str) -> crate::Result<MockStream> {
+            let n = self.connects.fetch_add(1, Ordering::SeqCst);
+            if n < self.fail {
+                return Err(std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "refused").into());
+            }
+            Ok(MockStream::with_input(self.responses[(n - self.fail).min(self.responses.len() - 1)]))
+        }
+    }
+
+    fn retry_policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_secs(1),
+        }
+    }
+
+    const CLOSED: &[u8] = b"";
+    const OK: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n";
+
+    #[test]
+    fn test_retry_idempotent() {
+        let connector = FlakyConnector::new(0, vec![CLOSED, CLOSED, CLOSED, OK]);
+        let connects = connector.connects.clone();
+        let mut client = Client::with_connector(connector);
+        assert!(client.get("http://127.0.0.1").send().is_err());
+
+        client.set_retry_policy(retry_policy());
+        let res = client.put("http://127.0.0.1").body("foo").send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        assert_eq!(connects.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn test_retry_gives_up() {
+        let mut client = Client::with_connector(FlakyConnector::new(0, vec![CLOSED]));
+        client.set_retry_policy(retry_policy());
+        assert!(client.get("http://127.0.0.1").send().is_err());
+    }
+
+    #[test]
+    fn test_retry_not_idempotent() {
+        let mut client = Client::with_connector(FlakyConnector::new(0, vec![CLOSED, OK]));
+        client.set_retry_policy(retry_policy());
+        assert!(client.post("http://127.0.0.1").body("foo").send().is_err());
+
+        // never written
+        let mut client = Client::with_connector(FlakyConnector::new(2, vec![OK]));
+        client.set_retry_policy(retry_policy());
+        let res = client.post("http://127.0.0.1").body(&mut Cursor::new("foo")).send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+    }
+
+    #[test]
+    fn test_retry_reader_body() {
+        let mut client = Client::with_connector(FlakyConnector::new(0, vec![CLOSED, OK]));
+        client.set_retry_policy(retry_policy());
+        assert!(client.put("http://127.0.0.1").body(&mut Cursor::new("foo")).send().is_err());
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let mut client = Client::with_connector(FlakyConnector::new(0, vec![
+            b"HTTP/1.1 503 Service Unavailable\r\nRetry-After: 0\r\nContent-Length: 0\r\n\r\n",
+            OK,
+        ]));
+        client.set_retry_policy(retry_policy());
+        assert_eq!(client.get("http://127.0.0.1").send().unwrap().status, StatusCode::Ok);
+
+        let mut client = Client::with_connector(FlakyConnector::new(0, vec![
+            b"HTTP/1.1 429 Too Many Requests\r\nRetry-After: 3600\r\nContent-Length: 0\r\n\r\n",
+            OK,
+        ]));
+        client.set_retry_policy(retry_policy());
+        assert_eq!(client.get("http://127.0.0.1").send().unwrap().status, StatusCode::TooManyRequests);
+
+        let mut client = Client::with_connector(FlakyConnector::new(0, vec![
+            b"HTTP/1.1 503 Service Unavailable\r\nRetry-After: 0\r\nContent-Length: 0\r\n\r\n",
+            OK,
+        ]));
+        client.set_retry_policy(retry_policy());
+        assert_eq!(client.post("http://127.0.0.1").send().unwrap().status, StatusCode::ServiceUnavailable);
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        let policy = RetryPolicy::default();
+        for _ in 0..100 {
+            let first = policy.backoff(1);
+            assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100), "{:?}", first);
+            let third = policy.backoff(3);
+            assert!(third >= Duration::from_millis(200) && third <= Duration::from_millis(400), "{:?}", third);
+            assert!(policy.backoff(40) <= policy.max_delay);
+        }
+    }
+
+    struct Recorded(MockStream, Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Read for Recorded {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl std::io::Write for Recorded {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.1.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl crate::net::NetworkStream for Recorded {
+        fn peer_addr(&mut self) -> std::io::Result<std::net::SocketAddr> {
+            self.0.peer_addr()
+        }
+        fn set_read_timeout(&self, dur: Option<Duration>) -> std::io::Result<()> {
+            self.0.set_read_timeout(dur)
+        }
+        fn set_write_timeout(&self, dur: Option<Duration>) -> std::io::Result<()> {
+            self.0.set_write_timeout(dur)
+        }
+    }
+
+    // answers with `responses` in turn, recording what is written
+    struct RecordingConnector {
+        responses: Vec<&'static [u8]>,
+        connects: AtomicUsize,
+        written: Arc<std::sync::Mutex<Vec<u8>>>,
+    }
+
+    impl crate::net::NetworkConnector for RecordingConnector {
+        type Stream = Recorded;
+        fn connect(&self, _: &str, _: u16, _: &str) -> crate::Result<Recorded> {
+            let n = self.connects.fetch_add(1, Ordering::SeqCst);
+            Ok(Recorded(MockStream::with_input(self.responses[n]), self.written.clone()))
+        }
+    }
+
+    #[test]
+    fn test_cookie_store_redirect() {
+        use super::cookie::CookieStore;
+        use crate::header::Cookie;
+
+        let written = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut client = Client::with_connector(RecordingConnector {
+            responses: vec![
+                b"HTTP/1.1 302 Found\r\nLocation: /next\r\nSet-Cookie: sid=abc; Path=/\r\nContent-Length: 0\r\n\r\n",
+                b"HTTP/1.1 200 OK\r\nSet-Cookie: theme=dark; Path=/next\r\nContent-Length: 0\r\n\r\n",
+            ],
+            connects: AtomicUsize::new(0),
+            written: written.clone(),
+        });
+        let store = Arc::new(CookieStore::new());
+        client.set_cookie_store(store.clone());
+        client.get("http://127.0.0.1/login")
+            .header(Cookie(vec!["mine=1".to_owned()]))
+            .send().unwrap();
+
+        let written = String::from_utf8(written.lock().unwrap().clone()).unwrap();
+        let redirected = &written[written.find("GET /next").unwrap()..];
+        assert!(redirected.contains("Cookie: mine=1; sid=abc\r\n"), "{:?}", redirected);
+        let names: Vec<String> = store.cookies().into_iter().map(|c| c.name).collect();
+        assert_eq!(names, vec!["sid", "theme"]);
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_decompress_opt_in() {
+        let written = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut client = Client::with_connector(RecordingConnector {
+            responses: vec![
+                b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n",
+                b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n",
+            ],
+            connects: AtomicUsize::new(0),
+            written: written.clone(),
+        });
+        client.get("http://127.0.0.1/").send().unwrap();
+        let sent = String::from_utf8(written.lock().unwrap().split_off(0)).unwrap();
+        assert!(!sent.contains("Accept-Encoding"), "{:?}", sent);
+
+        client.set_decompress(true);
+        client.get("http://127.0.0.1/").send().unwrap();
+        let sent = String::from_utf8(written.lock().unwrap().split_off(0)).unwrap();
+        assert!(sent.contains("Accept-Encoding: gzip, deflate"), "{:?}", sent);
+    }
 }
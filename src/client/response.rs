@@ -3,6 +3,8 @@ use std::io::{self, Read};
 
 use url::Url;
 
+#[cfg(feature = "compression")]
+use crate::compress::Decoder;
 use crate::header;
 use crate::net::NetworkStream;
 use crate::http::{self, RawStatus, ResponseHead, HttpMessage};
@@ -23,6 +25,8 @@ pub struct Response {
     pub url: Url,
     status_raw: RawStatus,
     message: Box<dyn HttpMessage>,
+    #[cfg(feature = "compression")]
+    decoder: Option<Decoder>,
 }
 
 impl Response {
@@ -53,6 +57,8 @@ impl Response {
             url: url,
             status_raw: raw_status,
             message: message,
+            #[cfg(feature = "compression")]
+            decoder: None,
         })
     }
 
@@ -62,6 +68,23 @@ impl Response {
         &self.status_raw
     }
 
+    /// Decode the body while it is read, if it has a `Content-Encoding` of `br`,
+    /// `gzip` or `deflate`. The `Content-Encoding` and `Content-Length` headers are
+    /// removed, since they no longer describe the body that is read.
+    ///
+    /// The `Client` does this for the responses to the requests it asked to be
+    /// compressed.
+    #[cfg(feature = "compression")]
+    pub fn decompress(&mut self) {
+        if self.decoder.is_none() {
+            self.decoder = Decoder::for_headers(&self.headers);
+            if self.decoder.is_some() {
+                self.headers.remove::<header::ContentEncoding>();
+                self.headers.remove::<header::ContentLength>();
+            }
+        }
+    }
+
     /// Gets a borrowed reference to the underlying `HttpMessage`.
     #[inline]
     pub fn get_ref(&self) -> &dyn HttpMessage {
@@ -73,7 +96,14 @@ impl Response {
 impl Read for Response {
     #[inline]
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        match self.message.read(buf) {
+        #[cfg(feature = "compression")]
+        let res = match self.decoder {
+            Some(ref mut decoder) => decoder.read_from(&mut *self.message, buf),
+            None => self.message.read(buf),
+        };
+        #[cfg(not(feature = "compression"))]
+        let res = self.message.read(buf);
+        match res {
             Err(e) => {
                 let _ = self.message.close_connection();
                 Err(e)
@@ -236,4 +266,27 @@ mod tests {
 
         assert!(Response::new(url, Box::new(stream)).is_err());
     }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_decompress() {
+        use crate::compress::Encoder;
+        use crate::header::{ContentEncoding, ContentLength};
+        use crate::server::BodyEncoder;
+
+        let mut body = Vec::new();
+        let mut encoder = Encoder::new(&Encoding::Gzip, 6).unwrap();
+        encoder.encode(b"hello gzip", &mut body).unwrap();
+        encoder.finish(&mut body).unwrap();
+        let mut input = format!("HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n",
+                                body.len()).into_bytes();
+        input.extend_from_slice(&body);
+
+        let url = Url::parse("http://mco_http.rs").unwrap();
+        let mut res = Response::new(url, Box::new(MockStream::with_input(&input))).unwrap();
+        res.decompress();
+        assert!(res.headers.get::<ContentEncoding>().is_none());
+        assert!(res.headers.get::<ContentLength>().is_none());
+        assert_eq!(read_to_string(res).unwrap(), "hello gzip");
+    }
 }
@@ -0,0 +1,569 @@
+//! Compression of message bodies.
+//!
+//! `Compression` is an `AroundMiddleWare` compressing response bodies with `br`,
+//! `gzip` or `deflate`, as negotiated with the request's `Accept-Encoding`. Bodies
+//! smaller than `min_size`, and content types that are already compressed, such as
+//! images, are sent as they are.
+//!
+//! ```no_run
+//! use mco_http::compress::Compression;
+//! use mco_http::route::Route;
+//!
+//! let route = Route::new();
+//! route.add_around(Compression::new());
+//! ```
+//!
+//! The `Client` asks for compressed responses and decodes them with `Decoder`.
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use brotli::{CompressorWriter, DecompressorWriter};
+use flate2::write::{GzDecoder, GzEncoder, ZlibDecoder, ZlibEncoder};
+
+use crate::Error;
+use crate::header::{AcceptEncoding, ContentEncoding, ContentLength, ContentRange, ContentType,
+                    Encoding, ETag, Headers, Vary};
+use crate::method::Method;
+use crate::mime::{Mime, SubLevel, TopLevel};
+use crate::net::Fresh;
+use crate::route::{AroundMiddleWare, Next};
+use crate::server::{BodyEncoder, Request, Response};
+use crate::status::{StatusClass, StatusCode};
+
+/// Bodies with a `Content-Length` below this are not compressed by default.
+pub const DEFAULT_MIN_SIZE: u64 = 1024;
+
+/// The buffer size of the brotli encoder and decoder.
+const BROTLI_BUFFER: usize = 4096;
+/// The brotli window, as a power of two.
+const BROTLI_LGWIN: u32 = 22;
+
+/// An `AroundMiddleWare` compressing response bodies.
+///
+/// A response is compressed when the client accepts one of `br`, `gzip` or `deflate`,
+/// its content type is compressible, and it is at least `min_size` bytes. Responses
+/// without a `Content-Length` are streamed, and compressed whatever their size.
+///
+/// A compressed response gets `Content-Encoding` and `Vary: Accept-Encoding`, loses
+/// its `Content-Length`, and a strong `ETag` becomes weak.
+#[derive(Debug)]
+pub struct Compression {
+    min_size: u64,
+    level: u32,
+}
+
+impl Compression {
+    /// Creates a middleware with the default settings.
+    pub fn new() -> Compression {
+        Compression {
+            min_size: DEFAULT_MIN_SIZE,
+            level: 6,
+        }
+    }
+
+    /// Sets the smallest `Content-Length` that is compressed.
+    pub fn min_size(&mut self, size: u64) -> &mut Compression {
+        self.min_size = size;
+        self
+    }
+
+    /// Sets the compression level, from `0` (none) to `9` (best). `br` uses a
+    /// quality of `level + 2`.
+    pub fn level(&mut self, level: u32) -> &mut Compression {
+        self.level = level.min(9);
+        self
+    }
+
+    /// Picks the response encoding for the head, if it should be compressed.
+    fn encoder<'a>(&self, accepted: Option<Encoding>, status: &mut StatusCode, headers: &mut Headers)
+                   -> Option<Box<dyn BodyEncoder + 'a>> {
+        if !self.compressible(*status, headers) {
+            return None;
+        }
+        add_vary(headers);
+        let encoding = accepted?;
+        let encoder = Encoder::new(&encoding, self.level)?;
+        headers.remove::<ContentLength>();
+        headers.set(ContentEncoding(vec![encoding]));
+        if let Some(etag) = headers.get_mut::<ETag>() {
+            etag.0.weak = true;
+        }
+        Some(Box::new(encoder))
+    }
+
+    fn compressible(&self, status: StatusCode, headers: &Headers) -> bool {
+        match status {
+            StatusCode::NoContent | StatusCode::NotModified | StatusCode::PartialContent => return false,
+            s if s.class() == StatusClass::Informational => return false,
+            _ => {}
+        }
+        if headers.has::<ContentEncoding>() || headers.has::<ContentRange>() {
+            return false;
+        }
+        if headers.get::<ContentLength>().is_some_and(|len| len.0 < self.min_size) {
+            return false;
+        }
+        match headers.get::<ContentType>() {
+            Some(ContentType(mime)) => is_compressible(mime),
+            None => false,
+        }
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Compression {
+        Compression::new()
+    }
+}
+
+impl AroundMiddleWare for Compression {
+    fn handle<'a, 'k>(&'a self, req: Request<'a, 'k>, mut res: Response<'a, Fresh>, next: Next<'a>) {
+        if req.method != Method::Head {
+            let accepted = req.headers.get::<AcceptEncoding>().and_then(negotiate);
+            res.encode_body(move |status, headers| self.encoder(accepted, status, headers));
+        }
+        next.run(req, res);
+    }
+}
+
+/// Picks the encoding the client prefers from `br`, `gzip` and `deflate`, by quality,
+/// or `None` if it accepts none of them.
+pub fn negotiate(accept: &AcceptEncoding) -> Option<Encoding> {
+    let quality = |encoding: &Encoding| {
+        let mut any = None;
+        for item in accept.iter() {
+            match item.item {
+                ref e if e == encoding => return item.quality.0,
+                Encoding::EncodingExt(ref e) if e == "*" => any = Some(item.quality.0),
+                _ => {}
+            }
+        }
+        any.unwrap_or(0)
+    };
+    let mut best: Option<(Encoding, u16)> = None;
+    for encoding in [Encoding::Brotli, Encoding::Gzip, Encoding::Deflate] {
+        let q = quality(&encoding);
+        if q > 0 && best.as_ref().is_none_or(|b| q > b.1) {
+            best = Some((encoding, q));
+        }
+    }
+    best.map(|b| b.0)
+}
+
+/// Whether a body of this type gets smaller when compressed. Images, audio, video
+/// and archives usually are compressed already.
+pub fn is_compressible(mime: &Mime) -> bool {
+    match (&mime.0, &mime.1) {
+        (TopLevel::Text, _) => true,
+        (TopLevel::Application, SubLevel::Json) |
+        (TopLevel::Application, SubLevel::Javascript) |
+        (TopLevel::Application, SubLevel::Xml) |
+        (TopLevel::Application, SubLevel::WwwFormUrlEncoded) => true,
+        (TopLevel::Application, SubLevel::Ext(sub)) => {
+            sub.ends_with("+json") || sub.ends_with("+xml") ||
+                matches!(&**sub, "wasm" | "x-javascript" | "ecmascript" | "graphql" | "x-ndjson")
+        }
+        (TopLevel::Image, SubLevel::Ext(sub)) => sub == "svg+xml" || sub == "x-icon",
+        (TopLevel::Image, SubLevel::Bmp) => true,
+        _ => false,
+    }
+}
+
+/// Adds `accept-encoding` to the `Vary` header.
+fn add_vary(headers: &mut Headers) {
+    let field = "accept-encoding".parse().unwrap();
+    match headers.get_mut::<Vary>() {
+        Some(Vary::Any) => {}
+        Some(Vary::Items(items)) => if !items.contains(&field) {
+            items.push(field);
+        },
+        None => headers.set(Vary::Items(vec![field])),
+    }
+}
+
+/// A streaming compressor for `br`, `gzip` or `deflate`.
+pub struct Encoder(EncoderInner);
+
+enum EncoderInner {
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+    // the brotli encoder only finishes when consumed
+    Brotli(Option<Box<CompressorWriter<Vec<u8>>>>),
+}
+
+impl Encoder {
+    /// Creates an encoder for `encoding` at a `level` from `0` to `9`, or `None`
+    /// if the encoding is not supported.
+    pub fn new(encoding: &Encoding, level: u32) -> Option<Encoder> {
+        let level = level.min(9);
+        let inner = match *encoding {
+            Encoding::Gzip => EncoderInner::Gzip(GzEncoder::new(Vec::new(), flate2::Compression::new(level))),
+            Encoding::Deflate => EncoderInner::Deflate(ZlibEncoder::new(Vec::new(), flate2::Compression::new(level))),
+            Encoding::Brotli => EncoderInner::Brotli(Some(Box::new(
+                CompressorWriter::new(Vec::new(), BROTLI_BUFFER, level + 2, BROTLI_LGWIN)))),
+            _ => return None,
+        };
+        Some(Encoder(inner))
+    }
+
+    fn writer(&mut self) -> io::Result<&mut dyn Write> {
+        match self.0 {
+            EncoderInner::Gzip(ref mut w) => Ok(w),
+            EncoderInner::Deflate(ref mut w) => Ok(w),
+            EncoderInner::Brotli(Some(ref mut w)) => Ok(&mut **w),
+            EncoderInner::Brotli(None) => Err(finished()),
+        }
+    }
+
+    fn drain(&mut self, out: &mut Vec<u8>) {
+        let buf = match self.0 {
+            EncoderInner::Gzip(ref mut w) => w.get_mut(),
+            EncoderInner::Deflate(ref mut w) => w.get_mut(),
+            EncoderInner::Brotli(Some(ref mut w)) => w.get_mut(),
+            EncoderInner::Brotli(None) => return,
+        };
+        out.append(buf);
+    }
+}
+
+impl BodyEncoder for Encoder {
+    fn encode(&mut self, buf: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+        self.writer()?.write_all(buf)?;
+        self.drain(out);
+        Ok(())
+    }
+
+    fn flush(&mut self, out: &mut Vec<u8>) -> io::Result<()> {
+        self.writer()?.flush()?;
+        self.drain(out);
+        Ok(())
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) -> io::Result<()> {
+        match self.0 {
+            EncoderInner::Gzip(ref mut w) => w.try_finish()?,
+            EncoderInner::Deflate(ref mut w) => w.try_finish()?,
+            EncoderInner::Brotli(ref mut w) => {
+                if let Some(w) = w.take() {
+                    out.append(&mut w.into_inner());
+                }
+                return Ok(());
+            }
+        }
+        self.drain(out);
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Encoder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self.0 {
+            EncoderInner::Gzip(..) => "Encoder(gzip)",
+            EncoderInner::Deflate(..) => "Encoder(deflate)",
+            EncoderInner::Brotli(..) => "Encoder(br)",
+        })
+    }
+}
+
+fn finished() -> io::Error {
+    io::Error::other("the encoder is finished")
+}
+
+/// A streaming decompressor for `br`, `gzip` or `deflate`, reading a body through
+/// `read_from`.
+pub struct Decoder {
+    inner: DecoderInner,
+    pos: usize,
+    started: bool,
+    done: bool,
+}
+
+enum DecoderInner {
+    Gzip(GzDecoder<Sink>),
+    Deflate(ZlibDecoder<Sink>),
+    Brotli(Box<DecompressorWriter<Sink>>),
+}
+
+/// The decoded bytes waiting to be read, failing to take more than `limit` in total.
+struct Sink {
+    buf: Vec<u8>,
+    total: u64,
+    limit: Option<u64>,
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.total += buf.len() as u64;
+        if self.limit.is_some_and(|max| self.total > max) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, Error::BodyTooLarge));
+        }
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Decoder {
+    /// Creates a decoder for `encoding`, or `None` if it is not supported.
+    pub fn new(encoding: &Encoding) -> Option<Decoder> {
+        let sink = Sink {
+            buf: Vec::new(),
+            total: 0,
+            limit: None,
+        };
+        let inner = match *encoding {
+            Encoding::Gzip => DecoderInner::Gzip(GzDecoder::new(sink)),
+            Encoding::Deflate => DecoderInner::Deflate(ZlibDecoder::new(sink)),
+            Encoding::Brotli => DecoderInner::Brotli(Box::new(DecompressorWriter::new(sink, BROTLI_BUFFER))),
+            _ => return None,
+        };
+        Some(Decoder {
+            inner,
+            pos: 0,
+            started: false,
+            done: false,
+        })
+    }
+
+    /// Creates a decoder for a body with these headers, if it has a single
+    /// `Content-Encoding` that is supported.
+    pub fn for_headers(headers: &Headers) -> Option<Decoder> {
+        match headers.get::<ContentEncoding>() {
+            Some(ContentEncoding(encodings)) if encodings.len() == 1 => Decoder::new(&encodings[0]),
+            _ => None,
+        }
+    }
+
+    /// Fails reading with `Error::BodyTooLarge` once the decoded body goes over
+    /// `max` bytes, however small the encoded body is. A smaller limit set before
+    /// is kept.
+    pub fn limit(&mut self, max: u64) {
+        let sink = self.sink();
+        sink.limit = Some(sink.limit.map_or(max, |limit| limit.min(max)));
+    }
+
+    fn sink(&mut self) -> &mut Sink {
+        match self.inner {
+            DecoderInner::Gzip(ref mut w) => w.get_mut(),
+            DecoderInner::Deflate(ref mut w) => w.get_mut(),
+            DecoderInner::Brotli(ref mut w) => w.get_mut(),
+        }
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.started = true;
+        match self.inner {
+            DecoderInner::Gzip(ref mut w) => w.write_all(buf),
+            DecoderInner::Deflate(ref mut w) => w.write_all(buf),
+            DecoderInner::Brotli(ref mut w) => w.write_all(buf),
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.done = true;
+        if !self.started {
+            // an empty body, such as for `HEAD`
+            return Ok(());
+        }
+        match self.inner {
+            DecoderInner::Gzip(ref mut w) => w.try_finish(),
+            DecoderInner::Deflate(ref mut w) => w.try_finish(),
+            DecoderInner::Brotli(ref mut w) => w.close(),
+        }
+    }
+
+    /// Reads decoded bytes into `buf`, reading the encoded body from `src` as needed.
+    pub fn read_from<R: Read + ?Sized>(&mut self, src: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+        let mut encoded = [0; 4096];
+        loop {
+            let pos = self.pos;
+            let out = &mut self.sink().buf;
+            if pos < out.len() {
+                let n = buf.len().min(out.len() - pos);
+                buf[..n].copy_from_slice(&out[pos..pos + n]);
+                if pos + n == out.len() {
+                    out.clear();
+                    self.pos = 0;
+                } else {
+                    self.pos += n;
+                }
+                return Ok(n);
+            }
+            if self.done || buf.is_empty() {
+                return Ok(0);
+            }
+            match src.read(&mut encoded)? {
+                0 => self.finish()?,
+                n => self.write(&encoded[..n])?,
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Decoder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self.inner {
+            DecoderInner::Gzip(..) => "Decoder(gzip)",
+            DecoderInner::Deflate(..) => "Decoder(deflate)",
+            DecoderInner::Brotli(..) => "Decoder(br)",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::header::{AcceptEncoding, ContentType, Encoding, Quality, QualityItem, qitem};
+    use crate::mock::serve_bytes;
+    use crate::route::Route;
+    use crate::server::{BodyEncoder, Request, Response};
+
+    use super::{negotiate, Compression, Decoder, Encoder};
+
+    fn split(res: &[u8]) -> (String, Vec<u8>) {
+        let end = res.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        (String::from_utf8(res[..end].to_vec()).unwrap(), res[end..].to_vec())
+    }
+
+    fn dechunk(mut body: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        loop {
+            let line = body.windows(2).position(|w| w == b"\r\n").unwrap();
+            let size = usize::from_str_radix(std::str::from_utf8(&body[..line]).unwrap(), 16).unwrap();
+            if size == 0 {
+                return out;
+            }
+            out.extend_from_slice(&body[line + 2..line + 2 + size]);
+            body = &body[line + 4 + size..];
+        }
+    }
+
+    fn decode(encoding: Encoding, body: &[u8]) -> String {
+        let mut decoder = Decoder::new(&encoding).unwrap();
+        let mut src = body;
+        let mut out = Vec::new();
+        let mut buf = [0; 7];
+        loop {
+            match decoder.read_from(&mut src, &mut buf).unwrap() {
+                0 => break,
+                n => out.extend_from_slice(&buf[..n]),
+            }
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    fn route() -> Arc<Route> {
+        let route = Route::new();
+        route.add_around(Compression::new());
+        route.get_fn("/text", |_: Request, res: Response| {
+            res.text(&"hello compression ".repeat(100)).unwrap();
+        });
+        route.get_fn("/small", |_: Request, res: Response| {
+            res.text("hello").unwrap();
+        });
+        route.get_fn("/png", |_: Request, mut res: Response| {
+            res.headers_mut().set(ContentType::png());
+            res.send(&[0; 2048]).unwrap();
+        });
+        Arc::new(route)
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let accept = |items| AcceptEncoding(items);
+        assert_eq!(negotiate(&accept(vec![qitem(Encoding::Gzip), qitem(Encoding::Deflate)])),
+                   Some(Encoding::Gzip));
+        assert_eq!(negotiate(&accept(vec![qitem(Encoding::Gzip), qitem(Encoding::Brotli)])),
+                   Some(Encoding::Brotli));
+        assert_eq!(negotiate(&accept(vec![QualityItem::new(Encoding::Brotli, Quality(500)), qitem(Encoding::Deflate)])),
+                   Some(Encoding::Deflate));
+        assert_eq!(negotiate(&accept(vec![qitem(Encoding::EncodingExt("*".to_owned())),
+                                          QualityItem::new(Encoding::Brotli, Quality(0))])),
+                   Some(Encoding::Gzip));
+        assert_eq!(negotiate(&accept(vec![qitem(Encoding::Identity)])), None);
+        assert_eq!(negotiate(&accept(vec![QualityItem::new(Encoding::Gzip, Quality(0))])), None);
+    }
+
+    #[test]
+    fn test_encoder_round_trip() {
+        let text = "round trip ".repeat(500);
+        for encoding in [Encoding::Gzip, Encoding::Deflate, Encoding::Brotli] {
+            let mut encoder = Encoder::new(&encoding, 6).unwrap();
+            let mut out = Vec::new();
+            for part in text.as_bytes().chunks(1000) {
+                encoder.encode(part, &mut out).unwrap();
+            }
+            encoder.flush(&mut out).unwrap();
+            encoder.finish(&mut out).unwrap();
+            assert!(out.len() < text.len() / 10, "{:?} {}", encoding, out.len());
+            assert_eq!(decode(encoding, &out), text);
+        }
+    }
+
+    #[test]
+    fn test_decoder_errors() {
+        let mut decoder = Decoder::new(&Encoding::Gzip).unwrap();
+        let mut src = &b"not gzip at all"[..];
+        assert!(decoder.read_from(&mut src, &mut [0; 64]).is_err());
+
+        let mut encoder = Encoder::new(&Encoding::Gzip, 6).unwrap();
+        let mut out = Vec::new();
+        encoder.encode(b"truncated", &mut out).unwrap();
+        encoder.finish(&mut out).unwrap();
+        let mut decoder = Decoder::new(&Encoding::Gzip).unwrap();
+        let mut src = &out[..out.len() - 4];
+        let mut body = Vec::new();
+        let mut buf = [0; 64];
+        loop {
+            match decoder.read_from(&mut src, &mut buf) {
+                Ok(0) => panic!("truncated body decoded"),
+                Ok(n) => body.extend_from_slice(&buf[..n]),
+                Err(_) => break,
+            }
+        }
+
+        // nothing to decode
+        let mut decoder = Decoder::new(&Encoding::Brotli).unwrap();
+        assert_eq!(decoder.read_from(&mut &b""[..], &mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_compress_response() {
+        let route = route();
+        for (accept, encoding) in [("gzip", Encoding::Gzip), ("deflate", Encoding::Deflate),
+                                   ("gzip;q=0.5, br", Encoding::Brotli)] {
+            let req = format!("GET /text HTTP/1.1\r\nAccept-Encoding: {}\r\nConnection: close\r\n\r\n", accept);
+            let (head, body) = split(&serve_bytes(route.clone(), req.as_bytes()));
+            assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+            assert!(head.contains(&format!("Content-Encoding: {}\r\n", encoding)), "{}", head);
+            assert!(head.contains("Vary: accept-encoding\r\n"), "{}", head);
+            assert!(head.contains("Transfer-Encoding: chunked\r\n"), "{}", head);
+            assert!(!head.contains("Content-Length"), "{}", head);
+            assert_eq!(decode(encoding, &dechunk(&body)), "hello compression ".repeat(100));
+        }
+    }
+
+    #[test]
+    fn test_compress_skipped() {
+        let route = route();
+        let (head, body) = split(&serve_bytes(route.clone(), b"GET /text HTTP/1.1\r\nConnection: close\r\n\r\n"));
+        assert!(!head.contains("Content-Encoding"), "{}", head);
+        assert!(head.contains("Vary: accept-encoding\r\n"), "{}", head);
+        assert_eq!(body, "hello compression ".repeat(100).as_bytes());
+
+        let (head, body) = split(&serve_bytes(route.clone(), b"GET /small HTTP/1.1\r\nAccept-Encoding: gzip\r\nConnection: close\r\n\r\n"));
+        assert!(!head.contains("Content-Encoding"), "{}", head);
+        assert_eq!(body, b"hello");
+
+        let (head, body) = split(&serve_bytes(route.clone(), b"GET /png HTTP/1.1\r\nAccept-Encoding: gzip\r\nConnection: close\r\n\r\n"));
+        assert!(!head.contains("Content-Encoding"), "{}", head);
+        assert_eq!(body.len(), 2048);
+
+        let (head, _) = split(&serve_bytes(route, b"HEAD /text HTTP/1.1\r\nAccept-Encoding: gzip\r\nConnection: close\r\n\r\n"));
+        assert!(!head.contains("Content-Encoding"), "{}", head);
+    }
+}
@@ -0,0 +1,505 @@
+//! Typed cookies for servers.
+//!
+//! A `CookieJar` holds the cookies of a request, and the changes to send back
+//! with `Set-Cookie`. Its signed and private views keep cookies that clients
+//! can't forge, or neither forge nor read, with a `Key` kept by the server.
+//!
+//! ```rust
+//! use std::time::Duration;
+//! use mco_http::cookie::{Cookie, CookieJar, Key, SameSite};
+//! use mco_http::server::{Request, Response};
+//!
+//! fn handle(req: Request, mut res: Response, key: &Key) {
+//!     let mut jar = CookieJar::from_headers(&req.headers);
+//!     let visits = jar.signed(key).get("visits")
+//!         .and_then(|v| v.parse::<u64>().ok())
+//!         .unwrap_or(0);
+//!     jar.signed(key).add(Cookie::new("visits", (visits + 1).to_string())
+//!         .path("/")
+//!         .max_age(Duration::from_secs(3600))
+//!         .http_only(true)
+//!         .same_site(SameSite::Lax));
+//!     jar.apply(&mut res.headers);
+//!     res.send(format!("{} visits before", visits).as_bytes()).unwrap();
+//! }
+//! ```
+use std::fmt::{self, Display, Formatter};
+use std::time::Duration;
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::extract::{FromRequest, Rejection};
+use crate::header::{self, Headers, HttpDate, SetCookie};
+use crate::server::Request;
+
+pub use crate::client::cookie::SameSite;
+
+/// A cookie to send with `Set-Cookie`, built with its attributes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    domain: Option<String>,
+    path: Option<String>,
+    max_age: Option<Duration>,
+    expires: Option<HttpDate>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    /// A cookie without attributes, which the client keeps for its session.
+    pub fn new<N: Into<String>, V: Into<String>>(name: N, value: V) -> Cookie {
+        Cookie {
+            name: name.into(),
+            value: value.into(),
+            domain: None,
+            path: None,
+            max_age: None,
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    /// A cookie with an empty value, to give attributes to for `CookieJar::remove`.
+    pub fn named<N: Into<String>>(name: N) -> Cookie {
+        Cookie::new(name, "")
+    }
+
+    /// Send the cookie to `domain` and its subdomains too.
+    pub fn domain<D: Into<String>>(mut self, domain: D) -> Cookie {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    /// Send the cookie to `path` and the paths below it.
+    pub fn path<P: Into<String>>(mut self, path: P) -> Cookie {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// Expire the cookie after `max_age`, in whole seconds.
+    pub fn max_age(mut self, max_age: Duration) -> Cookie {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Expire the cookie at `expires`, for clients ignoring `Max-Age`.
+    pub fn expires(mut self, expires: HttpDate) -> Cookie {
+        self.expires = Some(expires);
+        self
+    }
+
+    /// Only send the cookie over https.
+    pub fn secure(mut self, secure: bool) -> Cookie {
+        self.secure = secure;
+        self
+    }
+
+    /// Hide the cookie from scripts.
+    pub fn http_only(mut self, http_only: bool) -> Cookie {
+        self.http_only = http_only;
+        self
+    }
+
+    /// Limit sending the cookie with requests from other sites.
+    pub fn same_site(mut self, same_site: SameSite) -> Cookie {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    /// The cookie name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The cookie value.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    // the same cookie with its value emptied and expired, so clients drop it
+    fn into_removal(self) -> Cookie {
+        Cookie {
+            value: String::new(),
+            max_age: Some(Duration::from_secs(0)),
+            expires: Some(HttpDate(time::at_utc(time::Timespec::new(0, 0)))),
+            ..self
+        }
+    }
+}
+
+/// The `Set-Cookie` value.
+impl Display for Cookie {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(ref domain) = self.domain {
+            write!(f, "; Domain={}", domain)?;
+        }
+        if let Some(ref path) = self.path {
+            write!(f, "; Path={}", path)?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if let Some(ref expires) = self.expires {
+            write!(f, "; Expires={}", expires)?;
+        }
+        if self.secure {
+            f.write_str("; Secure")?;
+        }
+        if self.http_only {
+            f.write_str("; HttpOnly")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+/// The cookies of a request, and those to add or remove with the response.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CookieJar {
+    original: Vec<(String, String)>,
+    delta: Vec<Cookie>,
+}
+
+impl CookieJar {
+    /// An empty jar.
+    pub fn new() -> CookieJar {
+        CookieJar::default()
+    }
+
+    /// A jar with the cookies of a `Cookie` header.
+    pub fn from_headers(headers: &Headers) -> CookieJar {
+        let original = match headers.get::<header::Cookie>() {
+            Some(cookies) => cookies.iter()
+                .filter_map(|pair| pair.split_once('='))
+                .map(|(name, value)| (name.trim().to_owned(), value.trim().to_owned()))
+                .collect(),
+            None => Vec::new(),
+        };
+        CookieJar {
+            original,
+            delta: Vec::new(),
+        }
+    }
+
+    /// The value of cookie `name`, including changes, `None` once removed.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        match self.delta.iter().rev().find(|c| c.name == name) {
+            Some(c) if c.max_age == Some(Duration::from_secs(0)) => None,
+            Some(c) => Some(&c.value),
+            None => self.original.iter().find(|c| c.0 == name).map(|c| &c.1[..]),
+        }
+    }
+
+    /// The names and values of the cookies, including changes.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        let mut names: Vec<&str> = Vec::new();
+        for name in self.original.iter().map(|c| &c.0[..]).chain(self.delta.iter().map(|c| &c.name[..])) {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        names.into_iter().filter_map(move |name| self.get(name).map(|value| (name, value)))
+    }
+
+    /// Send `cookie` with the response.
+    pub fn add(&mut self, cookie: Cookie) {
+        self.delta.retain(|c| c.name != cookie.name);
+        self.delta.push(cookie);
+    }
+
+    /// Tell the client to drop `cookie`, which needs the `Domain` and `Path`
+    /// it was added with.
+    pub fn remove(&mut self, cookie: Cookie) {
+        self.add(cookie.into_removal());
+    }
+
+    /// The changes made, as a `Set-Cookie` header.
+    pub fn set_cookie(&self) -> Option<SetCookie> {
+        if self.delta.is_empty() {
+            None
+        } else {
+            Some(SetCookie(self.delta.iter().map(|c| c.to_string()).collect()))
+        }
+    }
+
+    /// Add the changes made to the `Set-Cookie` header of `headers`.
+    pub fn apply(&self, headers: &mut Headers) {
+        let set_cookie = match self.set_cookie() {
+            Some(SetCookie(cookies)) => cookies,
+            None => return,
+        };
+        match headers.get_mut::<SetCookie>() {
+            Some(&mut SetCookie(ref mut existing)) => existing.extend(set_cookie),
+            None => headers.set(SetCookie(set_cookie)),
+        }
+    }
+
+    /// A view of the jar whose cookies are signed with `key`, so their value
+    /// is readable but can't be changed by the client.
+    pub fn signed<'a>(&'a mut self, key: &'a Key) -> SignedJar<'a> {
+        SignedJar {
+            jar: self,
+            key,
+        }
+    }
+
+    /// A view of the jar whose cookies are encrypted with `key`, so the
+    /// client can neither read nor change their value.
+    pub fn private<'a>(&'a mut self, key: &'a Key) -> PrivateJar<'a> {
+        PrivateJar {
+            jar: self,
+            key,
+        }
+    }
+}
+
+impl FromRequest for CookieJar {
+    fn from_request(req: &mut Request) -> Result<Self, Rejection> {
+        Ok(CookieJar::from_headers(&req.headers))
+    }
+}
+
+/// The secret a server signs and encrypts cookies with.
+///
+/// Every server sharing cookies needs the same key, and changing it drops
+/// the signed and private cookies of all clients.
+#[derive(Clone)]
+pub struct Key {
+    signing: [u8; 32],
+    encryption: [u8; 32],
+}
+
+impl Key {
+    /// Derive a key from `secret`, which should be at least 32 random bytes.
+    pub fn derive(secret: &[u8]) -> Key {
+        Key {
+            signing: hmac(secret, b"mco_http cookie signing"),
+            encryption: hmac(secret, b"mco_http cookie encryption"),
+        }
+    }
+
+    /// A random key, for cookies that don't need to outlive the process.
+    pub fn generate() -> Key {
+        Key {
+            signing: rand::random(),
+            encryption: rand::random(),
+        }
+    }
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("Key(..)")
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC takes keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+// the MAC covers the name too, so a value can't be moved to another cookie
+fn signature(key: &Key, name: &str, value: &str) -> Hmac<Sha256> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&key.signing).expect("HMAC takes keys of any size");
+    mac.update(name.as_bytes());
+    mac.update(b"=");
+    mac.update(value.as_bytes());
+    mac
+}
+
+// the base64 of a 32 byte MAC
+const SIGNATURE_LEN: usize = 43;
+
+/// A `CookieJar` whose cookies carry a signature, see `CookieJar::signed`.
+#[derive(Debug)]
+pub struct SignedJar<'a> {
+    jar: &'a mut CookieJar,
+    key: &'a Key,
+}
+
+impl<'a> SignedJar<'a> {
+    /// The value of cookie `name`, `None` if it is missing or its signature
+    /// doesn't match.
+    pub fn get(&self, name: &str) -> Option<String> {
+        let value = self.jar.get(name)?;
+        if value.len() < SIGNATURE_LEN || !value.is_char_boundary(SIGNATURE_LEN) {
+            return None;
+        }
+        let (tag, value) = value.split_at(SIGNATURE_LEN);
+        let tag = BASE64.decode(tag).ok()?;
+        signature(self.key, name, value).verify_slice(&tag).ok()?;
+        Some(value.to_owned())
+    }
+
+    /// Sign and add `cookie`.
+    pub fn add(&mut self, mut cookie: Cookie) {
+        let tag = signature(self.key, &cookie.name, &cookie.value).finalize().into_bytes();
+        cookie.value = format!("{}{}", BASE64.encode(tag), cookie.value);
+        self.jar.add(cookie);
+    }
+
+    /// See `CookieJar::remove`.
+    pub fn remove(&mut self, cookie: Cookie) {
+        self.jar.remove(cookie);
+    }
+}
+
+// the nonce of AES-GCM
+const NONCE_LEN: usize = 12;
+
+/// A `CookieJar` whose cookies are encrypted, see `CookieJar::private`.
+#[derive(Debug)]
+pub struct PrivateJar<'a> {
+    jar: &'a mut CookieJar,
+    key: &'a Key,
+}
+
+impl<'a> PrivateJar<'a> {
+    /// The decrypted value of cookie `name`, `None` if it is missing or was
+    /// not encrypted with the key.
+    pub fn get(&self, name: &str) -> Option<String> {
+        let sealed = BASE64.decode(self.jar.get(name)?).ok()?;
+        if sealed.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let cipher = Aes256Gcm::new((&self.key.encryption).into());
+        // the name is authenticated too, so a value can't be moved to another cookie
+        let value = cipher.decrypt(Nonce::from_slice(nonce), Payload {
+            msg: ciphertext,
+            aad: name.as_bytes(),
+        }).ok()?;
+        String::from_utf8(value).ok()
+    }
+
+    /// Encrypt and add `cookie`.
+    pub fn add(&mut self, mut cookie: Cookie) {
+        let nonce: [u8; NONCE_LEN] = rand::random();
+        let cipher = Aes256Gcm::new((&self.key.encryption).into());
+        let ciphertext = cipher.encrypt(Nonce::from_slice(&nonce), Payload {
+            msg: cookie.value.as_bytes(),
+            aad: cookie.name.as_bytes(),
+        }).expect("cookie values are short enough to encrypt");
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        cookie.value = BASE64.encode(sealed);
+        self.jar.add(cookie);
+    }
+
+    /// See `CookieJar::remove`.
+    pub fn remove(&mut self, cookie: Cookie) {
+        self.jar.remove(cookie);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::header::{self, Headers, SetCookie};
+    use super::{Cookie, CookieJar, Key, SameSite};
+
+    fn request_jar(cookies: &[&str]) -> CookieJar {
+        let mut headers = Headers::new();
+        headers.set(header::Cookie(cookies.iter().map(|c| c.to_string()).collect()));
+        CookieJar::from_headers(&headers)
+    }
+
+    #[test]
+    fn test_cookie_display() {
+        let cookie = Cookie::new("sid", "abc")
+            .domain("example.com")
+            .path("/")
+            .max_age(Duration::from_secs(60))
+            .secure(true)
+            .http_only(true)
+            .same_site(SameSite::Strict);
+        assert_eq!(cookie.to_string(),
+                   "sid=abc; Domain=example.com; Path=/; Max-Age=60; Secure; HttpOnly; SameSite=Strict");
+        assert_eq!(Cookie::named("sid").path("/").into_removal().to_string(),
+                   "sid=; Path=/; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT");
+    }
+
+    #[test]
+    fn test_jar() {
+        let mut jar = request_jar(&["a=1", "b=2"]);
+        assert_eq!(jar.get("a"), Some("1"));
+        assert_eq!(jar.get("c"), None);
+        assert_eq!(jar.set_cookie(), None);
+
+        jar.add(Cookie::new("c", "3"));
+        jar.add(Cookie::new("a", "4"));
+        jar.remove(Cookie::named("b"));
+        assert_eq!(jar.get("a"), Some("4"));
+        assert_eq!(jar.get("b"), None);
+        assert_eq!(jar.iter().collect::<Vec<_>>(), vec![("a", "4"), ("c", "3")]);
+
+        let mut headers = Headers::new();
+        headers.set(SetCookie(vec!["other=1".to_owned()]));
+        jar.apply(&mut headers);
+        let set_cookie = headers.get::<SetCookie>().unwrap();
+        assert_eq!(set_cookie.len(), 4);
+        assert_eq!(set_cookie[1], "c=3");
+        assert_eq!(set_cookie[2], "a=4");
+        assert_eq!(set_cookie[3], "b=; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT");
+    }
+
+    // a jar holding the cookies `jar` sends
+    fn round_trip(jar: &CookieJar) -> CookieJar {
+        let set_cookie = jar.set_cookie().unwrap();
+        let pairs: Vec<&str> = set_cookie.iter().map(|c| c.split(';').next().unwrap()).collect();
+        request_jar(&pairs)
+    }
+
+    #[test]
+    fn test_signed() {
+        let key = Key::generate();
+        let mut jar = CookieJar::new();
+        jar.signed(&key).add(Cookie::new("user", "joe"));
+        let value = jar.get("user").unwrap().to_owned();
+        assert!(value.ends_with("joe"));
+
+        let mut received = round_trip(&jar);
+        assert_eq!(received.signed(&key).get("user").as_deref(), Some("joe"));
+        assert_eq!(received.signed(&Key::generate()).get("user"), None);
+
+        let mut tampered = request_jar(&[&format!("user={}", value.replace("joe", "ann"))]);
+        assert_eq!(tampered.signed(&key).get("user"), None);
+        let mut moved = request_jar(&[&format!("admin={}", value)]);
+        assert_eq!(moved.signed(&key).get("admin"), None);
+        let mut short = request_jar(&["user=joe"]);
+        assert_eq!(short.signed(&key).get("user"), None);
+    }
+
+    #[test]
+    fn test_private() {
+        let key = Key::derive(b"a secret of at least thirty two bytes");
+        let mut jar = CookieJar::new();
+        jar.private(&key).add(Cookie::new("session", "id=42").http_only(true));
+        let value = jar.get("session").unwrap().to_owned();
+        assert!(!value.contains("42"));
+
+        let mut received = round_trip(&jar);
+        let same_key = Key::derive(b"a secret of at least thirty two bytes");
+        assert_eq!(received.private(&same_key).get("session").as_deref(), Some("id=42"));
+        assert_eq!(received.private(&Key::derive(b"another secret")).get("session"), None);
+
+        let mut moved = request_jar(&[&format!("other={}", value)]);
+        assert_eq!(moved.private(&key).get("other"), None);
+        let mut garbage = request_jar(&["session=AAAA"]);
+        assert_eq!(garbage.private(&key).get("session"), None);
+    }
+}
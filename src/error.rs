@@ -21,6 +21,9 @@ use self::Error::{
     Io,
     Ssl,
     TooLarge,
+    BodyTooLarge,
+    UriTooLong,
+    TransferCoding,
     Utf8
 };
 
@@ -42,6 +45,12 @@ pub enum Error {
     Header,
     /// A message head is too large to be reasonable.
     TooLarge,
+    /// A message body is larger than the configured limit.
+    BodyTooLarge,
+    /// A request-target is longer than the configured limit.
+    UriTooLong,
+    /// A request body has a transfer coding other than `chunked` applied.
+    TransferCoding,
     /// An invalid `Status`, such as `1337 ELITE`.
     Status,
     /// An `io::Error` that occurred while trying to read or write to a network stream.
@@ -86,6 +95,9 @@ impl Display for Error{
             Version => f.write_str("Invalid HTTP version specified"),
             Header => f.write_str("Invalid Header provided"),
             TooLarge => f.write_str("Message head is too large"),
+            BodyTooLarge => f.write_str("Message body is too large"),
+            UriTooLong => f.write_str("Request URI is too long"),
+            TransferCoding => f.write_str("Unsupported transfer coding"),
             Status => f.write_str("Invalid Status provided"),
             Uri(e) => write!(f, "{}", e),
             Io(e) => write!(f, "{}", e),
@@ -99,6 +111,10 @@ impl Display for Error{
 
 impl From<IoError> for Error {
     fn from(err: IoError) -> Error {
+        // a body read past its limit, see `HttpReader::limit`
+        if err.get_ref().is_some_and(|e| matches!(e.downcast_ref::<Error>(), Some(BodyTooLarge))) {
+            return BodyTooLarge;
+        }
         Io(err)
     }
 }
@@ -200,6 +216,7 @@ mod tests {
         from!(httparse::Error::Token => Header);
         from!(httparse::Error::TooManyHeaders => TooLarge);
         from!(httparse::Error::Version => Version);
+        from!(io::Error::new(io::ErrorKind::InvalidData, BodyTooLarge) => BodyTooLarge);
     }
 
     #[cfg(feature = "openssl")]
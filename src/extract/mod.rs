@@ -0,0 +1,476 @@
+//! Typed request extractors.
+//!
+//! A handler wrapped with `extract` declares what it needs from the request as
+//! parameters, followed by the `Response`. Each parameter implements `FromRequest`.
+//! If one of them fails, the handler is not called and the `Rejection` is written
+//! instead, as a `400 Bad Request`, `413 Payload Too Large` or `415 Unsupported Media Type`
+//! with the error as body.
+//!
+//! ```rust
+//! use serde::Deserialize;
+//! use mco_http::extract::{extract, Json, PathParams, Query};
+//! use mco_http::route::Route;
+//! use mco_http::server::Response;
+//!
+//! #[derive(Deserialize)]
+//! struct UserPath { id: u64 }
+//!
+//! #[derive(Deserialize)]
+//! struct Paging { page: Option<u32> }
+//!
+//! #[derive(Deserialize)]
+//! struct User { name: String }
+//!
+//! let route = Route::new();
+//! route.post_fn("/user/{id}", extract(|PathParams(path): PathParams<UserPath>,
+//!                                      Query(paging): Query<Paging>,
+//!                                      Json(user): Json<User>,
+//!                                      res: Response| {
+//!     let body = format!("{} {} {:?}", path.id, user.name, paging.page);
+//!     res.send(body.as_bytes()).unwrap();
+//! }));
+//! ```
+use std::fmt::{self, Debug, Display, Formatter};
+use std::io::Read;
+use std::marker::PhantomData;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use crate::header::{ContentType, Header, HeaderFormat};
+use crate::mime::{Mime, SubLevel, TopLevel};
+use crate::net::Fresh;
+use crate::route::Params;
+use crate::server::{Handler, IntoResponse, Request, Response};
+use crate::status::StatusCode;
+use crate::uri::RequestUri;
+
+/// Why a request could not be extracted. It is written as the response.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rejection {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl Rejection {
+    pub fn new<M: Into<String>>(status: StatusCode, message: M) -> Rejection {
+        Rejection {
+            status,
+            message: message.into(),
+        }
+    }
+
+    /// a `400 Bad Request` rejection
+    pub fn bad_request<M: Into<String>>(message: M) -> Rejection {
+        Rejection::new(StatusCode::BadRequest, message)
+    }
+
+    /// a `415 Unsupported Media Type` rejection
+    pub fn unsupported_media_type<M: Into<String>>(message: M) -> Rejection {
+        Rejection::new(StatusCode::UnsupportedMediaType, message)
+    }
+
+    /// write the status and the message as a plain text body
+    pub fn write(self, mut res: Response) {
+        res.status = self.status;
+        res.headers.set(ContentType::plaintext());
+        if let Err(e) = res.send(self.message.as_bytes()) {
+            debug!("error writing rejection: {:?}", e);
+        }
+    }
+}
+
+impl IntoResponse for Rejection {
+    fn into_response(self, res: Response) {
+        self.write(res)
+    }
+}
+
+impl Display for Rejection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.status, self.message)
+    }
+}
+
+impl std::error::Error for Rejection {}
+
+/// Something that can be created from a request, before the handler runs.
+pub trait FromRequest: Sized {
+    fn from_request(req: &mut Request) -> Result<Self, Rejection>;
+}
+
+impl<T: FromRequest> FromRequest for Option<T> {
+    fn from_request(req: &mut Request) -> Result<Self, Rejection> {
+        Ok(T::from_request(req).ok())
+    }
+}
+
+impl<T: FromRequest> FromRequest for Result<T, Rejection> {
+    fn from_request(req: &mut Request) -> Result<Self, Rejection> {
+        Ok(T::from_request(req))
+    }
+}
+
+fn has_content_type(req: &Request, sub: &SubLevel, suffix: &str) -> bool {
+    match req.headers.get::<ContentType>() {
+        Some(ContentType(Mime(TopLevel::Application, s, _))) => {
+            s == sub || match s {
+                SubLevel::Ext(ext) => ext.ends_with(suffix),
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+fn read_body(req: &mut Request) -> Result<Vec<u8>, Rejection> {
+    let mut body = Vec::new();
+    req.read_to_end(&mut body).map_err(|e| match crate::Error::from(e) {
+        crate::Error::BodyTooLarge => Rejection::new(StatusCode::PayloadTooLarge, "body too large"),
+        e => Rejection::bad_request(format!("failed to read body: {}", e)),
+    })?;
+    Ok(body)
+}
+
+/// the query string of the request uri, without the `?`
+fn query_str(uri: &RequestUri) -> &str {
+    match uri {
+        RequestUri::AbsolutePath(p) => p.find('?').map(|i| &p[i + 1..]).unwrap_or(""),
+        RequestUri::AbsoluteUri(url) => url.query().unwrap_or(""),
+        _ => "",
+    }
+}
+
+/// A body deserialized from `application/json` (or `application/*+json`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Json<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Json<T> {
+    fn from_request(req: &mut Request) -> Result<Self, Rejection> {
+        if !has_content_type(req, &SubLevel::Json, "+json") {
+            return Err(Rejection::unsupported_media_type("expected Content-Type: application/json"));
+        }
+        let body = read_body(req)?;
+        serde_json::from_slice(&body)
+            .map(Json)
+            .map_err(|e| Rejection::bad_request(format!("invalid json body: {}", e)))
+    }
+}
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    /// Write `T` as a `application/json` body.
+    fn into_response(self, res: Response) {
+        if let Err(e) = res.json(&self.0) {
+            debug!("error writing json: {:?}", e);
+        }
+    }
+}
+
+/// A body deserialized from `application/x-www-form-urlencoded`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Form<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Form<T> {
+    fn from_request(req: &mut Request) -> Result<Self, Rejection> {
+        if !has_content_type(req, &SubLevel::WwwFormUrlEncoded, "") {
+            return Err(Rejection::unsupported_media_type("expected Content-Type: application/x-www-form-urlencoded"));
+        }
+        let body = read_body(req)?;
+        serde_urlencoded::from_bytes(&body)
+            .map(Form)
+            .map_err(|e| Rejection::bad_request(format!("invalid form body: {}", e)))
+    }
+}
+
+/// The query string deserialized with `serde_urlencoded`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Query<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Query<T> {
+    fn from_request(req: &mut Request) -> Result<Self, Rejection> {
+        serde_urlencoded::from_str(query_str(&req.uri))
+            .map(Query)
+            .map_err(|e| Rejection::bad_request(format!("invalid query string: {}", e)))
+    }
+}
+
+/// The `route::Params` matched by a `Route`, deserialized into a struct or map.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PathParams<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for PathParams<T> {
+    fn from_request(req: &mut Request) -> Result<Self, Rejection> {
+        let params: Vec<(&str, &str)> = match req.extra.get::<Params>() {
+            Some(p) => p.iter().collect(),
+            None => vec![],
+        };
+        serde_urlencoded::to_string(params)
+            .map_err(|e| e.to_string())
+            .and_then(|s| serde_urlencoded::from_str(&s).map_err(|e| e.to_string()))
+            .map(PathParams)
+            .map_err(|e| Rejection::bad_request(format!("invalid path params: {}", e)))
+    }
+}
+
+/// A typed header, rejected if it is missing or invalid.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TypedHeader<H>(pub H);
+
+impl<H: Header + HeaderFormat> FromRequest for TypedHeader<H> {
+    fn from_request(req: &mut Request) -> Result<Self, Rejection> {
+        match req.headers.get::<H>() {
+            Some(h) => Ok(TypedHeader(h.clone())),
+            None if req.headers.has::<H>() => {
+                Err(Rejection::bad_request(format!("invalid header: {}", H::header_name())))
+            }
+            None => Err(Rejection::bad_request(format!("missing header: {}", H::header_name()))),
+        }
+    }
+}
+
+/// A clone of a value in `Request::extra`, usually inserted by a middleware.
+/// If it is missing the request is rejected with `500 Internal Server Error`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Extra<T>(pub T);
+
+impl<T: Clone + Send + Sync + 'static> FromRequest for Extra<T> {
+    fn from_request(req: &mut Request) -> Result<Self, Rejection> {
+        match req.extra.get::<T>() {
+            Some(v) => Ok(Extra(v.clone())),
+            None => Err(Rejection::new(StatusCode::InternalServerError,
+                                       format!("missing extra: {}", std::any::type_name::<T>()))),
+        }
+    }
+}
+
+/// A fn taking extractors and a `Response`, see `extract`.
+pub trait ExtractHandler<Args>: Send + Sync {
+    fn call<'a, 'k>(&'a self, req: Request<'a, 'k>, res: Response<'a, Fresh>);
+}
+
+/// A `Handler` running a fn with its extracted arguments.
+pub struct Extract<F, Args> {
+    f: F,
+    _args: PhantomData<fn() -> Args>,
+}
+
+impl<F, Args> Debug for Extract<F, Args> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("Extract")
+    }
+}
+
+/// Turn a fn taking extractors and a `Response` into a `Handler`.
+/// The extractors run in order, the first rejection is written as the response.
+pub fn extract<F: ExtractHandler<Args>, Args>(f: F) -> Extract<F, Args> {
+    Extract {
+        f,
+        _args: PhantomData,
+    }
+}
+
+impl<F: ExtractHandler<Args> + 'static, Args: 'static> Handler for Extract<F, Args> {
+    fn handle<'a, 'k>(&'a self, req: Request<'a, 'k>, res: Response<'a, Fresh>) {
+        self.f.call(req, res)
+    }
+}
+
+/// A fn taking extractors and returning an `IntoResponse`, see `reply`.
+pub trait ReplyHandler<Args>: Send + Sync {
+    fn call<'a, 'k>(&'a self, req: Request<'a, 'k>, res: Response<'a, Fresh>);
+}
+
+/// A `Handler` writing the value returned by a fn with its extracted arguments.
+pub struct Reply<F, Args> {
+    f: F,
+    _args: PhantomData<fn() -> Args>,
+}
+
+impl<F, Args> Debug for Reply<F, Args> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("Reply")
+    }
+}
+
+/// Turn a fn taking extractors and returning an `IntoResponse` into a `Handler`.
+///
+/// ```rust
+/// use mco_http::extract::{reply, Json, PathParams};
+/// use mco_http::route::Route;
+/// use mco_http::status::StatusCode;
+///
+/// let route = Route::new();
+/// route.get_fn("/user/{id}", reply(|PathParams(id): PathParams<std::collections::BTreeMap<String, u64>>| {
+///     match id.get("id") {
+///         Some(1) => Ok(Json(vec!["joe"])),
+///         _ => Err((StatusCode::NotFound, "no such user")),
+///     }
+/// }));
+/// ```
+pub fn reply<F: ReplyHandler<Args>, Args>(f: F) -> Reply<F, Args> {
+    Reply {
+        f,
+        _args: PhantomData,
+    }
+}
+
+impl<F: ReplyHandler<Args> + 'static, Args: 'static> Handler for Reply<F, Args> {
+    fn handle<'a, 'k>(&'a self, req: Request<'a, 'k>, res: Response<'a, Fresh>) {
+        self.f.call(req, res)
+    }
+}
+
+macro_rules! impl_reply_handler {
+    ($($ty:ident),*) => {
+        impl<F, R, $($ty,)*> ReplyHandler<($($ty,)*)> for F
+            where F: Fn($($ty,)*) -> R + Send + Sync, R: IntoResponse, $($ty: FromRequest,)* {
+            #[allow(non_snake_case, unused_mut, unused_variables)]
+            fn call<'a, 'k>(&'a self, mut req: Request<'a, 'k>, res: Response<'a, Fresh>) {
+                $(
+                    let $ty = match $ty::from_request(&mut req) {
+                        Ok(v) => v,
+                        Err(e) => return e.write(res),
+                    };
+                )*
+                self($($ty,)*).into_response(res)
+            }
+        }
+    };
+}
+
+impl_reply_handler!();
+impl_reply_handler!(T1);
+impl_reply_handler!(T1, T2);
+impl_reply_handler!(T1, T2, T3);
+impl_reply_handler!(T1, T2, T3, T4);
+impl_reply_handler!(T1, T2, T3, T4, T5);
+impl_reply_handler!(T1, T2, T3, T4, T5, T6);
+
+macro_rules! impl_extract_handler {
+    ($($ty:ident),*) => {
+        impl<F, $($ty,)*> ExtractHandler<($($ty,)*)> for F
+            where F: Fn($($ty,)* Response) + Send + Sync, $($ty: FromRequest,)* {
+            #[allow(non_snake_case, unused_mut)]
+            fn call<'a, 'k>(&'a self, mut req: Request<'a, 'k>, res: Response<'a, Fresh>) {
+                $(
+                    let $ty = match $ty::from_request(&mut req) {
+                        Ok(v) => v,
+                        Err(e) => return e.write(res),
+                    };
+                )*
+                self($($ty,)* res)
+            }
+        }
+    };
+}
+
+impl_extract_handler!(T1);
+impl_extract_handler!(T1, T2);
+impl_extract_handler!(T1, T2, T3);
+impl_extract_handler!(T1, T2, T3, T4);
+impl_extract_handler!(T1, T2, T3, T4, T5);
+impl_extract_handler!(T1, T2, T3, T4, T5, T6);
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use serde::Deserialize;
+    use crate::header::Host;
+    use crate::mock::serve;
+    use crate::route::{limited, Route};
+    use std::sync::Arc;
+    use crate::server::{Redirect, RequestLimits, Response};
+    use crate::status::StatusCode;
+    use super::{extract, reply, Form, Json, PathParams, Query, TypedHeader};
+
+    #[derive(Deserialize)]
+    struct User {
+        name: String,
+        age: u32,
+    }
+
+    #[derive(Deserialize)]
+    struct UserPath {
+        id: u64,
+    }
+
+    fn route() -> Route {
+        let route = Route::new();
+        route.post_fn("/user/{id}", extract(|PathParams(path): PathParams<UserPath>, Json(user): Json<User>, res: Response| {
+            res.send(format!("{} {} {}", path.id, user.name, user.age).as_bytes()).unwrap();
+        }));
+        route.post_fn("/form", extract(|Form(user): Form<User>, res: Response| {
+            res.send(format!("{} {}", user.name, user.age).as_bytes()).unwrap();
+        }));
+        route.get_fn("/query", extract(|Query(q): Query<BTreeMap<String, String>>, TypedHeader(host): TypedHeader<Host>, res: Response| {
+            res.send(format!("{:?} {}", q, host.hostname).as_bytes()).unwrap();
+        }));
+        route
+    }
+
+    #[test]
+    fn test_extract_json_and_path() {
+        let s = serve(route(), b"POST /user/7 HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: 23\r\nConnection: close\r\n\r\n{\"name\":\"joe\",\"age\":30}");
+        assert!(s.starts_with("HTTP/1.1 200 OK\r\n"), "{}", s);
+        assert!(s.ends_with("\r\n\r\n7 joe 30"), "{}", s);
+    }
+
+    #[test]
+    fn test_reply() {
+        let route = Route::new();
+        route.get_fn("/user/{id}", reply(|PathParams(path): PathParams<UserPath>| {
+            if path.id == 1 {
+                Ok(Json(vec!["joe"]))
+            } else {
+                Err((StatusCode::NotFound, format!("no user {}", path.id)))
+            }
+        }));
+        route.get_fn("/old", reply(|| Redirect::permanent("/new")));
+        let route = Arc::new(route);
+
+        let s = serve(route.clone(), b"GET /user/1 HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(s.contains("Content-Type: application/json\r\n"), "{}", s);
+        assert!(s.ends_with("\r\n\r\n[\"joe\"]"), "{}", s);
+
+        let s = serve(route.clone(), b"GET /user/2 HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(s.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", s);
+        assert!(s.ends_with("\r\n\r\nno user 2"), "{}", s);
+
+        let s = serve(route, b"GET /old HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(s.starts_with("HTTP/1.1 308 Permanent Redirect\r\n"), "{}", s);
+        assert!(s.contains("Location: /new\r\n"), "{}", s);
+    }
+
+    #[test]
+    fn test_extract_rejections() {
+        let s = serve(route(), b"POST /user/x HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{}");
+        assert!(s.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", s);
+        assert!(s.contains("invalid path params"), "{}", s);
+
+        let s = serve(route(), b"POST /user/7 HTTP/1.1\r\nContent-Type: text/plain\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{}");
+        assert!(s.starts_with("HTTP/1.1 415 Unsupported Media Type\r\n"), "{}", s);
+
+        let s = serve(route(), b"POST /user/7 HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{}");
+        assert!(s.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", s);
+        assert!(s.contains("invalid json body"), "{}", s);
+
+        let limits = RequestLimits { max_body_size: Some(8), ..Default::default() };
+        let small = Route::new();
+        small.post_fn("/user", limited(limits, extract(|Json(user): Json<User>, res: Response| {
+            res.send(user.name.as_bytes()).unwrap();
+        })));
+        let s = serve(small, b"POST /user HTTP/1.1\r\nContent-Type: application/json\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n17\r\n{\"name\":\"joe\",\"age\":30}\r\n0\r\n\r\n");
+        assert!(s.starts_with("HTTP/1.1 413 Payload Too Large\r\n"), "{}", s);
+
+        let s = serve(route(), b"GET /query?a=1 HTTP/1.0\r\n\r\n");
+        assert!(s.starts_with("HTTP/1.0 400 Bad Request\r\n"), "{}", s);
+        assert!(s.ends_with("missing header: Host"), "{}", s);
+    }
+
+    #[test]
+    fn test_extract_form_query_header() {
+        let s = serve(route(), b"POST /form HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: 15\r\nConnection: close\r\n\r\nname=joe&age=30");
+        assert!(s.ends_with("\r\n\r\njoe 30"), "{}", s);
+
+        let s = serve(route(), b"GET /query?a=1&b=x%20y HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n");
+        assert!(s.ends_with("\r\n\r\n{\"a\": \"1\", \"b\": \"x y\"} example.com"), "{}", s);
+    }
+}
@@ -164,18 +164,15 @@ impl FromStr for Basic {
         match STANDARD.decode(s) {
             Ok(decoded) => match String::from_utf8(decoded) {
                 Ok(text) => {
-                    let parts = &mut text.split(':');
+                    let parts = &mut text.splitn(2, ':');
                     let user = match parts.next() {
                         Some(part) => part.to_owned(),
                         None => return Err(crate::Error::Header)
                     };
-                    let password = match parts.next() {
-                        Some(part) => Some(part.to_owned()),
-                        None => None
-                    };
+                    let password = parts.next().map(|part| part.to_owned());
                     Ok(Basic {
                         username: user,
-                        password: password
+                        password
                     })
                 },
                 Err(e) => {
@@ -215,9 +212,168 @@ impl FromStr for Bearer {
 	}
 }
 
+/// Credentials for Digest Authentication, defined in
+/// [RFC7616](https://tools.ietf.org/html/rfc7616#section-3.4)
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Digest {
+    /// The user's name in the realm
+    pub username: String,
+    /// The realm of the challenge answered
+    pub realm: String,
+    /// The nonce of the challenge answered
+    pub nonce: String,
+    /// The request target
+    pub uri: String,
+    /// The hex digest proving the client knows the password
+    pub response: String,
+    /// The hash algorithm, `MD5` if `None`
+    pub algorithm: Option<String>,
+    /// The quality of protection, `auth` or `auth-int`
+    pub qop: Option<String>,
+    /// How many requests the client made with this nonce, in hex
+    pub nc: Option<String>,
+    /// The client's nonce, sent with `qop`
+    pub cnonce: Option<String>,
+    /// The opaque value of the challenge answered
+    pub opaque: Option<String>,
+}
+
+impl Scheme for Digest {
+    fn scheme() -> Option<&'static str> {
+        Some("Digest")
+    }
+
+    fn fmt_scheme(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "username={}, realm={}, nonce={}, uri={}, response={}",
+               Quoted(&self.username), Quoted(&self.realm), Quoted(&self.nonce),
+               Quoted(&self.uri), Quoted(&self.response))?;
+        if let Some(ref algorithm) = self.algorithm {
+            write!(f, ", algorithm={}", algorithm)?;
+        }
+        if let Some(ref qop) = self.qop {
+            write!(f, ", qop={}", qop)?;
+        }
+        if let Some(ref nc) = self.nc {
+            write!(f, ", nc={}", nc)?;
+        }
+        if let Some(ref cnonce) = self.cnonce {
+            write!(f, ", cnonce={}", Quoted(cnonce))?;
+        }
+        if let Some(ref opaque) = self.opaque {
+            write!(f, ", opaque={}", Quoted(opaque))?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Digest {
+    type Err = crate::Error;
+    fn from_str(s: &str) -> crate::Result<Digest> {
+        let mut digest = Digest::default();
+        let (mut username, mut realm, mut nonce, mut uri, mut response) = (false, false, false, false, false);
+        for (name, value) in parse_auth_params(s)? {
+            match &name.to_ascii_lowercase()[..] {
+                "username" => { digest.username = value; username = true; }
+                "realm" => { digest.realm = value; realm = true; }
+                "nonce" => { digest.nonce = value; nonce = true; }
+                "uri" => { digest.uri = value; uri = true; }
+                "response" => { digest.response = value; response = true; }
+                "algorithm" => digest.algorithm = Some(value),
+                "qop" => digest.qop = Some(value),
+                "nc" => digest.nc = Some(value),
+                "cnonce" => digest.cnonce = Some(value),
+                "opaque" => digest.opaque = Some(value),
+                _ => {}
+            }
+        }
+        if username && realm && nonce && uri && response {
+            Ok(digest)
+        } else {
+            debug!("Digest missing a parameter: {:?}", s);
+            Err(crate::Error::Header)
+        }
+    }
+}
+
+/// Formats a value as a quoted-string.
+struct Quoted<'a>(&'a str);
+
+impl<'a> Display for Quoted<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("\"")?;
+        for c in self.0.chars() {
+            if c == '"' || c == '\\' {
+                f.write_str("\\")?;
+            }
+            write!(f, "{}", c)?;
+        }
+        f.write_str("\"")
+    }
+}
+
+/// Parses `name=value` pairs separated by commas, values being tokens or
+/// quoted-strings.
+fn parse_auth_params(s: &str) -> crate::Result<Vec<(String, String)>> {
+    let mut params = Vec::new();
+    let mut chars = s.chars().peekable();
+    loop {
+        while let Some(&c) = chars.peek() {
+            if c == ',' || c == ' ' || c == '\t' {
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        if chars.peek().is_none() {
+            return Ok(params);
+        }
+        let mut name = String::new();
+        while let Some(&c) = chars.peek() {
+            if c == '=' || c == ' ' || c == '\t' {
+                break;
+            }
+            name.push(c);
+            chars.next();
+        }
+        while let Some(' ') | Some('\t') = chars.peek() {
+            chars.next();
+        }
+        if chars.next() != Some('=') || name.is_empty() {
+            return Err(crate::Error::Header);
+        }
+        while let Some(' ') | Some('\t') = chars.peek() {
+            chars.next();
+        }
+        let mut value = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some(c) => value.push(c),
+                        None => return Err(crate::Error::Header),
+                    },
+                    Some(c) => value.push(c),
+                    None => return Err(crate::Error::Header),
+                }
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c == ',' || c == ' ' || c == '\t' {
+                    break;
+                }
+                value.push(c);
+                chars.next();
+            }
+        }
+        params.push((name, value));
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{Authorization, Basic, Bearer};
+    use super::{Authorization, Basic, Bearer, Digest};
     use super::super::super::{Headers, Header};
 
     #[test]
@@ -283,6 +439,58 @@ mod tests {
             &[b"Bearer fpKL54jvWmEGVoRdCNjG".to_vec()]).unwrap();
         assert_eq!(auth.0.token, "fpKL54jvWmEGVoRdCNjG");
     }
+
+    #[test]
+    fn test_basic_auth_parse_colon_in_password() {
+        let auth: Authorization<Basic> = Header::parse_header(
+            &[b"Basic dXNlcjpwYTpzcw==".to_vec()]).unwrap();
+        assert_eq!(auth.0.username, "user");
+        assert_eq!(auth.0.password, Some("pa:ss".to_owned()));
+    }
+
+    #[test]
+    fn test_digest_auth() {
+        let digest = Digest {
+            username: "Mufasa".to_owned(),
+            realm: "http-auth@example.org".to_owned(),
+            nonce: "7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v".to_owned(),
+            uri: "/dir/index.html".to_owned(),
+            response: "8ca523f5e9506fed4657c9700eebdbec".to_owned(),
+            algorithm: Some("MD5".to_owned()),
+            qop: Some("auth".to_owned()),
+            nc: Some("00000001".to_owned()),
+            cnonce: Some("f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ".to_owned()),
+            opaque: Some("FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS".to_owned()),
+        };
+        let mut headers = Headers::new();
+        headers.set(Authorization(digest.clone()));
+        let s = headers.to_string();
+        assert_eq!(s, "Authorization: Digest username=\"Mufasa\", realm=\"http-auth@example.org\", \
+            nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", uri=\"/dir/index.html\", \
+            response=\"8ca523f5e9506fed4657c9700eebdbec\", algorithm=MD5, qop=auth, nc=00000001, \
+            cnonce=\"f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ\", \
+            opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\"\r\n");
+        let auth: Authorization<Digest> = Header::parse_header(
+            &[s.as_bytes()["Authorization: ".len()..s.len() - 2].to_vec()]).unwrap();
+        assert_eq!(auth.0, digest);
+    }
+
+    #[test]
+    fn test_digest_auth_parse() {
+        let auth: Authorization<Digest> = Header::parse_header(
+            &[b"Digest username=\"a \\\"b\\\"\",realm=\"r\", nonce = \"n\", uri=\"/\", response=\"x\", qop=auth".to_vec()]).unwrap();
+        assert_eq!(auth.0.username, "a \"b\"");
+        assert_eq!(auth.0.nonce, "n");
+        assert_eq!(auth.0.qop, Some("auth".to_owned()));
+        assert_eq!(auth.0.cnonce, None);
+
+        let missing: crate::Result<Authorization<Digest>> = Header::parse_header(
+            &[b"Digest username=\"a\", realm=\"r\"".to_vec()]);
+        assert!(missing.is_err());
+        let unterminated: crate::Result<Authorization<Digest>> = Header::parse_header(
+            &[b"Digest username=\"a".to_vec()]);
+        assert!(unterminated.is_err());
+    }
 }
 
 bench_header!(raw, Authorization<String>, { vec![b"foo bar baz".to_vec()] });
@@ -43,7 +43,13 @@ impl Header for ContentLength {
         // correctly. If not, then it's an error.
         raw.iter()
             .map(std::ops::Deref::deref)
-            .map(parsing::from_raw_str)
+            .map(|raw| {
+                // `u64::from_str` takes a leading `+`, the ABNF doesn't
+                if raw.is_empty() || !raw.iter().all(u8::is_ascii_digit) {
+                    return Err(crate::Error::Header);
+                }
+                parsing::from_raw_str(raw)
+            })
             .fold(None, |prev, x| {
                 match (prev, x) {
                     (None, x) => Some(x),
@@ -78,6 +84,8 @@ __mco_http__tm!(ContentLength, tests {
     test_header!(test1, vec![b"3495"], Some(HeaderField(3495)));
 
     test_header!(test_invalid, vec![b"34v95"], None);
+    test_header!(test_invalid_sign, vec![b"+3495"], None);
+    test_header!(test_invalid_list, vec![b"5, 6"], None);
 
     // Can't use the test_header macro because "5, 5" gets cleaned to "5".
     #[test]
@@ -22,8 +22,8 @@ header! {
     (LastEventID, "Last-Event-ID") => [String]
 
     test_last_event_id {
-        // Initial state
-        test_header!(test1, vec![b""]);
+        // Initial state, the same as no header
+        test_header!(test1, vec![b""], None);
         // Own testcase
         test_header!(test2, vec![b"1"], Some(LastEventID("1".to_owned())));
     }
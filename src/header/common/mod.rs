@@ -20,7 +20,7 @@ pub use self::accept_encoding::AcceptEncoding;
 pub use self::accept_language::AcceptLanguage;
 pub use self::accept_ranges::{AcceptRanges, RangeUnit};
 pub use self::allow::Allow;
-pub use self::authorization::{Authorization, Scheme, Basic, Bearer};
+pub use self::authorization::{Authorization, Scheme, Basic, Bearer, Digest};
 pub use self::cache_control::{CacheControl, CacheDirective};
 pub use self::connection::{Connection, ConnectionOption};
 pub use self::content_disposition::{ContentDisposition, DispositionType, DispositionParam};
@@ -41,6 +41,7 @@ pub use self::if_modified_since::IfModifiedSince;
 pub use self::if_none_match::IfNoneMatch;
 pub use self::if_unmodified_since::IfUnmodifiedSince;
 pub use self::if_range::IfRange;
+pub use self::last_event_id::LastEventID;
 pub use self::last_modified::LastModified;
 pub use self::location::Location;
 pub use self::origin::Origin;
@@ -50,6 +51,7 @@ pub use self::preference_applied::PreferenceApplied;
 pub use self::range::{Range, ByteRangeSpec};
 pub use self::referer::Referer;
 pub use self::referrer_policy::ReferrerPolicy;
+pub use self::retry_after::RetryAfter;
 pub use self::server::Server;
 pub use self::set_cookie::SetCookie;
 pub use self::strict_transport_security::StrictTransportSecurity;
@@ -375,6 +377,7 @@ mod if_modified_since;
 mod if_none_match;
 mod if_range;
 mod if_unmodified_since;
+mod last_event_id;
 mod last_modified;
 mod location;
 mod origin;
@@ -384,6 +387,7 @@ mod preference_applied;
 mod range;
 mod referer;
 mod referrer_policy;
+mod retry_after;
 mod server;
 mod set_cookie;
 mod strict_transport_security;
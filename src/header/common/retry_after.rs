@@ -0,0 +1,92 @@
+use std::fmt::{self, Display};
+use std::time::Duration;
+use crate::header::{self, Header, HeaderFormat, HttpDate};
+
+/// `Retry-After` header, defined in [RFC7231](http://tools.ietf.org/html/rfc7231#section-7.1.3)
+///
+/// The `Retry-After` response-header field can be used with a 503 (Service
+/// Unavailable) or 429 (Too Many Requests) response to indicate how long the
+/// service is expected to be unavailable to the requesting client.
+///
+/// # ABNF
+/// ```plain
+/// Retry-After = HTTP-date / delay-seconds
+/// ```
+///
+/// # Example values
+/// * `Fri, 31 Dec 1999 23:59:59 GMT`
+/// * `120`
+///
+/// # Examples
+/// ```
+/// use std::time::Duration;
+/// use mco_http::header::{Headers, RetryAfter};
+///
+/// let mut headers = Headers::new();
+/// headers.set(RetryAfter::Delay(Duration::from_secs(120)));
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub enum RetryAfter {
+    /// How long to wait, in whole seconds
+    Delay(Duration),
+    /// The date after which to retry
+    DateTime(HttpDate),
+}
+
+impl RetryAfter {
+    /// How long from now to wait, zero for a date already past.
+    pub fn delay(&self) -> Duration {
+        match *self {
+            RetryAfter::Delay(delay) => delay,
+            RetryAfter::DateTime(HttpDate(ref tm)) => {
+                let ms = (tm.to_timespec() - time::get_time()).num_milliseconds();
+                Duration::from_millis(ms.max(0) as u64)
+            }
+        }
+    }
+}
+
+impl Header for RetryAfter {
+    fn header_name() -> &'static str {
+        "Retry-After"
+    }
+    fn parse_header(raw: &[Vec<u8>]) -> crate::Result<RetryAfter> {
+        if let Ok(secs) = header::parsing::from_one_raw_str::<u64>(raw) {
+            return Ok(RetryAfter::Delay(Duration::from_secs(secs)));
+        }
+        header::parsing::from_one_raw_str(raw).map(RetryAfter::DateTime)
+    }
+}
+
+impl HeaderFormat for RetryAfter {
+    fn fmt_header(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RetryAfter::Delay(ref x) => Display::fmt(&x.as_secs(), f),
+            RetryAfter::DateTime(ref x) => Display::fmt(x, f),
+        }
+    }
+}
+
+impl Display for RetryAfter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_header(f)
+    }
+}
+
+#[cfg(test)]
+mod test_retry_after {
+    use std::str;
+    use std::time::Duration;
+    use crate::header::*;
+    use super::RetryAfter as HeaderField;
+    test_header!(test1, [b"Fri, 31 Dec 1999 23:59:59 GMT"]);
+    test_header!(test2, vec![b"120"], Some(HeaderField::Delay(Duration::from_secs(120))));
+    test_header!(test3, vec![b"soon"], None::<RetryAfter>);
+
+    #[test]
+    fn test_delay() {
+        assert_eq!(HeaderField::Delay(Duration::from_secs(3)).delay(), Duration::from_secs(3));
+        let past: HeaderField = Header::parse_header(&[b"Fri, 31 Dec 1999 23:59:59 GMT".to_vec()]).unwrap();
+        assert_eq!(past.delay(), Duration::from_secs(0));
+    }
+}
@@ -516,6 +516,12 @@ impl<'a> HeaderView<'a> {
     pub fn value_string(&self) -> String {
         ValueString(self.1).to_string()
     }
+
+    /// The number of raw values, one for each line the header was received on.
+    #[inline]
+    pub(crate) fn raw_len(&self) -> usize {
+        self.1.raw().len()
+    }
 }
 
 impl<'a> fmt::Display for HeaderView<'a> {
@@ -20,7 +20,7 @@ use crate::version::HttpVersion;
 use crate::version::HttpVersion::{Http10, Http11};
 use crate::uri::RequestUri;
 
-use self::HttpReader::{SizedReader, ChunkedReader, EofReader, EmptyReader};
+use self::HttpReader::{SizedReader, ChunkedReader, EofReader, EmptyReader, LimitedReader};
 use self::HttpWriter::{ChunkedWriter, SizedWriter, EmptyWriter, ThroughWriter};
 
 use crate::http::{
@@ -511,10 +511,26 @@ pub enum HttpReader<R> {
     ///
     /// See https://tools.ietf.org/html/rfc7230#section-3.3.3
     EmptyReader(R),
+    /// A Reader that fails with `Error::BodyTooLarge` once the inner reader
+    /// yields more than the remaining number of bytes.
+    ///
+    /// See `HttpReader::limit`.
+    LimitedReader(Box<HttpReader<R>>, u64),
 }
 
 impl<R: Read> HttpReader<R> {
 
+    /// Limits this body to `max` bytes, reading past it fails with an `io::Error`
+    /// wrapping `Error::BodyTooLarge`.
+    ///
+    /// An already limited reader keeps the smaller of both limits.
+    pub fn limit(self, max: u64) -> HttpReader<R> {
+        match self {
+            LimitedReader(r, rem) => LimitedReader(r, min(rem, max)),
+            r => LimitedReader(Box::new(r), max),
+        }
+    }
+
     /// Unwraps this HttpReader and returns the underlying Reader.
     pub fn into_inner(self) -> R {
         match self {
@@ -522,6 +538,7 @@ impl<R: Read> HttpReader<R> {
             ChunkedReader(r, _) => r,
             EofReader(r) => r,
             EmptyReader(r) => r,
+            LimitedReader(r, _) => r.into_inner(),
         }
     }
 
@@ -532,6 +549,7 @@ impl<R: Read> HttpReader<R> {
             ChunkedReader(ref r, _) => r,
             EofReader(ref r) => r,
             EmptyReader(ref r) => r,
+            LimitedReader(ref r, _) => r.get_ref(),
         }
     }
 
@@ -542,6 +560,7 @@ impl<R: Read> HttpReader<R> {
             ChunkedReader(ref mut r, _) => r,
             EofReader(ref mut r) => r,
             EmptyReader(ref mut r) => r,
+            LimitedReader(ref mut r, _) => r.get_mut(),
         }
     }
 }
@@ -554,6 +573,7 @@ impl<R> fmt::Debug for HttpReader<R> {
             ChunkedReader(_, Some(rem)) => write!(fmt, "ChunkedReader(chunk_remaining={:?})", rem),
             EofReader(_) => write!(fmt, "EofReader"),
             EmptyReader(_) => write!(fmt, "EmptyReader"),
+            LimitedReader(ref r, rem) => write!(fmt, "LimitedReader({:?}, remaining={:?})", r, rem),
         }
     }
 }
@@ -627,11 +647,35 @@ impl<R: Read> Read for HttpReader<R> {
                 trace!("eofread: {:?}", r);
                 r
             },
-            EmptyReader(_) => Ok(0)
+            EmptyReader(_) => Ok(0),
+            LimitedReader(ref mut body, ref mut remaining) => {
+                trace!("Limited read, remaining={:?}", remaining);
+                if let SizedReader(_, len) = **body {
+                    if len > *remaining {
+                        return Err(body_too_large());
+                    }
+                }
+                if *remaining == 0 {
+                    // anything left in the body is over the limit
+                    let mut probe = [0];
+                    return match body.read(&mut probe)? {
+                        0 => Ok(0),
+                        _ => Err(body_too_large()),
+                    };
+                }
+                let to_read = min(*remaining, buf.len() as u64) as usize;
+                let num = body.read(&mut buf[..to_read])?;
+                *remaining -= num as u64;
+                Ok(num)
+            }
         }
     }
 }
 
+fn body_too_large() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, Error::BodyTooLarge)
+}
+
 fn eat<R: Read>(rdr: &mut R, bytes: &[u8]) -> io::Result<()> {
     let mut buf = [0];
     for &b in bytes.iter() {
@@ -644,47 +688,53 @@ fn eat<R: Read>(rdr: &mut R, bytes: &[u8]) -> io::Result<()> {
     Ok(())
 }
 
+/// The longest chunk size line, extensions included, that is read.
+const MAX_CHUNK_SIZE_LINE: usize = 4096;
+
 /// Chunked chunks start with 1*HEXDIGIT, indicating the size of the chunk.
 fn read_chunk_size<R: Read>(rdr: &mut R) -> io::Result<u64> {
+    macro_rules! invalid (
+        () => (return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                         "Invalid chunk size line")))
+    );
+    let mut line = 0;
     macro_rules! byte (
         ($rdr:ident) => ({
+            line += 1;
+            if line > MAX_CHUNK_SIZE_LINE {
+                invalid!();
+            }
             let mut buf = [0];
             match $rdr.read(&mut buf)? {
                 1 => buf[0],
-                _ => return Err(io::Error::new(io::ErrorKind::InvalidInput,
-                                                  "Invalid chunk size line")),
-
+                _ => invalid!(),
             }
         })
     );
     let mut size = 0u64;
-    let radix = 16;
+    let mut digits = 0;
     let mut in_ext = false;
     let mut in_chunk_size = true;
     loop {
         match byte!(rdr) {
-            b@b'0'..=b'9' if in_chunk_size => {
-                size *= radix;
-                size += (b - b'0') as u64;
-            },
-            b@b'a'..=b'f' if in_chunk_size => {
-                size *= radix;
-                size += (b + 10 - b'a') as u64;
-            },
-            b@b'A'..=b'F' if in_chunk_size => {
-                size *= radix;
-                size += (b + 10 - b'A') as u64;
+            b@(b'0'..=b'9' | b'a'..=b'f' | b'A'..=b'F') if in_chunk_size => {
+                let digit = (b as char).to_digit(16).unwrap() as u64;
+                size = match size.checked_mul(16) {
+                    Some(size) => size + digit,
+                    None => invalid!(),
+                };
+                digits += 1;
             },
-            CR => {
+            // The line must start with at least one digit, an empty size
+            // would be read as 0 and end the body early.
+            CR if digits > 0 => {
                 match byte!(rdr) {
                     LF => break,
-                    _ => return Err(io::Error::new(io::ErrorKind::InvalidInput,
-                                                  "Invalid chunk size line"))
-
+                    _ => invalid!(),
                 }
             },
             // If we weren't in the extension yet, the ";" signals its start
-            b';' if !in_ext => {
+            b';' if !in_ext && digits > 0 => {
                 in_ext = true;
                 in_chunk_size = false;
             },
@@ -692,21 +742,17 @@ fn read_chunk_size<R: Read>(rdr: &mut R) -> io::Result<u64> {
             // extension separator token (";") due to the "implied *LWS rule".
             b'\t' | b' ' if !in_ext & !in_chunk_size => {},
             // LWS can follow the chunk size, but no more digits can come
-            b'\t' | b' ' if in_chunk_size => in_chunk_size = false,
-            // We allow any arbitrary octet once we are in the extension, since
+            b'\t' | b' ' if in_chunk_size && digits > 0 => in_chunk_size = false,
+            // We allow any visible octet once we are in the extension, since
             // they all get ignored anyway. According to the HTTP spec, valid
             // extensions would have a more strict syntax:
             //     (token ["=" (token | quoted-string)])
-            // but we gain nothing by rejecting an otherwise valid chunk size.
-            ext if in_ext => {
-                todo!("chunk extension byte={}", ext);
-            },
+            // but control characters, like a bare LF, are rejected as another
+            // parser could take them as the end of the line.
+            b'\t' | b' '..=b'~' | 0x80..=0xff if in_ext => {},
             // Finally, if we aren't in the extension and we're reading any
             // other octet, the chunk size line is invalid!
-            _ => {
-                return Err(io::Error::new(io::ErrorKind::InvalidInput,
-                                         "Invalid chunk size line"));
-            }
+            _ => invalid!(),
         }
     }
     trace!("chunk size={:?}", size);
@@ -860,32 +906,50 @@ impl<W: Write> fmt::Debug for HttpWriter<W> {
     }
 }
 
-const MAX_HEADERS: usize = 100;
+/// The default for the most headers a message head may have.
+pub const MAX_HEADERS: usize = 100;
 
 /// Parses a request into an Incoming message head.
 #[inline]
 pub fn parse_request<R: Read>(buf: &mut BufReader<R>) -> crate::Result<Incoming<(Method, RequestUri)>> {
-    parse::<R, httparse::Request, (Method, RequestUri)>(buf)
+    parse_request_limited(buf, MAX_HEADERS, crate::buffer::MAX_BUFFER_SIZE)
+}
+
+/// Parses a request into an Incoming message head, with at most `max_headers`
+/// headers in a head of at most `max_size` bytes, otherwise `Error::TooLarge`.
+///
+/// `max_size` can't go over `buffer::MAX_BUFFER_SIZE`.
+#[inline]
+pub fn parse_request_limited<R: Read>(buf: &mut BufReader<R>, max_headers: usize, max_size: usize)
+        -> crate::Result<Incoming<(Method, RequestUri)>> {
+    parse::<R, httparse::Request, (Method, RequestUri)>(buf, max_headers, max_size)
 }
 
 /// Parses a response into an Incoming message head.
 #[inline]
 pub fn parse_response<R: Read>(buf: &mut BufReader<R>) -> crate::Result<Incoming<RawStatus>> {
-    parse::<R, httparse::Response, RawStatus>(buf)
+    parse::<R, httparse::Response, RawStatus>(buf, MAX_HEADERS, crate::buffer::MAX_BUFFER_SIZE)
 }
 
-fn parse<R: Read, T: TryParse<Subject=I>, I>(rdr: &mut BufReader<R>) -> crate::Result<Incoming<I>> {
+fn parse<R: Read, T: TryParse<Subject=I>, I>(rdr: &mut BufReader<R>, max_headers: usize, max_size: usize)
+        -> crate::Result<Incoming<I>> {
     loop {
-        match try_parse::<R, T, I>(rdr)? {
+        match try_parse::<R, T, I>(rdr, max_headers)? {
+            httparse::Status::Complete((_, len)) if len > max_size => {
+                return Err(Error::TooLarge);
+            },
             httparse::Status::Complete((inc, len)) => {
                 rdr.consume(len);
                 return Ok(inc);
             },
             _partial => ()
         }
+        let buffered = rdr.get_buf().len();
+        if buffered >= max_size {
+            return Err(Error::TooLarge);
+        }
         let n = rdr.read_into_buf()?;
         if n == 0 {
-            let buffered = rdr.get_buf().len();
             if buffered == crate::buffer::MAX_BUFFER_SIZE {
                 return Err(Error::TooLarge);
             } else {
@@ -898,14 +962,19 @@ fn parse<R: Read, T: TryParse<Subject=I>, I>(rdr: &mut BufReader<R>) -> crate::R
     }
 }
 
-fn try_parse<R: Read, T: TryParse<Subject=I>, I>(rdr: &mut BufReader<R>) -> TryParseResult<I> {
-    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+fn try_parse<R: Read, T: TryParse<Subject=I>, I>(rdr: &mut BufReader<R>, max_headers: usize) -> TryParseResult<I> {
     let buf = rdr.get_buf();
     if buf.len() == 0 {
         return Ok(httparse::Status::Partial);
     }
     trace!("try_parse({:?})", buf);
-    <T as TryParse>::try_parse(&mut headers, buf)
+    if max_headers <= MAX_HEADERS {
+        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        <T as TryParse>::try_parse(&mut headers[..max_headers], buf)
+    } else {
+        let mut headers = vec![httparse::EMPTY_HEADER; max_headers];
+        <T as TryParse>::try_parse(&mut headers, buf)
+    }
 }
 
 #[doc(hidden)]
@@ -990,11 +1059,11 @@ mod tests {
     use std::io::{self, Read, Write};
 
 
-    use crate::buffer::BufReader;
+    use crate::buffer::{BufReader, MAX_BUFFER_SIZE};
     use crate::mock::MockStream;
     use crate::http::HttpMessage;
 
-    use super::{read_chunk_size, parse_request, parse_response, Http11Message};
+    use super::{read_chunk_size, parse_request, parse_request_limited, parse_response, Http11Message};
 
     #[test]
     fn test_write_chunked() {
@@ -1059,6 +1128,16 @@ mod tests {
         read_err("1 invalid extension\r\n");
         read_err("1 A\r\n");
         read_err("1;no CRLF");
+        read_err("1;bare\nLF\r\n");
+        read_err("1;nul\0\r\n");
+        // The size must come first
+        read_err("\r\n");
+        read_err(";ext\r\n");
+        read_err(" 1\r\n");
+        // Sizes over u64::MAX and overly long lines
+        read("00000000000000000001\r\n", 1);
+        read_err("10000000000000000\r\n");
+        read_err(&format!("1;{}\r\n", "a".repeat(super::MAX_CHUNK_SIZE_LINE)));
     }
 
     #[test]
@@ -1085,6 +1164,44 @@ mod tests {
         assert_eq!(e.description(), "early eof");
     }
 
+    #[test]
+    fn test_read_limited() {
+        let chunked = || super::HttpReader::ChunkedReader(MockStream::with_input(b"\
+            3\r\n\
+            foo\r\n\
+            4\r\n\
+            _bar\r\n\
+            0\r\n\r\n\
+        "), None);
+
+        let mut s = String::new();
+        chunked().limit(7).read_to_string(&mut s).unwrap();
+        assert_eq!(s, "foo_bar");
+
+        let e = chunked().limit(6).read_to_string(&mut String::new()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(matches!(crate::Error::from(e), crate::Error::BodyTooLarge));
+
+        let mut r = super::HttpReader::SizedReader(MockStream::with_input(b"foo bar"), 7).limit(10).limit(6);
+        let mut buf = [0u8; 10];
+        assert!(matches!(crate::Error::from(r.read(&mut buf).unwrap_err()), crate::Error::BodyTooLarge));
+    }
+
+    #[test]
+    fn test_parse_request_limited() {
+        let head = b"GET /path HTTP/1.1\r\nHost: example.domain\r\nAccept: */*\r\n\r\n";
+
+        let mut buf = BufReader::new(MockStream::with_input(head));
+        assert!(matches!(parse_request_limited(&mut buf, 1, MAX_BUFFER_SIZE), Err(crate::Error::TooLarge)));
+
+        let mut buf = BufReader::new(MockStream::with_input(head));
+        assert!(matches!(parse_request_limited(&mut buf, 2, 32), Err(crate::Error::TooLarge)));
+
+        let mut buf = BufReader::new(MockStream::with_input(head));
+        let raw = parse_request_limited(&mut buf, 200, head.len()).unwrap();
+        assert_eq!(raw.headers.len(), 2);
+    }
+
     #[test]
     fn test_read_sized_zero_len_buf() {
         let mut r = super::HttpReader::SizedReader(MockStream::with_input(b"foo bar"), 7);
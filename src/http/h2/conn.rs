@@ -0,0 +1,996 @@
+//! An HTTP/2 connection, shared by its streams and the coroutine reading it.
+//!
+//! The `Reader` handles every frame the peer sends, and hands the headers and
+//! data of each stream to its `RecvStream` over a channel. A `SendStream` writes
+//! the frames of its stream, waiting for the peer to open flow-control windows.
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::net::NetworkStream;
+use crate::runtime::{self, Mutex, MutexGuard, Receiver, Sender};
+
+use super::frame::{self, Frame, FrameReader, ReadError};
+use super::frame::{ACK, CONTINUATION, DATA, END_HEADERS, END_STREAM, GOAWAY, HEADERS, PING,
+                   PRIORITY, PUSH_PROMISE, RST_STREAM, SETTINGS, WINDOW_UPDATE};
+use super::frame::{CANCEL, COMPRESSION_ERROR, ENHANCE_YOUR_CALM, FLOW_CONTROL_ERROR, FRAME_SIZE_ERROR,
+                   NO_ERROR, PROTOCOL_ERROR, REFUSED_STREAM, STREAM_CLOSED};
+use super::hpack::{Decoder, Encoder, Field};
+
+/// The flow-control window we give the peer for each stream.
+pub(crate) const RECV_WINDOW: i64 = 1 << 20;
+
+/// The streams a client may reset in a second before the connection is closed.
+/// Opening and resetting streams costs the client nothing but starts a handler
+/// each time, see CVE-2023-44487.
+const MAX_RESETS_PER_SECOND: u32 = 2 * super::MAX_CONCURRENT_STREAMS;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Role {
+    Client,
+    Server,
+}
+
+/// What the `Reader` hands to a stream.
+#[derive(Debug)]
+pub(crate) enum Event {
+    Headers(Vec<Field>),
+    Data(Vec<u8>),
+    End,
+    Reset(u32),
+}
+
+pub(crate) struct Conn {
+    role: Role,
+    peer_addr: Option<SocketAddr>,
+    writer: Mutex<Writer>,
+    state: Mutex<State>,
+}
+
+struct Writer {
+    stream: Box<dyn NetworkStream + Send>,
+    encoder: Encoder,
+}
+
+// the writer is always locked before the state, when both are
+struct State {
+    streams: HashMap<u32, StreamState>,
+    /// The window of the connection for what we send.
+    send_window: i64,
+    /// The window of new streams for what we send, the peer's `SETTINGS_INITIAL_WINDOW_SIZE`.
+    initial_window: i64,
+    /// The largest frame the peer accepts.
+    max_frame: usize,
+    /// The most streams of ours the peer accepts at once.
+    max_streams: usize,
+    /// The identifier of the next stream we open.
+    next_stream: u32,
+    /// The last stream the peer opened.
+    last_peer_stream: u32,
+    /// No more streams may be opened.
+    going_away: bool,
+    /// The connection is gone, with this error code.
+    closed: Option<u32>,
+    /// Close the connection once it has no streams left.
+    close_when_idle: bool,
+}
+
+struct StreamState {
+    events: Sender<Event>,
+    wake: Sender<()>,
+    send_window: i64,
+    recv_window: i64,
+    local_end: bool,
+    remote_end: bool,
+}
+
+impl Conn {
+    pub(crate) fn new(role: Role, mut stream: Box<dyn NetworkStream + Send>) -> Arc<Conn> {
+        Arc::new(Conn {
+            role,
+            peer_addr: stream.peer_addr().ok(),
+            writer: Mutex::new(Writer { stream, encoder: Encoder }),
+            state: Mutex::new(State {
+                streams: HashMap::new(),
+                send_window: frame::DEFAULT_WINDOW_SIZE,
+                initial_window: frame::DEFAULT_WINDOW_SIZE,
+                max_frame: frame::DEFAULT_MAX_FRAME_SIZE,
+                max_streams: usize::MAX,
+                next_stream: 1,
+                last_peer_stream: 0,
+                going_away: false,
+                closed: None,
+                close_when_idle: false,
+            }),
+        })
+    }
+
+    fn lock_writer(&self) -> MutexGuard<'_, Writer> {
+        self.writer.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn lock_state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Send our `SETTINGS`, after the connection preface for a client.
+    pub(crate) fn handshake(&self, settings: &[(u16, u32)]) -> io::Result<()> {
+        let mut w = self.lock_writer();
+        if self.role == Role::Client {
+            w.stream.write_all(super::PREFACE)?;
+        }
+        frame::write_frame(&mut w.stream, SETTINGS, 0, 0, &frame::settings_payload(settings))?;
+        w.stream.flush()
+    }
+
+    pub(crate) fn write_frame(&self, kind: u8, flags: u8, stream: u32, payload: &[u8]) -> io::Result<()> {
+        let mut w = self.lock_writer();
+        frame::write_frame(&mut w.stream, kind, flags, stream, payload)?;
+        w.stream.flush()
+    }
+
+    pub(crate) fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.peer_addr.ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "no peer address"))
+    }
+
+    /// Whether a new stream of ours can be opened.
+    pub(crate) fn can_open(&self) -> bool {
+        let state = self.lock_state();
+        state.closed.is_none() && !state.going_away && state.streams.len() < state.max_streams &&
+            state.next_stream < (1 << 31)
+    }
+
+    pub(crate) fn is_going_away(&self) -> bool {
+        let state = self.lock_state();
+        state.going_away || state.closed.is_some()
+    }
+
+    /// Open a stream of ours, sending its `HEADERS`.
+    pub(crate) fn open_stream(self: &Arc<Self>, fields: &[Field], end: bool) -> io::Result<(RecvStream, SendStream)> {
+        // the identifiers have to be sent in order
+        let mut w = self.lock_writer();
+        let (id, halves) = {
+            let mut state = self.lock_state();
+            if let Some(code) = state.closed {
+                return Err(conn_error(code));
+            }
+            if state.going_away {
+                return Err(io::Error::new(io::ErrorKind::ConnectionAborted, "connection is going away"));
+            }
+            let id = state.next_stream;
+            state.next_stream += 2;
+            (id, self.insert(&mut state, id, end, false))
+        };
+        self.write_headers(&mut w, id, fields, end, self.lock_state().max_frame)?;
+        Ok(halves)
+    }
+
+    /// Register a stream the peer opened.
+    fn accept_stream(self: &Arc<Self>, state: &mut State, id: u32, end: bool) -> (RecvStream, SendStream) {
+        let halves = self.insert(state, id, false, end);
+        if end {
+            let _ = state.streams[&id].events.send(Event::End);
+        }
+        halves
+    }
+
+    fn insert(self: &Arc<Self>, state: &mut State, id: u32, local_end: bool, remote_end: bool) -> (RecvStream, SendStream) {
+        let (events_tx, events) = runtime::chan();
+        let (wake_tx, wake) = runtime::chan();
+        state.streams.insert(id, StreamState {
+            events: events_tx,
+            wake: wake_tx,
+            send_window: state.initial_window,
+            recv_window: RECV_WINDOW,
+            local_end,
+            remote_end,
+        });
+        let recv = RecvStream {
+            conn: self.clone(),
+            id,
+            events,
+            peeked: None,
+            chunk: Vec::new(),
+            pos: 0,
+            ended: false,
+            reset: None,
+            unacked: 0,
+            timeout: Cell::new(None),
+            transport: None,
+        };
+        let send = SendStream {
+            conn: self.clone(),
+            id,
+            wake,
+            ended: local_end,
+            timeout: None,
+        };
+        (recv, send)
+    }
+
+    fn write_headers(&self, w: &mut Writer, id: u32, fields: &[Field], end: bool, max_frame: usize) -> io::Result<()> {
+        let mut block = Vec::new();
+        w.encoder.encode(fields.iter().map(|f| (&f.0[..], &f.1[..])), &mut block);
+        let mut chunks = block.chunks(max_frame).peekable();
+        let mut kind = HEADERS;
+        let mut flags = if end { END_STREAM } else { 0 };
+        if chunks.peek().is_none() {
+            frame::write_frame(&mut w.stream, kind, flags | END_HEADERS, id, &[])?;
+        }
+        while let Some(chunk) = chunks.next() {
+            if chunks.peek().is_none() {
+                flags |= END_HEADERS;
+            }
+            frame::write_frame(&mut w.stream, kind, flags, id, chunk)?;
+            kind = CONTINUATION;
+            flags = 0;
+        }
+        w.stream.flush()
+    }
+
+    fn send_headers(&self, id: u32, fields: &[Field], end: bool) -> io::Result<()> {
+        let mut w = self.lock_writer();
+        let max_frame = {
+            let state = self.lock_state();
+            if !state.streams.contains_key(&id) {
+                return Err(state.closed.map_or_else(stream_reset, conn_error));
+            }
+            state.max_frame
+        };
+        self.write_headers(&mut w, id, fields, end, max_frame)?;
+        drop(w);
+        if end {
+            self.end_local(id);
+        }
+        Ok(())
+    }
+
+    fn send_data(&self, id: u32, mut data: &[u8], end: bool, wake: &Receiver<()>, timeout: Option<Duration>) -> io::Result<()> {
+        loop {
+            let n = {
+                let mut guard = self.lock_state();
+                let state = &mut *guard;
+                let stream = match state.streams.get_mut(&id) {
+                    Some(s) => s,
+                    None => return Err(state.closed.map_or_else(stream_reset, conn_error)),
+                };
+                let window = stream.send_window.min(state.send_window).max(0) as usize;
+                let n = data.len().min(window).min(state.max_frame);
+                stream.send_window -= n as i64;
+                state.send_window -= n as i64;
+                n
+            };
+            if n > 0 || data.is_empty() {
+                let last = end && n == data.len();
+                self.write_frame(DATA, if last { END_STREAM } else { 0 }, id, &data[..n])?;
+                data = &data[n..];
+                if data.is_empty() {
+                    if end {
+                        self.end_local(id);
+                    }
+                    return Ok(());
+                }
+                continue;
+            }
+            // the peer has to open the window first
+            recv(wake, timeout)?;
+        }
+    }
+
+    fn end_local(&self, id: u32) {
+        let mut state = self.lock_state();
+        if let Some(s) = state.streams.get_mut(&id) {
+            s.local_end = true;
+            if s.remote_end {
+                self.remove(state, id);
+            }
+        }
+    }
+
+    /// Forget a stream, closing the connection if that was asked for once idle.
+    fn remove(&self, mut state: MutexGuard<'_, State>, id: u32) -> Option<StreamState> {
+        let stream = state.streams.remove(&id);
+        let close = state.close_when_idle && state.streams.is_empty();
+        drop(state);
+        if close {
+            self.close();
+        }
+        stream
+    }
+
+    /// Reset a stream, if it's still open.
+    pub(crate) fn reset(&self, id: u32, code: u32) {
+        let state = self.lock_state();
+        if !state.streams.contains_key(&id) || state.closed.is_some() {
+            return;
+        }
+        if let Some(s) = self.remove(state, id) {
+            let _ = s.events.send(Event::Reset(code));
+            let _ = s.wake.send(());
+        }
+        if let Err(e) = self.write_frame(RST_STREAM, 0, id, &code.to_be_bytes()) {
+            debug!("error resetting stream {}: {:?}", id, e);
+        }
+    }
+
+    /// Let the peer send `n` more bytes on a stream.
+    fn release(&self, id: u32, n: i64) {
+        {
+            let mut state = self.lock_state();
+            match state.streams.get_mut(&id) {
+                Some(s) if !s.remote_end => s.recv_window += n,
+                _ => return,
+            }
+        }
+        if let Err(e) = self.write_frame(WINDOW_UPDATE, 0, id, &(n as u32).to_be_bytes()) {
+            debug!("error updating the window of stream {}: {:?}", id, e);
+        }
+    }
+
+    /// Tell the peer no more streams are accepted, the ones it opened so far still are.
+    pub(crate) fn go_away(&self, code: u32) {
+        let last = {
+            let mut state = self.lock_state();
+            if state.closed.is_some() {
+                return;
+            }
+            state.going_away = true;
+            state.last_peer_stream
+        };
+        if let Err(e) = self.write_frame(GOAWAY, 0, 0, &frame::goaway_payload(last, code)) {
+            debug!("error sending GOAWAY: {:?}", e);
+        }
+    }
+
+    /// Close the connection once its streams are done.
+    pub(crate) fn close_when_idle(&self) {
+        let idle = {
+            let mut state = self.lock_state();
+            state.close_when_idle = true;
+            state.streams.is_empty()
+        };
+        if idle {
+            self.close();
+        }
+    }
+
+    /// Close the transport, the `Reader` then stops.
+    pub(crate) fn close(&self) {
+        if let Err(e) = self.lock_writer().stream.close(Shutdown::Both) {
+            debug!("error closing connection: {:?}", e);
+        }
+    }
+
+    /// The connection is gone, fail its streams with `code`.
+    pub(crate) fn shutdown(&self, code: u32) {
+        let mut state = self.lock_state();
+        state.going_away = true;
+        if state.closed.is_none() {
+            state.closed = Some(code);
+        }
+        for (_, s) in state.streams.drain() {
+            let _ = s.events.send(Event::Reset(code));
+            let _ = s.wake.send(());
+        }
+    }
+}
+
+impl fmt::Debug for Conn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Conn")
+            .field("role", &self.role)
+            .field("peer_addr", &self.peer_addr)
+            .finish()
+    }
+}
+
+fn recv<T>(rx: &Receiver<T>, timeout: Option<Duration>) -> io::Result<T> {
+    match timeout {
+        Some(timeout) => rx.recv_timeout(timeout)
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "stream timed out")),
+        None => rx.recv().map_err(|_| conn_error(CANCEL)),
+    }
+}
+
+fn stream_reset() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionReset, "stream was reset")
+}
+
+pub(crate) fn reset_error(code: u32) -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionReset, format!("stream was reset: {}", error_name(code)))
+}
+
+fn conn_error(code: u32) -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionAborted, format!("connection closed: {}", error_name(code)))
+}
+
+fn error_name(code: u32) -> &'static str {
+    match code {
+        NO_ERROR => "NO_ERROR",
+        PROTOCOL_ERROR => "PROTOCOL_ERROR",
+        frame::INTERNAL_ERROR => "INTERNAL_ERROR",
+        FLOW_CONTROL_ERROR => "FLOW_CONTROL_ERROR",
+        STREAM_CLOSED => "STREAM_CLOSED",
+        FRAME_SIZE_ERROR => "FRAME_SIZE_ERROR",
+        REFUSED_STREAM => "REFUSED_STREAM",
+        CANCEL => "CANCEL",
+        COMPRESSION_ERROR => "COMPRESSION_ERROR",
+        ENHANCE_YOUR_CALM => "ENHANCE_YOUR_CALM",
+        _ => "unknown error",
+    }
+}
+
+/// The receiving side of a stream: its headers, then its data.
+pub(crate) struct RecvStream {
+    conn: Arc<Conn>,
+    id: u32,
+    events: Receiver<Event>,
+    peeked: Option<Event>,
+    chunk: Vec<u8>,
+    pos: usize,
+    ended: bool,
+    reset: Option<u32>,
+    /// Data read since the window was last opened.
+    unacked: i64,
+    timeout: Cell<Option<Duration>>,
+    /// A clone of the stream the connection is read from.
+    transport: Option<Box<dyn NetworkStream + Send>>,
+}
+
+impl RecvStream {
+    pub(crate) fn set_transport(&mut self, transport: Box<dyn NetworkStream + Send>) {
+        self.transport = Some(transport);
+    }
+
+    /// The stream of the connection, such as a TLS stream.
+    pub(crate) fn transport(&self) -> Option<&(dyn NetworkStream + Send)> {
+        self.transport.as_deref()
+    }
+
+    fn next_event(&mut self) -> io::Result<Event> {
+        match self.peeked.take() {
+            Some(event) => Ok(event),
+            None => recv(&self.events, self.timeout.get()),
+        }
+    }
+
+    /// Whether all the data was read.
+    pub(crate) fn is_ended(&self) -> bool {
+        self.ended && self.pos == self.chunk.len()
+    }
+
+    /// Wait for the headers of the stream.
+    pub(crate) fn recv_headers(&mut self) -> io::Result<Vec<Field>> {
+        match self.next_event()? {
+            Event::Headers(fields) => {
+                // the end of a stream without a body comes right along
+                match self.events.try_recv() {
+                    Ok(Event::End) => self.ended = true,
+                    Ok(event) => self.peeked = Some(event),
+                    Err(_) => {}
+                }
+                Ok(fields)
+            }
+            Event::Reset(code) => {
+                self.reset = Some(code);
+                Err(reset_error(code))
+            }
+            event => {
+                debug!("stream {} got {:?} before its headers", self.id, event);
+                self.conn.reset(self.id, PROTOCOL_ERROR);
+                self.reset = Some(PROTOCOL_ERROR);
+                Err(reset_error(PROTOCOL_ERROR))
+            }
+        }
+    }
+}
+
+impl Read for RecvStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.pos < self.chunk.len() {
+                let n = buf.len().min(self.chunk.len() - self.pos);
+                buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+                self.pos += n;
+                self.unacked += n as i64;
+                if self.unacked >= RECV_WINDOW / 2 && !self.ended {
+                    self.conn.release(self.id, self.unacked);
+                    self.unacked = 0;
+                }
+                return Ok(n);
+            }
+            if self.ended || buf.is_empty() {
+                return Ok(0);
+            }
+            if let Some(code) = self.reset {
+                return Err(reset_error(code));
+            }
+            match self.next_event()? {
+                Event::Data(data) => {
+                    self.chunk = data;
+                    self.pos = 0;
+                }
+                // trailers
+                Event::Headers(_) => {}
+                Event::End => self.ended = true,
+                Event::Reset(code) => self.reset = Some(code),
+            }
+        }
+    }
+}
+
+impl Write for RecvStream {
+    fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "the receiving side of a stream"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl NetworkStream for RecvStream {
+    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+        self.conn.peer_addr()
+    }
+
+    fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        self.timeout.set(dur);
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, _dur: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The sending side of a stream.
+pub(crate) struct SendStream {
+    conn: Arc<Conn>,
+    id: u32,
+    wake: Receiver<()>,
+    ended: bool,
+    timeout: Option<Duration>,
+}
+
+impl SendStream {
+    pub(crate) fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub(crate) fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    pub(crate) fn is_ended(&self) -> bool {
+        self.ended
+    }
+
+    pub(crate) fn send_headers(&mut self, fields: &[Field], end: bool) -> io::Result<()> {
+        self.conn.send_headers(self.id, fields, end)?;
+        self.ended |= end;
+        Ok(())
+    }
+
+    pub(crate) fn send_data(&mut self, data: &[u8], end: bool) -> io::Result<()> {
+        self.conn.send_data(self.id, data, end, &self.wake, self.timeout)?;
+        self.ended |= end;
+        Ok(())
+    }
+
+    /// End the stream, and reset it if the peer is still sending, as the data
+    /// won't be read.
+    pub(crate) fn finish(&mut self) -> io::Result<()> {
+        if !self.ended {
+            self.send_data(&[], true)?;
+        }
+        self.conn.reset(self.id, NO_ERROR);
+        Ok(())
+    }
+
+    pub(crate) fn reset(&mut self, code: u32) {
+        self.ended = true;
+        self.conn.reset(self.id, code);
+    }
+}
+
+/// Reads the frames of a connection, and acts on them.
+pub(crate) struct Reader {
+    conn: Arc<Conn>,
+    frames: FrameReader,
+    decoder: Decoder,
+    /// The header block of a stream waiting for `CONTINUATION` frames, and
+    /// whether the stream ends with it.
+    pending: Option<(u32, bool, Vec<u8>)>,
+    max_header_list: usize,
+    got_settings: bool,
+    /// The streams the peer reset since `resets_since`.
+    resets: u32,
+    resets_since: Instant,
+}
+
+/// A stream the peer opened, with its headers.
+pub(crate) struct Incoming {
+    pub(crate) fields: Vec<Field>,
+    /// The stream has no data.
+    pub(crate) end: bool,
+    pub(crate) recv: RecvStream,
+    pub(crate) send: SendStream,
+}
+
+impl Reader {
+    pub(crate) fn new(conn: Arc<Conn>, max_header_list: usize) -> Reader {
+        Reader {
+            conn,
+            frames: FrameReader::new(frame::DEFAULT_MAX_FRAME_SIZE),
+            decoder: Decoder::new(),
+            pending: None,
+            max_header_list,
+            got_settings: false,
+            resets: 0,
+            resets_since: Instant::now(),
+        }
+    }
+
+    /// Whether a frame was partly read.
+    pub(crate) fn is_reading(&self) -> bool {
+        !self.frames.is_empty() || self.pending.is_some()
+    }
+
+    /// Read frames until the peer opens a stream, `None` once the connection is
+    /// gone. Only a read timing out is an error, reading may go on after it.
+    pub(crate) fn next<R: Read + ?Sized>(&mut self, r: &mut R) -> io::Result<Option<Incoming>> {
+        loop {
+            let frame = match self.frames.read(r) {
+                Ok(frame) => frame,
+                Err(ReadError::Io(e)) => {
+                    if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut {
+                        return Err(e);
+                    }
+                    debug!("error reading HTTP/2 connection: {:?}", e);
+                    self.conn.shutdown(CANCEL);
+                    return Ok(None);
+                }
+                Err(ReadError::Eof(between_frames)) => {
+                    debug!("HTTP/2 connection closed, between frames: {}", between_frames);
+                    self.conn.shutdown(CANCEL);
+                    return Ok(None);
+                }
+                Err(ReadError::TooLarge) => {
+                    self.fail(FRAME_SIZE_ERROR);
+                    return Ok(None);
+                }
+            };
+            match self.handle(frame) {
+                Ok(Some(incoming)) => return Ok(Some(incoming)),
+                Ok(None) => {}
+                Err(code) => {
+                    self.fail(code);
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
+    /// A connection error: tell the peer, and close the connection.
+    fn fail(&mut self, code: u32) {
+        debug!("HTTP/2 connection error: {}", error_name(code));
+        self.conn.go_away(code);
+        self.conn.shutdown(code);
+        self.conn.close();
+    }
+
+    fn handle(&mut self, frame: Frame) -> Result<Option<Incoming>, u32> {
+        trace!("frame type={} flags={:#x} stream={} len={}", frame.kind, frame.flags, frame.stream, frame.payload.len());
+        if !self.got_settings && (frame.kind != SETTINGS || frame.has(ACK)) {
+            return Err(PROTOCOL_ERROR);
+        }
+        if let Some((id, ..)) = self.pending {
+            if frame.kind != CONTINUATION || frame.stream != id {
+                return Err(PROTOCOL_ERROR);
+            }
+        }
+        match frame.kind {
+            DATA => self.on_data(frame),
+            HEADERS => {
+                if frame.stream == 0 {
+                    return Err(PROTOCOL_ERROR);
+                }
+                let block = frame.data()?.to_vec();
+                self.on_header_block(frame.stream, frame.has(END_STREAM), frame.has(END_HEADERS), block)
+            }
+            CONTINUATION => {
+                let (id, end, mut block) = self.pending.take().ok_or(PROTOCOL_ERROR)?;
+                block.extend_from_slice(&frame.payload);
+                self.on_header_block(id, end, frame.has(END_HEADERS), block)
+            }
+            PRIORITY => {
+                if frame.payload.len() != 5 {
+                    return Err(FRAME_SIZE_ERROR);
+                }
+                Ok(None)
+            }
+            RST_STREAM => {
+                if frame.stream == 0 {
+                    return Err(PROTOCOL_ERROR);
+                }
+                if frame.payload.len() != 4 {
+                    return Err(FRAME_SIZE_ERROR);
+                }
+                let code = frame::read_u32(&frame.payload);
+                let state = self.conn.lock_state();
+                if self.is_idle(&state, frame.stream) {
+                    return Err(PROTOCOL_ERROR);
+                }
+                if let Some(s) = self.conn.remove(state, frame.stream) {
+                    let _ = s.events.send(Event::Reset(code));
+                    let _ = s.wake.send(());
+                }
+                self.count_reset()?;
+                Ok(None)
+            }
+            SETTINGS => self.on_settings(frame),
+            // we never enable push
+            PUSH_PROMISE => Err(PROTOCOL_ERROR),
+            PING => {
+                if frame.stream != 0 {
+                    return Err(PROTOCOL_ERROR);
+                }
+                if frame.payload.len() != 8 {
+                    return Err(FRAME_SIZE_ERROR);
+                }
+                if !frame.has(ACK) {
+                    self.write(PING, ACK, 0, &frame.payload);
+                }
+                Ok(None)
+            }
+            GOAWAY => {
+                if frame.stream != 0 {
+                    return Err(PROTOCOL_ERROR);
+                }
+                if frame.payload.len() < 8 {
+                    return Err(FRAME_SIZE_ERROR);
+                }
+                let last = frame::read_u32(&frame.payload) & 0x7fff_ffff;
+                debug!("GOAWAY last_stream={} code={}", last, error_name(frame::read_u32(&frame.payload[4..])));
+                let mut state = self.conn.lock_state();
+                state.going_away = true;
+                // our streams after the last one weren't processed, and can be retried
+                let role = self.conn.role;
+                let refused: Vec<u32> = state.streams.keys()
+                    .copied()
+                    .filter(|&id| id > last && is_ours(role, id))
+                    .collect();
+                for id in refused {
+                    if let Some(s) = state.streams.remove(&id) {
+                        let _ = s.events.send(Event::Reset(REFUSED_STREAM));
+                        let _ = s.wake.send(());
+                    }
+                }
+                Ok(None)
+            }
+            WINDOW_UPDATE => {
+                if frame.payload.len() != 4 {
+                    return Err(FRAME_SIZE_ERROR);
+                }
+                let increment = i64::from(frame::read_u32(&frame.payload) & 0x7fff_ffff);
+                self.on_window_update(frame.stream, increment)
+            }
+            // unknown frames are ignored
+            _ => Ok(None),
+        }
+    }
+
+    /// Count a stream the peer reset, failing once a client resets too many.
+    fn count_reset(&mut self) -> Result<(), u32> {
+        if self.conn.role == Role::Client {
+            return Ok(());
+        }
+        if self.resets_since.elapsed() >= Duration::from_secs(1) {
+            self.resets = 0;
+            self.resets_since = Instant::now();
+        }
+        self.resets += 1;
+        if self.resets > MAX_RESETS_PER_SECOND {
+            debug!("HTTP/2 client reset more than {} streams in a second", MAX_RESETS_PER_SECOND);
+            return Err(ENHANCE_YOUR_CALM);
+        }
+        Ok(())
+    }
+
+    fn write(&self, kind: u8, flags: u8, stream: u32, payload: &[u8]) {
+        if let Err(e) = self.conn.write_frame(kind, flags, stream, payload) {
+            debug!("error writing frame: {:?}", e);
+        }
+    }
+
+    /// A stream that was never opened.
+    fn is_idle(&self, state: &State, id: u32) -> bool {
+        if is_ours(self.conn.role, id) {
+            id >= state.next_stream
+        } else {
+            id > state.last_peer_stream
+        }
+    }
+
+    fn on_data(&mut self, frame: Frame) -> Result<Option<Incoming>, u32> {
+        let id = frame.stream;
+        if id == 0 {
+            return Err(PROTOCOL_ERROR);
+        }
+        let data = frame.data()?;
+        // padding counts against the windows too
+        let len = frame.payload.len() as i64;
+        // the connection window is opened right away, streams are limited by their own
+        if len > 0 {
+            self.write(WINDOW_UPDATE, 0, 0, &(len as u32).to_be_bytes());
+        }
+        let mut state = self.conn.lock_state();
+        if self.is_idle(&state, id) {
+            return Err(PROTOCOL_ERROR);
+        }
+        let code = match state.streams.get_mut(&id) {
+            Some(s) if !s.remote_end => {
+                s.recv_window -= len;
+                if s.recv_window < 0 {
+                    FLOW_CONTROL_ERROR
+                } else {
+                    if !data.is_empty() {
+                        let _ = s.events.send(Event::Data(data.to_vec()));
+                    }
+                    if frame.has(END_STREAM) {
+                        s.remote_end = true;
+                        let _ = s.events.send(Event::End);
+                        if s.local_end {
+                            self.conn.remove(state, id);
+                        }
+                    }
+                    return Ok(None);
+                }
+            }
+            Some(_) => STREAM_CLOSED,
+            // it was reset
+            None => return Ok(None),
+        };
+        drop(state);
+        self.conn.reset(id, code);
+        Ok(None)
+    }
+
+    fn on_header_block(&mut self, id: u32, end: bool, end_headers: bool, block: Vec<u8>) -> Result<Option<Incoming>, u32> {
+        if !end_headers {
+            if block.len() > self.max_header_list {
+                return Err(PROTOCOL_ERROR);
+            }
+            self.pending = Some((id, end, block));
+            return Ok(None);
+        }
+        // the table has to be updated, even for a stream that is gone
+        let fields = self.decoder.decode(&block, self.max_header_list).map_err(|e| {
+            debug!("header block of stream {}: {}", id, e);
+            COMPRESSION_ERROR
+        })?;
+        let mut state = self.conn.lock_state();
+        if let Some(s) = state.streams.get_mut(&id) {
+            if s.remote_end {
+                drop(state);
+                self.conn.reset(id, STREAM_CLOSED);
+                return Ok(None);
+            }
+            let _ = s.events.send(Event::Headers(fields));
+            if end {
+                s.remote_end = true;
+                let _ = s.events.send(Event::End);
+                if s.local_end {
+                    self.conn.remove(state, id);
+                }
+            }
+            return Ok(None);
+        }
+        if self.conn.role == Role::Client || !self.is_idle(&state, id) || is_ours(self.conn.role, id) {
+            // a stream that was reset, or a stream we can't accept
+            return if self.is_idle(&state, id) { Err(PROTOCOL_ERROR) } else { Ok(None) };
+        }
+        state.last_peer_stream = id;
+        if state.going_away || state.streams.len() >= super::MAX_CONCURRENT_STREAMS as usize {
+            drop(state);
+            self.write(RST_STREAM, 0, id, &REFUSED_STREAM.to_be_bytes());
+            return Ok(None);
+        }
+        let (recv, send) = self.conn.accept_stream(&mut state, id, end);
+        Ok(Some(Incoming { fields, end, recv, send }))
+    }
+
+    fn on_settings(&mut self, frame: Frame) -> Result<Option<Incoming>, u32> {
+        if frame.stream != 0 {
+            return Err(PROTOCOL_ERROR);
+        }
+        if frame.has(ACK) {
+            return if frame.payload.is_empty() { Ok(None) } else { Err(FRAME_SIZE_ERROR) };
+        }
+        if !frame.payload.len().is_multiple_of(6) {
+            return Err(FRAME_SIZE_ERROR);
+        }
+        self.got_settings = true;
+        {
+            let mut guard = self.conn.lock_state();
+            let state = &mut *guard;
+            for (id, value) in frame.settings() {
+                match id {
+                    frame::SETTINGS_ENABLE_PUSH if value > 1 => return Err(PROTOCOL_ERROR),
+                    frame::SETTINGS_MAX_CONCURRENT_STREAMS => state.max_streams = value as usize,
+                    frame::SETTINGS_INITIAL_WINDOW_SIZE => {
+                        let value = i64::from(value);
+                        if value > frame::MAX_WINDOW_SIZE {
+                            return Err(FLOW_CONTROL_ERROR);
+                        }
+                        let delta = value - state.initial_window;
+                        state.initial_window = value;
+                        for s in state.streams.values_mut() {
+                            s.send_window += delta;
+                            if s.send_window > frame::MAX_WINDOW_SIZE {
+                                return Err(FLOW_CONTROL_ERROR);
+                            }
+                            let _ = s.wake.send(());
+                        }
+                    }
+                    frame::SETTINGS_MAX_FRAME_SIZE => {
+                        if !(frame::DEFAULT_MAX_FRAME_SIZE as u32..1 << 24).contains(&value) {
+                            return Err(PROTOCOL_ERROR);
+                        }
+                        state.max_frame = value as usize;
+                    }
+                    // the encoder doesn't use a dynamic table, and other settings don't matter
+                    _ => {}
+                }
+            }
+        }
+        self.write(SETTINGS, ACK, 0, &[]);
+        Ok(None)
+    }
+
+    fn on_window_update(&mut self, id: u32, increment: i64) -> Result<Option<Incoming>, u32> {
+        let mut guard = self.conn.lock_state();
+        let state = &mut *guard;
+        if id == 0 {
+            if increment == 0 {
+                return Err(PROTOCOL_ERROR);
+            }
+            state.send_window += increment;
+            if state.send_window > frame::MAX_WINDOW_SIZE {
+                return Err(FLOW_CONTROL_ERROR);
+            }
+            for s in state.streams.values() {
+                let _ = s.wake.send(());
+            }
+            return Ok(None);
+        }
+        if self.is_idle(state, id) {
+            return Err(PROTOCOL_ERROR);
+        }
+        let code = match state.streams.get_mut(&id) {
+            Some(_) if increment == 0 => PROTOCOL_ERROR,
+            Some(s) => {
+                s.send_window += increment;
+                if s.send_window <= frame::MAX_WINDOW_SIZE {
+                    let _ = s.wake.send(());
+                    return Ok(None);
+                }
+                FLOW_CONTROL_ERROR
+            }
+            None => return Ok(None),
+        };
+        drop(guard);
+        self.conn.reset(id, code);
+        Ok(None)
+    }
+}
+
+/// Clients open odd streams, servers even ones.
+fn is_ours(role: Role, id: u32) -> bool {
+    (id % 2 == 1) == (role == Role::Client)
+}
@@ -0,0 +1,249 @@
+//! HTTP/2 framing, RFC 9113 section 4 and 6.
+use std::io::{self, Read, Write};
+
+pub(crate) const DATA: u8 = 0x0;
+pub(crate) const HEADERS: u8 = 0x1;
+pub(crate) const PRIORITY: u8 = 0x2;
+pub(crate) const RST_STREAM: u8 = 0x3;
+pub(crate) const SETTINGS: u8 = 0x4;
+pub(crate) const PUSH_PROMISE: u8 = 0x5;
+pub(crate) const PING: u8 = 0x6;
+pub(crate) const GOAWAY: u8 = 0x7;
+pub(crate) const WINDOW_UPDATE: u8 = 0x8;
+pub(crate) const CONTINUATION: u8 = 0x9;
+
+pub(crate) const END_STREAM: u8 = 0x1;
+pub(crate) const ACK: u8 = 0x1;
+pub(crate) const END_HEADERS: u8 = 0x4;
+pub(crate) const PADDED: u8 = 0x8;
+pub(crate) const PRIORITY_FLAG: u8 = 0x20;
+
+pub(crate) const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+pub(crate) const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+pub(crate) const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+pub(crate) const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+pub(crate) const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+pub(crate) const NO_ERROR: u32 = 0x0;
+pub(crate) const PROTOCOL_ERROR: u32 = 0x1;
+pub(crate) const INTERNAL_ERROR: u32 = 0x2;
+pub(crate) const FLOW_CONTROL_ERROR: u32 = 0x3;
+pub(crate) const STREAM_CLOSED: u32 = 0x5;
+pub(crate) const FRAME_SIZE_ERROR: u32 = 0x6;
+pub(crate) const REFUSED_STREAM: u32 = 0x7;
+pub(crate) const CANCEL: u32 = 0x8;
+pub(crate) const COMPRESSION_ERROR: u32 = 0x9;
+pub(crate) const ENHANCE_YOUR_CALM: u32 = 0xb;
+
+/// The frame size every peer accepts, until it says otherwise.
+pub(crate) const DEFAULT_MAX_FRAME_SIZE: usize = 16_384;
+/// The flow-control window of a connection and its streams, until a peer says otherwise.
+pub(crate) const DEFAULT_WINDOW_SIZE: i64 = 65_535;
+/// The largest flow-control window.
+pub(crate) const MAX_WINDOW_SIZE: i64 = (1 << 31) - 1;
+
+const HEAD_LEN: usize = 9;
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Frame {
+    pub(crate) kind: u8,
+    pub(crate) flags: u8,
+    pub(crate) stream: u32,
+    pub(crate) payload: Vec<u8>,
+}
+
+impl Frame {
+    #[cfg(test)]
+    pub(crate) fn new(kind: u8, flags: u8, stream: u32, payload: Vec<u8>) -> Frame {
+        Frame { kind, flags, stream, payload }
+    }
+
+    pub(crate) fn has(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+
+    /// The payload without its padding, and without the priority of a `HEADERS` frame.
+    pub(crate) fn data(&self) -> Result<&[u8], u32> {
+        let mut data = &self.payload[..];
+        let mut pad = 0;
+        if self.has(PADDED) && (self.kind == DATA || self.kind == HEADERS || self.kind == PUSH_PROMISE) {
+            pad = *data.first().ok_or(FRAME_SIZE_ERROR)? as usize;
+            data = &data[1..];
+        }
+        if self.kind == HEADERS && self.has(PRIORITY_FLAG) {
+            data = data.get(5..).ok_or(FRAME_SIZE_ERROR)?;
+        }
+        if pad > data.len() {
+            return Err(PROTOCOL_ERROR);
+        }
+        Ok(&data[..data.len() - pad])
+    }
+
+    /// The identifier and value of each setting of a `SETTINGS` frame.
+    pub(crate) fn settings(&self) -> impl Iterator<Item = (u16, u32)> + '_ {
+        self.payload.chunks_exact(6).map(|s| (u16::from_be_bytes([s[0], s[1]]), read_u32(&s[2..])))
+    }
+
+    /// Write the frame, along with its header.
+    #[cfg(test)]
+    pub(crate) fn write_to<W: Write + ?Sized>(&self, w: &mut W) -> io::Result<()> {
+        write_frame(w, self.kind, self.flags, self.stream, &self.payload)
+    }
+}
+
+pub(crate) fn write_frame<W: Write + ?Sized>(w: &mut W, kind: u8, flags: u8, stream: u32, payload: &[u8]) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(HEAD_LEN + payload.len());
+    let len = payload.len() as u32;
+    buf.extend_from_slice(&len.to_be_bytes()[1..]);
+    buf.push(kind);
+    buf.push(flags);
+    buf.extend_from_slice(&(stream & 0x7fff_ffff).to_be_bytes());
+    buf.extend_from_slice(payload);
+    w.write_all(&buf)
+}
+
+pub(crate) fn settings_payload(settings: &[(u16, u32)]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(settings.len() * 6);
+    for &(id, value) in settings {
+        payload.extend_from_slice(&id.to_be_bytes());
+        payload.extend_from_slice(&value.to_be_bytes());
+    }
+    payload
+}
+
+pub(crate) fn goaway_payload(last_stream: u32, code: u32) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(8);
+    payload.extend_from_slice(&last_stream.to_be_bytes());
+    payload.extend_from_slice(&code.to_be_bytes());
+    payload
+}
+
+pub(crate) fn read_u32(b: &[u8]) -> u32 {
+    u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+}
+
+/// Reads frames off a stream, keeping a partly read frame when a read times out.
+pub(crate) struct FrameReader {
+    buf: Vec<u8>,
+    max_size: usize,
+}
+
+/// A frame that couldn't be read.
+#[derive(Debug)]
+pub(crate) enum ReadError {
+    Io(io::Error),
+    /// The peer closed the connection, `true` if it was between frames.
+    Eof(bool),
+    /// A frame larger than we accept, a `FRAME_SIZE_ERROR`.
+    TooLarge,
+}
+
+impl FrameReader {
+    pub(crate) fn new(max_size: usize) -> FrameReader {
+        FrameReader { buf: Vec::new(), max_size }
+    }
+
+    /// Whether part of a frame was read.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    pub(crate) fn read<R: Read + ?Sized>(&mut self, r: &mut R) -> Result<Frame, ReadError> {
+        self.fill(r, HEAD_LEN)?;
+        let len = read_u32(&[0, self.buf[0], self.buf[1], self.buf[2]]) as usize;
+        if len > self.max_size {
+            return Err(ReadError::TooLarge);
+        }
+        self.fill(r, HEAD_LEN + len)?;
+        let payload = self.buf.split_off(HEAD_LEN);
+        let frame = Frame {
+            kind: self.buf[3],
+            flags: self.buf[4],
+            stream: read_u32(&self.buf[5..9]) & 0x7fff_ffff,
+            payload,
+        };
+        self.buf.clear();
+        Ok(frame)
+    }
+
+    fn fill<R: Read + ?Sized>(&mut self, r: &mut R, len: usize) -> Result<(), ReadError> {
+        let mut chunk = [0; 4096];
+        while self.buf.len() < len {
+            let want = (len - self.buf.len()).min(chunk.len());
+            match r.read(&mut chunk[..want]) {
+                Ok(0) => return Err(ReadError::Eof(self.buf.is_empty())),
+                Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(ReadError::Io(e)),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+
+    /// Returns the bytes it was given one read at a time, then `WouldBlock`.
+    struct Chunks(Vec<Vec<u8>>);
+
+    impl Read for Chunks {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, "timed out"));
+            }
+            let mut chunk = self.0.remove(0);
+            let n = chunk.len().min(buf.len());
+            buf[..n].copy_from_slice(&chunk[..n]);
+            if n < chunk.len() {
+                self.0.insert(0, chunk.split_off(n));
+            }
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let frame = Frame::new(HEADERS, END_HEADERS | END_STREAM, 3, b"block".to_vec());
+        let mut out = Vec::new();
+        frame.write_to(&mut out).unwrap();
+        assert_eq!(&out[..9], &[0, 0, 5, 1, 5, 0, 0, 0, 3]);
+        let mut reader = FrameReader::new(DEFAULT_MAX_FRAME_SIZE);
+        assert_eq!(reader.read(&mut &out[..]).unwrap(), frame);
+    }
+
+    #[test]
+    fn test_partial_read_is_kept() {
+        let mut out = Vec::new();
+        write_frame(&mut out, DATA, 0, 1, b"hello").unwrap();
+        let mut reader = FrameReader::new(DEFAULT_MAX_FRAME_SIZE);
+        let mut input = Chunks(vec![out[..4].to_vec()]);
+        assert!(matches!(reader.read(&mut input), Err(ReadError::Io(_))));
+        assert!(!reader.is_empty());
+        let mut input = Chunks(vec![out[4..].to_vec()]);
+        assert_eq!(reader.read(&mut input).unwrap().payload, b"hello");
+        assert!(reader.is_empty());
+        assert!(matches!(reader.read(&mut &b""[..]), Err(ReadError::Eof(true))));
+    }
+
+    #[test]
+    fn test_too_large() {
+        let mut out = Vec::new();
+        write_frame(&mut out, DATA, 0, 1, &[0; 100]).unwrap();
+        let mut reader = FrameReader::new(50);
+        assert!(matches!(reader.read(&mut &out[..]), Err(ReadError::TooLarge)));
+    }
+
+    #[test]
+    fn test_padding_and_priority() {
+        let frame = Frame::new(HEADERS, PADDED | PRIORITY_FLAG, 1, b"\x02\0\0\0\x01\x10abc\0\0".to_vec());
+        assert_eq!(frame.data(), Ok(&b"abc"[..]));
+        let frame = Frame::new(DATA, PADDED, 1, b"\x05abc".to_vec());
+        assert_eq!(frame.data(), Err(PROTOCOL_ERROR));
+        let frame = Frame::new(SETTINGS, 0, 0, settings_payload(&[(SETTINGS_ENABLE_PUSH, 0), (SETTINGS_MAX_FRAME_SIZE, 1 << 20)]));
+        assert_eq!(frame.settings().collect::<Vec<_>>(), vec![(SETTINGS_ENABLE_PUSH, 0), (SETTINGS_MAX_FRAME_SIZE, 1 << 20)]);
+    }
+}
@@ -0,0 +1,389 @@
+//! HPACK header compression, RFC 7541.
+//!
+//! The `Decoder` keeps the dynamic table the peer fills. The `Encoder` never
+//! adds to a dynamic table, so it needs no state shared with the peer.
+use std::collections::VecDeque;
+use std::fmt;
+
+use super::huffman;
+
+/// A header field, lowercase name and value.
+pub(crate) type Field = (Vec<u8>, Vec<u8>);
+
+/// The default, and our, `SETTINGS_HEADER_TABLE_SIZE`.
+pub(crate) const DEFAULT_TABLE_SIZE: usize = 4096;
+
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// Every entry of the dynamic table takes this much more than its name and value.
+const ENTRY_OVERHEAD: usize = 32;
+
+/// A header block that can't be decoded, a `COMPRESSION_ERROR` of the connection.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct DecodeError(&'static str);
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+/// Decodes the header blocks of a connection, in the order they were sent.
+pub(crate) struct Decoder {
+    table: VecDeque<Field>,
+    size: usize,
+    max_size: usize,
+    /// The most `max_size` may be set to, our `SETTINGS_HEADER_TABLE_SIZE`.
+    limit: usize,
+}
+
+impl Decoder {
+    pub(crate) fn new() -> Decoder {
+        Decoder {
+            table: VecDeque::new(),
+            size: 0,
+            max_size: DEFAULT_TABLE_SIZE,
+            limit: DEFAULT_TABLE_SIZE,
+        }
+    }
+
+    /// Decode a whole header block, failing once the fields add up to more than
+    /// `max_list_size`, counted like `SETTINGS_MAX_HEADER_LIST_SIZE`.
+    pub(crate) fn decode(&mut self, mut block: &[u8], max_list_size: usize) -> Result<Vec<Field>, DecodeError> {
+        let mut fields = Vec::new();
+        let mut list_size = 0;
+        let mut first = true;
+        while let Some(&b) = block.first() {
+            let field = if b & 0x80 != 0 {
+                let index = decode_int(&mut block, 7)?;
+                self.get(index)?
+            } else if b & 0x40 != 0 {
+                let field = self.literal(&mut block, 6)?;
+                self.insert(field.clone());
+                field
+            } else if b & 0x20 != 0 {
+                // a size update may only start the block
+                if !first {
+                    return Err(DecodeError("table size update after a field"));
+                }
+                let size = decode_int(&mut block, 5)?;
+                if size > self.limit {
+                    return Err(DecodeError("table size update over the limit"));
+                }
+                self.max_size = size;
+                self.evict();
+                continue;
+            } else {
+                // without indexing, or never indexed
+                self.literal(&mut block, 4)?
+            };
+            first = false;
+            list_size += field.0.len() + field.1.len() + ENTRY_OVERHEAD;
+            if list_size > max_list_size {
+                return Err(DecodeError("header list too large"));
+            }
+            fields.push(field);
+        }
+        Ok(fields)
+    }
+
+    fn literal(&mut self, block: &mut &[u8], prefix: u8) -> Result<Field, DecodeError> {
+        let index = decode_int(block, prefix)?;
+        let name = if index == 0 {
+            decode_string(block)?
+        } else {
+            self.get(index)?.0
+        };
+        let value = decode_string(block)?;
+        Ok((name, value))
+    }
+
+    fn get(&self, index: usize) -> Result<Field, DecodeError> {
+        match index {
+            0 => Err(DecodeError("index 0")),
+            i if i <= STATIC_TABLE.len() => {
+                let (name, value) = STATIC_TABLE[i - 1];
+                Ok((name.as_bytes().to_vec(), value.as_bytes().to_vec()))
+            }
+            i => self.table.get(i - STATIC_TABLE.len() - 1).cloned().ok_or(DecodeError("index out of the table")),
+        }
+    }
+
+    fn insert(&mut self, field: Field) {
+        let size = field.0.len() + field.1.len() + ENTRY_OVERHEAD;
+        self.size += size;
+        self.table.push_front(field);
+        // an entry bigger than the table empties it
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.size > self.max_size {
+            match self.table.pop_back() {
+                Some(f) => self.size -= f.0.len() + f.1.len() + ENTRY_OVERHEAD,
+                None => break,
+            }
+        }
+    }
+}
+
+fn decode_int(block: &mut &[u8], prefix: u8) -> Result<usize, DecodeError> {
+    let max = (1usize << prefix) - 1;
+    let (&first, mut rest) = block.split_first().ok_or(DecodeError("truncated integer"))?;
+    let mut value = first as usize & max;
+    if value == max {
+        let mut shift = 0;
+        loop {
+            let (&b, tail) = rest.split_first().ok_or(DecodeError("truncated integer"))?;
+            rest = tail;
+            if shift > 28 {
+                return Err(DecodeError("integer overflow"));
+            }
+            value += ((b & 0x7f) as usize) << shift;
+            shift += 7;
+            if b & 0x80 == 0 {
+                break;
+            }
+        }
+    }
+    *block = rest;
+    Ok(value)
+}
+
+fn decode_string(block: &mut &[u8]) -> Result<Vec<u8>, DecodeError> {
+    let huffman = block.first().is_some_and(|b| b & 0x80 != 0);
+    let len = decode_int(block, 7)?;
+    if len > block.len() {
+        return Err(DecodeError("truncated string"));
+    }
+    let (s, rest) = block.split_at(len);
+    *block = rest;
+    if huffman {
+        let mut out = Vec::with_capacity(len * 8 / 5);
+        huffman::decode(s, &mut out).map_err(|_| DecodeError("invalid huffman string"))?;
+        Ok(out)
+    } else {
+        Ok(s.to_vec())
+    }
+}
+
+/// Encodes header blocks with the static table and literals, Huffman coded
+/// where that's shorter.
+#[derive(Default)]
+pub(crate) struct Encoder;
+
+impl Encoder {
+    pub(crate) fn encode<'f, I>(&mut self, fields: I, dst: &mut Vec<u8>)
+    where I: IntoIterator<Item = (&'f [u8], &'f [u8])> {
+        for (name, value) in fields {
+            let mut name_index = 0;
+            for (i, &(n, v)) in STATIC_TABLE.iter().enumerate() {
+                if n.as_bytes() == name {
+                    if v.as_bytes() == value {
+                        encode_int(i + 1, 7, 0x80, dst);
+                        name_index = usize::MAX;
+                        break;
+                    }
+                    if name_index == 0 {
+                        name_index = i + 1;
+                    }
+                }
+            }
+            if name_index == usize::MAX {
+                continue;
+            }
+            // keep credentials out of the dynamic tables of intermediaries
+            let flags = if is_sensitive(name) { 0x10 } else { 0 };
+            encode_int(name_index, 4, flags, dst);
+            if name_index == 0 {
+                encode_string(name, dst);
+            }
+            encode_string(value, dst);
+        }
+    }
+}
+
+fn is_sensitive(name: &[u8]) -> bool {
+    name == b"authorization" || name == b"proxy-authorization" || name == b"cookie" || name == b"set-cookie"
+}
+
+fn encode_int(mut value: usize, prefix: u8, flags: u8, dst: &mut Vec<u8>) {
+    let max = (1usize << prefix) - 1;
+    if value < max {
+        dst.push(flags | value as u8);
+        return;
+    }
+    dst.push(flags | max as u8);
+    value -= max;
+    while value >= 0x80 {
+        dst.push(0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+    dst.push(value as u8);
+}
+
+fn encode_string(s: &[u8], dst: &mut Vec<u8>) {
+    let len = huffman::encoded_len(s);
+    if len < s.len() {
+        encode_int(len, 7, 0x80, dst);
+        huffman::encode(s, dst);
+    } else {
+        encode_int(s.len(), 7, 0, dst);
+        dst.extend_from_slice(s);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_int, encode_int, Decoder, Encoder, Field};
+
+    fn unhex(s: &str) -> Vec<u8> {
+        let s: String = s.split_whitespace().collect();
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    fn fields(list: &[(&str, &str)]) -> Vec<Field> {
+        list.iter().map(|&(n, v)| (n.as_bytes().to_vec(), v.as_bytes().to_vec())).collect()
+    }
+
+    #[test]
+    fn test_integers() {
+        // RFC 7541 C.1
+        let mut out = Vec::new();
+        encode_int(10, 5, 0, &mut out);
+        assert_eq!(out, [0x0a]);
+        out.clear();
+        encode_int(1337, 5, 0, &mut out);
+        assert_eq!(out, [0x1f, 0x9a, 0x0a]);
+        let mut block = &out[..];
+        assert_eq!(decode_int(&mut block, 5), Ok(1337));
+        assert!(block.is_empty());
+        let mut overflow = &[0x1f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f][..];
+        assert!(decode_int(&mut overflow, 5).is_err());
+    }
+
+    #[test]
+    fn test_decode_requests_with_huffman() {
+        // RFC 7541 C.4, the dynamic table carries over between requests
+        let mut decoder = Decoder::new();
+        let first = decoder.decode(&unhex("8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff"), 8192).unwrap();
+        assert_eq!(first, fields(&[(":method", "GET"), (":scheme", "http"), (":path", "/"),
+                                   (":authority", "www.example.com")]));
+        let second = decoder.decode(&unhex("8286 84be 5886 a8eb 1064 9cbf"), 8192).unwrap();
+        assert_eq!(second, fields(&[(":method", "GET"), (":scheme", "http"), (":path", "/"),
+                                    (":authority", "www.example.com"), ("cache-control", "no-cache")]));
+        let third = decoder.decode(&unhex("8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf"), 8192).unwrap();
+        assert_eq!(third, fields(&[(":method", "GET"), (":scheme", "https"), (":path", "/index.html"),
+                                   (":authority", "www.example.com"), ("custom-key", "custom-value")]));
+        assert_eq!(decoder.size, 164);
+    }
+
+    #[test]
+    fn test_decode_eviction() {
+        // RFC 7541 C.5, with a table of 256 bytes
+        let mut decoder = Decoder::new();
+        let mut block = unhex("3fe1 01");
+        block.extend(unhex("4803 3330 3258 0770 7269 7661 7465 611d 4d6f 6e2c 2032 3120 4f63 7420 3230 3133
+                            2032 303a 3133 3a32 3120 474d 546e 1768 7474 7073 3a2f 2f77 7777 2e65 7861 6d70
+                            6c65 2e63 6f6d"));
+        let first = decoder.decode(&block, 8192).unwrap();
+        assert_eq!(first[0], (b":status".to_vec(), b"302".to_vec()));
+        assert_eq!(decoder.size, 222);
+        let second = decoder.decode(&unhex("4803 3330 37c1 c0bf"), 8192).unwrap();
+        assert_eq!(second, fields(&[(":status", "307"), ("cache-control", "private"),
+                                    ("date", "Mon, 21 Oct 2013 20:13:21 GMT"),
+                                    ("location", "https://www.example.com")]));
+        // ":status: 302" was evicted
+        assert_eq!(decoder.size, 222);
+        assert_eq!(decoder.table.len(), 4);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let mut decoder = Decoder::new();
+        // index out of the tables
+        assert!(decoder.decode(&[0xbe], 8192).is_err());
+        // truncated string
+        assert!(decoder.decode(&[0x40, 0x05, b'a'], 8192).is_err());
+        // size update over the limit
+        assert!(decoder.decode(&unhex("3fe1 ff01"), 8192).is_err());
+        // too large for the header list
+        assert!(decoder.decode(&unhex("8286 84"), 64).is_err());
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let list = fields(&[(":status", "200"), (":status", "302"), ("content-type", "text/html"),
+                            ("x-custom", "Some Value"), ("authorization", "secret")]);
+        let mut block = Vec::new();
+        Encoder.encode(list.iter().map(|f| (&f.0[..], &f.1[..])), &mut block);
+        // indexed
+        assert_eq!(block[0], 0x88);
+        let mut decoder = Decoder::new();
+        assert_eq!(decoder.decode(&block, 8192).unwrap(), list);
+        // nothing was added to the peer's table
+        assert_eq!(decoder.size, 0);
+    }
+}
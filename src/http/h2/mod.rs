@@ -0,0 +1,343 @@
+//! Adapts the HTTP/2 protocol to an HTTP `Protocol`.
+//!
+//! Requests of a `Client` using `Http2Protocol` are streams multiplexed over
+//! one connection per host. The `Server` speaks HTTP/2 to clients that start a
+//! connection with its preface: over TLS after negotiating `h2` with ALPN, or
+//! in plain text with prior knowledge (`h2c`). Its handlers get the same
+//! `Request` and `Response` as for HTTP/1.
+//!
+//! ```no_run
+//! use mco_http::Client;
+//! use mco_http::http::h2::Http2Protocol;
+//!
+//! let client = Client::with_protocol(Http2Protocol::new());
+//! let res = client.get("http://127.0.0.1:3000/").send().unwrap();
+//! assert_eq!(res.version, mco_http::version::HttpVersion::Http20);
+//! ```
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::header::{Headers, Host};
+use crate::http::{HttpMessage, Protocol, RawStatus, RequestHead, ResponseHead};
+use crate::method::Method;
+use crate::net::{HttpConnector, NetworkConnector, NetworkStream};
+use crate::runtime::{self, Mutex, MutexGuard};
+use crate::status::StatusCode;
+use crate::version::HttpVersion;
+use crate::Error;
+
+use self::conn::{Conn, Reader, Role, RecvStream, SendStream, RECV_WINDOW};
+
+pub(crate) mod conn;
+pub(crate) mod frame;
+mod hpack;
+mod huffman;
+
+pub(crate) use self::hpack::Field;
+
+/// What a client sends first on an HTTP/2 connection.
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// The most streams a client may open at once on a connection to the server.
+/// A stream the client reset counts until its handler returns.
+pub const MAX_CONCURRENT_STREAMS: u32 = 100;
+
+/// Headers that only mean something for one HTTP/1 connection.
+const CONNECTION_HEADERS: [&str; 5] = ["connection", "keep-alive", "proxy-connection", "transfer-encoding", "upgrade"];
+
+/// Whether `name` is a header HTTP/2 doesn't allow, see RFC 9113 section 8.2.2.
+pub(crate) fn is_connection_header(name: &[u8], value: &[u8]) -> bool {
+    CONNECTION_HEADERS.iter().any(|h| h.as_bytes() == name) || (name == b"te" && value != b"trailers")
+}
+
+/// The fields of the `Name: value` lines of an HTTP/1 head, with lowercase names
+/// and without the headers HTTP/2 doesn't allow.
+pub(crate) fn head_fields(lines: &str, fields: &mut Vec<Field>) {
+    for line in lines.split("\r\n") {
+        if let Some((name, value)) = line.split_once(':') {
+            let name = name.trim().to_ascii_lowercase().into_bytes();
+            let value = value.trim().as_bytes().to_vec();
+            if !is_connection_header(&name, &value) {
+                fields.push((name, value));
+            }
+        }
+    }
+}
+
+/// The settings we send, a client doesn't want pushed streams.
+pub(crate) fn settings(role: Role, max_header_list: usize) -> Vec<(u16, u32)> {
+    let mut settings = vec![
+        (frame::SETTINGS_INITIAL_WINDOW_SIZE, RECV_WINDOW as u32),
+        (frame::SETTINGS_MAX_HEADER_LIST_SIZE, max_header_list as u32),
+    ];
+    match role {
+        Role::Client => settings.push((frame::SETTINGS_ENABLE_PUSH, 0)),
+        Role::Server => settings.push((frame::SETTINGS_MAX_CONCURRENT_STREAMS, MAX_CONCURRENT_STREAMS)),
+    }
+    settings
+}
+
+/// The largest header list a client accepts in a response.
+const CLIENT_MAX_HEADER_LIST: usize = crate::buffer::MAX_BUFFER_SIZE;
+
+type Connect = dyn Fn(&str, u16, &str) -> crate::Result<(Box<dyn NetworkStream + Send>, Box<dyn NetworkStream + Send>)> + Send + Sync;
+
+/// An `HttpMessage` factory for HTTP/2, keeping one connection per host and
+/// scheme that the requests to it share.
+pub struct Http2Protocol {
+    connect: Box<Connect>,
+    conns: Mutex<HashMap<(String, u16, String), Arc<Conn>>>,
+}
+
+impl Http2Protocol {
+    /// Connect with an `HttpConnector`, speaking HTTP/2 in plain text with prior knowledge.
+    pub fn new() -> Http2Protocol {
+        Http2Protocol::with_connector(HttpConnector)
+    }
+
+    /// Connect with `connector`. Its streams are read from a coroutine while the
+    /// requests write to them, so they are cloned.
+    pub fn with_connector<C, S>(connector: C) -> Http2Protocol
+    where C: NetworkConnector<Stream = S> + Send + Sync + 'static, S: NetworkStream + Clone + Send {
+        Http2Protocol {
+            connect: Box::new(move |host, port, scheme| {
+                let stream = connector.connect(host, port, scheme)?;
+                Ok((Box::new(stream.clone()), Box::new(stream)))
+            }),
+            conns: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<(String, u16, String), Arc<Conn>>> {
+        self.conns.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn connect(&self, host: &str, port: u16, scheme: &str) -> crate::Result<Arc<Conn>> {
+        let (mut reader, writer) = (self.connect)(host, port, scheme)?;
+        let conn = Conn::new(Role::Client, writer);
+        conn.handshake(&settings(Role::Client, CLIENT_MAX_HEADER_LIST))?;
+        // timeouts are up to each request
+        reader.set_read_timeout(None)?;
+        let c = conn.clone();
+        runtime::spawn(move || {
+            let mut frames = Reader::new(c, CLIENT_MAX_HEADER_LIST);
+            loop {
+                match frames.next(&mut reader) {
+                    Ok(_) => break,
+                    Err(e) => trace!("HTTP/2 client read {:?}", e),
+                }
+            }
+        });
+        Ok(conn)
+    }
+}
+
+impl Default for Http2Protocol {
+    fn default() -> Http2Protocol {
+        Http2Protocol::new()
+    }
+}
+
+impl Protocol for Http2Protocol {
+    fn new_message(&self, host: &str, port: u16, scheme: &str) -> crate::Result<Box<dyn HttpMessage>> {
+        let key = (host.to_owned(), port, scheme.to_owned());
+        let open = self.lock().get(&key).filter(|c| c.can_open()).cloned();
+        let conn = match open {
+            Some(conn) => conn,
+            None => {
+                let conn = self.connect(host, port, scheme)?;
+                let mut conns = self.lock();
+                match conns.get(&key).filter(|c| c.can_open()).cloned() {
+                    // another request connected first
+                    Some(open) => {
+                        conn.close_when_idle();
+                        open
+                    }
+                    None => {
+                        if let Some(old) = conns.insert(key, conn.clone()) {
+                            old.close_when_idle();
+                        }
+                        conn
+                    }
+                }
+            }
+        };
+        Ok(Box::new(Http2Message::new(conn, scheme)))
+    }
+}
+
+impl Drop for Http2Protocol {
+    fn drop(&mut self) {
+        for (_, conn) in self.lock().drain() {
+            conn.close_when_idle();
+        }
+    }
+}
+
+impl fmt::Debug for Http2Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Http2Protocol")
+            .field("connections", &self.lock().len())
+            .finish()
+    }
+}
+
+/// A request and its response, as one stream of an HTTP/2 connection.
+pub struct Http2Message {
+    conn: Arc<Conn>,
+    scheme: String,
+    stream: Option<(RecvStream, SendStream)>,
+    read_timeout: Cell<Option<Duration>>,
+    write_timeout: Cell<Option<Duration>>,
+}
+
+impl Http2Message {
+    fn new(conn: Arc<Conn>, scheme: &str) -> Http2Message {
+        Http2Message {
+            conn,
+            scheme: scheme.to_owned(),
+            stream: None,
+            read_timeout: Cell::new(None),
+            write_timeout: Cell::new(None),
+        }
+    }
+
+    fn stream(&mut self) -> io::Result<&mut (RecvStream, SendStream)> {
+        let (read, write) = (self.read_timeout.get(), self.write_timeout.get());
+        let stream = self.stream.as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "request was not sent"))?;
+        stream.0.set_read_timeout(read)?;
+        stream.1.set_timeout(write);
+        Ok(stream)
+    }
+}
+
+impl Write for Http2Message {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.stream()?.1.send_data(buf, false)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Read for Http2Message {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream()?.0.read(buf)
+    }
+}
+
+impl HttpMessage for Http2Message {
+    fn set_outgoing(&mut self, head: RequestHead) -> crate::Result<RequestHead> {
+        let authority = match head.headers.get::<Host>() {
+            Some(host) => host.to_string(),
+            None => head.url.host_str().unwrap_or_default().to_owned(),
+        };
+        let mut path = head.url.path().to_owned();
+        if let Some(query) = head.url.query() {
+            path.push('?');
+            path.push_str(query);
+        }
+        let mut fields: Vec<Field> = vec![
+            (b":method".to_vec(), head.method.to_string().into_bytes()),
+            (b":scheme".to_vec(), self.scheme.clone().into_bytes()),
+            (b":authority".to_vec(), authority.into_bytes()),
+            (b":path".to_vec(), path.into_bytes()),
+        ];
+        let mut headers = head.headers.clone();
+        headers.remove::<Host>();
+        head_fields(&headers.to_string(), &mut fields);
+
+        let no_body = matches!(head.method, Method::Get | Method::Head) ||
+            head.headers.get::<crate::header::ContentLength>().is_some_and(|len| len.0 == 0);
+        let stream = self.conn.open_stream(&fields, no_body)?;
+        self.stream = Some(stream);
+        Ok(head)
+    }
+
+    fn get_incoming(&mut self) -> crate::Result<ResponseHead> {
+        let (recv, send) = self.stream()?;
+        if !send.is_ended() {
+            send.send_data(&[], true)?;
+        }
+        loop {
+            let fields = recv.recv_headers()?;
+            let mut status = None;
+            let mut headers = Headers::new();
+            for (name, value) in fields {
+                if name == b":status" {
+                    status = std::str::from_utf8(&value).ok().and_then(|s| s.parse::<u16>().ok());
+                } else if !name.starts_with(b":") {
+                    let name = String::from_utf8(name).map_err(|_| Error::Header)?;
+                    headers.append_raw(name, value);
+                }
+            }
+            let status = status.ok_or(Error::Status)?;
+            // informational responses come before the final one
+            if (100..200).contains(&status) {
+                continue;
+            }
+            let reason = StatusCode::from_u16(status).canonical_reason().unwrap_or("");
+            return Ok(ResponseHead {
+                headers,
+                raw_status: RawStatus(status, Cow::Borrowed(reason)),
+                version: HttpVersion::Http20,
+            });
+        }
+    }
+
+    fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        self.read_timeout.set(dur);
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        self.write_timeout.set(dur);
+        Ok(())
+    }
+
+    /// Cancels the stream, other requests on the connection go on.
+    fn close_connection(&mut self) -> crate::Result<()> {
+        if let Some((recv, mut send)) = self.stream.take() {
+            if !(recv.is_ended() && send.is_ended()) {
+                send.reset(frame::CANCEL);
+            }
+        }
+        Ok(())
+    }
+
+    fn has_body(&self) -> bool {
+        self.stream.as_ref().is_some_and(|s| !s.0.is_ended())
+    }
+
+    fn set_proxied(&mut self, val: bool) {
+        if val {
+            warn!("HTTP/2 requests are not sent through a proxy");
+        }
+    }
+}
+
+impl Drop for Http2Message {
+    fn drop(&mut self) {
+        let _ = self.close_connection();
+    }
+}
+
+impl fmt::Debug for Http2Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Http2Message")
+            .field("conn", &self.conn)
+            .field("stream", &self.stream.as_ref().map(|s| s.1.id()))
+            .finish()
+    }
+}
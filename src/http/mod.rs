@@ -11,6 +11,7 @@ use crate::version::HttpVersion::{Http10, Http11};
 pub use self::message::{HttpMessage, RequestHead, ResponseHead, Protocol};
 
 pub mod h1;
+pub mod h2;
 pub mod message;
 
 /// The raw status code and reason-phrase.
@@ -181,11 +181,20 @@ pub mod uri;
 pub mod version;
 
 pub mod multipart;
+pub mod extract;
 pub mod json;
 pub mod path;
 pub mod query;
 pub mod route;
 pub mod runtime;
+pub mod static_files;
+pub mod websocket;
+pub mod sse;
+pub mod cookie;
+pub mod session;
+pub mod auth;
+#[cfg(feature = "compression")]
+pub mod compress;
 
 /// Re-exporting the mime crate, for convenience.
 pub mod mime {
@@ -4,6 +4,8 @@ use std::time::Duration;
 use std::cell::Cell;
 
 use crate::net::{NetworkStream, NetworkConnector, SslClient};
+#[cfg(test)]
+use crate::server::{Handler, Worker};
 
 #[derive(Clone, Debug)]
 pub struct MockStream {
@@ -166,3 +168,29 @@ impl<T: NetworkStream + Send + Clone> SslClient<T> for MockSsl {
         Ok(stream)
     }
 }
+
+/// Serve the requests of `input` on one connection with `handler`, returning
+/// what was written back.
+#[cfg(test)]
+pub(crate) fn serve<H: Handler + 'static, I: AsRef<[u8]>>(handler: H, input: I) -> String {
+    serve_worker(Worker::new(handler, Default::default()), input)
+}
+
+/// Like `serve`, with a worker the test has configured, such as with limits.
+#[cfg(test)]
+pub(crate) fn serve_worker<H: Handler + 'static, I: AsRef<[u8]>>(worker: Worker<H>, input: I) -> String {
+    String::from_utf8(written(worker, input.as_ref())).unwrap()
+}
+
+/// Like `serve`, for responses that aren't text, such as compressed bodies.
+#[cfg(test)]
+pub(crate) fn serve_bytes<H: Handler + 'static, I: AsRef<[u8]>>(handler: H, input: I) -> Vec<u8> {
+    written(Worker::new(handler, Default::default()), input.as_ref())
+}
+
+#[cfg(test)]
+fn written<H: Handler + 'static>(worker: Worker<H>, input: &[u8]) -> Vec<u8> {
+    let mut mock = MockStream::with_input(input);
+    worker.handle_connection(&mut mock);
+    mock.write
+}
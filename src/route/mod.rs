@@ -1,14 +1,22 @@
 use std::any::Any;
+use std::cell::Cell;
 use std::fmt::{Debug, Formatter};
 use std::ops::Deref;
+use std::rc::Rc;
+use crate::header::{Allow, Connection};
+use crate::method::Method;
 use crate::net::Fresh;
-use crate::server::{Handler, Request, Response};
+use crate::server::{Handler, Request, RequestLimits, Response};
 use crate::status::StatusCode;
 use crate::uri::RequestUri::AbsolutePath;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock, RwLockReadGuard};
 use crate::runtime::{SyncHashMap, SyncVec};
 use crate::uri::RequestUri;
 
+pub use self::tree::{Match, Params, Tree};
+
+pub mod tree;
+
 pub struct HandleBox {
     pub url: String,
     pub inner: Box<dyn Handler>,
@@ -40,10 +48,82 @@ impl<F> MiddleWare for F where F: Fn(&mut Request, &mut Option<Response>), F: Sy
     }
 }
 
+/// a middleware that wraps the rest of the chain. call `next.run(req, res)` to run the
+/// downstream middleware and handler, or don't call it to answer the request yourself.
+///
+/// use `Response::on_head` to change the status and headers after the handler has set them.
+pub trait AroundMiddleWare: Send + Sync {
+    fn handle<'a, 'k>(&'a self, req: Request<'a, 'k>, res: Response<'a, Fresh>, next: Next<'a>);
+}
+
+impl<T: AroundMiddleWare> AroundMiddleWare for Arc<T> {
+    fn handle<'a, 'k>(&'a self, req: Request<'a, 'k>, res: Response<'a, Fresh>, next: Next<'a>) {
+        T::handle(self, req, res, next)
+    }
+}
+
+impl<F> AroundMiddleWare for F where F: for<'a, 'k> Fn(Request<'a, 'k>, Response<'a, Fresh>, Next<'a>), F: Sync + Send {
+    fn handle<'a, 'k>(&'a self, req: Request<'a, 'k>, res: Response<'a, Fresh>, next: Next<'a>) {
+        self(req, res, next)
+    }
+}
+
+/// the rest of a `Route`'s middleware chain, ending with the route's handler
+pub struct Next<'a> {
+    route: &'a Route,
+    middleware: usize,
+    around: usize,
+}
+
+impl<'a> Next<'a> {
+    /// run the downstream middleware and handler.
+    /// returns the status that was written, or `None` if the head was not written
+    pub fn run<'k>(self, req: Request<'a, 'k>, mut res: Response<'a, Fresh>) -> Option<StatusCode> {
+        let written = Rc::new(Cell::new(None));
+        let w = written.clone();
+        res.on_head(move |status, _| w.set(Some(*status)));
+        self.call(req, res);
+        written.get()
+    }
+
+    /// run the next middleware, in the order they were added, or the handler
+    fn call<'k>(self, mut req: Request<'a, 'k>, res: Response<'a, Fresh>) {
+        match self.route.around.get(self.around) {
+            Some((before, m)) if *before <= self.middleware => {
+                m.handle(req, res, Next { around: self.around + 1, ..self });
+            }
+            _ => match self.route.middleware.get(self.middleware) {
+                Some(m) => {
+                    let mut r = Some(res);
+                    m.handle(&mut req, &mut r);
+                    if let Some(res) = r {
+                        Next { middleware: self.middleware + 1, ..self }.call(req, res);
+                    }
+                }
+                None => self.route.dispatch(req, res),
+            },
+        }
+    }
+}
+
+impl Debug for Next<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Next")
+            .field("middleware", &self.middleware)
+            .field("around", &self.around)
+            .finish()
+    }
+}
+
 pub struct Route {
     pub container: SyncHashMap<String, Arc<Box<dyn Any>>>,
     pub middleware: SyncVec<Box<dyn MiddleWare>>,
+    /// the `AroundMiddleWare`, each with the count of `middleware` added before it
+    around: SyncVec<(usize, Box<dyn AroundMiddleWare>)>,
+    /// key is `url` for any method, or `METHOD url`. requests are matched through the
+    /// url tree filled by `handle_fn` and friends, a handler inserted here directly isn't routed
     pub handlers: SyncHashMap<String, HandleBox>,
+    tree: RwLock<Tree<String>>,
 }
 
 impl Debug for Route {
@@ -51,7 +131,8 @@ impl Debug for Route {
         f.debug_struct("Route")
             .field("container", &self.container.len())
             .field("middleware", &self.middleware.len())
-            .field("handlers", &self.handlers)
+            .field("around", &self.around.len())
+            .field("handlers", &self.read_tree())
             .finish()
     }
 }
@@ -62,7 +143,9 @@ impl Route {
         Self {
             container: SyncHashMap::new(),
             middleware: SyncVec::new(),
+            around: SyncVec::new(),
             handlers: SyncHashMap::new(),
+            tree: RwLock::new(Tree::new()),
         }
     }
     /// handle a fn
@@ -85,11 +168,126 @@ impl Route {
     ///
     ///
     /// ```
+    ///
+    /// the url may contain `{param}` and `*wildcard` segments, the matched values
+    /// are inserted into `Request::extra` as `Params`. see `route::tree`
     pub fn handle_fn<H: Handler + 'static>(&self, url: &str, h: H) {
-        self.handlers.insert(url.to_string(), HandleBox {
+        self.insert_handler(None, url, h);
+    }
+
+    /// handle a fn only for the request method, other methods on the same url get 405 Method Not Allowed.
+    /// for example:
+    /// ```rust
+    /// use mco_http::method::Method;
+    /// use mco_http::route::{Params, Route};
+    /// use mco_http::server::{Request, Response};
+    ///
+    /// let route = Route::new();
+    /// route.handle_method(Method::Get, "/user/{id}", |req: Request, res: Response| {
+    ///     let params = req.extra.get::<Params>().unwrap();
+    ///     res.send(format!("user {}", params.get("id").unwrap()).as_bytes()).unwrap();
+    /// });
+    /// route.handle_method(Method::Delete, "/user/{id}", |req: Request, res: Response| {
+    ///     res.send(b"deleted").unwrap();
+    /// });
+    /// ```
+    pub fn handle_method<H: Handler + 'static>(&self, method: Method, url: &str, h: H) {
+        self.insert_handler(Some(method), url, h);
+    }
+
+    /// handle a fn for GET (and HEAD) requests
+    pub fn get_fn<H: Handler + 'static>(&self, url: &str, h: H) {
+        self.handle_method(Method::Get, url, h);
+    }
+
+    /// handle a fn for POST requests
+    pub fn post_fn<H: Handler + 'static>(&self, url: &str, h: H) {
+        self.handle_method(Method::Post, url, h);
+    }
+
+    /// handle a fn for PUT requests
+    pub fn put_fn<H: Handler + 'static>(&self, url: &str, h: H) {
+        self.handle_method(Method::Put, url, h);
+    }
+
+    /// handle a fn for PATCH requests
+    pub fn patch_fn<H: Handler + 'static>(&self, url: &str, h: H) {
+        self.handle_method(Method::Patch, url, h);
+    }
+
+    /// handle a fn for DELETE requests
+    pub fn delete_fn<H: Handler + 'static>(&self, url: &str, h: H) {
+        self.handle_method(Method::Delete, url, h);
+    }
+
+    fn insert_handler<H: Handler + 'static>(&self, method: Option<Method>, url: &str, h: H) {
+        let key = match &method {
+            None => url.to_string(),
+            Some(m) => format!("{} {}", m, url),
+        };
+        self.handlers.insert(key.clone(), HandleBox {
             url: url.to_string(),
             inner: Box::new(h),
         });
+        self.tree.write().unwrap_or_else(|e| e.into_inner()).insert(method, url, key);
+    }
+
+    /// mount a sub `Route` under `prefix`. requests below the prefix are handed to `route` with
+    /// the prefix removed from the path, so the sub route runs its own middleware only for that subtree.
+    /// `Params` captured by the prefix are kept, for example:
+    /// ```rust
+    /// use mco_http::route::{Params, Route};
+    /// use mco_http::server::{Request, Response};
+    ///
+    /// let api = Route::new();
+    /// api.add_middleware(|req: &mut Request, res: &mut Option<Response>| {
+    ///     //only runs for /org/{org}/api/v1/*
+    /// });
+    /// api.get_fn("/user/{id}", |req: Request, res: Response| {
+    ///     let params = req.extra.get::<Params>().unwrap();
+    ///     let body = format!("{} {}", params.get("org").unwrap(), params.get("id").unwrap());
+    ///     res.send(body.as_bytes()).unwrap();
+    /// });
+    ///
+    /// let route = Route::new();
+    /// route.mount("/org/{org}/api/v1", api);
+    /// ```
+    pub fn mount<R: Into<Arc<Route>>>(&self, prefix: &str, route: R) {
+        let route = route.into();
+        let prefix = prefix.trim_matches('/');
+        if prefix.is_empty() {
+            self.handle_fn("/{*}", Mount { depth: 0, route });
+        } else {
+            let depth = prefix.split('/').count();
+            self.handle_fn(&format!("/{}", prefix), Mount { depth, route: route.clone() });
+            self.handle_fn(&format!("/{}/{{*}}", prefix), Mount { depth, route });
+        }
+    }
+
+    fn read_tree(&self) -> RwLockReadGuard<'_, Tree<String>> {
+        self.tree.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// the registered urls
+    pub fn urls(&self) -> Vec<String> {
+        self.read_tree().urls().to_vec()
+    }
+
+    /// find the handler for a method and path(without the query string)
+    pub fn find(&self, method: &Method, path: &str) -> Match<&HandleBox> {
+        let found = match self.read_tree().find(method, path) {
+            Match::Found(key, params) => Match::Found(key.clone(), params),
+            Match::MethodNotAllowed(allow) => Match::MethodNotAllowed(allow),
+            Match::NotFound => Match::NotFound,
+        };
+        match found {
+            Match::Found(key, params) => match self.handlers.get(&key) {
+                Some(h) => Match::Found(h, params),
+                None => Match::NotFound,
+            },
+            Match::MethodNotAllowed(allow) => Match::MethodNotAllowed(allow),
+            Match::NotFound => Match::NotFound,
+        }
     }
 
     /// if you take Response. handle be done
@@ -106,6 +304,33 @@ impl Route {
         self.middleware.push(Box::new(m));
     }
 
+    /// add a middleware wrapping the rest of the chain, it runs in order with the `add_middleware` ones
+    pub fn add_around<M: AroundMiddleWare + 'static>(&self, m: M) {
+        self.around.push((self.middleware.len(), Box::new(m)));
+    }
+
+    /// add a fn as `AroundMiddleWare`
+    /// for example:
+    /// ```rust
+    /// use std::time::Instant;
+    /// use mco_http::route::{Next, Route};
+    /// use mco_http::server::{Request, Response};
+    /// let route = Route::new();
+    /// route.add_around_fn(|req: Request, mut res: Response, next: Next| {
+    ///     let start = Instant::now();
+    ///     let uri = req.uri.to_string();
+    ///     res.on_head(|_status, headers| {
+    ///         headers.set_raw("X-Powered-By", vec![b"mco-http".to_vec()]);
+    ///     });
+    ///     let status = next.run(req, res);
+    ///     println!("{} {:?} {:?}", uri, status, start.elapsed());
+    /// });
+    /// ```
+    pub fn add_around_fn<F>(&self, f: F)
+        where F: for<'a, 'k> Fn(Request<'a, 'k>, Response<'a, Fresh>, Next<'a>) + Sync + Send + 'static {
+        self.add_around(f);
+    }
+
     pub fn insert<T: Any>(&self, key: &str, data: T) {
         self.container.insert(key.to_string(), Arc::new(Box::new(data)));
     }
@@ -130,36 +355,120 @@ impl Route {
 
 
 impl Handler for Route {
-    fn handle<'a, 'k>(&'a self, mut req: Request<'a, 'k>, mut res: Response<'a, Fresh>){
-        for  m in &self.middleware {
-            let mut r = Some(res);
-            //finish?.this is safety
-            m.handle(&mut req, &mut r);
-            if r.is_none() {
-                return;
-            } else {
-                res = r.unwrap();
+    fn handle<'a, 'k>(&'a self, req: Request<'a, 'k>, res: Response<'a, Fresh>) {
+        Next { route: self, middleware: 0, around: 0 }.run(req, res);
+    }
+}
+
+impl Route {
+    /// find and run the handler, after the middleware chain
+    fn dispatch<'a, 'k>(&'a self, mut req: Request<'a, 'k>, mut res: Response<'a, Fresh>) {
+        let path = match request_path(&req) {
+            Some(p) => p.to_string(),
+            None => return,
+        };
+        match self.find(&req.method, &path) {
+            Match::Found(h, params) => {
+                //keep the params captured by a parent route
+                let mut all = req.extra.remove::<Params>().unwrap_or_default();
+                for (k, v) in params.iter().filter(|(k, _)| !k.is_empty()) {
+                    all.push(k, v);
+                }
+                if !all.is_empty() {
+                    req.extra.insert(all);
+                }
+                h.inner.handle(req, res);
+            }
+            Match::MethodNotAllowed(allow) => {
+                //405
+                res.status = StatusCode::MethodNotAllowed;
+                res.headers.set(Allow(allow));
+            }
+            Match::NotFound => {
+                //404
+                res.status = StatusCode::NotFound;
             }
         }
-        match &req.uri {
-            AbsolutePath(p) => {
-                let path = &p[0..p.find("?").unwrap_or(p.len())];
-                match self.handlers.get(path) {
-                    None => {
-                        //404
-                        res.status = StatusCode::NotFound;
-                        return;
-                    }
-                    Some(h) => {
-                        let i = &h.inner;
-                        i.handle(req, res);
-                        return;
-                    }
-                }
+    }
+}
+
+/// the path (without the query string) of a request uri
+fn route_path(uri: &RequestUri) -> Option<&str> {
+    match uri {
+        AbsolutePath(p) => Some(&p[0..p.find("?").unwrap_or(p.len())]),
+        RequestUri::AbsoluteUri(url) => Some(url.path()),
+        RequestUri::Authority(_) => None,
+        RequestUri::Star => None,
+    }
+}
+
+/// the path the current route matches the request on, without the prefix of the mounts it went through
+pub(crate) fn request_path<'r>(req: &'r Request) -> Option<&'r str> {
+    match req.extra.get::<RoutePath>() {
+        Some(RoutePath(p)) => Some(p),
+        None => route_path(&req.uri),
+    }
+}
+
+/// the path left for a mounted sub route to match
+struct RoutePath(String);
+
+/// a sub route mounted under a prefix of `depth` segments
+struct Mount {
+    depth: usize,
+    route: Arc<Route>,
+}
+
+impl Handler for Mount {
+    fn handle<'a, 'k>(&'a self, mut req: Request<'a, 'k>, res: Response<'a, Fresh>) {
+        let path = match req.extra.get::<RoutePath>() {
+            Some(RoutePath(p)) => p.as_str(),
+            None => route_path(&req.uri).unwrap_or("/"),
+        };
+        let rest = path.strip_prefix('/').unwrap_or(path)
+            .splitn(self.depth + 1, '/')
+            .nth(self.depth)
+            .unwrap_or("");
+        let rest = format!("/{}", rest);
+        req.extra.insert(RoutePath(rest));
+        self.route.handle(req, res)
+    }
+}
+
+/// wrap a handler with its own `RequestLimits`, checked on top of the server's limits.
+/// a request over them is answered with 413, 414 or 431 and the connection is closed.
+/// for example:
+/// ```rust
+/// use mco_http::route::{limited, Route};
+/// use mco_http::server::{Request, RequestLimits, Response};
+///
+/// let route = Route::new();
+/// let limits = RequestLimits { max_body_size: Some(1024), ..Default::default() };
+/// route.post_fn("/comment", limited(limits, |req: Request, res: Response| {
+///     res.send(b"ok").unwrap();
+/// }));
+/// ```
+/// the server already parsed the head, so a route can only tighten the server's limits.
+pub fn limited<H: Handler>(limits: RequestLimits, handler: H) -> Limited<H> {
+    Limited { limits, handler }
+}
+
+/// a handler with its own `RequestLimits`, see `limited`
+#[derive(Debug)]
+pub struct Limited<H> {
+    limits: RequestLimits,
+    handler: H,
+}
+
+impl<H: Handler> Handler for Limited<H> {
+    fn handle<'a, 'k>(&'a self, req: Request<'a, 'k>, mut res: Response<'a, Fresh>) {
+        match self.limits.apply(req) {
+            Ok(req) => self.handler.handle(req, res),
+            Err(e) => {
+                info!("request over the route limits: {}", e);
+                res.status = crate::server::error_status(&e);
+                res.headers_mut().set(Connection::close());
             }
-            RequestUri::AbsoluteUri(_) => {}
-            RequestUri::Authority(_) => {}
-            RequestUri::Star => {}
         }
     }
 }
@@ -168,4 +477,192 @@ impl Handler for Arc<Route> {
     fn handle<'a, 'k>(&'a self,  req: Request<'a, 'k>,  res: Response<'a, Fresh>) {
         self.deref().handle(req,res)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use crate::mock::serve;
+    use crate::server::{Request, Response};
+    use crate::status::StatusCode;
+    use std::io::Read;
+    use crate::server::RequestLimits;
+    use super::{limited, Next, Params, Route};
+
+    fn route() -> Route {
+        let route = Route::new();
+        route.get_fn("/user/{id}", |req: Request, res: Response| {
+            let id = req.extra.get::<Params>().unwrap().get("id").unwrap().to_string();
+            res.send(id.as_bytes()).unwrap();
+        });
+        route.delete_fn("/user/{id}", |_: Request, res: Response| {
+            res.send(b"deleted").unwrap();
+        });
+        route
+    }
+
+    #[test]
+    fn test_route_params() {
+        let s = serve(route(), b"GET /user/42?x=1 HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(s.starts_with("HTTP/1.1 200 OK\r\n"), "{}", s);
+        assert!(s.ends_with("\r\n\r\n42"), "{}", s);
+
+        let s = serve(route(), b"DELETE /user/42 HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(s.ends_with("\r\n\r\ndeleted"), "{}", s);
+    }
+
+    #[test]
+    fn test_route_method_not_allowed() {
+        let s = serve(route(), b"POST /user/42 HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(s.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"), "{}", s);
+        assert!(s.contains("Allow: GET, DELETE, HEAD\r\n"), "{}", s);
+    }
+
+    #[test]
+    fn test_route_mount() {
+        let api = Route::new();
+        api.add_middleware(|_: &mut Request, res: &mut Option<Response>| {
+            res.as_mut().unwrap().headers_mut().set_raw("X-Group", vec![b"api".to_vec()]);
+        });
+        api.get_fn("/", |_: Request, res: Response| {
+            res.send(b"index").unwrap();
+        });
+        api.get_fn("/user/{id}", |req: Request, res: Response| {
+            let params = req.extra.get::<Params>().unwrap();
+            let body = format!("{}:{}", params.get("org").unwrap(), params.get("id").unwrap());
+            res.send(body.as_bytes()).unwrap();
+        });
+        let parent = || {
+            let route = Route::new();
+            route.get_fn("/health", |_: Request, res: Response| {
+                res.send(b"ok").unwrap();
+            });
+            route
+        };
+
+        let route = parent();
+        route.mount("/org/{org}/api/v1", api);
+        let route = Arc::new(route);
+        let s = serve(route.clone(), b"GET /org/acme/api/v1/user/7 HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(s.contains("X-Group: api\r\n"), "{}", s);
+        assert!(s.ends_with("\r\n\r\nacme:7"), "{}", s);
+
+        let s = serve(route.clone(), b"GET /org/acme/api/v1 HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(s.ends_with("\r\n\r\nindex"), "{}", s);
+
+        let s = serve(route.clone(), b"DELETE /org/acme/api/v1/user/7 HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(s.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"), "{}", s);
+
+        //group middleware does not run outside the subtree
+        let s = serve(route, b"GET /health HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(!s.contains("X-Group"), "{}", s);
+        assert!(s.ends_with("\r\n\r\nok"), "{}", s);
+    }
+
+    #[test]
+    fn test_route_mount_nested() {
+        let admin = Route::new();
+        admin.get_fn("/users", |_: Request, res: Response| {
+            res.send(b"users").unwrap();
+        });
+        let api = Route::new();
+        api.mount("/admin/", admin);
+        let route = Route::new();
+        route.mount("/api", api);
+        let s = serve(route, b"GET /api/admin/users HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(s.ends_with("\r\n\r\nusers"), "{}", s);
+    }
+
+    #[test]
+    fn test_route_around_middleware() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let route = route();
+        let log = seen.clone();
+        route.add_middleware(move |_: &mut Request, _: &mut Option<Response>| {
+            log.lock().unwrap().push("before".to_string());
+        });
+        let log = seen.clone();
+        route.add_around_fn(move |req: Request, mut res: Response, next: Next| {
+            res.on_head(|status, headers| {
+                headers.set_raw("X-Status", vec![status.to_u16().to_string().into_bytes()]);
+            });
+            let status = next.run(req, res);
+            log.lock().unwrap().push(format!("after {:?}", status));
+        });
+        let s = serve(route, b"GET /user/1 HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(s.contains("X-Status: 200\r\n"), "{}", s);
+        assert!(s.ends_with("\r\n\r\n1"), "{}", s);
+        assert_eq!(*seen.lock().unwrap(), vec!["before".to_string(), "after Some(Ok)".to_string()]);
+    }
+
+    #[test]
+    fn test_route_middleware_order() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let route = route();
+        let log = seen.clone();
+        route.add_around_fn(move |req: Request, res: Response, next: Next| {
+            log.lock().unwrap().push("around 1");
+            next.run(req, res);
+        });
+        let log = seen.clone();
+        route.add_middleware(move |_: &mut Request, _: &mut Option<Response>| {
+            log.lock().unwrap().push("middleware 2");
+        });
+        let log = seen.clone();
+        route.add_around_fn(move |req: Request, res: Response, next: Next| {
+            log.lock().unwrap().push("around 3");
+            next.run(req, res);
+        });
+        let log = seen.clone();
+        route.middleware.push(Box::new(move |_: &mut Request, _: &mut Option<Response>| {
+            log.lock().unwrap().push("middleware 4");
+        }));
+        serve(route, b"GET /user/1 HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert_eq!(*seen.lock().unwrap(), vec!["around 1", "middleware 2", "around 3", "middleware 4"]);
+    }
+
+    #[test]
+    fn test_route_around_middleware_short_circuit() {
+        let route = route();
+        route.add_around_fn(|req: Request, mut res: Response, next: Next| {
+            if req.headers.get_raw("X-Token").is_none() {
+                *res.status_mut() = StatusCode::Unauthorized;
+                return;
+            }
+            next.run(req, res);
+        });
+        let s = serve(route, b"GET /user/1 HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(s.starts_with("HTTP/1.1 401 Unauthorized\r\n"), "{}", s);
+    }
+
+    #[test]
+    fn test_route_limited() {
+        let route = Route::new();
+        let limits = RequestLimits { max_headers: 3, max_body_size: Some(4), max_uri_length: Some(16), ..Default::default() };
+        route.post_fn("/{*}", limited(limits, |mut req: Request, res: Response| {
+            let mut body = String::new();
+            req.read_to_string(&mut body).unwrap();
+            res.send(body.as_bytes()).unwrap();
+        }));
+        let route = Arc::new(route);
+
+        let s = serve(route.clone(), b"POST /a HTTP/1.1\r\nContent-Length: 4\r\n\r\nbody");
+        assert!(s.ends_with("\r\n\r\nbody"), "{}", s);
+
+        let s = serve(route.clone(), b"POST /a HTTP/1.1\r\nContent-Length: 5\r\n\r\nbody!");
+        assert!(s.starts_with("HTTP/1.1 413 Payload Too Large\r\n"), "{}", s);
+        assert!(s.contains("Connection: close\r\n"), "{}", s);
+
+        let s = serve(route.clone(), b"POST /a/very/long/path HTTP/1.1\r\nContent-Length: 0\r\n\r\n");
+        assert!(s.starts_with("HTTP/1.1 414 URI Too Long\r\n"), "{}", s);
+
+        // header lines are counted, not names
+        let s = serve(route, b"POST /a HTTP/1.1\r\nContent-Length: 0\r\nCookie: a=1\r\nCookie: b=2\r\nCookie: c=3\r\n\r\n");
+        assert!(s.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"), "{}", s);
+    }
+
+    #[test]
+    fn test_route_not_found() {
+        let s = serve(route(), b"GET /users HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(s.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", s);
+    }
+}
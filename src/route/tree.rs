@@ -0,0 +1,398 @@
+//! The path tree `Route` uses to match a request to a handler.
+//!
+//! A url pattern is split on `/` into segments. Each segment is one of:
+//!
+//! * a static segment, such as `user`, which must match exactly,
+//! * a parameter, such as `{id}`, which matches any non-empty segment,
+//! * a wildcard, such as `*path`, which must be the last segment and
+//!   matches the rest of the path.
+//!
+//! Static segments are tried before parameters, and parameters before
+//! wildcards, so `/user/new` wins over `/user/{id}` for the path `/user/new`.
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+
+use url::percent_encoding::percent_decode;
+
+use crate::method::Method;
+
+/// The values captured from `{param}` and `*wildcard` segments of a route.
+///
+/// `Route` inserts them into `Request::extra` before calling the handler:
+///
+/// ```rust
+/// use mco_http::route::{Params, Route};
+/// use mco_http::server::{Request, Response};
+///
+/// let route = Route::new();
+/// route.get_fn("/user/{id}", |req: Request, res: Response| {
+///     let id = req.extra.get::<Params>().and_then(|p| p.get("id")).unwrap_or_default();
+///     res.send(id.as_bytes()).unwrap();
+/// });
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Params(Vec<(String, String)>);
+
+impl Params {
+    /// Create an empty `Params`.
+    pub fn new() -> Params {
+        Params(Vec::new())
+    }
+
+    /// Get the value captured for `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Add a captured value.
+    pub fn push<K: Into<String>, V: Into<String>>(&mut self, name: K, value: V) {
+        self.0.push((name.into(), value.into()));
+    }
+
+    /// Iterate over the captured `(name, value)` pairs, in path order.
+    pub fn iter(&self) -> impl Iterator<Item=(&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// The number of captured values.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Whether nothing was captured.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn truncate(&mut self, len: usize) {
+        self.0.truncate(len);
+    }
+}
+
+/// The result of looking up a request in a `Tree`.
+#[derive(Debug)]
+pub enum Match<T> {
+    /// The path and method matched.
+    Found(T, Params),
+    /// The path matched, but not for this method. Holds the allowed methods.
+    MethodNotAllowed(Vec<Method>),
+    /// Nothing matched the path.
+    NotFound,
+}
+
+/// The handlers registered for one url pattern.
+struct Endpoint<T> {
+    url: String,
+    any: Option<T>,
+    methods: Vec<(Method, T)>,
+}
+
+impl<T> Endpoint<T> {
+    fn new(url: &str) -> Endpoint<T> {
+        Endpoint {
+            url: url.to_string(),
+            any: None,
+            methods: Vec::new(),
+        }
+    }
+
+    fn insert(&mut self, method: Option<Method>, value: T) -> Option<T> {
+        match method {
+            None => self.any.replace(value),
+            Some(method) => {
+                match self.methods.iter_mut().find(|(m, _)| *m == method) {
+                    Some((_, v)) => Some(std::mem::replace(v, value)),
+                    None => {
+                        self.methods.push((method, value));
+                        None
+                    }
+                }
+            }
+        }
+    }
+
+    fn get(&self, method: &Method) -> Option<&T> {
+        let find = |method: &Method| self.methods.iter()
+            .find(|(m, _)| m == method)
+            .map(|(_, v)| v);
+        find(method)
+            // a HEAD request can always be answered by the GET handler
+            .or_else(|| if *method == Method::Head { find(&Method::Get) } else { None })
+            .or(self.any.as_ref())
+    }
+
+    fn allowed(&self) -> Vec<Method> {
+        let mut allow: Vec<Method> = self.methods.iter().map(|(m, _)| m.clone()).collect();
+        if allow.contains(&Method::Get) && !allow.contains(&Method::Head) {
+            allow.push(Method::Head);
+        }
+        allow
+    }
+}
+
+struct Node<T> {
+    statics: HashMap<String, Node<T>>,
+    param: Option<(String, Box<Node<T>>)>,
+    wildcard: Option<(String, Endpoint<T>)>,
+    endpoint: Option<Endpoint<T>>,
+}
+
+impl<T> Default for Node<T> {
+    fn default() -> Node<T> {
+        Node {
+            statics: HashMap::new(),
+            param: None,
+            wildcard: None,
+            endpoint: None,
+        }
+    }
+}
+
+enum Segment<'s> {
+    Static(&'s str),
+    Param(&'s str),
+    Wildcard(&'s str),
+}
+
+impl<'s> Segment<'s> {
+    fn parse(seg: &'s str) -> Segment<'s> {
+        if let Some(name) = seg.strip_prefix("{*").and_then(|s| s.strip_suffix('}')) {
+            Segment::Wildcard(name)
+        } else if let Some(name) = seg.strip_prefix('*') {
+            Segment::Wildcard(name)
+        } else if let Some(name) = seg.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+            Segment::Param(name)
+        } else {
+            Segment::Static(seg)
+        }
+    }
+}
+
+fn segments(path: &str) -> Vec<&str> {
+    path.strip_prefix('/').unwrap_or(path).split('/').collect()
+}
+
+fn decode(s: &str) -> String {
+    percent_decode(s.as_bytes()).decode_utf8_lossy().into_owned()
+}
+
+impl<T> Node<T> {
+    fn find<'t>(&'t self, segs: &[&str], params: &mut Params) -> Option<&'t Endpoint<T>> {
+        let (seg, rest) = match segs.split_first() {
+            None => return self.endpoint.as_ref(),
+            Some(v) => v,
+        };
+        if let Some(child) = self.statics.get(*seg) {
+            if let Some(ep) = child.find(rest, params) {
+                return Some(ep);
+            }
+        }
+        if let Some((name, child)) = &self.param {
+            if !seg.is_empty() {
+                let len = params.len();
+                params.push(name.as_str(), decode(seg));
+                if let Some(ep) = child.find(rest, params) {
+                    return Some(ep);
+                }
+                params.truncate(len);
+            }
+        }
+        if let Some((name, ep)) = &self.wildcard {
+            params.push(name.as_str(), decode(&segs.join("/")));
+            return Some(ep);
+        }
+        None
+    }
+}
+
+/// Maps url patterns and methods to values, usually handlers.
+pub struct Tree<T> {
+    root: Node<T>,
+    urls: Vec<String>,
+}
+
+impl<T> Default for Tree<T> {
+    fn default() -> Tree<T> {
+        Tree {
+            root: Node::default(),
+            urls: Vec::new(),
+        }
+    }
+}
+
+impl<T> Debug for Tree<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(&self.urls).finish()
+    }
+}
+
+impl<T> Tree<T> {
+    /// Create an empty `Tree`.
+    pub fn new() -> Tree<T> {
+        Tree::default()
+    }
+
+    /// Register `value` for `url`. A `method` of `None` matches any method
+    /// that has no handler of its own.
+    ///
+    /// Returns the value previously registered for the same url and method.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if a wildcard is not the last segment, or if a parameter
+    /// uses a different name than one already registered at the same
+    /// position, such as `/user/{id}` and `/user/{name}/posts`.
+    pub fn insert(&mut self, method: Option<Method>, url: &str, value: T) -> Option<T> {
+        let segs = segments(url);
+        let mut node = &mut self.root;
+        for (idx, seg) in segs.iter().enumerate() {
+            node = match Segment::parse(seg) {
+                Segment::Static(s) => node.statics.entry(s.to_string()).or_default(),
+                Segment::Param(name) => {
+                    let (exist, child) = node.param
+                        .get_or_insert_with(|| (name.to_string(), Box::default()));
+                    if exist != name {
+                        panic!("route {} conflicts with parameter {{{}}}", url, exist);
+                    }
+                    child
+                }
+                Segment::Wildcard(name) => {
+                    if idx + 1 != segs.len() {
+                        panic!("route {}: wildcard *{} must be the last segment", url, name);
+                    }
+                    let (exist, ep) = node.wildcard
+                        .get_or_insert_with(|| (name.to_string(), Endpoint::new(url)));
+                    if exist != name {
+                        panic!("route {} conflicts with wildcard *{}", url, exist);
+                    }
+                    if !self.urls.iter().any(|u| u == url) {
+                        self.urls.push(url.to_string());
+                    }
+                    return ep.insert(method, value);
+                }
+            };
+        }
+        if !self.urls.iter().any(|u| u == url) {
+            self.urls.push(url.to_string());
+        }
+        node.endpoint
+            .get_or_insert_with(|| Endpoint::new(url))
+            .insert(method, value)
+    }
+
+    /// Look up the value for a request `method` and `path`.
+    ///
+    /// The query string, if any, must already be removed from `path`.
+    pub fn find(&self, method: &Method, path: &str) -> Match<&T> {
+        let mut params = Params::new();
+        match self.root.find(&segments(path), &mut params) {
+            None => Match::NotFound,
+            Some(ep) => {
+                trace!("route {} matched {}", ep.url, path);
+                match ep.get(method) {
+                    Some(v) => Match::Found(v, params),
+                    None => Match::MethodNotAllowed(ep.allowed()),
+                }
+            }
+        }
+    }
+
+    /// The registered url patterns, in registration order.
+    pub fn urls(&self) -> &[String] {
+        &self.urls
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::method::Method::{Get, Post, Delete, Head};
+    use super::{Tree, Match};
+
+    fn tree() -> Tree<&'static str> {
+        let mut tree = Tree::new();
+        tree.insert(None, "/", "index");
+        tree.insert(Some(Get), "/user", "list");
+        tree.insert(Some(Post), "/user", "create");
+        tree.insert(Some(Get), "/user/new", "new");
+        tree.insert(Some(Get), "/user/{id}", "show");
+        tree.insert(Some(Delete), "/user/{id}", "delete");
+        tree.insert(Some(Get), "/user/{id}/posts/{post}", "post");
+        tree.insert(Some(Get), "/static/*path", "static");
+        tree
+    }
+
+    fn found(m: Match<&&'static str>) -> (&'static str, Vec<(String, String)>) {
+        match m {
+            Match::Found(v, params) => (*v, params.iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()),
+            other => panic!("expected a match, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_static() {
+        let tree = tree();
+        assert_eq!(found(tree.find(&Get, "/")).0, "index");
+        assert_eq!(found(tree.find(&Post, "/")).0, "index");
+        assert_eq!(found(tree.find(&Get, "/user")).0, "list");
+        assert_eq!(found(tree.find(&Post, "/user")).0, "create");
+        assert_eq!(found(tree.find(&Get, "/user/new")).0, "new");
+    }
+
+    #[test]
+    fn test_params() {
+        let tree = tree();
+        let (v, params) = found(tree.find(&Get, "/user/42"));
+        assert_eq!(v, "show");
+        assert_eq!(params, vec![("id".to_string(), "42".to_string())]);
+
+        let (v, _) = found(tree.find(&Delete, "/user/42"));
+        assert_eq!(v, "delete");
+
+        let (v, params) = found(tree.find(&Get, "/user/7/posts/hello%20world"));
+        assert_eq!(v, "post");
+        assert_eq!(params, vec![("id".to_string(), "7".to_string()),
+                                ("post".to_string(), "hello world".to_string())]);
+    }
+
+    #[test]
+    fn test_wildcard() {
+        let tree = tree();
+        let (v, params) = found(tree.find(&Get, "/static/css/site.css"));
+        assert_eq!(v, "static");
+        assert_eq!(params, vec![("path".to_string(), "css/site.css".to_string())]);
+    }
+
+    #[test]
+    fn test_head_uses_get() {
+        let tree = tree();
+        assert_eq!(found(tree.find(&Head, "/user/42")).0, "show");
+    }
+
+    #[test]
+    fn test_method_not_allowed() {
+        let tree = tree();
+        match tree.find(&Post, "/user/42") {
+            Match::MethodNotAllowed(allow) => assert_eq!(allow, vec![Get, Delete, Head]),
+            other => panic!("expected 405, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_not_found() {
+        let tree = tree();
+        assert!(matches!(tree.find(&Get, "/nope"), Match::NotFound));
+        assert!(matches!(tree.find(&Get, "/user/"), Match::NotFound));
+        assert!(matches!(tree.find(&Get, "/user/1/posts"), Match::NotFound));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_conflicting_param() {
+        let mut tree = tree();
+        tree.insert(Some(Get), "/user/{name}/edit", "edit");
+    }
+}
@@ -0,0 +1,564 @@
+//! Serving HTTP/2 connections, see `http::h2`.
+//!
+//! The `Worker` reads the frames of the connection, and handles each stream in
+//! its own coroutine, with a `Request` reading the data of the stream and a
+//! `Response` whose head becomes the `HEADERS` of the stream.
+use std::io::{self, BufWriter, Write};
+use std::mem;
+use std::net::SocketAddr;
+use std::str;
+
+use crate::buffer::BufReader;
+use crate::header::{Headers, Host};
+use crate::http::h2::conn::{Conn, Incoming, Reader, Role, SendStream};
+use crate::http::h2::{self, frame};
+use crate::method::Method;
+use crate::net::NetworkStream;
+use crate::runtime::{self, Mutex, Receiver, Sender};
+use crate::uri::RequestUri;
+use crate::version::HttpVersion::Http20;
+
+use super::{Handler, Request, Response, Worker};
+
+/// Whether the client starts the connection with the HTTP/2 preface, which is
+/// consumed if so. Reads no more than it takes to tell.
+pub(crate) fn read_preface(rdr: &mut BufReader<&mut dyn NetworkStream>) -> io::Result<bool> {
+    loop {
+        let buf = rdr.get_buf();
+        if buf.len() >= h2::PREFACE.len() || !h2::PREFACE.starts_with(buf) {
+            break;
+        }
+        if rdr.read_into_buf()? == 0 {
+            return Ok(false);
+        }
+    }
+    if rdr.get_buf().starts_with(h2::PREFACE) {
+        io::BufRead::consume(rdr, h2::PREFACE.len());
+        Ok(true)
+    } else {
+        Ok(false)
+    }
+}
+
+impl<H: Handler + 'static> Worker<H> {
+    /// Serve an HTTP/2 connection after its preface, `rdr` reads a clone of `stream`.
+    pub(super) fn handle_h2<S>(&self, rdr: &mut BufReader<&mut dyn NetworkStream>, stream: &S,
+                               addr: SocketAddr, id: usize) where S: NetworkStream + Clone {
+        let max_head_size = self.request_limits.max_head_size;
+        let conn = Conn::new(Role::Server, Box::new(stream.clone()));
+        if let Err(e) = conn.handshake(&h2::settings(Role::Server, max_head_size)) {
+            debug!("error starting HTTP/2 connection: {:?}", e);
+            return;
+        }
+        // reading frames times out once the connection is idle
+        if let Err(e) = rdr.get_ref().set_read_timeout(self.timeouts.keep_alive) {
+            info!("set_read_timeout keep_alive {:?}", e);
+            return;
+        }
+
+        let mut frames = Reader::new(conn.clone(), max_head_size);
+        let active = Mutex::new(0usize);
+        let mut requests = 0;
+        let mut scope = Scope::new();
+        loop {
+            let mut incoming = match frames.next(rdr) {
+                Ok(Some(incoming)) => incoming,
+                Ok(None) => break,
+                Err(e) => {
+                    if *lock(&active) == 0 && !frames.is_reading() {
+                        debug!("HTTP/2 connection idle for {}: {:?}", addr, e);
+                        conn.go_away(frame::NO_ERROR);
+                        break;
+                    }
+                    continue;
+                }
+            };
+
+            {
+                let mut active = lock(&active);
+                // a stream the client reset counts until its handler returns
+                if *active >= h2::MAX_CONCURRENT_STREAMS as usize {
+                    drop(active);
+                    incoming.send.reset(frame::REFUSED_STREAM);
+                    continue;
+                }
+                *active += 1;
+                requests += 1;
+                let closing = !self.graceful.start_request(id);
+                let last = self.limits.max_requests_per_connection.is_some_and(|max| requests >= max);
+                // the streams opened so far are still handled
+                if closing || last {
+                    conn.go_away(frame::NO_ERROR);
+                }
+            }
+            // for `Request::ssl`
+            incoming.recv.set_transport(Box::new(stream.clone()));
+            let (conn, active) = (&conn, &active);
+            scope.spawn(move || {
+                self.handle_h2_stream(incoming, addr);
+                let mut active = lock(active);
+                *active -= 1;
+                if *active == 0 && (!self.graceful.finish_request(id) || conn.is_going_away()) {
+                    conn.close();
+                }
+            });
+        }
+        // the handlers finish before the connection goes away
+        drop(scope);
+        conn.shutdown(frame::CANCEL);
+        debug!("HTTP/2 connection ending for {}", addr);
+    }
+
+    fn handle_h2_stream(&self, incoming: Incoming, addr: SocketAddr) {
+        let Incoming { fields, end, mut recv, mut send } = incoming;
+        let head = match request_head(fields) {
+            Some(head) => head,
+            None => {
+                debug!("malformed HTTP/2 request from {}", addr);
+                send.reset(frame::PROTOCOL_ERROR);
+                return;
+            }
+        };
+        let head_only = head.0 == Method::Head;
+        if let Err(e) = recv.set_read_timeout(self.timeouts.read) {
+            info!("set_read_timeout {:?}", e);
+        }
+        let mut out = StreamWriter::new(send, head_only);
+        {
+            let mut wrt = BufWriter::new(&mut out);
+            let stream: &mut dyn NetworkStream = &mut recv;
+            let mut rdr = BufReader::new(stream);
+            match Request::with_head(&mut rdr, addr, head, Http20, end, &self.request_limits) {
+                Ok(req) => {
+                    let mut res_headers = Headers::new();
+                    let mut res = Response::new(&mut wrt, &mut res_headers);
+                    res.version = Http20;
+                    self.handler.handle(req, res);
+                }
+                Err(e) => {
+                    info!("request error = {:?}", e);
+                    self.send_error(&mut wrt, &e, Http20);
+                }
+            }
+            if let Err(e) = wrt.flush() {
+                debug!("error writing HTTP/2 response: {:?}", e);
+            }
+        }
+        if let Err(e) = out.finish() {
+            debug!("error ending HTTP/2 response: {:?}", e);
+        }
+    }
+}
+
+fn lock(active: &Mutex<usize>) -> runtime::MutexGuard<'_, usize> {
+    active.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The method, target and headers of a request, `None` if it's malformed.
+fn request_head(fields: Vec<h2::Field>) -> Option<(Method, RequestUri, Headers)> {
+    let (mut method, mut scheme, mut authority, mut path) = (None, None, None, None);
+    let mut headers = Headers::new();
+    let mut pseudo = true;
+    for (name, value) in fields {
+        let value_str = || String::from_utf8(value.clone()).ok();
+        if let Some(pseudo_name) = name.strip_prefix(b":") {
+            // pseudo-headers come first, once each
+            let field = match pseudo_name {
+                _ if !pseudo => return None,
+                b"method" => &mut method,
+                b"scheme" => &mut scheme,
+                b"authority" => &mut authority,
+                b"path" => &mut path,
+                _ => return None,
+            };
+            if field.replace(value_str()?).is_some() {
+                return None;
+            }
+            continue;
+        }
+        pseudo = false;
+        if name.iter().any(u8::is_ascii_uppercase) || h2::is_connection_header(&name, &value) {
+            return None;
+        }
+        headers.append_raw(String::from_utf8(name).ok()?, value);
+    }
+    let method: Method = method?.parse().ok()?;
+    let uri = if method == Method::Connect {
+        RequestUri::Authority(authority.clone()?)
+    } else {
+        scheme?;
+        path?.parse().ok()?
+    };
+    if let Some(authority) = authority {
+        if !headers.has::<Host>() {
+            headers.set_raw("Host", vec![authority.into_bytes()]);
+        }
+    }
+    Some((method, uri, headers))
+}
+
+/// Turns what a `Response` writes into frames: the HTTP/1 head it formats
+/// becomes `HEADERS`, and the body `DATA`.
+struct StreamWriter {
+    send: SendStream,
+    /// The head written so far, until it's complete.
+    head: Option<Vec<u8>>,
+    /// The fields of the head, until they're sent along with the body.
+    fields: Option<Vec<h2::Field>>,
+    /// The response to a `HEAD` request has no body.
+    head_only: bool,
+}
+
+impl StreamWriter {
+    fn new(send: SendStream, head_only: bool) -> StreamWriter {
+        StreamWriter {
+            send,
+            head: Some(Vec::new()),
+            fields: None,
+            head_only,
+        }
+    }
+
+    fn send_fields(&mut self, end: bool) -> io::Result<()> {
+        match self.fields.take() {
+            Some(fields) => self.send.send_headers(&fields, end),
+            None => Ok(()),
+        }
+    }
+
+    /// End the stream, once the response was written.
+    fn finish(&mut self) -> io::Result<()> {
+        if self.fields.is_some() {
+            self.send_fields(true)?;
+        }
+        self.send.finish()
+    }
+}
+
+impl Write for StreamWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(ref mut head) = self.head {
+            let start = head.len().saturating_sub(3);
+            head.extend_from_slice(buf);
+            let end = match head[start..].windows(4).position(|w| w == b"\r\n\r\n") {
+                Some(i) => start + i + 4,
+                None => return Ok(buf.len()),
+            };
+            let rest = head.split_off(end);
+            let fields = response_fields(head)?;
+            let informational = fields[0].1.starts_with(b"1");
+            if informational {
+                self.send.send_headers(&fields, false)?;
+                head.clear();
+            } else {
+                self.fields = Some(fields);
+                self.head = None;
+            }
+            self.write_all(&rest)?;
+            return Ok(buf.len());
+        }
+        if buf.is_empty() || self.head_only {
+            return Ok(buf.len());
+        }
+        self.send_fields(false)?;
+        self.send.send_data(buf, false)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send_fields(false)
+    }
+}
+
+/// The `:status` and headers of a head like `HTTP/2.0 200 OK\r\nName: value\r\n\r\n`.
+fn response_fields(head: &[u8]) -> io::Result<Vec<h2::Field>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid response head");
+    let head = str::from_utf8(head).map_err(|_| invalid())?;
+    let (line, rest) = head.split_once("\r\n").ok_or_else(invalid)?;
+    let status = line.split(' ').nth(1).filter(|s| s.len() == 3).ok_or_else(invalid)?;
+    let mut fields = vec![(b":status".to_vec(), status.as_bytes().to_vec())];
+    h2::head_fields(rest, &mut fields);
+    Ok(fields)
+}
+
+/// Runs the handlers of a connection's streams, which borrow the `Worker`, and
+/// waits for them once dropped.
+struct Scope {
+    running: usize,
+    done_tx: Sender<()>,
+    done: Receiver<()>,
+}
+
+/// Tells the `Scope` its coroutine is done, even if it panics.
+struct Done(Sender<()>);
+
+impl Drop for Done {
+    fn drop(&mut self) {
+        let _ = self.0.send(());
+    }
+}
+
+impl Scope {
+    fn new() -> Scope {
+        let (done_tx, done) = runtime::chan();
+        Scope { running: 0, done_tx, done }
+    }
+
+    fn spawn<'s, F: FnOnce() + Send + 's>(&mut self, f: F) {
+        let done = Done(self.done_tx.clone());
+        let f: Box<dyn FnOnce() + Send + 's> = Box::new(move || {
+            let _done = done;
+            f()
+        });
+        // Safety: dropping the scope waits for `f`, so what it borrows outlives it
+        let f: Box<dyn FnOnce() + Send + 'static> = unsafe { mem::transmute(f) };
+        self.running += 1;
+        runtime::spawn(f);
+    }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        while self.running > 0 {
+            let _ = self.done.recv();
+            self.running -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::client::Client;
+    use crate::header::ContentLength;
+    use crate::http::h2::{self, frame, Http2Protocol};
+    use crate::method::Method;
+    use crate::net::HttpStream;
+    use crate::runtime;
+    use crate::server::{Request, Response, Server};
+    use crate::status::StatusCode;
+    use crate::version::HttpVersion;
+
+    use super::request_head;
+
+    fn fields(list: &[(&str, &str)]) -> Vec<h2::Field> {
+        list.iter().map(|&(n, v)| (n.as_bytes().to_vec(), v.as_bytes().to_vec())).collect()
+    }
+
+    #[test]
+    fn test_request_head() {
+        let (method, uri, headers) = request_head(fields(&[
+            (":method", "POST"), (":scheme", "http"), (":authority", "example.domain"),
+            (":path", "/a?b"), ("accept", "*/*"),
+        ])).unwrap();
+        assert_eq!(method, Method::Post);
+        assert_eq!(uri, "/a?b".parse().unwrap());
+        assert_eq!(headers.get_raw("Host").unwrap(), &[b"example.domain".to_vec()][..]);
+        assert_eq!(headers.get_raw("Accept").unwrap(), &[b"*/*".to_vec()][..]);
+
+        let get = [(":method", "GET"), (":scheme", "http"), (":path", "/")];
+        assert!(request_head(fields(&get)).is_some());
+        // a pseudo-header after a header, twice, or unknown
+        assert!(request_head(fields(&[get[0], get[1], ("accept", "*/*"), get[2]])).is_none());
+        assert!(request_head(fields(&[get[0], get[1], get[2], get[2]])).is_none());
+        assert!(request_head(fields(&[get[0], get[1], get[2], (":status", "200")])).is_none());
+        // missing a pseudo-header
+        assert!(request_head(fields(&get[..2])).is_none());
+        // uppercase and connection headers
+        assert!(request_head(fields(&[get[0], get[1], get[2], ("Accept", "*/*")])).is_none());
+        assert!(request_head(fields(&[get[0], get[1], get[2], ("connection", "close")])).is_none());
+        assert!(request_head(fields(&[get[0], get[1], get[2], ("te", "gzip")])).is_none());
+        assert!(request_head(fields(&[get[0], get[1], get[2], ("te", "trailers")])).is_some());
+    }
+
+    fn echo(mut req: Request, mut res: Response) {
+        let mut body = Vec::new();
+        req.read_to_end(&mut body).unwrap();
+        res.headers_mut().set_raw("X-Method", vec![req.method.to_string().into_bytes()]);
+        res.headers_mut().set_raw("X-Uri", vec![req.uri.to_string().into_bytes()]);
+        if body.is_empty() {
+            *res.status_mut() = StatusCode::NoContent;
+            return;
+        }
+        res.send(&body).unwrap();
+    }
+
+    #[test]
+    fn test_h2c_round_trip() {
+        let mut listening = Server::http("127.0.0.1:0").unwrap().handle_threads(echo, 2).unwrap();
+        let url = format!("http://{}/echo?x=1", listening.socket);
+        let client = Client::with_protocol(Http2Protocol::new());
+
+        let mut res = client.post(&url).body("hello").send().unwrap();
+        assert_eq!(res.version, HttpVersion::Http20);
+        assert_eq!(res.status, StatusCode::Ok);
+        assert_eq!(res.headers.get_raw("X-Method").unwrap(), &[b"POST".to_vec()][..]);
+        assert_eq!(res.headers.get_raw("X-Uri").unwrap(), &[b"/echo?x=1".to_vec()][..]);
+        assert_eq!(res.headers.get(), Some(&ContentLength(5)));
+        let mut body = String::new();
+        res.read_to_string(&mut body).unwrap();
+        assert_eq!(body, "hello");
+
+        let res = client.get(&url).send().unwrap();
+        assert_eq!(res.status, StatusCode::NoContent);
+        assert_eq!(res.headers.get_raw("X-Method").unwrap(), &[b"GET".to_vec()][..]);
+
+        let mut res = client.head(&url).send().unwrap();
+        assert_eq!(res.status, StatusCode::NoContent);
+        let mut body = Vec::new();
+        res.read_to_end(&mut body).unwrap();
+        assert!(body.is_empty());
+        drop(client);
+        listening.close().unwrap();
+    }
+
+    #[test]
+    fn test_h2c_multiplexing() {
+        let peers = Arc::new(Mutex::new(HashSet::new()));
+        let seen = peers.clone();
+        let mut listening = Server::http("127.0.0.1:0").unwrap().handle_threads(move |req: Request, res: Response| {
+            seen.lock().unwrap().insert(req.remote_addr);
+            runtime::sleep(Duration::from_millis(100));
+            echo(req, res);
+        }, 2).unwrap();
+        let url = format!("http://{}/", listening.socket);
+        let client = Arc::new(Client::with_protocol(Http2Protocol::new()));
+
+        // larger than the initial flow-control windows
+        let body: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+        let threads: Vec<_> = (0..8).map(|_| {
+            let (client, url, body) = (client.clone(), url.clone(), body.clone());
+            thread::spawn(move || {
+                let mut res = client.post(&url).body(&body[..]).send().unwrap();
+                let mut out = Vec::new();
+                res.read_to_end(&mut out).unwrap();
+                assert_eq!(out, body);
+            })
+        }).collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        // the requests that connected at once share one connection
+        assert_eq!(peers.lock().unwrap().len(), 1);
+        drop(client);
+        listening.close().unwrap();
+    }
+
+    #[test]
+    fn test_h2c_connection_stream() {
+        let mut listening = Server::http("127.0.0.1:0").unwrap().handle_threads(|req: Request, res: Response| {
+            let peer = req.downcast_ref::<HttpStream>().map(|s| s.0.local_addr().unwrap());
+            res.send(format!("{:?}", peer).as_bytes()).unwrap();
+        }, 2).unwrap();
+        let client = Client::with_protocol(Http2Protocol::new());
+        let mut res = client.get(&format!("http://{}/", listening.socket)).send().unwrap();
+        let mut body = String::new();
+        res.read_to_string(&mut body).unwrap();
+        assert_eq!(body, format!("Some({})", listening.socket));
+        drop(client);
+        listening.close().unwrap();
+    }
+
+    #[test]
+    fn test_h2c_malformed_request_is_reset() {
+        let mut listening = Server::http("127.0.0.1:0").unwrap().handle_threads(echo, 2).unwrap();
+        let mut s = TcpStream::connect(listening.socket).unwrap();
+        s.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut out = h2::PREFACE.to_vec();
+        frame::write_frame(&mut out, frame::SETTINGS, 0, 0, &[]).unwrap();
+        // a HEADERS block of `:method: GET` alone, indexed from the static table
+        frame::write_frame(&mut out, frame::HEADERS, frame::END_HEADERS | frame::END_STREAM, 1, &[0x82]).unwrap();
+        s.write_all(&out).unwrap();
+
+        let mut frames = frame::FrameReader::new(frame::DEFAULT_MAX_FRAME_SIZE);
+        loop {
+            let f = frames.read(&mut s).unwrap();
+            if f.kind == frame::RST_STREAM {
+                assert_eq!(f.stream, 1);
+                assert_eq!(frame::read_u32(&f.payload), frame::PROTOCOL_ERROR);
+                break;
+            }
+        }
+        listening.close().unwrap();
+    }
+
+    /// The preface and a `SETTINGS` frame, then a `GET /` for each stream in
+    /// `ids`, reset right after if `reset`.
+    fn open_streams(ids: impl Iterator<Item = u32>, reset: bool) -> Vec<u8> {
+        let mut out = Vec::new();
+        for id in ids {
+            // `:method: GET`, `:scheme: http` and `:path: /` from the static table
+            frame::write_frame(&mut out, frame::HEADERS, frame::END_HEADERS | frame::END_STREAM, id, &[0x82, 0x86, 0x84]).unwrap();
+            if reset {
+                frame::write_frame(&mut out, frame::RST_STREAM, 0, id, &frame::CANCEL.to_be_bytes()).unwrap();
+            }
+        }
+        out
+    }
+
+    fn connect_h2(addr: std::net::SocketAddr) -> TcpStream {
+        let mut s = TcpStream::connect(addr).unwrap();
+        s.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut out = h2::PREFACE.to_vec();
+        frame::write_frame(&mut out, frame::SETTINGS, 0, 0, &[]).unwrap();
+        s.write_all(&out).unwrap();
+        s
+    }
+
+    #[test]
+    fn test_h2c_reset_streams_count_until_handled() {
+        let released = Arc::new(AtomicBool::new(false));
+        let release = released.clone();
+        let mut listening = Server::http("127.0.0.1:0").unwrap().handle_threads(move |_: Request, res: Response| {
+            while !released.load(Ordering::SeqCst) {
+                runtime::sleep(Duration::from_millis(10));
+            }
+            res.send(b"ok").unwrap();
+        }, 2).unwrap();
+        let mut s = connect_h2(listening.socket);
+        // the handlers of the streams reset are still running
+        let max = h2::MAX_CONCURRENT_STREAMS;
+        s.write_all(&open_streams((0..max).map(|i| 2 * i + 1), true)).unwrap();
+        s.write_all(&open_streams(std::iter::once(2 * max + 1), false)).unwrap();
+
+        let mut frames = frame::FrameReader::new(frame::DEFAULT_MAX_FRAME_SIZE);
+        loop {
+            let f = frames.read(&mut s).unwrap();
+            assert_ne!(f.kind, frame::HEADERS);
+            if f.kind == frame::RST_STREAM {
+                assert_eq!(f.stream, 2 * max + 1);
+                assert_eq!(frame::read_u32(&f.payload), frame::REFUSED_STREAM);
+                break;
+            }
+        }
+        release.store(true, Ordering::SeqCst);
+        listening.close().unwrap();
+    }
+
+    #[test]
+    fn test_h2c_reset_flood() {
+        let mut listening = Server::http("127.0.0.1:0").unwrap().handle_threads(|_: Request, res: Response| {
+            runtime::sleep(Duration::from_millis(100));
+            res.send(b"ok").unwrap();
+        }, 2).unwrap();
+        let mut s = connect_h2(listening.socket);
+        s.write_all(&open_streams((0..1000).map(|i| 2 * i + 1), true)).unwrap();
+
+        let mut frames = frame::FrameReader::new(frame::DEFAULT_MAX_FRAME_SIZE);
+        loop {
+            let f = frames.read(&mut s).unwrap();
+            if f.kind == frame::GOAWAY {
+                assert_eq!(frame::read_u32(&f.payload[4..]), frame::ENHANCE_YOUR_CALM);
+                break;
+            }
+        }
+        listening.close().unwrap();
+    }
+}
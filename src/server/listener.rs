@@ -1,68 +1,175 @@
-use std::sync::{Arc, mpsc};
+use std::collections::HashMap;
+use std::net::{IpAddr, Shutdown};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-use crate::net::NetworkListener;
-use crate::runtime;
+use crate::net::{NetworkListener, NetworkStream};
+use crate::runtime::{self, Mutex, Sender};
+use crate::status::StatusCode;
+use crate::version::HttpVersion::Http11;
+
+use super::{ConnectionLimits, Graceful};
 
 pub struct ListenerPool<A: NetworkListener> {
-    acceptor: A
+    acceptor: A,
+    limits: ConnectionLimits,
 }
 
 impl<A: NetworkListener + Send + 'static> ListenerPool<A> {
     /// Create a thread pool to manage the acceptor.
-    pub fn new(acceptor: A) -> ListenerPool<A> {
-        ListenerPool { acceptor: acceptor }
+    pub fn new(acceptor: A, limits: ConnectionLimits) -> ListenerPool<A> {
+        ListenerPool { acceptor, limits }
     }
 
-    /// Runs the acceptor pool. Blocks until the acceptors are closed.
+    /// Runs the acceptor pool. Blocks until the acceptors are closed,
+    /// which happens once `graceful` is closing and each acceptor has woken up.
     ///
     /// ## Panics
     ///
     /// Panics if threads == 0.
-    pub fn accept<F>(self, work: F, threads: usize)
+    pub fn accept<F>(self, work: F, threads: usize, graceful: Arc<Graceful>)
         where F: Fn(A::Stream) + Send + Sync + 'static {
         assert!(threads != 0, "Can't accept on 0 threads.");
 
-        let (super_tx, supervisor_rx) = mpsc::channel();
+        let (super_tx, supervisor_rx) = runtime::chan();
 
         let work = Arc::new(work);
+        let limiter = Arc::new(Limiter::new(self.limits));
 
         // Begin work.
         for _ in 0..threads {
-            spawn_with(super_tx.clone(), work.clone(), self.acceptor.clone())
+            spawn_with(super_tx.clone(), work.clone(), self.acceptor.clone(), graceful.clone(), limiter.clone())
         }
 
-        // Monitor for panics.
-        // FIXME(reem): This won't ever exit since we still have a super_tx handle.
+        // Monitor for panics, and for acceptors stopping on close.
+        let mut live = threads;
         for _ in supervisor_rx.iter() {
-            spawn_with(super_tx.clone(), work.clone(), self.acceptor.clone());
+            if graceful.is_closing() {
+                live -= 1;
+                if live == 0 {
+                    break;
+                }
+            } else {
+                spawn_with(super_tx.clone(), work.clone(), self.acceptor.clone(), graceful.clone(), limiter.clone());
+            }
         }
+        debug!("acceptors closed");
     }
 }
 
-fn spawn_with<A, F>(supervisor: mpsc::Sender<()>, work: Arc<F>, mut acceptor: A)
+fn spawn_with<A, F>(supervisor: Sender<()>, work: Arc<F>, mut acceptor: A,
+                    graceful: Arc<Graceful>, limiter: Arc<Limiter>)
 where A: NetworkListener + Send + 'static,
       F: Fn(<A as NetworkListener>::Stream) + Send + Sync + 'static {
     runtime::spawn(move || {
         let _sentinel = Sentinel::new(supervisor, ());
 
-        loop {
-            match acceptor.accept() {
-                Ok(stream) => work(stream),
+        while !graceful.is_closing() {
+            let accepted = acceptor.accept();
+            if graceful.is_closing() {
+                break;
+            }
+            let mut stream = match accepted {
+                Ok(stream) => stream,
                 Err(e) => {
                     info!("Connection failed: {}", e);
+                    continue;
+                }
+            };
+            let ip = stream.peer_addr().ok().map(|addr| addr.ip());
+            match Limiter::acquire(&limiter, ip) {
+                Some(permit) => {
+                    let work = work.clone();
+                    runtime::spawn(move || {
+                        let _permit = permit;
+                        work(stream)
+                    });
+                }
+                None => {
+                    debug!("connection limit hit for {:?}", ip);
+                    reject(&mut stream);
                 }
             }
         }
     });
 }
 
+/// Answer a connection over the limits with `503 Service Unavailable` and close it.
+fn reject<S: NetworkStream>(stream: &mut S) {
+    let res = write!(stream, "{} {}\r\nConnection: close\r\nContent-Length: 0\r\n\r\n",
+                     Http11, StatusCode::ServiceUnavailable)
+        .and_then(|_| stream.flush());
+    if let Err(e) = res {
+        debug!("error writing 503: {:?}", e);
+    }
+    let _ = stream.close(Shutdown::Both);
+}
+
+/// Counts the open connections, in total and per client ip.
+struct Limiter {
+    limits: ConnectionLimits,
+    total: AtomicUsize,
+    per_ip: Mutex<HashMap<IpAddr, usize>>,
+}
+
+impl Limiter {
+    fn new(limits: ConnectionLimits) -> Limiter {
+        Limiter {
+            limits,
+            total: AtomicUsize::new(0),
+            per_ip: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Count a new connection, `None` if it is over the limits.
+    fn acquire(limiter: &Arc<Limiter>, ip: Option<IpAddr>) -> Option<Permit> {
+        let total = limiter.total.fetch_add(1, Ordering::SeqCst) + 1;
+        // uncounts the connection if it is rejected
+        let mut permit = Permit { limiter: limiter.clone(), ip: None };
+        if limiter.limits.max_connections.is_some_and(|max| total > max) {
+            return None;
+        }
+        if let (Some(max), Some(ip)) = (limiter.limits.max_connections_per_ip, ip) {
+            let mut per_ip = limiter.per_ip.lock().unwrap_or_else(|e| e.into_inner());
+            let count = per_ip.entry(ip).or_insert(0);
+            if *count >= max {
+                return None;
+            }
+            *count += 1;
+            permit.ip = Some(ip);
+        }
+        Some(permit)
+    }
+}
+
+/// A counted connection, uncounted on drop.
+struct Permit {
+    limiter: Arc<Limiter>,
+    ip: Option<IpAddr>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.limiter.total.fetch_sub(1, Ordering::SeqCst);
+        if let Some(ip) = self.ip {
+            let mut per_ip = self.limiter.per_ip.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(count) = per_ip.get_mut(&ip) {
+                *count -= 1;
+                if *count == 0 {
+                    per_ip.remove(&ip);
+                }
+            }
+        }
+    }
+}
+
 struct Sentinel<T: Send + 'static> {
     value: Option<T>,
-    supervisor: mpsc::Sender<T>,
+    supervisor: Sender<T>,
 }
 
 impl<T: Send + 'static> Sentinel<T> {
-    fn new(channel: mpsc::Sender<T>, data: T) -> Sentinel<T> {
+    fn new(channel: Sender<T>, data: T) -> Sentinel<T> {
         Sentinel {
             value: Some(data),
             supervisor: channel,
@@ -20,7 +20,7 @@
 //!     // handle things here
 //! }
 //!
-//! Server::http("0.0.0.0:0").unwrap().handle(hello).unwrap();
+//! Server::http("0.0.0.0:0").unwrap().handle(hello).unwrap().join().unwrap();
 //! ```
 //!
 //! As with any trait, you can also define a struct and implement `Handler`
@@ -107,29 +107,37 @@
 //! out by calling `start` on the `Response<Fresh>`. This will return a new
 //! `Response<Streaming>` object, that no longer has `headers_mut()`, but does
 //! implement `Write`.
+use std::cell::Cell;
+use std::collections::HashMap;
 use std::fmt;
 use std::io::{self, ErrorKind, BufWriter, Write};
-use std::net::{SocketAddr, ToSocketAddrs, Shutdown};
-use std::time::Duration;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, Shutdown};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
 use num_cpus;
 
 pub use self::request::Request;
-pub use self::response::Response;
+pub use self::response::{BodyEncoder, Html, IntoResponse, Redirect, Response};
+pub use self::upgrade::Upgraded;
 
 pub use crate::net::{Fresh, Streaming};
 
 use crate::{Error, runtime};
 use crate::buffer::BufReader;
-use crate::header::{Headers, Expect, Connection};
+use crate::header::{Headers, Expect, Connection, ContentLength};
+#[cfg(feature = "compression")]
+use crate::header::ContentEncoding;
 use crate::http;
 use crate::method::Method;
 use crate::net::{NetworkListener, NetworkStream, HttpListener, HttpsListener, SslServer};
 use crate::status::StatusCode;
 use crate::uri::RequestUri;
-use crate::version::HttpVersion::Http11;
+use crate::version::HttpVersion::{self, Http11};
 
 use self::listener::ListenerPool;
+use self::upgrade::Hijack;
 
 pub mod request;
 pub mod response;
@@ -138,7 +146,9 @@ pub mod extensions;
 
 pub use extensions::*;
 
+mod h2;
 mod listener;
+mod upgrade;
 
 /// A server can listen on a TCP socket.
 ///
@@ -148,6 +158,8 @@ mod listener;
 pub struct Server<L = HttpListener> {
     listener: L,
     timeouts: Timeouts,
+    limits: ConnectionLimits,
+    request_limits: RequestLimits,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -165,13 +177,116 @@ impl Default for Timeouts {
     }
 }
 
+/// Caps on the connections a server handles, `None` means no limit.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ConnectionLimits {
+    /// Connections open at the same time, more get a `503 Service Unavailable`.
+    pub max_connections: Option<usize>,
+    /// Connections open at the same time from one client ip, more get a `503 Service Unavailable`.
+    pub max_connections_per_ip: Option<usize>,
+    /// Requests on one keep-alive connection, the last one is answered with `Connection: close`.
+    pub max_requests_per_connection: Option<usize>,
+}
+
+/// Limits on the requests a server accepts, `None` means no limit.
+///
+/// A `Route` can apply its own limits on top of the server's, see `route::limited`.
+#[derive(Clone, Copy, Debug)]
+pub struct RequestLimits {
+    /// The most header lines in a request, more get `431 Request Header Fields Too Large`.
+    /// A header sent on several lines counts once for each.
+    pub max_headers: usize,
+    /// The largest request line and headers in bytes, a bigger head gets
+    /// `431 Request Header Fields Too Large`. It can't go over `buffer::MAX_BUFFER_SIZE`.
+    pub max_head_size: usize,
+    /// The longest request-target, a longer one gets `414 URI Too Long`.
+    pub max_uri_length: Option<usize>,
+    /// The largest body, a bigger one gets `413 Payload Too Large`. A body without
+    /// a `Content-Length` fails to read once it goes over the limit.
+    pub max_body_size: Option<u64>,
+    /// Decode bodies with a `Content-Encoding` of `br`, `gzip` or `deflate` while
+    /// they are read, failing with `Error::BodyTooLarge` once the decoded body goes
+    /// over this many bytes. `None` leaves the body as it was sent. Needs the
+    /// `compression` feature, without it bodies are always left as they were sent.
+    pub max_decoded_body_size: Option<u64>,
+}
+
+impl Default for RequestLimits {
+    fn default() -> RequestLimits {
+        RequestLimits {
+            max_headers: http::h1::MAX_HEADERS,
+            max_head_size: crate::buffer::MAX_BUFFER_SIZE,
+            max_uri_length: None,
+            max_body_size: None,
+            max_decoded_body_size: None,
+        }
+    }
+}
+
+impl RequestLimits {
+    /// Checks the head of `req` against these limits and limits its body to
+    /// `max_body_size`. The size of the head is only checked while parsing it.
+    pub fn apply<'a, 'k>(&self, mut req: Request<'a, 'k>) -> crate::Result<Request<'a, 'k>> {
+        if req.headers.iter().map(|h| h.raw_len()).sum::<usize>() > self.max_headers {
+            return Err(Error::TooLarge);
+        }
+        if let Some(max) = self.max_uri_length {
+            let len = match req.uri {
+                RequestUri::AbsolutePath(ref p) | RequestUri::Authority(ref p) => p.len(),
+                RequestUri::AbsoluteUri(ref url) => url.as_str().len(),
+                RequestUri::Star => 1,
+            };
+            if len > max {
+                return Err(Error::UriTooLong);
+            }
+        }
+        if let Some(max) = self.max_body_size {
+            if let Some(&ContentLength(len)) = req.headers.get() {
+                if len > max {
+                    return Err(Error::BodyTooLarge);
+                }
+            }
+            req.body = req.body.limit(max);
+        }
+        #[cfg(feature = "compression")]
+        if let Some(max) = self.max_decoded_body_size {
+            if req.decoder.is_none() {
+                req.decoder = crate::compress::Decoder::for_headers(&req.headers);
+                if req.decoder.is_some() {
+                    // the handler reads the decoded body
+                    req.headers.remove::<ContentEncoding>();
+                    req.headers.remove::<ContentLength>();
+                }
+            }
+            if let Some(ref mut decoder) = req.decoder {
+                decoder.limit(max);
+            }
+        }
+        Ok(req)
+    }
+}
+
+/// The status a request failing with `err` is answered with.
+pub(crate) fn error_status(err: &Error) -> StatusCode {
+    match *err {
+        Error::TooLarge => StatusCode::RequestHeaderFieldsTooLarge,
+        Error::BodyTooLarge => StatusCode::PayloadTooLarge,
+        Error::UriTooLong => StatusCode::UriTooLong,
+        Error::TransferCoding => StatusCode::NotImplemented,
+        Error::Version => StatusCode::HttpVersionNotSupported,
+        _ => StatusCode::BadRequest,
+    }
+}
+
 impl<L: NetworkListener> Server<L> {
     /// Creates a new server with the provided handler.
     #[inline]
     pub fn new(listener: L) -> Server<L> {
         Server {
             listener: listener,
-            timeouts: Timeouts::default()
+            timeouts: Timeouts::default(),
+            limits: ConnectionLimits::default(),
+            request_limits: RequestLimits::default(),
         }
     }
 
@@ -188,6 +303,83 @@ impl<L: NetworkListener> Server<L> {
         self.timeouts.keep_alive = timeout;
     }
 
+    /// Limits the connections handled at the same time. When the limit is hit,
+    /// new connections are answered with `503 Service Unavailable` and closed.
+    ///
+    /// Default is no limit.
+    #[inline]
+    pub fn max_connections(&mut self, max: Option<usize>) {
+        self.limits.max_connections = max;
+    }
+
+    /// Limits the connections handled at the same time for each client ip.
+    /// When the limit is hit, new connections from that ip are answered with
+    /// `503 Service Unavailable` and closed.
+    ///
+    /// Default is no limit.
+    #[inline]
+    pub fn max_connections_per_ip(&mut self, max: Option<usize>) {
+        self.limits.max_connections_per_ip = max;
+    }
+
+    /// Limits the requests served on one keep-alive connection, the connection
+    /// is closed after the last one.
+    ///
+    /// Default is no limit.
+    #[inline]
+    pub fn max_requests_per_connection(&mut self, max: Option<usize>) {
+        self.limits.max_requests_per_connection = max;
+    }
+
+    /// Limits the size of request bodies. A request with a bigger `Content-Length`
+    /// is answered with `413 Payload Too Large`, without calling the handler.
+    /// Reading a chunked body fails once it goes over the limit.
+    ///
+    /// Default is no limit.
+    #[inline]
+    pub fn max_body_size(&mut self, max: Option<u64>) {
+        self.request_limits.max_body_size = max;
+    }
+
+    /// Limits the number of headers in a request, a request with more is answered
+    /// with `431 Request Header Fields Too Large`.
+    ///
+    /// Default is 100.
+    #[inline]
+    pub fn max_headers(&mut self, max: usize) {
+        self.request_limits.max_headers = max;
+    }
+
+    /// Limits the size in bytes of the request line and headers, a bigger head is
+    /// answered with `431 Request Header Fields Too Large`. Values over
+    /// `buffer::MAX_BUFFER_SIZE` have no effect.
+    ///
+    /// Default is `buffer::MAX_BUFFER_SIZE`.
+    #[inline]
+    pub fn max_head_size(&mut self, max: usize) {
+        self.request_limits.max_head_size = max;
+    }
+
+    /// Limits the length of the request-target, a longer one is answered with
+    /// `414 URI Too Long`.
+    ///
+    /// Default is no limit.
+    #[inline]
+    pub fn max_uri_length(&mut self, max: Option<usize>) {
+        self.request_limits.max_uri_length = max;
+    }
+
+    /// Decodes request bodies sent with a `Content-Encoding` of `br`, `gzip` or
+    /// `deflate`, so handlers read them as plain bytes. Reading fails once the
+    /// decoded body goes over `max_size`, which guards against decompression bombs.
+    ///
+    /// Default is `None`, bodies are read as they were sent.
+    #[cfg(feature = "compression")]
+    #[inline]
+    pub fn decompress_requests(&mut self, max_size: Option<u64>) {
+        self.request_limits.max_decoded_body_size = max_size;
+    }
+
     /// Sets the read timeout for all Request reads.
     pub fn set_read_timeout(&mut self, dur: Option<Duration>) {
         self.listener.set_read_timeout(dur);
@@ -223,18 +415,21 @@ impl<S: SslServer + Clone + Send> Server<HttpsListener<S>> {
 
 impl<L: NetworkListener + Send + 'static> Server<L> {
     /// Binds to a socket and starts handling connections.
+    #[must_use = "dropping the Listening closes the server, call `join` to serve until it stops"]
     pub fn handle<H: Handler + 'static>(self, handler: H) -> crate::Result<Listening> {
         self.handle_threads(handler, num_cpus::get() * 2)
     }
 
     /// Binds to a socket and starts handling connections with the provided
     /// number of threads.
+    #[must_use = "dropping the Listening closes the server, call `join` to serve until it stops"]
     pub fn handle_threads<H: Handler + 'static>(self, handler: H,
             threads: usize) -> crate::Result<Listening> {
         handle(self, handler, threads)
     }
 
     /// Binds to a socket and starts handling connections.
+    #[must_use = "dropping the Listening closes the server, call `join` to serve until it stops"]
     pub fn handle_accept(self, handler: fn(L::Stream)) -> crate::Result<Listening> {
         handle_accept::<L>(self,handler, num_cpus::get() * 2)
     }
@@ -245,17 +440,23 @@ where H: Handler + 'static, L: NetworkListener + Send + 'static {
     let socket = server.listener.local_addr()?;
 
     debug!("threads = {:?}", threads);
-    let pool = ListenerPool::new(server.listener);
-    let worker = Worker::new(handler, server.timeouts);
+    let pool = ListenerPool::new(server.listener, server.limits);
+    let mut worker = Worker::new(handler, server.timeouts);
+    worker.limits = server.limits;
+    worker.request_limits = server.request_limits;
+    let graceful = worker.graceful.clone();
     let work = move |mut stream| {
         worker.handle_connection(&mut stream);
     };
 
-    let guard = runtime::spawn(move || pool.accept(work, threads));
+    let g = graceful.clone();
+    let guard = runtime::spawn(move || pool.accept(work, threads, g));
 
     Ok(Listening {
         _guard: Some(guard),
         socket: socket,
+        graceful,
+        threads,
     })
 }
 
@@ -264,22 +465,115 @@ fn handle_accept<L>(mut server: Server<L>, handler: fn(L::Stream), threads: usiz
     let socket = server.listener.local_addr()?;
 
     debug!("threads = {:?}", threads);
-    let pool = ListenerPool::new(server.listener);
+    let pool = ListenerPool::new(server.listener, server.limits);
     let work = move |stream| {
         handler(stream)
     };
 
-    let guard = runtime::spawn(move || pool.accept(work, threads));
+    let graceful = Arc::new(Graceful::new());
+    let g = graceful.clone();
+    let guard = runtime::spawn(move || pool.accept(work, threads, g));
 
     Ok(Listening {
         _guard: Some(guard),
         socket: socket,
+        graceful,
+        threads,
     })
 }
 
+/// The open connections of a server, so that `Listening` can stop accepting,
+/// close the idle connections and wait for the busy ones.
+pub(crate) struct Graceful {
+    closing: AtomicBool,
+    next_id: AtomicUsize,
+    connections: runtime::Mutex<HashMap<usize, Conn>>,
+}
+
+struct Conn {
+    stream: Box<dyn NetworkStream>,
+    busy: bool,
+}
+
+impl Graceful {
+    fn new() -> Graceful {
+        Graceful {
+            closing: AtomicBool::new(false),
+            next_id: AtomicUsize::new(0),
+            connections: runtime::Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn lock(&self) -> runtime::MutexGuard<'_, HashMap<usize, Conn>> {
+        self.connections.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn is_closing(&self) -> bool {
+        self.closing.load(Ordering::SeqCst)
+    }
+
+    /// Track a new connection, `None` if the server is closing.
+    fn register(&self, stream: Box<dyn NetworkStream>) -> Option<usize> {
+        let mut connections = self.lock();
+        if self.is_closing() {
+            return None;
+        }
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        connections.insert(id, Conn { stream, busy: false });
+        Some(id)
+    }
+
+    fn remove(&self, id: usize) {
+        self.lock().remove(&id);
+    }
+
+    /// Mark a connection as handling a request. Returns false if the server is closing,
+    /// so this must be the last request on the connection.
+    fn start_request(&self, id: usize) -> bool {
+        let mut connections = self.lock();
+        if let Some(c) = connections.get_mut(&id) {
+            c.busy = true;
+        }
+        !self.is_closing()
+    }
+
+    /// Mark a connection as idle. Returns false if the server is closing,
+    /// so the connection must not wait for another request.
+    fn finish_request(&self, id: usize) -> bool {
+        let mut connections = self.lock();
+        if let Some(c) = connections.get_mut(&id) {
+            c.busy = false;
+        }
+        !self.is_closing()
+    }
+
+    /// Stop accepting, and close the connections waiting for a request.
+    fn close_idle(&self) {
+        let mut connections = self.lock();
+        self.closing.store(true, Ordering::SeqCst);
+        for c in connections.values_mut().filter(|c| !c.busy) {
+            let _ = c.stream.close(Shutdown::Both);
+        }
+    }
+
+    /// Close every connection, even in the middle of a request.
+    fn close_all(&self) {
+        for c in self.lock().values_mut() {
+            let _ = c.stream.close(Shutdown::Both);
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.lock().len()
+    }
+}
+
 pub struct Worker<H: Handler + 'static> {
     handler: H,
     timeouts: Timeouts,
+    limits: ConnectionLimits,
+    request_limits: RequestLimits,
+    graceful: Arc<Graceful>,
 }
 
 impl<H: Handler + 'static> Worker<H> {
@@ -287,6 +581,9 @@ impl<H: Handler + 'static> Worker<H> {
         Worker {
             handler: handler,
             timeouts: timeouts,
+            limits: ConnectionLimits::default(),
+            request_limits: RequestLimits::default(),
+            graceful: Arc::new(Graceful::new()),
         }
     }
 
@@ -303,21 +600,44 @@ impl<H: Handler + 'static> Worker<H> {
             }
         };
 
+        let id = match self.graceful.register(Box::new(stream.clone())) {
+            Some(id) => id,
+            None => {
+                debug!("server is closing, dropping connection from {}", addr);
+                self.handler.on_connection_end();
+                return;
+            }
+        };
+
         let stream2: &mut dyn NetworkStream = &mut stream.clone();
         let mut rdr = BufReader::new(stream2);
-        let mut wrt = BufWriter::new(stream);
 
-        while self.keep_alive_loop(&mut rdr, &mut wrt, addr) {
-            if let Err(e) = self.set_read_timeout(*rdr.get_ref(), self.timeouts.keep_alive) {
-                info!("set_read_timeout keep_alive {:?}", e);
-                break;
+        let hijacked = Cell::new(false);
+        match h2::read_preface(&mut rdr) {
+            Ok(true) => self.handle_h2(&mut rdr, stream, addr, id),
+            Ok(false) => {
+                let mut wrt = BufWriter::new(stream);
+                let hijack = Hijack::new::<S>(&hijacked);
+                let mut requests = 0;
+                while self.keep_alive_loop(&mut rdr, &mut wrt, addr, id, &mut requests, hijack) {
+                    if let Err(e) = self.set_read_timeout(*rdr.get_ref(), self.timeouts.keep_alive) {
+                        info!("set_read_timeout keep_alive {:?}", e);
+                        break;
+                    }
+                }
             }
+            Err(e) => debug!("error reading from {}: {:?}", addr, e),
         }
 
+        self.graceful.remove(id);
         self.handler.on_connection_end();
 
         debug!("keep_alive loop ending for {}", addr);
 
+        // the handler owns the connection now
+        if hijacked.get() {
+            return;
+        }
         if let Err(e) = rdr.get_mut().close(Shutdown::Both) {
             info!("failed to close stream: {}", e);
         }
@@ -328,8 +648,9 @@ impl<H: Handler + 'static> Worker<H> {
     }
 
     fn keep_alive_loop<W: Write>(&self, rdr: &mut BufReader<&mut dyn NetworkStream>,
-            wrt: &mut W, addr: SocketAddr) -> bool {
-        let req = match Request::new(rdr, addr) {
+            wrt: &mut W, addr: SocketAddr, id: usize, requests: &mut usize,
+            hijack: Hijack) -> bool {
+        let mut req = match Request::with_limits(rdr, addr, &self.request_limits) {
             Ok(req) => req,
             Err(Error::Io(ref e)) if e.kind() == ErrorKind::ConnectionAborted => {
                 trace!("tcp closed, cancelling keep-alive loop");
@@ -340,12 +661,18 @@ impl<H: Handler + 'static> Worker<H> {
                 return false;
             }
             Err(e) => {
-                //TODO: send a 400 response
                 info!("request error = {:?}", e);
+                self.send_error(wrt, &e, Http11);
                 return false;
             }
         };
 
+        req.hijack = Some(hijack);
+
+        let closing = !self.graceful.start_request(id);
+        *requests += 1;
+        let last = self.limits.max_requests_per_connection.is_some_and(|max| *requests >= max);
+
         if !self.handle_expect(&req, wrt) {
             return false;
         }
@@ -355,16 +682,28 @@ impl<H: Handler + 'static> Worker<H> {
             return false;
         }
 
-        let mut keep_alive = self.timeouts.keep_alive.is_some() &&
+        let mut keep_alive = !closing && !last && self.timeouts.keep_alive.is_some() &&
             http::should_keep_alive(req.version, &req.headers);
         let version = req.version;
         let mut res_headers = Headers::new();
         if !keep_alive {
             res_headers.set(Connection::close());
         }
+        let upgraded = Cell::new(false);
         {
             let mut res = Response::new(wrt, &mut res_headers);
             res.version = version;
+            // after a protocol switch the connection belongs to the handler
+            res.on_head(|status, _| upgraded.set(*status == StatusCode::SwitchingProtocols));
+            if keep_alive {
+                // the server may start closing while the handler runs
+                let graceful = &self.graceful;
+                res.on_head(move |_, headers| {
+                    if graceful.is_closing() {
+                        headers.set(Connection::close());
+                    }
+                });
+            }
             self.handler.handle(req, res);
         }
 
@@ -373,11 +712,30 @@ impl<H: Handler + 'static> Worker<H> {
         if keep_alive {
             keep_alive = http::should_keep_alive(version, &res_headers);
         }
+        if !self.graceful.finish_request(id) || upgraded.get() || hijack.taken.get() {
+            keep_alive = false;
+        }
 
         debug!("keep_alive = {:?} for {}", keep_alive, addr);
         keep_alive
     }
 
+    /// Answer a request that can't be handled, the connection is closed afterwards.
+    fn send_error<W: Write>(&self, wrt: &mut W, err: &Error, version: HttpVersion) {
+        let status = error_status(err);
+        let mut headers = Headers::new();
+        headers.set(Connection::close());
+        {
+            let mut res = Response::new(wrt, &mut headers);
+            res.status = status;
+            res.version = version;
+            self.handler.on_error(err, res);
+        }
+        if let Err(e) = wrt.flush() {
+            info!("error writing {} response: {:?}", status, e);
+        }
+    }
+
     fn handle_expect<W: Write>(&self, req: &Request, wrt: &mut W) -> bool {
          if req.version == Http11 && req.headers.get() == Some(&Expect::Continue) {
             let status = self.handler.check_continue((&req.method, &req.uri, &req.headers));
@@ -400,10 +758,16 @@ impl<H: Handler + 'static> Worker<H> {
 }
 
 /// A listening server, which can later be closed.
+///
+/// Dropping a `Listening` closes the server, call `join` to serve until the
+/// server stops instead.
+#[must_use = "dropping the Listening closes the server, call `join` to serve until it stops"]
 pub struct Listening {
     _guard: Option<runtime::JoinHandle<()>>,
     /// The socket addresses that the server is bound to.
     pub socket: SocketAddr,
+    graceful: Arc<Graceful>,
+    threads: usize,
 }
 
 impl fmt::Debug for Listening {
@@ -414,20 +778,78 @@ impl fmt::Debug for Listening {
 
 impl Drop for Listening {
     fn drop(&mut self) {
-        let _ = self._guard.take().map(|g| g.join());
+        if let Err(e) = self.close() {
+            debug!("error closing server: {:?}", e);
+        }
     }
 }
 
 impl Listening {
-    /// Warning: This function doesn't work. The server remains listening after you called
-    /// it. See https://github.com/mco_httpium/mco_http/issues/338 for more details.
+    /// Block until the server stops, which it only does if its acceptors fail.
     ///
+    /// This is what a program serving until it is killed calls last.
+    pub fn join(&mut self) -> crate::Result<()> {
+        match self._guard.take() {
+            Some(guard) => guard.join()
+                .map_err(|_| Error::Other("the acceptor pool panicked".to_string())),
+            None => Ok(()),
+        }
+    }
+
     /// Stop the server from listening to its socket address.
+    ///
+    /// This is `shutdown` with a 10 second deadline.
     pub fn close(&mut self) -> crate::Result<()> {
-        let _ = self._guard.take();
+        self.shutdown(Duration::from_secs(10))
+    }
+
+    /// Gracefully stop the server.
+    ///
+    /// The server stops accepting and closes the keep-alive connections waiting
+    /// for a request. The connections in the middle of a request may finish it
+    /// until `timeout`, after that they are closed too. Returns once the
+    /// acceptors have stopped and the socket is released.
+    pub fn shutdown(&mut self, timeout: Duration) -> crate::Result<()> {
+        let guard = match self._guard.take() {
+            Some(guard) => guard,
+            None => return Ok(()),
+        };
         debug!("closing server");
+        self.graceful.close_idle();
+        self.wake_acceptors();
+
+        let deadline = Instant::now() + timeout;
+        while self.graceful.len() != 0 && Instant::now() < deadline {
+            runtime::sleep(Duration::from_millis(10));
+        }
+        if self.graceful.len() != 0 {
+            debug!("closing {} busy connections", self.graceful.len());
+            self.graceful.close_all();
+        }
+
+        if guard.join().is_err() {
+            return Err(Error::Other("the acceptor pool panicked".to_string()));
+        }
         Ok(())
     }
+
+    /// The acceptors are blocked in `accept`, connect to each of them so they
+    /// notice the server is closing.
+    fn wake_acceptors(&self) {
+        let mut addr = self.socket;
+        if addr.ip().is_unspecified() {
+            addr.set_ip(match addr.ip() {
+                IpAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                IpAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+            });
+        }
+        for _ in 0..self.threads {
+            if let Err(e) = std::net::TcpStream::connect_timeout(&addr, Duration::from_secs(1)) {
+                debug!("failed to wake acceptor: {:?}", e);
+                break;
+            }
+        }
+    }
 }
 
 /// A handler that can handle incoming requests for a server.
@@ -445,6 +867,21 @@ pub trait Handler: Sync + Send {
         StatusCode::Continue
     }
 
+    /// Called instead of `handle` when a request can't be handled. The status
+    /// of the `Response` is already set:
+    ///
+    /// * `400 Bad Request` for a malformed request line or header,
+    /// * `431 Request Header Fields Too Large` for too many headers or a head too large,
+    /// * `413 Payload Too Large` for a body over the limit,
+    /// * `505 HTTP Version Not Supported` for an unsupported version.
+    ///
+    /// The connection is closed afterwards. By default, the body is empty.
+    fn on_error(&self, _err: &Error, res: Response<Fresh>) {
+        if let Err(e) = res.send(&[]) {
+            debug!("error writing error response: {:?}", e);
+        }
+    }
+
     /// This is run after a connection is received, on a per-connection basis (not a
     /// per-request basis, as a connection with keep-alive may handle multiple
     /// requests)
@@ -464,13 +901,398 @@ impl<F> Handler for F where F: Fn(Request, Response<Fresh>), F: Sync + Send {
 
 #[cfg(test)]
 mod tests {
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpStream};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::buffer::BufReader;
     use crate::header::Headers;
     use crate::method::Method;
-    use crate::mock::MockStream;
+    use crate::mock::{serve, serve_worker, MockStream};
+    use crate::net::NetworkStream;
+    use crate::runtime;
     use crate::status::StatusCode;
     use crate::uri::RequestUri;
 
-    use super::{Request, Response, Fresh, Handler, Worker};
+    use crate::Error;
+
+    use super::{Request, Response, Fresh, Handler, Server, Worker};
+
+    #[test]
+    fn test_listening_close() {
+        let mut listening = Server::http("127.0.0.1:0").unwrap()
+            .handle_threads(|_: Request, res: Response| {
+                res.send(b"ok").unwrap();
+            }, 2).unwrap();
+        let addr = listening.socket;
+
+        // an idle keep-alive connection
+        let mut idle = TcpStream::connect(addr).unwrap();
+        idle.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        idle.write_all(b"GET / HTTP/1.1\r\nHost: example.domain\r\n\r\n").unwrap();
+        let mut buf = [0; 1024];
+        let n = idle.read(&mut buf).unwrap();
+        assert!(buf[..n].ends_with(b"\r\n\r\nok"));
+
+        let start = Instant::now();
+        listening.close().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(idle.read(&mut buf).unwrap(), 0);
+        assert!(TcpStream::connect(addr).is_err());
+        // closing again does nothing
+        listening.close().unwrap();
+    }
+
+    #[test]
+    fn test_listening_drop_closes() {
+        let listening = Server::http("127.0.0.1:0").unwrap()
+            .handle(|_: Request, res: Response| {
+                res.send(b"ok").unwrap();
+            }).unwrap();
+        let addr = listening.socket;
+
+        let start = Instant::now();
+        drop(listening);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(TcpStream::connect(addr).is_err());
+    }
+
+    #[test]
+    fn test_listening_shutdown_drains_busy() {
+        let (started_tx, started) = mpsc::channel();
+        let mut listening = Server::http("127.0.0.1:0").unwrap()
+            .handle_threads(move |_: Request, res: Response| {
+                started_tx.send(()).unwrap();
+                runtime::sleep(Duration::from_millis(300));
+                res.send(b"done").unwrap();
+            }, 2).unwrap();
+        let addr = listening.socket;
+
+        let client = thread::spawn(move || {
+            let mut s = TcpStream::connect(addr).unwrap();
+            s.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            s.write_all(b"GET / HTTP/1.1\r\nHost: example.domain\r\n\r\n").unwrap();
+            let mut out = String::new();
+            s.read_to_string(&mut out).unwrap();
+            out
+        });
+        started.recv_timeout(Duration::from_secs(5)).unwrap();
+        listening.shutdown(Duration::from_secs(5)).unwrap();
+
+        let out = client.join().unwrap();
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\n"), "{}", out);
+        assert!(out.contains("Connection: close\r\n"), "{}", out);
+        assert!(out.ends_with("\r\n\r\ndone"), "{}", out);
+    }
+
+    #[test]
+    fn test_max_requests_per_connection() {
+        fn handle(_: Request, res: Response<Fresh>) {
+            res.send(b"ok").unwrap();
+        }
+
+        let mut worker = Worker::new(handle, Default::default());
+        worker.limits.max_requests_per_connection = Some(2);
+        let s = serve_worker(worker, b"\
+            GET / HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            \r\n\
+            GET / HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            \r\n\
+            GET / HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            \r\n\
+        ");
+        assert_eq!(s.matches("HTTP/1.1 200 OK\r\n").count(), 2, "{}", s);
+        assert_eq!(s.matches("Connection: close\r\n").count(), 1, "{}", s);
+    }
+
+    fn get(addr: SocketAddr) -> TcpStream {
+        let mut s = TcpStream::connect(addr).unwrap();
+        s.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        s.write_all(b"GET / HTTP/1.1\r\nHost: example.domain\r\n\r\n").unwrap();
+        s
+    }
+
+    /// Reads until the end of a response head, it may arrive in several parts.
+    fn read_some(s: &mut TcpStream) -> String {
+        let mut buf = Vec::new();
+        let mut part = [0; 1024];
+        while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+            match s.read(&mut part).unwrap() {
+                0 => break,
+                n => buf.extend_from_slice(&part[..n]),
+            }
+        }
+        String::from_utf8_lossy(&buf).into_owned()
+    }
+
+    #[test]
+    fn test_max_connections() {
+        let mut server = Server::http("127.0.0.1:0").unwrap();
+        server.max_connections(Some(1));
+        let mut listening = server.handle_threads(|_: Request, res: Response| {
+            res.send(b"ok").unwrap();
+        }, 2).unwrap();
+
+        let mut first = get(listening.socket);
+        assert!(read_some(&mut first).starts_with("HTTP/1.1 200 OK\r\n"));
+        // the first connection is kept alive, so the second is over the limit
+        let mut second = get(listening.socket);
+        let s = read_some(&mut second);
+        assert!(s.starts_with("HTTP/1.1 503 Service Unavailable\r\n"), "{}", s);
+
+        drop(first);
+        thread::sleep(Duration::from_millis(100));
+        let mut third = get(listening.socket);
+        assert!(read_some(&mut third).starts_with("HTTP/1.1 200 OK\r\n"));
+        listening.close().unwrap();
+    }
+
+    #[test]
+    fn test_max_connections_per_ip() {
+        let mut server = Server::http("127.0.0.1:0").unwrap();
+        server.max_connections_per_ip(Some(2));
+        let mut listening = server.handle_threads(|_: Request, res: Response| {
+            res.send(b"ok").unwrap();
+        }, 2).unwrap();
+
+        let mut conns = [get(listening.socket), get(listening.socket)];
+        for c in conns.iter_mut() {
+            assert!(read_some(c).starts_with("HTTP/1.1 200 OK\r\n"));
+        }
+        let mut over = get(listening.socket);
+        let s = read_some(&mut over);
+        assert!(s.starts_with("HTTP/1.1 503 Service Unavailable\r\n"), "{}", s);
+        listening.close().unwrap();
+    }
+
+    #[test]
+    fn test_upgrade() {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let mut listening = server.handle_threads(|req: Request, mut res: Response| {
+            *res.status_mut() = StatusCode::SwitchingProtocols;
+            res.headers_mut().set_raw("Upgrade", vec![b"shout".to_vec()]);
+            res.headers_mut().set_raw("Connection", vec![b"upgrade".to_vec()]);
+            let mut conn = res.upgrade(req).unwrap();
+            // the connection outlives the handler
+            thread::spawn(move || {
+                let mut buf = [0; 64];
+                while let Ok(n @ 1..) = conn.read(&mut buf) {
+                    conn.write_all(&buf[..n].to_ascii_uppercase()).unwrap();
+                }
+            });
+        }, 2).unwrap();
+
+        let mut s = TcpStream::connect(listening.socket).unwrap();
+        s.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        // sent right after the head, so read with it
+        s.write_all(b"GET / HTTP/1.1\r\nUpgrade: shout\r\nConnection: upgrade\r\n\r\nhello").unwrap();
+        let head = read_some(&mut s);
+        assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"), "{}", head);
+        assert!(!head.contains("Transfer-Encoding"), "{}", head);
+        // the echo may come in the same read as the head
+        let echoed = &head[head.find("\r\n\r\n").unwrap() + 4..];
+        let mut buf = [0; 5];
+        buf[..echoed.len()].copy_from_slice(echoed.as_bytes());
+        s.read_exact(&mut buf[echoed.len()..]).unwrap();
+        assert_eq!(&buf, b"HELLO");
+        s.write_all(b"again").unwrap();
+        s.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"AGAIN");
+        listening.close().unwrap();
+    }
+
+    #[test]
+    fn test_upgrade_without_server() {
+        let mut mock = MockStream::with_input(b"GET / HTTP/1.1\r\n\r\n");
+        let mut stream = BufReader::new(&mut mock as &mut dyn NetworkStream);
+        let req = Request::new(&mut stream, "127.0.0.1:80".parse().unwrap()).unwrap();
+        let mut headers = Headers::new();
+        let mut out = Vec::new();
+        let res = Response::new(&mut out, &mut headers);
+        assert!(res.upgrade(req).is_err());
+    }
+
+    fn ok(_: Request, res: Response<Fresh>) {
+        res.send(b"ok").unwrap();
+    }
+
+    #[test]
+    fn test_error_responses() {
+        let s = serve(ok, b"GET / HTTP/1.1\r\nBad Header\r\n\r\n");
+        assert!(s.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", s);
+        assert!(s.contains("Connection: close\r\n"), "{}", s);
+
+        let s = serve(ok, b"GET / HTTP/2.0\r\n\r\n");
+        assert!(s.starts_with("HTTP/1.1 505 HTTP Version Not Supported\r\n"), "{}", s);
+
+        let mut input = b"GET / HTTP/1.1\r\n".to_vec();
+        for i in 0..101 {
+            input.extend_from_slice(format!("X-Header-{}: {}\r\n", i, i).as_bytes());
+        }
+        input.extend_from_slice(b"\r\n");
+        let s = serve(ok, &input);
+        assert!(s.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"), "{}", s);
+
+        let mut worker = Worker::new(ok, Default::default());
+        worker.request_limits.max_body_size = Some(4);
+        let s = serve_worker(worker, b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello");
+        assert!(s.starts_with("HTTP/1.1 413 Payload Too Large\r\n"), "{}", s);
+    }
+
+    fn echo(mut req: Request, res: Response<Fresh>) {
+        let mut body = String::new();
+        match req.read_to_string(&mut body) {
+            Ok(_) => res.send(body.as_bytes()).unwrap(),
+            Err(e) => res.send(format!("error: {}", e).as_bytes()).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_smuggling_rejected() {
+        let vectors: &[&[u8]] = &[
+            // CL.TE and TE.CL
+            b"POST / HTTP/1.1\r\nContent-Length: 6\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\nG",
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 3\r\n\r\n8\r\nSMUGGLED\r\n0\r\n\r\n",
+            // differing or malformed Content-Length
+            b"POST / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 6\r\n\r\nhello!",
+            b"POST / HTTP/1.1\r\nContent-Length: 5, 6\r\n\r\nhello!",
+            b"POST / HTTP/1.1\r\nContent-Length: +5\r\n\r\nhello",
+            b"POST / HTTP/1.1\r\nContent-Length: 0x5\r\n\r\nhello",
+            // chunked not final, applied twice, or obfuscated
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked, identity\r\n\r\n0\r\n\r\n",
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: xchunked\r\n\r\n0\r\n\r\n",
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: \x0bchunked\r\n\r\n0\r\n\r\n",
+            b"POST / HTTP/1.1\r\nTransfer-Encoding : chunked\r\n\r\n0\r\n\r\n",
+            b"POST / HTTP/1.1\r\nTransfer-Encoding:\r\n\r\n0\r\n\r\n",
+            // Transfer-Encoding isn't defined for HTTP/1.0
+            b"POST / HTTP/1.0\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+        ];
+        for input in vectors {
+            let s = serve(echo, input);
+            assert!(s.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{:?} => {}", String::from_utf8_lossy(input), s);
+            assert!(s.contains("Connection: close\r\n"), "{}", s);
+            assert_eq!(s.matches("HTTP/1.1").count(), 1, "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_smuggling_chunk_size() {
+        let vectors: &[&[u8]] = &[
+            // an empty or space led size would be read as 0
+            b"\r\nfoo\r\n0\r\n\r\n",
+            b" 3\r\nfoo\r\n0\r\n\r\n",
+            // a bare LF or NUL in an extension
+            b"3;a\nfoo\r\n0\r\n\r\n",
+            b"3;a\0\r\nfoo\r\n0\r\n\r\n",
+            // a size overflowing u64
+            b"10000000000000003\r\nfoo\r\n0\r\n\r\n",
+            // data not ended by CRLF
+            b"3\r\nfoobar\r\n0\r\n\r\n",
+            b"3\nfoo\n0\n\n",
+        ];
+        for chunks in vectors {
+            let mut input = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n".to_vec();
+            input.extend_from_slice(chunks);
+            let s = serve(echo, &input);
+            assert!(s.contains("\r\n\r\nerror: "), "{:?} => {}", String::from_utf8_lossy(chunks), s);
+        }
+
+        let mut input = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n3;".to_vec();
+        input.extend(vec![b'a'; 8192]);
+        input.extend_from_slice(b"\r\nfoo\r\n0\r\n\r\n");
+        let s = serve(echo, &input);
+        assert!(s.contains("\r\n\r\nerror: "), "{}", s);
+    }
+
+    #[test]
+    fn test_chunked_accepted() {
+        let s = serve(echo, b"\
+            POST / HTTP/1.1\r\n\
+            Transfer-Encoding: Chunked\r\n\
+            Connection: close\r\n\
+            \r\n\
+            3;name=\"value\"\r\n\
+            foo\r\n\
+            0\r\n\
+            \r\n\
+        ");
+        assert!(s.starts_with("HTTP/1.1 200 OK\r\n"), "{}", s);
+        assert!(s.ends_with("\r\n\r\nfoo"), "{}", s);
+
+        // other codings can't be decoded
+        let s = serve(echo, b"\
+            POST / HTTP/1.1\r\n\
+            Transfer-Encoding: gzip, chunked\r\n\
+            \r\n\
+            3\r\n\
+            foo\r\n\
+            0\r\n\
+            \r\n\
+        ");
+        assert!(s.starts_with("HTTP/1.1 501 Not Implemented\r\n"), "{}", s);
+        assert!(s.contains("Connection: close\r\n"), "{}", s);
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_decompress_requests() {
+        use crate::compress::Encoder;
+        use crate::header::Encoding;
+        use super::BodyEncoder;
+
+        fn gzip_request(body: &[u8]) -> Vec<u8> {
+            let mut gz = Vec::new();
+            let mut encoder = Encoder::new(&Encoding::Gzip, 6).unwrap();
+            encoder.encode(body, &mut gz).unwrap();
+            encoder.finish(&mut gz).unwrap();
+            let mut req = format!("POST / HTTP/1.1\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\
+                                   Connection: close\r\n\r\n", gz.len()).into_bytes();
+            req.extend_from_slice(&gz);
+            req
+        }
+        let worker = || {
+            let mut worker = Worker::new(echo, Default::default());
+            worker.request_limits.max_decoded_body_size = Some(100);
+            worker
+        };
+
+        let s = serve_worker(worker(), gzip_request(b"{\"name\":\"joe\"}"));
+        assert!(s.ends_with("\r\n\r\n{\"name\":\"joe\"}"), "{}", s);
+
+        // a small body decoding to more than the limit
+        let bomb = gzip_request(&[b'a'; 100_000]);
+        assert!(bomb.len() < 1000);
+        let s = serve_worker(worker(), &bomb);
+        assert!(s.ends_with("error: Message body is too large"), "{}", s);
+
+        // off by default
+        let s = serve(echo, gzip_request(b"hello"));
+        assert!(!s.ends_with("hello"), "{}", s);
+    }
+
+    #[test]
+    fn test_on_error() {
+        struct Custom;
+        impl Handler for Custom {
+            fn handle<'a, 'k>(&'a self, _: Request<'a, 'k>, res: Response<'a, Fresh>) {
+                res.send(b"ok").unwrap();
+            }
+
+            fn on_error(&self, err: &Error, res: Response<Fresh>) {
+                let body = format!("{}: {}", res.status, err);
+                res.send(body.as_bytes()).unwrap();
+            }
+        }
+
+        let s = serve(Custom, b"GET / HTTP/1.1\r\nBad Header\r\n\r\n");
+        assert!(s.ends_with("\r\n\r\n400 Bad Request: Invalid Header provided"), "{}", s);
+    }
 
     #[test]
     fn test_check_continue_default() {
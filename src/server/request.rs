@@ -7,13 +7,19 @@ use std::net::SocketAddr;
 use std::time::Duration;
 
 use crate::buffer::BufReader;
+#[cfg(feature = "compression")]
+use crate::compress::Decoder;
 use crate::net::NetworkStream;
-use crate::version::{HttpVersion};
+use crate::Error;
+use crate::version::HttpVersion::{self, Http10};
 use crate::method::Method;
-use crate::header::{Headers, ContentLength, TransferEncoding};
+use crate::header::{Headers, ContentLength, Encoding, TransferEncoding};
 use crate::http::h1::{self, Incoming, HttpReader};
-use crate::http::h1::HttpReader::{SizedReader, ChunkedReader, EmptyReader};
+use crate::http::h2::conn::RecvStream;
+use crate::http::h1::HttpReader::{SizedReader, ChunkedReader, EofReader, EmptyReader};
 use crate::server::extensions::Extensions;
+use crate::server::RequestLimits;
+use crate::server::upgrade::Hijack;
 use crate::uri::RequestUri;
 
 /// A request bundles several parts of an incoming `NetworkStream`, given to a `Handler`.
@@ -31,7 +37,12 @@ pub struct Request<'a, 'b: 'a> {
     /// The extra User defined data
     pub extra: Extensions,
     /// http body
-    pub body: HttpReader<&'a mut BufReader<&'b mut dyn NetworkStream>>
+    pub body: HttpReader<&'a mut BufReader<&'b mut dyn NetworkStream>>,
+    /// decodes the body when `RequestLimits::max_decoded_body_size` is set
+    #[cfg(feature = "compression")]
+    pub(crate) decoder: Option<Decoder>,
+    /// set by the `Worker` so `Response::upgrade` can take the connection
+    pub(crate) hijack: Option<Hijack<'a>>,
 }
 
 
@@ -40,24 +51,49 @@ impl<'a, 'b: 'a> Request<'a, 'b> {
     /// immediately useful.
     pub fn new(stream: &'a mut BufReader<&'b mut dyn NetworkStream>, addr: SocketAddr)
         -> crate::Result<Request<'a, 'b>> {
+        Request::with_limits(stream, addr, &RequestLimits::default())
+    }
+
+    /// Create a new Request like `new`, failing with `Error::TooLarge`,
+    /// `Error::UriTooLong` or `Error::BodyTooLarge` when it goes over `limits`.
+    ///
+    /// A body framed with a transfer coding other than `chunked` fails with
+    /// `Error::TransferCoding`.
+    pub fn with_limits(stream: &'a mut BufReader<&'b mut dyn NetworkStream>, addr: SocketAddr,
+                       limits: &RequestLimits) -> crate::Result<Request<'a, 'b>> {
 
-        let Incoming { version, subject: (method, uri), headers } = h1::parse_request(stream)?;
+        let Incoming { version, subject: (method, uri), headers } =
+            h1::parse_request_limited(stream, limits.max_headers, limits.max_head_size)?;
         debug!("Request Line: {:?} {:?} {:?}", method, uri, version);
         debug!("{:?}", headers);
 
-        let body = if headers.has::<ContentLength>() {
+        let body = if headers.has::<TransferEncoding>() {
+            // a request framed by both could be read differently by a proxy
+            // in front of us, see RFC 9112 section 6.3
+            if headers.has::<ContentLength>() || version == Http10 {
+                return Err(Error::Header);
+            }
+            match headers.get::<TransferEncoding>() {
+                Some(TransferEncoding(codings)) if is_chunked(codings) => {
+                    // only chunked is decoded here
+                    if codings.len() > 1 {
+                        return Err(Error::TransferCoding);
+                    }
+                    ChunkedReader(stream, None)
+                }
+                _ => return Err(Error::Header),
+            }
+        } else if headers.has::<ContentLength>() {
             match headers.get::<ContentLength>() {
                 Some(&ContentLength(len)) => SizedReader(stream, len),
-                None => unreachable!()
+                // differing or invalid values
+                None => return Err(Error::Header),
             }
-        } else if headers.has::<TransferEncoding>() {
-            todo!("check for Transfer-Encoding: chunked");
-            ChunkedReader(stream, None)
         } else {
             EmptyReader(stream)
         };
 
-        Ok(Request {
+        limits.apply(Request {
             remote_addr: addr,
             method: method,
             uri: uri,
@@ -65,6 +101,43 @@ impl<'a, 'b: 'a> Request<'a, 'b> {
             version: version,
             body: body,
             extra: Default::default(),
+            #[cfg(feature = "compression")]
+            decoder: None,
+            hijack: None,
+        })
+    }
+
+    /// Create a Request from a head that was already read, such as the headers
+    /// of an HTTP/2 stream. Without a `Content-Length`, the body is read until
+    /// the stream ends, `end` is set if it already has.
+    pub(crate) fn with_head(stream: &'a mut BufReader<&'b mut dyn NetworkStream>, addr: SocketAddr,
+                            (method, uri, headers): (Method, RequestUri, Headers), version: HttpVersion,
+                            end: bool, limits: &RequestLimits) -> crate::Result<Request<'a, 'b>> {
+        debug!("Request Line: {:?} {:?} {:?}", method, uri, version);
+        debug!("{:?}", headers);
+
+        let body = if end {
+            EmptyReader(stream)
+        } else if headers.has::<ContentLength>() {
+            match headers.get::<ContentLength>() {
+                Some(&ContentLength(len)) => SizedReader(stream, len),
+                None => return Err(Error::Header),
+            }
+        } else {
+            EofReader(stream)
+        };
+
+        limits.apply(Request {
+            remote_addr: addr,
+            method,
+            uri,
+            headers,
+            version,
+            body,
+            extra: Default::default(),
+            #[cfg(feature = "compression")]
+            decoder: None,
+            hijack: None,
         })
     }
 
@@ -75,9 +148,15 @@ impl<'a, 'b: 'a> Request<'a, 'b> {
     }
 
     /// Get a reference to the underlying `NetworkStream`.
+    ///
+    /// For an HTTP/2 request, this is the stream of its connection.
     #[inline]
     pub fn downcast_ref<T: NetworkStream>(&self) -> Option<&T> {
-        self.body.get_ref().get_ref().downcast_ref()
+        let stream = self.body.get_ref().get_ref();
+        match stream.downcast_ref::<RecvStream>() {
+            Some(h2) => h2.transport().and_then(|t| t.downcast_ref()),
+            None => stream.downcast_ref(),
+        }
     }
 
     /// Get a reference to the underlying Ssl stream, if connected
@@ -99,9 +178,21 @@ impl<'a, 'b: 'a> Request<'a, 'b> {
     }
 }
 
+/// Chunked must be the final coding, and only applied once.
+fn is_chunked(codings: &[Encoding]) -> bool {
+    match codings.split_last() {
+        Some((&Encoding::Chunked, rest)) => !rest.contains(&Encoding::Chunked),
+        _ => false,
+    }
+}
+
 impl<'a, 'b> Read for Request<'a, 'b> {
     #[inline]
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        #[cfg(feature = "compression")]
+        if let Some(ref mut decoder) = self.decoder {
+            return decoder.read_from(&mut self.body, buf);
+        }
         self.body.read(buf)
     }
 }
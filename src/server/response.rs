@@ -5,10 +5,12 @@
 use std::any::{Any, TypeId};
 use std::marker::PhantomData;
 use std::mem;
+use std::fmt;
 use std::io::{self, Write};
 use std::ptr;
 use std::thread;
 
+use serde::Serialize;
 use time::now_utc;
 
 use crate::header;
@@ -16,9 +18,58 @@ use crate::http::h1::{LINE_ENDING, HttpWriter};
 use crate::http::h1::HttpWriter::{ThroughWriter, ChunkedWriter, SizedWriter, EmptyWriter};
 use crate::status;
 use crate::net::{Fresh, Streaming};
+use crate::server::{Request, Upgraded};
 use crate::version;
 
 
+/// A callback run just before the head of a `Response` is written.
+type HeadHook<'a> = Box<dyn FnOnce(&mut status::StatusCode, &mut header::Headers) + 'a>;
+
+/// The hooks waiting for the head of a `Response` to be written.
+#[derive(Default)]
+struct HeadHooks<'a>(Vec<HeadHook<'a>>);
+
+impl<'a> fmt::Debug for HeadHooks<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "HeadHooks({})", self.0.len())
+    }
+}
+
+/// Encodes the body of a `Response` while it is written, such as a compressor.
+///
+/// See `Response::encode_body`.
+pub trait BodyEncoder {
+    /// Encode `buf`, appending any output to `out`.
+    fn encode(&mut self, buf: &[u8], out: &mut Vec<u8>) -> io::Result<()>;
+
+    /// Append everything buffered so far to `out`, so it can be sent right away.
+    fn flush(&mut self, out: &mut Vec<u8>) -> io::Result<()>;
+
+    /// End the encoded stream, appending the rest of it to `out`.
+    fn finish(&mut self, out: &mut Vec<u8>) -> io::Result<()>;
+}
+
+/// Picks the `BodyEncoder` for a `Response` once its head is final.
+type EncoderFactory<'a> = Box<dyn FnOnce(&mut status::StatusCode, &mut header::Headers)
+    -> Option<Box<dyn BodyEncoder + 'a>> + 'a>;
+
+/// How the body of a `Response` is encoded.
+enum BodyEncoding<'a> {
+    Identity,
+    Pending(EncoderFactory<'a>),
+    Active(Box<dyn BodyEncoder + 'a>, Vec<u8>),
+}
+
+impl<'a> fmt::Debug for BodyEncoding<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            BodyEncoding::Identity => "Identity",
+            BodyEncoding::Pending(..) => "Pending",
+            BodyEncoding::Active(..) => "Active",
+        })
+    }
+}
+
 /// The outgoing half for a Tcp connection, created by a `Server` and given to a `Handler`.
 ///
 /// The default `StatusCode` for a `Response` is `200 OK`.
@@ -37,6 +88,8 @@ pub struct Response<'a, W: Any = Fresh> {
     // The outgoing headers on this response.
     pub headers: &'a mut header::Headers,
 
+    hooks: HeadHooks<'a>,
+    encoding: BodyEncoding<'a>,
     _writing: PhantomData<W>
 }
 
@@ -60,6 +113,8 @@ impl<'a, W: Any> Response<'a, W> {
             version: version,
             body: body,
             headers: headers,
+            hooks: HeadHooks::default(),
+            encoding: BodyEncoding::Identity,
             _writing: PhantomData,
         }
     }
@@ -75,12 +130,25 @@ impl<'a, W: Any> Response<'a, W> {
                 self.status,
                 ptr::read(&self.headers)
             );
+            drop(ptr::read(&self.hooks));
+            drop(ptr::read(&self.encoding));
             mem::forget(self);
             parts
         }
     }
 
-    fn write_head(&mut self) -> io::Result<Body> {
+    /// Write the head, without a body if `upgrade` is set as the connection
+    /// switches to another protocol.
+    fn write_head(&mut self, upgrade: bool) -> io::Result<Body> {
+        // the last registered hook runs first, so outer middleware sees the final head
+        while let Some(hook) = self.hooks.0.pop() {
+            hook(&mut self.status, self.headers);
+        }
+        if let BodyEncoding::Pending(factory) = mem::replace(&mut self.encoding, BodyEncoding::Identity) {
+            if let Some(encoder) = factory(&mut self.status, self.headers) {
+                self.encoding = BodyEncoding::Active(encoder, Vec::new());
+            }
+        }
         debug!("writing head: {:?} {:?}", self.version, self.status);
         write!(&mut self.body, "{} {}\r\n", self.version, self.status)?;
 
@@ -89,10 +157,14 @@ impl<'a, W: Any> Response<'a, W> {
         }
 
         let body_type = match self.status {
+            _ if upgrade => Body::Empty,
             status::StatusCode::NoContent | status::StatusCode::NotModified => Body::Empty,
             c if c.class() == status::StatusClass::Informational => Body::Empty,
             _ => if let Some(cl) = self.headers.get::<header::ContentLength>() {
                 Body::Sized(**cl)
+            } else if self.version == version::HttpVersion::Http20 {
+                // the stream frames the body
+                Body::Through
             } else {
                 Body::Chunked
             }
@@ -122,6 +194,15 @@ impl<'a, W: Any> Response<'a, W> {
 
         Ok(body_type)
     }
+
+    /// Write the rest of the encoded body, if it is encoded.
+    fn finish_encoding(&mut self) -> io::Result<()> {
+        if let BodyEncoding::Active(mut encoder, mut out) = mem::replace(&mut self.encoding, BodyEncoding::Identity) {
+            encoder.finish(&mut out)?;
+            write_encoded(&mut self.body, &mut out)?;
+        }
+        Ok(())
+    }
 }
 
 impl<'a> Response<'a, Fresh> {
@@ -134,6 +215,8 @@ impl<'a> Response<'a, Fresh> {
             version: version::HttpVersion::Http11,
             headers: headers,
             body: ThroughWriter(stream),
+            hooks: HeadHooks::default(),
+            encoding: BodyEncoding::Identity,
             _writing: PhantomData,
         }
     }
@@ -176,12 +259,14 @@ impl<'a> Response<'a, Fresh> {
     /// Consume this Response<Fresh>, writing the Headers and Status and
     /// creating a Response<Streaming>
     pub fn start(mut self) -> io::Result<Response<'a, Streaming>> {
-        let body_type = self.write_head()?;
+        let body_type = self.write_head(false)?;
+        let encoding = mem::replace(&mut self.encoding, BodyEncoding::Identity);
         let (version, body, status, headers) = self.deconstruct();
         let stream = match body_type {
             Body::Chunked => ChunkedWriter(body.into_inner()),
             Body::Sized(len) => SizedWriter(body.into_inner(), len),
             Body::Empty => EmptyWriter(body.into_inner()),
+            Body::Through => ThroughWriter(body.into_inner()),
         };
 
         // "copy" to change the phantom type
@@ -190,9 +275,180 @@ impl<'a> Response<'a, Fresh> {
             body: stream,
             status: status,
             headers: headers,
+            hooks: HeadHooks::default(),
+            encoding,
             _writing: PhantomData,
         })
     }
+
+    /// Register a hook that runs just before the head is written, whether by
+    /// `send`, `start` or `Drop`. The hook may change the status and headers.
+    ///
+    /// Hooks run in reverse order of registration.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use mco_http::server::Response;
+    /// fn handler(mut res: Response) {
+    ///     res.on_head(|status, headers| {
+    ///         headers.set_raw("X-Status", vec![status.to_u16().to_string().into_bytes()]);
+    ///     });
+    ///     res.send(b"Hello World!").unwrap();
+    /// }
+    /// ```
+    #[inline]
+    pub fn on_head<F>(&mut self, f: F)
+        where F: FnOnce(&mut status::StatusCode, &mut header::Headers) + 'a {
+        self.hooks.0.push(Box::new(f));
+    }
+
+    /// Encode the body with a `BodyEncoder`, such as a compressor.
+    ///
+    /// `f` runs after the `on_head` hooks, just before the head is written, and
+    /// returns the encoder to use, or `None` to write the body as is. When it
+    /// returns an encoder it should fix up the headers to match, such as removing
+    /// `ContentLength` and setting `ContentEncoding`.
+    ///
+    /// Replaces any encoder registered before. If the response is dropped without
+    /// being started, `f` is not run.
+    #[inline]
+    pub fn encode_body<F>(&mut self, f: F)
+        where F: FnOnce(&mut status::StatusCode, &mut header::Headers)
+            -> Option<Box<dyn BodyEncoder + 'a>> + 'a {
+        self.encoding = BodyEncoding::Pending(Box::new(f));
+    }
+
+    /// Writes `value` as a `application/json` body and ends the response.
+    ///
+    /// If `value` fails to serialize, the status is set to `500 Internal Server Error`
+    /// and an empty body is written.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use std::collections::BTreeMap;
+    /// # use mco_http::server::Response;
+    /// fn handler(res: Response) {
+    ///     let mut user = BTreeMap::new();
+    ///     user.insert("name", "joe");
+    ///     res.json(&user).unwrap();
+    /// }
+    /// ```
+    pub fn json<T: Serialize + ?Sized>(mut self, value: &T) -> io::Result<()> {
+        match serde_json::to_vec(value) {
+            Ok(body) => {
+                self.headers.set(header::ContentType::json());
+                self.send(&body)
+            }
+            Err(e) => {
+                self.status = status::StatusCode::InternalServerError;
+                self.send(&[])?;
+                Err(io::Error::new(io::ErrorKind::InvalidData, e))
+            }
+        }
+    }
+
+    /// Writes a `text/plain; charset=utf-8` body and ends the response.
+    #[inline]
+    pub fn text(self, body: &str) -> io::Result<()> {
+        self.headers.set(header::ContentType::plaintext());
+        self.send(body.as_bytes())
+    }
+
+    /// Writes a `text/html; charset=utf-8` body and ends the response.
+    #[inline]
+    pub fn html(self, body: &str) -> io::Result<()> {
+        self.headers.set(header::ContentType::html());
+        self.send(body.as_bytes())
+    }
+
+    /// Redirects to `location` with a `3xx` status, such as `302 Found`,
+    /// `303 See Other` or `308 Permanent Redirect`, and ends the response.
+    /// Fails with `InvalidInput` for any other status, answering
+    /// `500 Internal Server Error` instead.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use mco_http::server::Response;
+    /// # use mco_http::status::StatusCode;
+    /// fn handler(res: Response) {
+    ///     res.redirect(StatusCode::SeeOther, "/login").unwrap();
+    /// }
+    /// ```
+    pub fn redirect(mut self, status: status::StatusCode, location: &str) -> io::Result<()> {
+        if !status.is_redirection() {
+            self.status = status::StatusCode::InternalServerError;
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      format!("redirect with a non 3xx status: {}", status)));
+        }
+        self.status = status;
+        self.headers.set(header::Location(location.to_owned()));
+        self.send(&[])
+    }
+
+    /// Take over the connection of `req` to switch to another protocol, such as
+    /// h2c or a `CONNECT` tunnel, once the head of this response is sent.
+    ///
+    /// The head is sent without a body, so set the status and headers first,
+    /// usually `101 Switching Protocols` with `Upgrade` and `Connection: upgrade`.
+    /// The server doesn't read more requests from the connection afterwards.
+    /// Fails if the connection can't be taken, as for a `Request` that didn't come
+    /// from a `Server`, answering `500 Internal Server Error` instead.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::io::{Read, Write};
+    /// # use mco_http::server::{Request, Response};
+    /// # use mco_http::status::StatusCode;
+    /// fn handler(req: Request, mut res: Response) {
+    ///     *res.status_mut() = StatusCode::SwitchingProtocols;
+    ///     res.headers_mut().set_raw("Upgrade", vec![b"echo".to_vec()]);
+    ///     res.headers_mut().set_raw("Connection", vec![b"upgrade".to_vec()]);
+    ///     let mut conn = res.upgrade(req).unwrap();
+    ///     let mut buf = [0; 1024];
+    ///     while let Ok(n @ 1..) = conn.read(&mut buf) {
+    ///         conn.write_all(&buf[..n]).unwrap();
+    ///     }
+    /// }
+    /// ```
+    pub fn upgrade(mut self, mut req: Request) -> crate::Result<Upgraded> {
+        let hijack = req.hijack.take();
+        let stream = hijack.and_then(|h| (h.clone)(*req.body.get_ref().get_ref()));
+        let (hijack, stream) = match (hijack, stream) {
+            (Some(hijack), Some(stream)) => (hijack, stream),
+            _ => {
+                // not the head set up for the switch
+                self.status = status::StatusCode::InternalServerError;
+                return Err(io::Error::new(io::ErrorKind::Unsupported, "connection can't be upgraded").into());
+            }
+        };
+        // there is no body to encode
+        self.encoding = BodyEncoding::Identity;
+        self.write_head(true)?;
+        let (_, body, _, _) = self.deconstruct();
+        body.into_inner().flush()?;
+
+        let (_, _, _, _, _, body) = req.deconstruct();
+        let (buf, pos, cap) = body.into_inner().take_buf();
+        hijack.taken.set(true);
+        Ok(Upgraded::new(stream, buf, pos, cap))
+    }
+
+    /// Accept a WebSocket handshake, answering `req` with `101 Switching Protocols`.
+    ///
+    /// A request that isn't a valid handshake is answered with `400 Bad Request`,
+    /// or `426 Upgrade Required` for an unsupported version, and `Err` is returned.
+    /// Headers set before, such as `Sec-WebSocket-Protocol`, are sent with the 101.
+    /// The connection is taken over as with `upgrade`, so the WebSocket can be
+    /// moved to another coroutine and outlive the handler.
+    pub fn upgrade_websocket(self, req: Request)
+            -> crate::Result<crate::websocket::WebSocket<crate::websocket::ServerStream>> {
+        crate::websocket::accept(req, self)
+    }
+
     /// Get a mutable reference to the status.
     #[inline]
     pub fn status_mut(&mut self) -> &mut status::StatusCode { &mut self.status }
@@ -206,32 +462,184 @@ impl<'a> Response<'a, Fresh> {
 impl<'a> Response<'a, Streaming> {
     /// Flushes all writing of a response to the client.
     #[inline]
-    pub fn end(self) -> io::Result<()> {
+    pub fn end(mut self) -> io::Result<()> {
         trace!("ending");
+        self.finish_encoding()?;
         let (_, body, _, _) = self.deconstruct();
         body.end()?;
         Ok(())
     }
 }
 
+/// Write out what an encoder produced, an empty write would end a chunked body.
+fn write_encoded<W: Write>(body: &mut W, out: &mut Vec<u8>) -> io::Result<()> {
+    if !out.is_empty() {
+        body.write_all(out)?;
+        out.clear();
+    }
+    Ok(())
+}
+
 impl<'a> Write for Response<'a, Streaming> {
     #[inline]
     fn write(&mut self, msg: &[u8]) -> io::Result<usize> {
         debug!("write {:?} bytes", msg.len());
-        self.body.write(msg)
+        match self.encoding {
+            BodyEncoding::Active(ref mut encoder, ref mut out) => {
+                encoder.encode(msg, out)?;
+                write_encoded(&mut self.body, out)?;
+                Ok(msg.len())
+            }
+            _ => self.body.write(msg)
+        }
     }
 
     #[inline]
     fn flush(&mut self) -> io::Result<()> {
+        if let BodyEncoding::Active(ref mut encoder, ref mut out) = self.encoding {
+            encoder.flush(out)?;
+            write_encoded(&mut self.body, out)?;
+        }
         self.body.flush()
     }
 }
 
+/// A value that a handler can return, to be written to the `Response`.
+///
+/// Implemented for `()`, `StatusCode`, strings (as `text/plain`), bytes,
+/// `Html`, `Redirect`, `extract::Json`, `(StatusCode, T)` and `Result<T, E>`,
+/// so a handler wrapped with `extract::reply` can use `?`.
+pub trait IntoResponse {
+    /// Write `self` to the response.
+    fn into_response(self, res: Response);
+}
+
+fn send_or_log(res: io::Result<()>) {
+    if let Err(e) = res {
+        debug!("error writing response: {:?}", e);
+    }
+}
+
+impl IntoResponse for () {
+    /// The response is ended by its `Drop`.
+    fn into_response(self, _res: Response) {}
+}
+
+impl IntoResponse for status::StatusCode {
+    fn into_response(self, mut res: Response) {
+        res.status = self;
+        send_or_log(res.send(&[]));
+    }
+}
+
+impl IntoResponse for String {
+    fn into_response(self, res: Response) {
+        send_or_log(res.text(&self));
+    }
+}
+
+impl IntoResponse for &'static str {
+    fn into_response(self, res: Response) {
+        send_or_log(res.text(self));
+    }
+}
+
+impl IntoResponse for Vec<u8> {
+    fn into_response(self, res: Response) {
+        send_or_log(res.send(&self));
+    }
+}
+
+impl IntoResponse for &'static [u8] {
+    fn into_response(self, res: Response) {
+        send_or_log(res.send(self));
+    }
+}
+
+impl<T: IntoResponse> IntoResponse for (status::StatusCode, T) {
+    fn into_response(self, mut res: Response) {
+        res.status = self.0;
+        self.1.into_response(res);
+    }
+}
+
+impl<T: IntoResponse, E: IntoResponse> IntoResponse for Result<T, E> {
+    fn into_response(self, res: Response) {
+        match self {
+            Ok(v) => v.into_response(res),
+            Err(e) => e.into_response(res),
+        }
+    }
+}
+
+impl IntoResponse for crate::Error {
+    /// A `500 Internal Server Error`. The error is logged, not sent, as it
+    /// may tell about the server's internals.
+    fn into_response(self, mut res: Response) {
+        error!("handler error: {}", self);
+        res.status = status::StatusCode::InternalServerError;
+        send_or_log(res.text("Internal Server Error"));
+    }
+}
+
+/// A `text/html; charset=utf-8` body.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Html<T>(pub T);
+
+impl<T: AsRef<str>> IntoResponse for Html<T> {
+    fn into_response(self, res: Response) {
+        send_or_log(res.html(self.0.as_ref()));
+    }
+}
+
+/// A redirect to another location.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Redirect {
+    status: status::StatusCode,
+    location: String,
+}
+
+impl Redirect {
+    /// `302 Found`
+    pub fn found<L: Into<String>>(location: L) -> Redirect {
+        Redirect::with_status(status::StatusCode::Found, location)
+    }
+
+    /// `303 See Other`, the client follows with a `GET`
+    pub fn see_other<L: Into<String>>(location: L) -> Redirect {
+        Redirect::with_status(status::StatusCode::SeeOther, location)
+    }
+
+    /// `307 Temporary Redirect`, the client keeps the method and body
+    pub fn temporary<L: Into<String>>(location: L) -> Redirect {
+        Redirect::with_status(status::StatusCode::TemporaryRedirect, location)
+    }
+
+    /// `308 Permanent Redirect`, the client keeps the method and body
+    pub fn permanent<L: Into<String>>(location: L) -> Redirect {
+        Redirect::with_status(status::StatusCode::PermanentRedirect, location)
+    }
+
+    fn with_status<L: Into<String>>(status: status::StatusCode, location: L) -> Redirect {
+        Redirect {
+            status,
+            location: location.into(),
+        }
+    }
+}
+
+impl IntoResponse for Redirect {
+    fn into_response(self, res: Response) {
+        send_or_log(res.redirect(self.status, &self.location));
+    }
+}
+
 #[derive(PartialEq)]
 enum Body {
     Chunked,
     Sized(u64),
     Empty,
+    Through,
 }
 
 impl<'a, T: Any> Drop for Response<'a, T> {
@@ -240,11 +648,14 @@ impl<'a, T: Any> Drop for Response<'a, T> {
             if thread::panicking() {
                 self.status = status::StatusCode::InternalServerError;
             }
+            // there is no body to encode
+            self.encoding = BodyEncoding::Identity;
 
-            let mut body = match self.write_head() {
+            let mut body = match self.write_head(false) {
                 Ok(Body::Chunked) => ChunkedWriter(self.body.get_mut()),
                 Ok(Body::Sized(len)) => SizedWriter(self.body.get_mut(), len),
                 Ok(Body::Empty) => EmptyWriter(self.body.get_mut()),
+                Ok(Body::Through) => ThroughWriter(self.body.get_mut()),
                 Err(e) => {
                     debug!("error dropping request: {:?}", e);
                     return;
@@ -252,6 +663,10 @@ impl<'a, T: Any> Drop for Response<'a, T> {
             };
             end(&mut body);
         } else {
+            if let Err(e) = self.finish_encoding() {
+                debug!("error dropping request: {:?}", e);
+                return;
+            }
             end(&mut self.body);
         };
 
@@ -271,8 +686,11 @@ impl<'a, T: Any> Drop for Response<'a, T> {
 
 #[cfg(test)]
 mod tests {
-    use crate::header::Headers;
+    use std::io;
+
+    use crate::header::{ContentLength, Headers};
     use crate::mock::MockStream;
+    use crate::status::StatusCode;
     use crate::runtime;
     use super::Response;
 
@@ -309,6 +727,121 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_on_head() {
+        let mut headers = Headers::new();
+        let mut stream = MockStream::new();
+        {
+            let mut res = Response::new(&mut stream, &mut headers);
+            res.on_head(|status, _| {
+                assert_eq!(*status, StatusCode::NotFound);
+                *status = StatusCode::Gone;
+            });
+            res.on_head(|status, headers| {
+                *status = StatusCode::NotFound;
+                headers.set(ContentLength(0));
+            });
+            res.start().unwrap().end().unwrap();
+        }
+
+        lines! { stream =
+            "HTTP/1.1 410 Gone",
+            "Content-Length: 0",
+            _date,
+            ""
+        }
+    }
+
+    #[test]
+    fn test_json() {
+        let mut headers = Headers::new();
+        let mut stream = MockStream::new();
+        {
+            let res = Response::new(&mut stream, &mut headers);
+            res.json(&vec!["a", "b"]).unwrap();
+        }
+
+        lines! { stream =
+            "HTTP/1.1 200 OK",
+            "Content-Type: application/json",
+            "Content-Length: 9",
+            _date,
+            "",
+            "[\"a\",\"b\"]"
+        }
+    }
+
+    #[test]
+    fn test_redirect() {
+        let mut headers = Headers::new();
+        let mut stream = MockStream::new();
+        {
+            let res = Response::new(&mut stream, &mut headers);
+            res.redirect(StatusCode::SeeOther, "/login").unwrap();
+        }
+
+        lines! { stream =
+            "HTTP/1.1 303 See Other",
+            "Location: /login",
+            "Content-Length: 0",
+            _date,
+            ""
+        }
+
+        let mut headers = Headers::new();
+        let mut stream = MockStream::new();
+        {
+            let res = Response::new(&mut stream, &mut headers);
+            let err = res.redirect(StatusCode::Ok, "/login").unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+        let s = String::from_utf8(stream.write).unwrap();
+        assert!(s.starts_with("HTTP/1.1 500 Internal Server Error\r\n"), "{}", s);
+        assert!(!s.contains("Location"), "{}", s);
+    }
+
+    #[test]
+    fn test_into_response() {
+        use super::IntoResponse;
+        let mut headers = Headers::new();
+        let mut stream = MockStream::new();
+        {
+            let res = Response::new(&mut stream, &mut headers);
+            let r: Result<String, (StatusCode, &'static str)> = Err((StatusCode::NotFound, "missing"));
+            r.into_response(res);
+        }
+
+        lines! { stream =
+            "HTTP/1.1 404 Not Found",
+            "Content-Type: text/plain; charset=utf-8",
+            "Content-Length: 7",
+            _date,
+            "",
+            "missing"
+        }
+    }
+
+    #[test]
+    fn test_error_into_response() {
+        use super::IntoResponse;
+        let mut headers = Headers::new();
+        let mut stream = MockStream::new();
+        {
+            let res = Response::new(&mut stream, &mut headers);
+            let e = std::io::Error::new(std::io::ErrorKind::NotFound, "/srv/secret/config.toml");
+            crate::Error::from(e).into_response(res);
+        }
+
+        lines! { stream =
+            "HTTP/1.1 500 Internal Server Error",
+            "Content-Type: text/plain; charset=utf-8",
+            "Content-Length: 21",
+            _date,
+            "",
+            "Internal Server Error"
+        }
+    }
+
     #[test]
     fn test_streaming_end() {
         let mut headers = Headers::new();
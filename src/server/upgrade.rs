@@ -0,0 +1,99 @@
+//! Taking over the connection of a request, to switch to another protocol.
+//!
+//! See `Response::upgrade`.
+use std::cell::Cell;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::buffer::BufReader;
+use crate::net::NetworkStream;
+
+/// Lets a `Handler` take the connection of a request away from the `Worker`.
+#[derive(Clone, Copy)]
+pub(crate) struct Hijack<'a> {
+    /// Clones the stream the request is read from, as an owned stream.
+    pub(crate) clone: fn(&dyn NetworkStream) -> Option<Box<dyn NetworkStream + Send>>,
+    /// Set once the connection is taken, the `Worker` leaves it alone after that.
+    pub(crate) taken: &'a Cell<bool>,
+}
+
+impl<'a> Hijack<'a> {
+    pub(crate) fn new<S: NetworkStream + Clone>(taken: &'a Cell<bool>) -> Hijack<'a> {
+        Hijack {
+            clone: clone_stream::<S>,
+            taken,
+        }
+    }
+}
+
+fn clone_stream<S: NetworkStream + Clone>(stream: &dyn NetworkStream) -> Option<Box<dyn NetworkStream + Send>> {
+    stream.downcast_ref::<S>().map(|s| Box::new(s.clone()) as Box<dyn NetworkStream + Send>)
+}
+
+/// A connection taken over by a `Handler`, after the response head was sent.
+///
+/// Reads first return the bytes the client sent after the request head, which
+/// were already buffered. The connection isn't used by the server anymore, so it
+/// can be moved to another coroutine, and is closed once dropped.
+pub struct Upgraded {
+    inner: BufReader<Box<dyn NetworkStream + Send>>,
+}
+
+impl Upgraded {
+    pub(crate) fn new(stream: Box<dyn NetworkStream + Send>, buf: Vec<u8>, pos: usize, cap: usize) -> Upgraded {
+        Upgraded {
+            inner: BufReader::from_parts(stream, buf, pos, cap),
+        }
+    }
+
+    /// Get a reference to the underlying `NetworkStream`.
+    pub fn get_ref(&self) -> &(dyn NetworkStream + Send) {
+        &**self.inner.get_ref()
+    }
+
+    /// Get a mutable reference to the underlying `NetworkStream`.
+    pub fn get_mut(&mut self) -> &mut (dyn NetworkStream + Send) {
+        &mut **self.inner.get_mut()
+    }
+
+    /// The bytes buffered but not read yet.
+    pub fn buffered(&self) -> &[u8] {
+        self.inner.get_buf()
+    }
+
+    /// Deconstruct into the stream and the bytes buffered but not read yet, that
+    /// came before anything the stream will read.
+    pub fn into_parts(self) -> (Box<dyn NetworkStream + Send>, Vec<u8>) {
+        let (stream, mut buf, pos, cap) = self.inner.into_parts();
+        buf.truncate(cap);
+        buf.drain(..pos.min(cap));
+        (stream, buf)
+    }
+}
+
+impl Read for Upgraded {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl Write for Upgraded {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.get_mut().write(buf)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.get_mut().flush()
+    }
+}
+
+impl fmt::Debug for Upgraded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Upgraded")
+            .field("buffered", &self.buffered().len())
+            .finish()
+    }
+}
@@ -0,0 +1,614 @@
+//! Server sessions.
+//!
+//! `Sessions` is an `AroundMiddleWare` keeping a `Session` per client, found
+//! by the id in a cookie. Before the handler the session is loaded from a
+//! `SessionStore` into `Request::extra`, and the changes are saved before the
+//! response head is sent, so a client following a redirect finds them.
+//!
+//! ```rust
+//! use mco_http::extract::reply;
+//! use mco_http::route::Route;
+//! use mco_http::session::{MemoryStore, Session, Sessions};
+//!
+//! let route = Route::new();
+//! route.add_around(Sessions::new(MemoryStore::new()));
+//! route.post_fn("/login", reply(|session: Session| {
+//!     // a new id once logged in, so an id known before can't be used
+//!     session.rotate();
+//!     session.insert("user", "joe");
+//!     "welcome"
+//! }));
+//! route.get_fn("/me", reply(|session: Session| {
+//!     session.get("user").unwrap_or_else(|| "nobody".to_owned())
+//! }));
+//! ```
+//!
+//! A client gets a session cookie once something is inserted in its session.
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Formatter};
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64;
+use base64::Engine;
+
+use crate::cookie::{Cookie, CookieJar, SameSite};
+use crate::extract::{FromRequest, Rejection};
+use crate::net::Fresh;
+use crate::route::{AroundMiddleWare, Next};
+use crate::runtime::Mutex;
+use crate::server::{Request, Response};
+use crate::status::StatusCode;
+
+/// The cookie holding the session id by default.
+pub const DEFAULT_COOKIE_NAME: &str = "mco_session";
+
+/// The data of a session, as kept by a `SessionStore`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionRecord {
+    /// The values inserted in the session.
+    pub values: HashMap<String, String>,
+    /// When the session was created, for its absolute expiry.
+    pub created: SystemTime,
+    /// When the session was last used, for its idle expiry.
+    pub accessed: SystemTime,
+}
+
+impl SessionRecord {
+    fn new() -> SessionRecord {
+        let now = SystemTime::now();
+        SessionRecord {
+            values: HashMap::new(),
+            created: now,
+            accessed: now,
+        }
+    }
+}
+
+/// Where `Sessions` keeps the sessions, by id.
+pub trait SessionStore: Send + Sync {
+    /// The session `id`, `None` if there is none or it expired.
+    fn load(&self, id: &str) -> io::Result<Option<SessionRecord>>;
+    /// Keep the session `id` for `ttl`, replacing it if it exists.
+    fn save(&self, id: &str, record: &SessionRecord, ttl: Duration) -> io::Result<()>;
+    /// Drop the session `id`.
+    fn remove(&self, id: &str) -> io::Result<()>;
+}
+
+impl<T: SessionStore> SessionStore for Arc<T> {
+    fn load(&self, id: &str) -> io::Result<Option<SessionRecord>> {
+        T::load(self, id)
+    }
+
+    fn save(&self, id: &str, record: &SessionRecord, ttl: Duration) -> io::Result<()> {
+        T::save(self, id, record, ttl)
+    }
+
+    fn remove(&self, id: &str) -> io::Result<()> {
+        T::remove(self, id)
+    }
+}
+
+/// How often a `MemoryStore` looks for expired sessions to drop.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A `SessionStore` in memory, losing the sessions when the process exits.
+///
+/// Expired sessions are not loaded, and are dropped every minute.
+pub struct MemoryStore {
+    // a lock rather than a `SyncHashMap`, whose readers would keep a copy of
+    // a replaced record
+    sessions: Mutex<HashMap<String, (SessionRecord, Instant)>>,
+    last_sweep: Mutex<Instant>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore {
+            sessions: Mutex::new(HashMap::new()),
+            last_sweep: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// The number of sessions kept, including expired ones not dropped yet.
+    pub fn len(&self) -> usize {
+        self.sessions().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drop the expired sessions.
+    pub fn evict_expired(&self) {
+        let now = Instant::now();
+        self.sessions().retain(|_, v| v.1 > now);
+    }
+
+    fn sessions(&self) -> crate::runtime::MutexGuard<'_, HashMap<String, (SessionRecord, Instant)>> {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> MemoryStore {
+        MemoryStore::new()
+    }
+}
+
+impl Debug for MemoryStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryStore")
+            .field("sessions", &self.len())
+            .finish()
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load(&self, id: &str) -> io::Result<Option<SessionRecord>> {
+        Ok(self.sessions().get(id)
+            .filter(|v| v.1 > Instant::now())
+            .map(|v| v.0.clone()))
+    }
+
+    fn save(&self, id: &str, record: &SessionRecord, ttl: Duration) -> io::Result<()> {
+        self.sessions().insert(id.to_owned(), (record.clone(), Instant::now() + ttl));
+        let sweep = {
+            let mut last = self.last_sweep.lock().unwrap_or_else(|e| e.into_inner());
+            let sweep = last.elapsed() >= SWEEP_INTERVAL;
+            if sweep {
+                *last = Instant::now();
+            }
+            sweep
+        };
+        if sweep {
+            self.evict_expired();
+        }
+        Ok(())
+    }
+
+    fn remove(&self, id: &str) -> io::Result<()> {
+        self.sessions().remove(id);
+        Ok(())
+    }
+}
+
+/// The session of a request, in `Request::extra` behind `Sessions`.
+///
+/// Clones share the session. Changes are saved when the response head is
+/// sent, and again after the handler if it made more.
+#[derive(Clone)]
+pub struct Session {
+    inner: Arc<Mutex<SessionState>>,
+}
+
+struct SessionState {
+    // the id the session is saved under, none until something is inserted
+    id: Option<String>,
+    // the valid id the request came with
+    cookie_id: Option<String>,
+    // ids to drop from the store, after a rotation or destruction
+    stale: Vec<String>,
+    record: SessionRecord,
+    // changed since it was last saved
+    dirty: bool,
+}
+
+impl Session {
+    fn new(id: Option<String>, record: SessionRecord) -> Session {
+        Session {
+            inner: Arc::new(Mutex::new(SessionState {
+                cookie_id: id.clone(),
+                id,
+                stale: Vec::new(),
+                record,
+                dirty: false,
+            })),
+        }
+    }
+
+    fn lock(&self) -> crate::runtime::MutexGuard<'_, SessionState> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The session id, `None` until something is inserted.
+    pub fn id(&self) -> Option<String> {
+        self.lock().id.clone()
+    }
+
+    /// The value of `key`.
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.lock().record.values.get(key).cloned()
+    }
+
+    /// Set `key` to `value`, starting the session if it is new.
+    pub fn insert<K: Into<String>, V: Into<String>>(&self, key: K, value: V) {
+        let mut state = self.lock();
+        if state.id.is_none() {
+            state.id = Some(new_id());
+        }
+        state.record.values.insert(key.into(), value.into());
+        state.dirty = true;
+    }
+
+    /// Remove `key`, returning its value.
+    pub fn remove(&self, key: &str) -> Option<String> {
+        let mut state = self.lock();
+        state.dirty = true;
+        state.record.values.remove(key)
+    }
+
+    /// Remove every value, keeping the session.
+    pub fn clear(&self) {
+        let mut state = self.lock();
+        state.dirty = true;
+        state.record.values.clear();
+    }
+
+    /// Move the session to a new id, keeping its values. Call it when the
+    /// privileges of the client change, such as on login, so that an id
+    /// someone else learned before doesn't get them.
+    pub fn rotate(&self) {
+        let mut state = self.lock();
+        if let Some(old) = state.id.replace(new_id()) {
+            state.stale.push(old);
+        }
+        state.dirty = true;
+    }
+
+    /// End the session, dropping it from the store and the client's cookies.
+    pub fn destroy(&self) {
+        let mut state = self.lock();
+        if let Some(old) = state.id.take() {
+            state.stale.push(old);
+        }
+        state.record = SessionRecord::new();
+        state.dirty = true;
+    }
+}
+
+impl Debug for Session {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let state = self.lock();
+        f.debug_struct("Session")
+            .field("values", &state.record.values.len())
+            .field("created", &state.record.created)
+            .finish()
+    }
+}
+
+impl FromRequest for Session {
+    fn from_request(req: &mut Request) -> Result<Self, Rejection> {
+        match req.extra.get::<Session>() {
+            Some(session) => Ok(session.clone()),
+            None => Err(Rejection::new(StatusCode::InternalServerError, "no session, add the Sessions middleware")),
+        }
+    }
+}
+
+// 256 random bits
+fn new_id() -> String {
+    BASE64.encode(rand::random::<[u8; 32]>())
+}
+
+/// An `AroundMiddleWare` giving each request its `Session`.
+///
+/// A session expires after `idle_timeout` without requests, 30 minutes by
+/// default, and `absolute_timeout` after it was created, if set. Its cookie
+/// is `HttpOnly` with `SameSite=Lax`, and lasts as long as the browser.
+pub struct Sessions<S: SessionStore> {
+    store: S,
+    cookie_name: String,
+    path: String,
+    domain: Option<String>,
+    secure: bool,
+    idle_timeout: Duration,
+    absolute_timeout: Option<Duration>,
+}
+
+impl<S: SessionStore> Sessions<S> {
+    /// Creates a middleware keeping sessions in `store`.
+    pub fn new(store: S) -> Sessions<S> {
+        Sessions {
+            store,
+            cookie_name: DEFAULT_COOKIE_NAME.to_owned(),
+            path: "/".to_owned(),
+            domain: None,
+            secure: false,
+            idle_timeout: Duration::from_secs(30 * 60),
+            absolute_timeout: None,
+        }
+    }
+
+    /// Sets the name of the session cookie.
+    pub fn cookie_name<N: Into<String>>(&mut self, name: N) -> &mut Sessions<S> {
+        self.cookie_name = name.into();
+        self
+    }
+
+    /// Sets the `Path` of the session cookie, `/` by default.
+    pub fn path<P: Into<String>>(&mut self, path: P) -> &mut Sessions<S> {
+        self.path = path.into();
+        self
+    }
+
+    /// Sets the `Domain` of the session cookie.
+    pub fn domain<D: Into<String>>(&mut self, domain: D) -> &mut Sessions<S> {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    /// Only send the session cookie over https.
+    pub fn secure(&mut self, secure: bool) -> &mut Sessions<S> {
+        self.secure = secure;
+        self
+    }
+
+    /// Sets how long a session lasts without requests.
+    pub fn idle_timeout(&mut self, timeout: Duration) -> &mut Sessions<S> {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Sets how long a session lasts after it was created, however used.
+    pub fn absolute_timeout(&mut self, timeout: Option<Duration>) -> &mut Sessions<S> {
+        self.absolute_timeout = timeout;
+        self
+    }
+
+    /// The store the sessions are kept in.
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    fn cookie(&self, value: String) -> Cookie {
+        let mut cookie = Cookie::new(self.cookie_name.clone(), value)
+            .path(self.path.clone())
+            .secure(self.secure)
+            .http_only(true)
+            .same_site(SameSite::Lax);
+        if let Some(ref domain) = self.domain {
+            cookie = cookie.domain(domain.clone());
+        }
+        cookie
+    }
+
+    // how long `record` has left, `None` once expired
+    fn ttl(&self, record: &SessionRecord) -> Option<Duration> {
+        let now = SystemTime::now();
+        let idle = now.duration_since(record.accessed).unwrap_or_default();
+        let mut ttl = self.idle_timeout.checked_sub(idle)?;
+        if let Some(absolute) = self.absolute_timeout {
+            let age = now.duration_since(record.created).unwrap_or_default();
+            ttl = ttl.min(absolute.checked_sub(age)?);
+        }
+        Some(ttl).filter(|ttl| !ttl.is_zero())
+    }
+
+    // the session the request's cookie points to, or a new one
+    fn load(&self, req: &Request) -> Session {
+        let jar = CookieJar::from_headers(&req.headers);
+        if let Some(id) = jar.get(&self.cookie_name) {
+            match self.store.load(id) {
+                Ok(Some(record)) if self.ttl(&record).is_some() => {
+                    return Session::new(Some(id.to_owned()), record);
+                }
+                Ok(Some(_)) => {
+                    if let Err(e) = self.store.remove(id) {
+                        warn!("failed to remove expired session: {}", e);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => warn!("failed to load session: {}", e),
+            }
+        }
+        Session::new(None, SessionRecord::new())
+    }
+
+    fn save(&self, session: &Session) {
+        let mut state = session.lock();
+        state.dirty = false;
+        for id in state.stale.drain(..) {
+            if let Err(e) = self.store.remove(&id) {
+                warn!("failed to remove session: {}", e);
+            }
+        }
+        let id = match state.id {
+            Some(ref id) => id.clone(),
+            None => return,
+        };
+        state.record.accessed = SystemTime::now();
+        let ttl = match self.ttl(&state.record) {
+            Some(ttl) => ttl,
+            None => return,
+        };
+        if let Err(e) = self.store.save(&id, &state.record, ttl) {
+            warn!("failed to save session: {}", e);
+        }
+    }
+}
+
+impl<S: SessionStore> Debug for Sessions<S> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sessions")
+            .field("cookie_name", &self.cookie_name)
+            .field("idle_timeout", &self.idle_timeout)
+            .field("absolute_timeout", &self.absolute_timeout)
+            .finish()
+    }
+}
+
+impl<S: SessionStore> AroundMiddleWare for Sessions<S> {
+    fn handle<'a, 'k>(&'a self, mut req: Request<'a, 'k>, mut res: Response<'a, Fresh>, next: Next<'a>) {
+        let session = self.load(&req);
+        req.extra.insert(session.clone());
+        let head = session.clone();
+        res.on_head(move |_, headers| {
+            {
+                let state = head.lock();
+                if state.id != state.cookie_id {
+                    let mut jar = CookieJar::new();
+                    match state.id {
+                        Some(ref id) => jar.add(self.cookie(id.clone())),
+                        None => jar.remove(self.cookie(String::new())),
+                    }
+                    jar.apply(headers);
+                }
+            }
+            // before the client sees the response, and may send the next request
+            self.save(&head);
+        });
+        let written = next.run(req, res);
+        if written.is_none() || session.lock().dirty {
+            self.save(&session);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+    use crate::extract::{extract, reply};
+    use crate::mock::serve;
+    use crate::route::Route;
+    use crate::server::Response;
+    use super::{MemoryStore, Session, SessionRecord, SessionStore, Sessions};
+
+    fn route(store: Arc<MemoryStore>, absolute: Option<Duration>) -> Arc<Route> {
+        let route = Route::new();
+        let mut sessions = Sessions::new(store);
+        sessions.absolute_timeout(absolute);
+        route.add_around(sessions);
+        route.post_fn("/login", reply(|session: Session| {
+            session.rotate();
+            session.insert("user", "joe");
+            "ok"
+        }));
+        route.get_fn("/me", reply(|session: Session| {
+            session.get("user").unwrap_or_default()
+        }));
+        route.post_fn("/logout", reply(|session: Session| {
+            session.destroy();
+            "bye"
+        }));
+        Arc::new(route)
+    }
+
+    // the head and body of the response
+    fn send(route: &Arc<Route>, method: &str, path: &str, cookie: Option<&str>) -> (String, String) {
+        let cookie = cookie.map(|c| format!("Cookie: {}\r\n", c)).unwrap_or_default();
+        let input = format!("{} {} HTTP/1.1\r\nContent-Length: 0\r\n{}Connection: close\r\n\r\n", method, path, cookie);
+        let s = serve(route.clone(), input);
+        let end = s.find("\r\n\r\n").unwrap();
+        (s[..end].to_owned(), s[end + 4..].to_owned())
+    }
+
+    fn set_cookie(head: &str) -> Option<&str> {
+        head.lines().find_map(|l| l.strip_prefix("Set-Cookie: "))
+    }
+
+    fn session_cookie(head: &str) -> String {
+        set_cookie(head).unwrap().split(';').next().unwrap().to_owned()
+    }
+
+    #[test]
+    fn test_session_lifecycle() {
+        let store = Arc::new(MemoryStore::new());
+        let route = route(store.clone(), None);
+
+        let (head, body) = send(&route, "GET", "/me", None);
+        assert_eq!(body, "");
+        assert_eq!(set_cookie(&head), None);
+        assert!(store.is_empty());
+
+        let (head, _) = send(&route, "POST", "/login", None);
+        assert!(set_cookie(&head).unwrap().ends_with("; Path=/; HttpOnly; SameSite=Lax"), "{}", head);
+        let cookie = session_cookie(&head);
+        assert_eq!(store.len(), 1);
+
+        let (head, body) = send(&route, "GET", "/me", Some(&cookie));
+        assert_eq!(body, "joe");
+        assert_eq!(set_cookie(&head), None);
+
+        // logging in again rotates the id, the old one stops working
+        let (head, _) = send(&route, "POST", "/login", Some(&cookie));
+        let rotated = session_cookie(&head);
+        assert_ne!(rotated, cookie);
+        assert_eq!(store.len(), 1);
+        assert_eq!(send(&route, "GET", "/me", Some(&cookie)).1, "");
+        assert_eq!(send(&route, "GET", "/me", Some(&rotated)).1, "joe");
+
+        let (head, _) = send(&route, "POST", "/logout", Some(&rotated));
+        assert_eq!(session_cookie(&head), "mco_session=");
+        assert!(store.is_empty());
+        assert_eq!(send(&route, "GET", "/me", Some(&rotated)).1, "");
+    }
+
+    #[test]
+    fn test_session_expiry() {
+        let store = Arc::new(MemoryStore::new());
+        let route = route(store.clone(), Some(Duration::from_secs(3600)));
+
+        // idle for too long
+        let cookie = session_cookie(&send(&route, "POST", "/login", None).0);
+        let id = &cookie["mco_session=".len()..];
+        let mut record = store.load(id).unwrap().unwrap();
+        record.accessed = SystemTime::now() - Duration::from_secs(31 * 60);
+        store.save(id, &record, Duration::from_secs(60)).unwrap();
+        assert_eq!(send(&route, "GET", "/me", Some(&cookie)).1, "");
+        assert!(store.is_empty());
+
+        // too old, however recently used
+        let cookie = session_cookie(&send(&route, "POST", "/login", None).0);
+        let id = &cookie["mco_session=".len()..];
+        let mut record = store.load(id).unwrap().unwrap();
+        record.created = SystemTime::now() - Duration::from_secs(3601);
+        store.save(id, &record, Duration::from_secs(60)).unwrap();
+        assert_eq!(send(&route, "GET", "/me", Some(&cookie)).1, "");
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn test_session_saved_before_head() {
+        let store = Arc::new(MemoryStore::new());
+        let route = Route::new();
+        route.add_around(Sessions::new(store.clone()));
+        let seen = store.clone();
+        route.post_fn("/login", extract(move |session: Session, res: Response| {
+            session.insert("user", "joe");
+            res.send(b"ok").unwrap();
+            // stored by the time the client has the response
+            assert_eq!(seen.len(), 1);
+            session.insert("late", "yes");
+        }));
+        route.get_fn("/late", reply(|session: Session| session.get("late").unwrap_or_default()));
+        let route = Arc::new(route);
+        let cookie = session_cookie(&send(&route, "POST", "/login", None).0);
+        assert_eq!(send(&route, "GET", "/late", Some(&cookie)).1, "yes");
+    }
+
+    #[test]
+    fn test_memory_store_ttl() {
+        let store = MemoryStore::new();
+        let record = SessionRecord::new();
+        store.save("a", &record, Duration::from_secs(60)).unwrap();
+        store.save("b", &record, Duration::from_secs(0)).unwrap();
+        assert_eq!(store.load("a").unwrap(), Some(record));
+        assert_eq!(store.load("b").unwrap(), None);
+        assert_eq!(store.len(), 2);
+        store.evict_expired();
+        assert_eq!(store.len(), 1);
+        store.remove("a").unwrap();
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn test_missing_middleware() {
+        let route = Route::new();
+        route.get_fn("/", reply(|_session: Session| "unreachable"));
+        let (head, _) = send(&Arc::new(route), "GET", "/", None);
+        assert!(head.starts_with("HTTP/1.1 500 Internal Server Error"), "{}", head);
+    }
+}
@@ -0,0 +1,309 @@
+//! Server-Sent Events (`text/event-stream`) responses.
+//!
+//! An `EventStream` sends `Event`s over a `Response`, each one is flushed to the
+//! client right away. Once the client disconnects, sending fails with `Closed`.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use mco_http::route::Route;
+//! use mco_http::server::{Request, Response};
+//! use mco_http::sse::{self, Event, EventStream};
+//!
+//! let route = Route::new();
+//! route.get_fn("/events", |req: Request, res: Response| {
+//!     // resume after the last event the client got
+//!     let mut n: u64 = sse::last_event_id(&req).and_then(|id| id.parse().ok()).unwrap_or(0);
+//!     let mut events = EventStream::new(res).unwrap();
+//!     loop {
+//!         n += 1;
+//!         let event = Event::data(format!("tick {}", n)).event("tick").id(n.to_string());
+//!         if events.send(&event).is_err() {
+//!             break;
+//!         }
+//!         std::thread::sleep(Duration::from_secs(1));
+//!     }
+//! });
+//! ```
+use std::error::Error as StdError;
+use std::fmt;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use crate::header::{CacheControl, CacheDirective, ContentType, LastEventID};
+use crate::mime::{Mime, SubLevel, TopLevel};
+use crate::net::{Fresh, Streaming};
+use crate::server::{Request, Response};
+
+/// An event sent by an `EventStream`.
+///
+/// Line breaks are removed from the `event` and `id` fields, the `data` and
+/// `comment` are sent as one field per line.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Event {
+    event: Option<String>,
+    id: Option<String>,
+    data: Option<String>,
+    retry: Option<Duration>,
+    comment: Option<String>,
+}
+
+impl Event {
+    /// An empty event, sending one only sends the fields set on it.
+    pub fn new() -> Event {
+        Event::default()
+    }
+
+    /// An event with `data`, the usual way to start one.
+    pub fn data<D: Into<String>>(data: D) -> Event {
+        Event::new().with_data(data)
+    }
+
+    /// Set the data, it may have several lines.
+    pub fn with_data<D: Into<String>>(mut self, data: D) -> Event {
+        self.data = Some(data.into());
+        self
+    }
+
+    /// Set the event type, the client dispatches a `message` event without one.
+    pub fn event<E: Into<String>>(mut self, event: E) -> Event {
+        self.event = Some(single_line(event.into()));
+        self
+    }
+
+    /// Set the id, sent back by a reconnecting client in `Last-Event-ID`.
+    pub fn id<I: Into<String>>(mut self, id: I) -> Event {
+        self.id = Some(single_line(id.into()));
+        self
+    }
+
+    /// Set how long the client waits before reconnecting.
+    pub fn retry(mut self, retry: Duration) -> Event {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Set a comment, ignored by the client.
+    pub fn comment<C: Into<String>>(mut self, comment: C) -> Event {
+        self.comment = Some(comment.into());
+        self
+    }
+
+    fn write_to(&self, out: &mut Vec<u8>) {
+        if let Some(ref comment) = self.comment {
+            write_lines(out, "", comment);
+        }
+        if let Some(ref event) = self.event {
+            write_field(out, "event", event);
+        }
+        if let Some(ref id) = self.id {
+            write_field(out, "id", id);
+        }
+        if let Some(retry) = self.retry {
+            write_field(out, "retry", &retry.as_millis().to_string());
+        }
+        if let Some(ref data) = self.data {
+            write_lines(out, "data", data);
+        }
+        out.push(b'\n');
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut out = Vec::new();
+        self.write_to(&mut out);
+        f.write_str(&String::from_utf8_lossy(&out))
+    }
+}
+
+fn single_line(mut s: String) -> String {
+    s.retain(|c| c != '\r' && c != '\n');
+    s
+}
+
+fn write_field(out: &mut Vec<u8>, name: &str, value: &str) {
+    out.extend_from_slice(name.as_bytes());
+    out.extend_from_slice(b": ");
+    out.extend_from_slice(value.as_bytes());
+    out.push(b'\n');
+}
+
+/// One field per line of `value`, a comment if `name` is empty.
+fn write_lines(out: &mut Vec<u8>, name: &str, value: &str) {
+    let value = value.replace("\r\n", "\n");
+    for line in value.split(['\n', '\r']) {
+        write_field(out, name, line);
+    }
+}
+
+/// The `Last-Event-ID` a reconnecting client sent, to resume after that event.
+pub fn last_event_id<'r>(req: &'r Request) -> Option<&'r str> {
+    req.headers.get::<LastEventID>().map(|id| id.as_str())
+}
+
+/// The client of an `EventStream` is gone.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Closed;
+
+impl fmt::Display for Closed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("event stream closed")
+    }
+}
+
+impl StdError for Closed {}
+
+/// Sends events over a `text/event-stream` response.
+pub struct EventStream<'a> {
+    res: Response<'a, Streaming>,
+    closed: bool,
+    last_write: Instant,
+}
+
+impl<'a> EventStream<'a> {
+    /// Start an event stream, sending the head of `res` with `Content-Type:
+    /// text/event-stream` and `Cache-Control: no-cache`.
+    pub fn new(mut res: Response<'a, Fresh>) -> io::Result<EventStream<'a>> {
+        res.headers_mut().set(ContentType(Mime(TopLevel::Text, SubLevel::Ext("event-stream".to_owned()), vec![])));
+        res.headers_mut().set(CacheControl(vec![CacheDirective::NoCache]));
+        let mut res = res.start()?;
+        res.flush()?;
+        Ok(EventStream {
+            res,
+            closed: false,
+            last_write: Instant::now(),
+        })
+    }
+
+    /// Send an event, and flush it to the client.
+    pub fn send(&mut self, event: &Event) -> Result<(), Closed> {
+        let mut out = Vec::new();
+        event.write_to(&mut out);
+        self.write(&out)
+    }
+
+    /// Send a heartbeat comment, so proxies don't close an idle stream and a
+    /// disconnected client is noticed.
+    pub fn heartbeat(&mut self) -> Result<(), Closed> {
+        self.write(b":\n\n")
+    }
+
+    /// Send a heartbeat if nothing was sent for `idle`.
+    pub fn heartbeat_if_idle(&mut self, idle: Duration) -> Result<(), Closed> {
+        if self.last_write.elapsed() >= idle {
+            self.heartbeat()
+        } else if self.closed {
+            Err(Closed)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Whether the client is gone.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// End the stream, the client reconnects unless it closes its `EventSource`.
+    pub fn end(self) -> Result<(), Closed> {
+        if self.closed {
+            return Err(Closed);
+        }
+        self.res.end().map_err(|e| {
+            debug!("event stream closed: {:?}", e);
+            Closed
+        })
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<(), Closed> {
+        if self.closed {
+            return Err(Closed);
+        }
+        match self.res.write_all(buf).and_then(|_| self.res.flush()) {
+            Ok(()) => {
+                self.last_write = Instant::now();
+                Ok(())
+            }
+            Err(e) => {
+                debug!("event stream closed: {:?}", e);
+                self.closed = true;
+                Err(Closed)
+            }
+        }
+    }
+}
+
+impl<'a> fmt::Debug for EventStream<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EventStream")
+            .field("closed", &self.closed)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::io::{self, Write};
+    use std::time::Duration;
+
+    use crate::header::Headers;
+    use crate::mock::serve;
+    use crate::server::{Request, Response};
+
+    use super::{last_event_id, Closed, Event, EventStream};
+
+    #[test]
+    fn test_event_format() {
+        let event = Event::data("one\ntwo\r\nthree").event("up\ndate").id("7").retry(Duration::from_secs(3));
+        assert_eq!(event.to_string(), "event: update\nid: 7\nretry: 3000\ndata: one\ndata: two\ndata: three\n\n");
+        assert_eq!(Event::data("").to_string(), "data: \n\n");
+        assert_eq!(Event::new().comment("hi").to_string(), ": hi\n\n");
+    }
+
+    #[test]
+    fn test_event_stream() {
+        fn handle(req: Request, res: Response) {
+            let id = last_event_id(&req).unwrap_or("none").to_owned();
+            let mut events = EventStream::new(res).unwrap();
+            events.send(&Event::data(id).id("2")).unwrap();
+            events.heartbeat().unwrap();
+            events.end().unwrap();
+        }
+        let s = serve(handle, "GET /events HTTP/1.1\r\nLast-Event-ID: 1\r\n\r\n");
+        assert!(s.contains("Content-Type: text/event-stream\r\n"), "{}", s);
+        assert!(s.contains("Cache-Control: no-cache\r\n"), "{}", s);
+        assert!(s.ends_with("\r\n\r\nF\r\nid: 2\ndata: 1\n\n\r\n3\r\n:\n\n\r\n0\r\n\r\n"), "{:?}", s);
+    }
+
+    /// Fails like a disconnected client once `gone` is set.
+    struct Disconnecting<'c>(&'c Cell<bool>);
+
+    impl<'c> Write for Disconnecting<'c> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.0.get() {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe"));
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_closed() {
+        let gone = Cell::new(false);
+        let mut out = Disconnecting(&gone);
+        let mut headers = Headers::new();
+        let mut events = EventStream::new(Response::new(&mut out, &mut headers)).unwrap();
+        events.send(&Event::data("sent")).unwrap();
+        gone.set(true);
+        assert!(!events.is_closed());
+        assert_eq!(events.send(&Event::data("lost")), Err(Closed));
+        assert!(events.is_closed());
+        assert_eq!(events.heartbeat(), Err(Closed));
+        assert_eq!(events.heartbeat_if_idle(Duration::from_secs(60)), Err(Closed));
+    }
+}
@@ -0,0 +1,491 @@
+//! Static file serving.
+//!
+//! `StaticFiles` is a `Handler` serving the files below a directory for the requests
+//! below a url prefix. It sets `Content-Type` from the file extension, `Last-Modified`
+//! and `ETag`, answers conditional requests with `304 Not Modified` and serves
+//! `Range` requests with `206 Partial Content`.
+//!
+//! ```no_run
+//! use mco_http::route::Route;
+//! use mco_http::static_files::StaticFiles;
+//!
+//! let route = Route::new();
+//! //GET /static/css/app.css serves ./assets/css/app.css
+//! route.get_fn("/static/{*}", StaticFiles::new("/static", "./assets"));
+//! ```
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use time;
+use url::percent_encoding::percent_decode;
+
+use crate::header::{AcceptRanges, Allow, ByteRangeSpec, ContentLength, ContentRange, ContentRangeSpec,
+                    ContentType, ETag, EntityTag, Headers, HttpDate, IfModifiedSince, IfNoneMatch,
+                    IfRange, LastModified, Location, Range, RangeUnit};
+use crate::method::Method;
+use crate::mime::Mime;
+use crate::net::Fresh;
+use crate::route::request_path;
+use crate::server::{Handler, Request, Response};
+use crate::status::StatusCode;
+use crate::uri::RequestUri;
+
+/// More ranges than this in one request are ignored, and the whole file is sent.
+const MAX_RANGES: usize = 16;
+
+/// Serves the files below `root` for the requests below `prefix`.
+///
+/// Only `GET` and `HEAD` are answered, paths leaving `root`, by `..` or by a
+/// symlink, get `404 Not Found`. A directory is served by its index file.
+/// Mounted in a sub route, `prefix` is matched below the mount prefix.
+#[derive(Debug)]
+pub struct StaticFiles {
+    prefix: String,
+    root: PathBuf,
+    index: Vec<String>,
+}
+
+impl StaticFiles {
+    /// Creates a handler serving `root` for the requests below `prefix`.
+    ///
+    /// `root` is resolved to an absolute path here, so it should exist already.
+    pub fn new<P: Into<PathBuf>>(prefix: &str, root: P) -> StaticFiles {
+        let root = root.into();
+        StaticFiles {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            root: fs::canonicalize(&root).unwrap_or(root),
+            index: vec!["index.html".to_string()],
+        }
+    }
+
+    /// Sets the files tried, in order, for a request to a directory. Without
+    /// any, a directory gets `404 Not Found`.
+    ///
+    /// Default is `index.html`.
+    pub fn index_files(&mut self, names: &[&str]) {
+        self.index = names.iter().map(|name| name.to_string()).collect();
+    }
+
+    /// The file for a request path, if it is below `prefix` and stays below `root`.
+    fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let rest = path.strip_prefix(self.prefix.as_str())?;
+        if !rest.is_empty() && !rest.starts_with('/') {
+            return None;
+        }
+        let rest = percent_decode(rest.as_bytes()).decode_utf8().ok()?;
+        let mut file = self.root.clone();
+        for segment in rest.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => return None,
+                s if s.contains('\\') || s.contains('\0') || Path::new(s).has_root() => return None,
+                s => file.push(s),
+            }
+        }
+        // a symlink may still point outside of root
+        let file = fs::canonicalize(file).ok()?;
+        if !file.starts_with(&self.root) {
+            return None;
+        }
+        Some(file)
+    }
+
+    /// Opens the file for `path`, `uri_path` is the path the client asked for,
+    /// including the prefix of the mounts it went through.
+    fn open(&self, path: &str, uri_path: &str, res: &mut Response) -> Option<(File, Metadata, PathBuf)> {
+        let mut file_path = self.resolve(path)?;
+        let mut meta = fs::metadata(&file_path).ok()?;
+        if meta.is_dir() {
+            if !path.ends_with('/') {
+                res.status = StatusCode::MovedPermanently;
+                res.headers_mut().set(Location(format!("{}/", uri_path)));
+                return None;
+            }
+            file_path = self.index.iter().map(|name| file_path.join(name)).find(|p| p.is_file())?;
+            meta = fs::metadata(&file_path).ok()?;
+        }
+        match File::open(&file_path) {
+            Ok(file) => Some((file, meta, file_path)),
+            Err(e) => {
+                if e.kind() == io::ErrorKind::PermissionDenied {
+                    res.status = StatusCode::Forbidden;
+                } else {
+                    info!("failed to open {:?}: {}", file_path, e);
+                    res.status = StatusCode::InternalServerError;
+                }
+                None
+            }
+        }
+    }
+}
+
+impl Handler for StaticFiles {
+    fn handle<'a, 'k>(&'a self, req: Request<'a, 'k>, mut res: Response<'a, Fresh>) {
+        if req.method != Method::Get && req.method != Method::Head {
+            res.status = StatusCode::MethodNotAllowed;
+            res.headers_mut().set(Allow(vec![Method::Get, Method::Head]));
+            return;
+        }
+        let (path, uri_path) = match (request_path(&req), &req.uri) {
+            (Some(path), RequestUri::AbsolutePath(p)) => (path, &p[..p.find('?').unwrap_or(p.len())]),
+            _ => {
+                res.status = StatusCode::NotFound;
+                return;
+            }
+        };
+        res.status = StatusCode::NotFound;
+        let (file, meta, file_path) = match self.open(path, uri_path, &mut res) {
+            Some(opened) => opened,
+            None => return,
+        };
+        res.status = StatusCode::Ok;
+        if let Err(e) = send_file(&req, res, file, &meta, &file_path) {
+            debug!("error sending {:?}: {:?}", file_path, e);
+        }
+    }
+}
+
+fn send_file(req: &Request, mut res: Response, mut file: File, meta: &Metadata, path: &Path) -> io::Result<()> {
+    let len = meta.len();
+    let modified = meta.modified().ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs());
+    let etag = EntityTag::strong(format!("{:x}-{:x}", modified.unwrap_or(0), len));
+
+    res.headers_mut().set(ETag(etag.clone()));
+    if let Some(secs) = modified {
+        res.headers_mut().set(LastModified(http_date(secs)));
+    }
+    res.headers_mut().set(AcceptRanges(vec![RangeUnit::Bytes]));
+
+    if !modified_since(&req.headers, &etag, modified) {
+        res.status = StatusCode::NotModified;
+        return Ok(());
+    }
+
+    let mime = mime_for(path);
+    let ranges = match req.headers.get::<Range>() {
+        Some(range) if req.method == Method::Get && if_range(&req.headers, &etag, modified) => {
+            byte_ranges(range, len)
+        }
+        _ => None,
+    };
+
+    match ranges {
+        None => {
+            res.headers_mut().set(ContentType(mime));
+            res.headers_mut().set(ContentLength(len));
+            let mut stream = res.start()?;
+            if req.method == Method::Get {
+                io::copy(&mut file, &mut stream)?;
+            }
+            stream.end()
+        }
+        Some(ref ranges) if ranges.is_empty() => {
+            res.status = StatusCode::RangeNotSatisfiable;
+            res.headers_mut().set(ContentRange(ContentRangeSpec::Bytes {
+                range: None,
+                instance_length: Some(len),
+            }));
+            res.send(b"")
+        }
+        Some(ref ranges) if ranges.len() == 1 => {
+            let (start, end) = ranges[0];
+            res.status = StatusCode::PartialContent;
+            res.headers_mut().set(ContentType(mime));
+            res.headers_mut().set(ContentRange(ContentRangeSpec::Bytes {
+                range: Some((start, end)),
+                instance_length: Some(len),
+            }));
+            res.headers_mut().set(ContentLength(end - start + 1));
+            let mut stream = res.start()?;
+            file.seek(SeekFrom::Start(start))?;
+            io::copy(&mut (&mut file).take(end - start + 1), &mut stream)?;
+            stream.end()
+        }
+        Some(ranges) => {
+            let boundary = format!("mco_http_byteranges_{}", etag.tag());
+            let parts: Vec<(String, u64, u64)> = ranges.into_iter().map(|(start, end)| {
+                let head = format!("--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                                   boundary, mime, start, end, len);
+                (head, start, end)
+            }).collect();
+            let tail = format!("--{}--\r\n", boundary);
+            let body_len = parts.iter()
+                .map(|&(ref head, start, end)| head.len() as u64 + end - start + 1 + 2)
+                .sum::<u64>() + tail.len() as u64;
+
+            res.status = StatusCode::PartialContent;
+            res.headers_mut().set(ContentType(format!("multipart/byteranges; boundary={}", boundary)
+                .parse().unwrap()));
+            res.headers_mut().set(ContentLength(body_len));
+            let mut stream = res.start()?;
+            for (head, start, end) in parts {
+                stream.write_all(head.as_bytes())?;
+                file.seek(SeekFrom::Start(start))?;
+                io::copy(&mut (&mut file).take(end - start + 1), &mut stream)?;
+                stream.write_all(b"\r\n")?;
+            }
+            stream.write_all(tail.as_bytes())?;
+            stream.end()
+        }
+    }
+}
+
+/// Whether the client's copy is outdated, by `If-None-Match` or else `If-Modified-Since`.
+fn modified_since(headers: &Headers, etag: &EntityTag, modified: Option<u64>) -> bool {
+    if let Some(if_none_match) = headers.get::<IfNoneMatch>() {
+        return match *if_none_match {
+            IfNoneMatch::Any => false,
+            IfNoneMatch::Items(ref tags) => !tags.iter().any(|tag| tag.weak_eq(etag)),
+        };
+    }
+    match (headers.get::<IfModifiedSince>(), modified) {
+        (Some(&IfModifiedSince(HttpDate(since))), Some(secs)) => secs as i64 > since.to_timespec().sec,
+        _ => true,
+    }
+}
+
+/// Whether the `Range` applies, a failed `If-Range` asks for the whole file.
+fn if_range(headers: &Headers, etag: &EntityTag, modified: Option<u64>) -> bool {
+    match headers.get::<IfRange>() {
+        None => true,
+        Some(IfRange::EntityTag(tag)) => tag.strong_eq(etag),
+        Some(&IfRange::Date(HttpDate(date))) => modified == Some(date.to_timespec().sec as u64),
+    }
+}
+
+/// The satisfiable ranges of a file of `len` bytes, as first and last byte.
+/// `None` means the header is ignored and an empty list is `416 Range Not Satisfiable`.
+fn byte_ranges(range: &Range, len: u64) -> Option<Vec<(u64, u64)>> {
+    let specs = match *range {
+        Range::Bytes(ref specs) if specs.len() <= MAX_RANGES => specs,
+        _ => return None,
+    };
+    let mut ranges = Vec::new();
+    for spec in specs {
+        match *spec {
+            ByteRangeSpec::FromTo(from, to) if from > to => return None,
+            ByteRangeSpec::FromTo(from, to) if from < len => ranges.push((from, to.min(len - 1))),
+            ByteRangeSpec::AllFrom(from) if from < len => ranges.push((from, len - 1)),
+            ByteRangeSpec::Last(n) if n > 0 && len > 0 => ranges.push((len - n.min(len), len - 1)),
+            _ => {}
+        }
+    }
+    Some(ranges)
+}
+
+fn http_date(secs: u64) -> HttpDate {
+    HttpDate(time::at_utc(time::Timespec::new(secs as i64, 0)))
+}
+
+/// The `Content-Type` for a file, by its extension.
+fn mime_for(path: &Path) -> Mime {
+    let ext = path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase())
+        .unwrap_or_default();
+    let mime = match ext.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "application/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "md" => "text/markdown; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "pdf" => "application/pdf",
+        "wasm" => "application/wasm",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        _ => "application/octet-stream",
+    };
+    mime.parse().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use crate::mock::serve;
+    use crate::route::Route;
+    use super::StaticFiles;
+
+    /// The files a test serves, in a directory of the test's own, removed when
+    /// the test ends.
+    struct Root {
+        dir: PathBuf,
+    }
+
+    impl Root {
+        fn new(name: &str) -> Root {
+            let dir = std::env::temp_dir().join(format!("mco_http_static_{}_{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            let root = Root { dir };
+            fs::create_dir_all(root.path().join("docs")).unwrap();
+            fs::write(root.path().join("hello.txt"), "hello world").unwrap();
+            fs::write(root.path().join("docs").join("index.html"), "<h1>docs</h1>").unwrap();
+            root
+        }
+
+        /// The directory served, below the one of the test.
+        fn path(&self) -> PathBuf {
+            self.dir.join("public")
+        }
+    }
+
+    impl Drop for Root {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn get(root: &Root, path: &str, headers: &str) -> String {
+        let input = format!("GET {} HTTP/1.1\r\n{}Connection: close\r\n\r\n", path, headers);
+        serve(StaticFiles::new("/static", root.path()), &input)
+    }
+
+    fn header<'a>(s: &'a str, name: &str) -> &'a str {
+        let start = s.find(&format!("{}: ", name)).unwrap() + name.len() + 2;
+        &s[start..start + s[start..].find("\r\n").unwrap()]
+    }
+
+    #[test]
+    fn test_static_files_get() {
+        let root = Root::new("get");
+        let s = get(&root, "/static/hello.txt", "");
+        assert!(s.starts_with("HTTP/1.1 200 OK\r\n"), "{}", s);
+        assert!(s.contains("Content-Type: text/plain; charset=utf-8\r\n"), "{}", s);
+        assert!(s.contains("Accept-Ranges: bytes\r\n"), "{}", s);
+        assert!(s.contains("Last-Modified: "), "{}", s);
+        assert!(s.ends_with("\r\n\r\nhello world"), "{}", s);
+
+        let s = serve(StaticFiles::new("/static", root.path()), "HEAD /static/hello.txt HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(s.contains("Content-Length: 11\r\n"), "{}", s);
+        assert!(s.ends_with("\r\n\r\n"), "{}", s);
+
+        let s = serve(StaticFiles::new("/static", root.path()), "POST /static/hello.txt HTTP/1.1\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+        assert!(s.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"), "{}", s);
+    }
+
+    #[test]
+    fn test_static_files_traversal() {
+        let root = Root::new("traversal");
+        // next to the directory served
+        fs::write(root.dir.join("secret"), "secret").unwrap();
+        for path in ["/static/../secret", "/static/%2e%2e/secret",
+                     "/static/docs/..%2f..%2fsecret", "/static/%2e%2e%5csecret",
+                     "/statichello.txt", "/static/missing.txt"] {
+            let s = get(&root, path, "");
+            assert!(s.starts_with("HTTP/1.1 404 Not Found\r\n"), "{} => {}", path, s);
+        }
+    }
+
+    #[test]
+    fn test_static_files_index() {
+        let root = Root::new("index");
+        let s = get(&root, "/static/docs/", "");
+        assert!(s.contains("Content-Type: text/html; charset=utf-8\r\n"), "{}", s);
+        assert!(s.ends_with("\r\n\r\n<h1>docs</h1>"), "{}", s);
+
+        let s = get(&root, "/static/docs?page=1", "");
+        assert!(s.starts_with("HTTP/1.1 301 Moved Permanently\r\n"), "{}", s);
+        assert!(s.contains("Location: /static/docs/\r\n"), "{}", s);
+
+        let s = get(&root, "/static/", "");
+        assert!(s.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", s);
+    }
+
+    #[test]
+    fn test_static_files_mounted() {
+        let root = Root::new("mounted");
+        let files = Route::new();
+        files.get_fn("/static/{*}", StaticFiles::new("/static", root.path()));
+        let route = Route::new();
+        route.mount("/app", files);
+        let route = Arc::new(route);
+
+        let s = serve(route.clone(), "GET /app/static/hello.txt HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(s.starts_with("HTTP/1.1 200 OK\r\n"), "{}", s);
+        assert!(s.ends_with("\r\n\r\nhello world"), "{}", s);
+
+        let s = serve(route, "GET /app/static/docs HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(s.starts_with("HTTP/1.1 301 Moved Permanently\r\n"), "{}", s);
+        assert!(s.contains("Location: /app/static/docs/\r\n"), "{}", s);
+    }
+
+    #[test]
+    fn test_static_files_conditional() {
+        let root = Root::new("conditional");
+        let s = get(&root, "/static/hello.txt", "");
+        let etag = header(&s, "ETag").to_string();
+        let modified = header(&s, "Last-Modified").to_string();
+
+        let s = get(&root, "/static/hello.txt", &format!("If-None-Match: {}\r\n", etag));
+        assert!(s.starts_with("HTTP/1.1 304 Not Modified\r\n"), "{}", s);
+        assert!(s.ends_with("\r\n\r\n"), "{}", s);
+
+        let s = get(&root, "/static/hello.txt", &format!("If-Modified-Since: {}\r\n", modified));
+        assert!(s.starts_with("HTTP/1.1 304 Not Modified\r\n"), "{}", s);
+
+        let s = get(&root, "/static/hello.txt", "If-None-Match: \"other\"\r\n");
+        assert!(s.starts_with("HTTP/1.1 200 OK\r\n"), "{}", s);
+
+        let s = get(&root, "/static/hello.txt", "If-Modified-Since: Sun, 07 Nov 1994 08:48:37 GMT\r\n");
+        assert!(s.starts_with("HTTP/1.1 200 OK\r\n"), "{}", s);
+    }
+
+    #[test]
+    fn test_static_files_range() {
+        let root = Root::new("range");
+        let s = get(&root, "/static/hello.txt", "Range: bytes=0-4\r\n");
+        assert!(s.starts_with("HTTP/1.1 206 Partial Content\r\n"), "{}", s);
+        assert!(s.contains("Content-Range: bytes 0-4/11\r\n"), "{}", s);
+        assert!(s.ends_with("\r\n\r\nhello"), "{}", s);
+
+        let s = get(&root, "/static/hello.txt", "Range: bytes=-5\r\n");
+        assert!(s.ends_with("\r\n\r\nworld"), "{}", s);
+
+        let s = get(&root, "/static/hello.txt", "Range: bytes=20-\r\n");
+        assert!(s.starts_with("HTTP/1.1 416 Range Not Satisfiable\r\n"), "{}", s);
+        assert!(s.contains("Content-Range: bytes */11\r\n"), "{}", s);
+
+        let etag = header(&get(&root, "/static/hello.txt", ""), "ETag").to_string();
+        let s = get(&root, "/static/hello.txt", &format!("Range: bytes=6-\r\nIf-Range: {}\r\n", etag));
+        assert!(s.ends_with("\r\n\r\nworld"), "{}", s);
+        let s = get(&root, "/static/hello.txt", "Range: bytes=6-\r\nIf-Range: \"other\"\r\n");
+        assert!(s.starts_with("HTTP/1.1 200 OK\r\n"), "{}", s);
+        assert!(s.ends_with("\r\n\r\nhello world"), "{}", s);
+    }
+
+    #[test]
+    fn test_static_files_multi_range() {
+        let root = Root::new("multi_range");
+        let s = get(&root, "/static/hello.txt", "Range: bytes=0-1,6-7\r\n");
+        assert!(s.starts_with("HTTP/1.1 206 Partial Content\r\n"), "{}", s);
+        let content_type = header(&s, "Content-Type");
+        let boundary = &content_type[content_type.find("boundary=").unwrap() + 9..];
+        let body = &s[s.find("\r\n\r\n").unwrap() + 4..];
+        assert_eq!(body, format!("\
+            --{b}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 0-1/11\r\n\r\nhe\r\n\
+            --{b}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 6-7/11\r\n\r\nwo\r\n\
+            --{b}--\r\n", b = boundary));
+        assert_eq!(header(&s, "Content-Length"), body.len().to_string());
+    }
+}
@@ -0,0 +1,711 @@
+//! WebSocket ([RFC6455](https://tools.ietf.org/html/rfc6455)) connections.
+//!
+//! A `Handler` upgrades a request with `Response::upgrade_websocket`, which checks
+//! the handshake and answers it with `101 Switching Protocols`, so routes and
+//! middleware run as for any other request.
+//!
+//! ```no_run
+//! use mco_http::route::Route;
+//! use mco_http::server::{Request, Response};
+//! use mco_http::websocket::Message;
+//!
+//! let route = Route::new();
+//! route.get_fn("/echo", |req: Request, res: Response| {
+//!     let mut ws = match res.upgrade_websocket(req) {
+//!         Ok(ws) => ws,
+//!         Err(_) => return,
+//!     };
+//!     while let Ok(msg) = ws.read() {
+//!         match msg {
+//!             Message::Text(_) | Message::Binary(_) => ws.send(msg).unwrap(),
+//!             Message::Close(_) => break,
+//!             _ => {}
+//!         }
+//!     }
+//! });
+//! ```
+//!
+//! `Client::websocket` connects to a `ws://` or `wss://` url.
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::Shutdown;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use sha1::{Digest, Sha1};
+use unicase::UniCase;
+
+use crate::buffer::BufReader;
+use crate::header::{Connection, ConnectionOption, Headers, Protocol, ProtocolName, Upgrade};
+use crate::method::Method;
+use crate::net::{Fresh, NetworkStream};
+use crate::server::{Request, Response, Upgraded};
+use crate::status::StatusCode;
+use crate::version::HttpVersion;
+use crate::Error;
+
+/// Appended to `Sec-WebSocket-Key` to compute `Sec-WebSocket-Accept`.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The only `Sec-WebSocket-Version` there is.
+const VERSION: &str = "13";
+
+/// Messages larger than this are refused with close code `1009` by default.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 << 20;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+/// Close codes sent when the peer breaks the protocol.
+const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+const CLOSE_INVALID_DATA: u16 = 1007;
+const CLOSE_TOO_BIG: u16 = 1009;
+
+/// Which end of the connection a `WebSocket` is. Clients mask the frames they
+/// send, servers don't.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    /// The end that accepted the connection.
+    Server,
+    /// The end that opened the connection.
+    Client,
+}
+
+/// A WebSocket message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+    /// A UTF-8 text message.
+    Text(String),
+    /// A binary message.
+    Binary(Vec<u8>),
+    /// A ping, answered with a pong by `WebSocket::read`.
+    Ping(Vec<u8>),
+    /// A pong.
+    Pong(Vec<u8>),
+    /// The peer is closing the connection.
+    Close(Option<CloseFrame>),
+}
+
+/// The status code and reason of a close message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CloseFrame {
+    /// The status code, such as `1000` for a normal closure.
+    pub code: u16,
+    /// Why the connection is closed, for humans.
+    pub reason: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Open,
+    /// We sent a close frame and wait for the peer's.
+    CloseSent,
+    /// Both ends sent a close frame, or the protocol was broken.
+    Closed,
+}
+
+/// A message oriented WebSocket connection over a stream.
+///
+/// `read` reassembles fragmented messages, answers pings and replies to the
+/// close handshake.
+pub struct WebSocket<S: Read + Write> {
+    stream: BufReader<S>,
+    role: Role,
+    state: State,
+    max_message_size: usize,
+    max_frame_size: Option<usize>,
+    /// The opcode and the data of a message that is not complete yet.
+    partial: Option<(u8, Vec<u8>)>,
+}
+
+impl<S: Read + Write> WebSocket<S> {
+    /// Creates a WebSocket over a stream that has done the opening handshake.
+    pub fn from_stream(stream: S, role: Role) -> WebSocket<S> {
+        WebSocket::from_buffered(BufReader::new(stream), role)
+    }
+
+    /// Like `from_stream`, for a stream that may have buffered the first frames.
+    pub fn from_buffered(stream: BufReader<S>, role: Role) -> WebSocket<S> {
+        WebSocket {
+            stream,
+            role,
+            state: State::Open,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            max_frame_size: None,
+            partial: None,
+        }
+    }
+
+    /// Sets the largest message `read` accepts, a bigger one closes the connection
+    /// with code `1009`.
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.max_message_size = size;
+    }
+
+    /// Splits the text and binary messages sent into fragments of at most `size`
+    /// bytes. Default is no limit, a message is sent in one frame.
+    pub fn set_max_frame_size(&mut self, size: Option<usize>) {
+        self.max_frame_size = size;
+    }
+
+    /// Gets a reference to the underlying stream.
+    pub fn get_ref(&self) -> &S {
+        self.stream.get_ref()
+    }
+
+    /// Gets a mutable reference to the underlying stream.
+    pub fn get_mut(&mut self) -> &mut S {
+        self.stream.get_mut()
+    }
+
+    /// Whether a close message was sent or received.
+    pub fn is_closing(&self) -> bool {
+        self.state != State::Open
+    }
+
+    /// Reads the next message.
+    ///
+    /// Pings are answered before they are returned. Once the peer's close message
+    /// has been returned, `read` fails with `ConnectionAborted`.
+    pub fn read(&mut self) -> io::Result<Message> {
+        loop {
+            if self.state == State::Closed {
+                return Err(closed());
+            }
+            let (fin, opcode, data) = self.read_frame()?;
+            match opcode {
+                OP_CONTINUATION => {
+                    let (op, mut buf) = match self.partial.take() {
+                        Some(partial) => partial,
+                        None => return Err(self.fail(CLOSE_PROTOCOL_ERROR, "continuation without a message")),
+                    };
+                    if buf.len() + data.len() > self.max_message_size {
+                        return Err(self.fail(CLOSE_TOO_BIG, "message too big"));
+                    }
+                    buf.extend_from_slice(&data);
+                    if fin {
+                        return self.message(op, buf);
+                    }
+                    self.partial = Some((op, buf));
+                }
+                OP_TEXT | OP_BINARY => {
+                    if self.partial.is_some() {
+                        return Err(self.fail(CLOSE_PROTOCOL_ERROR, "message inside a fragmented message"));
+                    }
+                    if fin {
+                        return self.message(opcode, data);
+                    }
+                    self.partial = Some((opcode, data));
+                }
+                OP_CLOSE => {
+                    let frame = match data.len() {
+                        0 => None,
+                        1 => return Err(self.fail(CLOSE_PROTOCOL_ERROR, "invalid close frame")),
+                        _ => {
+                            let code = u16::from_be_bytes([data[0], data[1]]);
+                            if !valid_close_code(code) {
+                                return Err(self.fail(CLOSE_PROTOCOL_ERROR, "invalid close code"));
+                            }
+                            match String::from_utf8(data[2..].to_vec()) {
+                                Ok(reason) => Some(CloseFrame { code, reason }),
+                                Err(_) => return Err(self.fail(CLOSE_INVALID_DATA, "invalid close reason")),
+                            }
+                        }
+                    };
+                    if self.state == State::Open {
+                        // echo the code to complete the closing handshake
+                        let reply = frame.as_ref().map(|f| f.code.to_be_bytes().to_vec()).unwrap_or_default();
+                        self.write_frame(true, OP_CLOSE, &reply)?;
+                    }
+                    self.state = State::Closed;
+                    return Ok(Message::Close(frame));
+                }
+                OP_PING => {
+                    if self.state == State::Open {
+                        self.write_frame(true, OP_PONG, &data)?;
+                    }
+                    return Ok(Message::Ping(data));
+                }
+                OP_PONG => return Ok(Message::Pong(data)),
+                _ => return Err(self.fail(CLOSE_PROTOCOL_ERROR, "unknown opcode")),
+            }
+        }
+    }
+
+    /// Sends a message. Sending a `Close` is the same as `close`.
+    pub fn send(&mut self, msg: Message) -> io::Result<()> {
+        if self.state != State::Open {
+            return Err(closed());
+        }
+        match msg {
+            Message::Text(text) => self.write_message(OP_TEXT, text.as_bytes()),
+            Message::Binary(data) => self.write_message(OP_BINARY, &data),
+            Message::Ping(data) => self.write_control(OP_PING, &data),
+            Message::Pong(data) => self.write_control(OP_PONG, &data),
+            Message::Close(frame) => self.close(frame),
+        }
+    }
+
+    /// Starts the closing handshake, `read` returns the peer's close message
+    /// once it arrives. Does nothing if a close message was already sent.
+    pub fn close(&mut self, frame: Option<CloseFrame>) -> io::Result<()> {
+        if self.state != State::Open {
+            return Ok(());
+        }
+        let mut data = Vec::new();
+        if let Some(frame) = frame {
+            data.extend_from_slice(&frame.code.to_be_bytes());
+            data.extend_from_slice(frame.reason.as_bytes());
+        }
+        self.write_control(OP_CLOSE, &data)?;
+        self.state = State::CloseSent;
+        Ok(())
+    }
+
+    fn message(&mut self, opcode: u8, data: Vec<u8>) -> io::Result<Message> {
+        if opcode == OP_BINARY {
+            return Ok(Message::Binary(data));
+        }
+        match String::from_utf8(data) {
+            Ok(text) => Ok(Message::Text(text)),
+            Err(_) => Err(self.fail(CLOSE_INVALID_DATA, "invalid utf-8 in a text message")),
+        }
+    }
+
+    fn read_frame(&mut self) -> io::Result<(bool, u8, Vec<u8>)> {
+        let mut head = [0; 2];
+        self.stream.read_exact(&mut head)?;
+        let fin = head[0] & 0x80 != 0;
+        let opcode = head[0] & 0x0F;
+        if head[0] & 0x70 != 0 {
+            return Err(self.fail(CLOSE_PROTOCOL_ERROR, "reserved bits set"));
+        }
+        // clients must mask their frames, servers must not
+        if (head[1] & 0x80 != 0) != (self.role == Role::Server) {
+            return Err(self.fail(CLOSE_PROTOCOL_ERROR, "invalid masking"));
+        }
+        let len = match head[1] & 0x7F {
+            126 => {
+                let mut len = [0; 2];
+                self.stream.read_exact(&mut len)?;
+                u16::from_be_bytes(len) as u64
+            }
+            127 => {
+                let mut len = [0; 8];
+                self.stream.read_exact(&mut len)?;
+                u64::from_be_bytes(len)
+            }
+            len => len as u64,
+        };
+        if opcode >= OP_CLOSE && (!fin || len > 125) {
+            return Err(self.fail(CLOSE_PROTOCOL_ERROR, "invalid control frame"));
+        }
+        if len > self.max_message_size as u64 {
+            return Err(self.fail(CLOSE_TOO_BIG, "message too big"));
+        }
+        let mut mask = [0; 4];
+        if self.role == Role::Server {
+            self.stream.read_exact(&mut mask)?;
+        }
+        let mut data = vec![0; len as usize];
+        self.stream.read_exact(&mut data)?;
+        apply_mask(&mut data, mask);
+        Ok((fin, opcode, data))
+    }
+
+    fn write_message(&mut self, opcode: u8, data: &[u8]) -> io::Result<()> {
+        let size = match self.max_frame_size {
+            Some(size) if size > 0 && data.len() > size => size,
+            _ => return self.write_frame(true, opcode, data),
+        };
+        let mut chunks = data.chunks(size).peekable();
+        let mut op = opcode;
+        while let Some(chunk) = chunks.next() {
+            self.write_frame(chunks.peek().is_none(), op, chunk)?;
+            op = OP_CONTINUATION;
+        }
+        Ok(())
+    }
+
+    fn write_control(&mut self, opcode: u8, data: &[u8]) -> io::Result<()> {
+        if data.len() > 125 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "control frame payload over 125 bytes"));
+        }
+        self.write_frame(true, opcode, data)
+    }
+
+    fn write_frame(&mut self, fin: bool, opcode: u8, data: &[u8]) -> io::Result<()> {
+        let mut frame = Vec::with_capacity(data.len() + 14);
+        frame.push(if fin { 0x80 } else { 0 } | opcode);
+        let mask_bit = if self.role == Role::Client { 0x80 } else { 0 };
+        match data.len() {
+            len if len < 126 => frame.push(mask_bit | len as u8),
+            len if len <= u16::MAX as usize => {
+                frame.push(mask_bit | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(mask_bit | 127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        let start = frame.len();
+        if self.role == Role::Client {
+            let mask: [u8; 4] = rand::random();
+            frame.extend_from_slice(&mask);
+            frame.extend_from_slice(data);
+            apply_mask(&mut frame[start + 4..], mask);
+        } else {
+            frame.extend_from_slice(data);
+        }
+        let stream = self.stream.get_mut();
+        stream.write_all(&frame)?;
+        stream.flush()
+    }
+
+    /// Closes the connection with `code` for breaking the protocol.
+    fn fail(&mut self, code: u16, msg: &'static str) -> io::Error {
+        debug!("websocket protocol error: {}", msg);
+        if self.state == State::Open {
+            let _ = self.write_frame(true, OP_CLOSE, &code.to_be_bytes());
+        }
+        self.state = State::Closed;
+        io::Error::new(io::ErrorKind::InvalidData, msg)
+    }
+}
+
+impl<S: Read + Write> fmt::Debug for WebSocket<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WebSocket")
+            .field("role", &self.role)
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
+fn closed() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionAborted, "websocket is closed")
+}
+
+fn apply_mask(data: &mut [u8], mask: [u8; 4]) {
+    for (i, b) in data.iter_mut().enumerate() {
+        *b ^= mask[i & 3];
+    }
+}
+
+/// The codes a peer may send, see RFC6455 section 7.4.
+fn valid_close_code(code: u16) -> bool {
+    matches!(code, 1000..=1003 | 1007..=1011 | 3000..=4999)
+}
+
+/// The stream of a WebSocket accepted by a `Handler`, the connection taken over
+/// from the server. The WebSocket can be moved to another coroutine and outlive
+/// the handler, the connection is closed once it is dropped.
+pub type ServerStream = Upgraded;
+
+/// The stream of a WebSocket opened by a `Client`, it is closed when dropped.
+pub struct ClientStream(Box<dyn NetworkStream + Send>);
+
+impl ClientStream {
+    /// Gets a reference to the underlying `NetworkStream`.
+    pub fn get_ref(&self) -> &(dyn NetworkStream + Send) {
+        &*self.0
+    }
+}
+
+impl Read for ClientStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for ClientStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl Drop for ClientStream {
+    fn drop(&mut self) {
+        // the connection can't go back to a pool
+        if let Err(e) = self.0.close(Shutdown::Both) {
+            debug!("error closing websocket stream: {:?}", e);
+        }
+    }
+}
+
+/// Checks the opening handshake of `req` and answers it with `101 Switching
+/// Protocols`. See `Response::upgrade_websocket`.
+pub fn accept(req: Request, mut res: Response<Fresh>) -> crate::Result<WebSocket<ServerStream>> {
+    let key = match check_request(&req) {
+        Ok(key) => key,
+        Err(status) => {
+            *res.status_mut() = status;
+            if status == StatusCode::UpgradeRequired {
+                res.headers_mut().set_raw("Sec-WebSocket-Version", vec![VERSION.into()]);
+            }
+            res.send(&[])?;
+            return Err(Error::Header);
+        }
+    };
+    *res.status_mut() = StatusCode::SwitchingProtocols;
+    let headers = res.headers_mut();
+    headers.set(Upgrade(vec![Protocol::new(ProtocolName::WebSocket, None)]));
+    headers.set(Connection(vec![ConnectionOption::ConnectionHeader(UniCase("Upgrade".to_owned()))]));
+    headers.set_raw("Sec-WebSocket-Accept", vec![accept_key(&key).into_bytes()]);
+    Ok(WebSocket::from_stream(res.upgrade(req)?, Role::Server))
+}
+
+/// The `Sec-WebSocket-Key` of a valid opening handshake, or the status to answer
+/// an invalid one with.
+fn check_request(req: &Request) -> Result<String, StatusCode> {
+    if req.method != Method::Get || req.version == HttpVersion::Http10 || !is_upgrade(&req.headers) {
+        return Err(StatusCode::BadRequest);
+    }
+    if raw_header(&req.headers, "Sec-WebSocket-Version") != Some(VERSION) {
+        return Err(StatusCode::UpgradeRequired);
+    }
+    match raw_header(&req.headers, "Sec-WebSocket-Key") {
+        Some(key) if BASE64.decode(key).is_ok_and(|k| k.len() == 16) => Ok(key.to_owned()),
+        _ => Err(StatusCode::BadRequest),
+    }
+}
+
+/// Whether the headers ask for, or agree to, an upgrade to WebSocket.
+fn is_upgrade(headers: &Headers) -> bool {
+    let upgrade = headers.get::<Upgrade>()
+        .is_some_and(|u| u.iter().any(|p| p.name == ProtocolName::WebSocket));
+    let connection = headers.get::<Connection>().is_some_and(|c| c.iter().any(|o| {
+        matches!(o, ConnectionOption::ConnectionHeader(name) if *name == UniCase("upgrade".to_owned()))
+    }));
+    upgrade && connection
+}
+
+fn raw_header<'h>(headers: &'h Headers, name: &str) -> Option<&'h str> {
+    match headers.get_raw(name) {
+        Some([value]) => std::str::from_utf8(value).ok().map(str::trim),
+        _ => None,
+    }
+}
+
+/// The `Sec-WebSocket-Accept` for a `Sec-WebSocket-Key`.
+pub fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(GUID.as_bytes());
+    BASE64.encode(sha1.finalize())
+}
+
+/// A new random `Sec-WebSocket-Key`.
+pub(crate) fn new_key() -> String {
+    BASE64.encode(rand::random::<[u8; 16]>())
+}
+
+/// Opens a WebSocket on a stream the client connected, sending the opening
+/// handshake for `path` with `headers`.
+pub(crate) fn connect(stream: Box<dyn NetworkStream + Send>, path: &str, mut headers: Headers)
+                      -> crate::Result<WebSocket<ClientStream>> {
+    let key = new_key();
+    headers.set(Upgrade(vec![Protocol::new(ProtocolName::WebSocket, None)]));
+    headers.set(Connection(vec![ConnectionOption::ConnectionHeader(UniCase("Upgrade".to_owned()))]));
+    headers.set_raw("Sec-WebSocket-Key", vec![key.clone().into_bytes()]);
+    headers.set_raw("Sec-WebSocket-Version", vec![VERSION.into()]);
+
+    let mut stream = ClientStream(stream);
+    debug!("websocket handshake: GET {} {:?}", path, headers);
+    write!(stream, "GET {} {}\r\n{}\r\n", path, HttpVersion::Http11, headers)?;
+    stream.flush()?;
+
+    let mut rdr = BufReader::new(stream);
+    let head = crate::http::h1::parse_response(&mut rdr)?;
+    if head.subject.0 != 101 {
+        debug!("websocket handshake refused: {:?}", head.subject);
+        return Err(Error::Status);
+    }
+    if !is_upgrade(&head.headers) || raw_header(&head.headers, "Sec-WebSocket-Accept") != Some(&*accept_key(&key)) {
+        return Err(Error::Header);
+    }
+    Ok(WebSocket::from_buffered(rdr, Role::Client))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read, Write};
+    use std::thread;
+
+    use crate::client::Client;
+    use crate::mock::serve;
+    use crate::server::{Request, Response, Server};
+
+    use super::{accept_key, CloseFrame, Message, Role, WebSocket};
+
+    /// Reads from `input`, writes to `output`.
+    struct Duplex {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Duplex {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Duplex {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn socket(role: Role, input: Vec<u8>) -> WebSocket<Duplex> {
+        WebSocket::from_stream(Duplex { input: Cursor::new(input), output: Vec::new() }, role)
+    }
+
+    /// The frames a client sends for `msgs`.
+    fn client_frames(msgs: Vec<Message>, frame_size: Option<usize>) -> Vec<u8> {
+        let mut client = socket(Role::Client, Vec::new());
+        client.set_max_frame_size(frame_size);
+        for msg in msgs {
+            client.send(msg).unwrap();
+        }
+        client.get_ref().output.clone()
+    }
+
+    #[test]
+    fn test_accept_key() {
+        // RFC6455 section 1.3
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn test_messages() {
+        let input = client_frames(vec![
+            Message::Text("hello".to_owned()),
+            Message::Binary(vec![0; 70_000]),
+            Message::Ping(b"p".to_vec()),
+            Message::Close(Some(CloseFrame { code: 1000, reason: "bye".to_owned() })),
+        ], None);
+        let mut server = socket(Role::Server, input);
+        assert_eq!(server.read().unwrap(), Message::Text("hello".to_owned()));
+        assert_eq!(server.read().unwrap(), Message::Binary(vec![0; 70_000]));
+        assert_eq!(server.read().unwrap(), Message::Ping(b"p".to_vec()));
+        assert_eq!(server.read().unwrap(),
+                   Message::Close(Some(CloseFrame { code: 1000, reason: "bye".to_owned() })));
+        assert!(server.read().is_err());
+        assert!(server.send(Message::Text("late".to_owned())).is_err());
+
+        // the pong and the close reply, unmasked
+        let mut client = socket(Role::Client, server.get_ref().output.clone());
+        assert_eq!(client.read().unwrap(), Message::Pong(b"p".to_vec()));
+        assert_eq!(client.read().unwrap(), Message::Close(Some(CloseFrame { code: 1000, reason: String::new() })));
+    }
+
+    #[test]
+    fn test_fragments() {
+        let text = "fragmented ☃ message".to_owned();
+        let mut input = client_frames(vec![Message::Text(text.clone())], Some(4));
+        // a ping between the fragments
+        let ping = client_frames(vec![Message::Ping(Vec::new())], None);
+        let at = 10; // after the first fragment
+        input.splice(at..at, ping);
+        let mut server = socket(Role::Server, input);
+        assert_eq!(server.read().unwrap(), Message::Ping(Vec::new()));
+        assert_eq!(server.read().unwrap(), Message::Text(text));
+    }
+
+    #[test]
+    fn test_protocol_errors() {
+        // an unmasked frame from a client
+        let mut server = socket(Role::Server, b"\x81\x02hi".to_vec());
+        assert!(server.read().is_err());
+        assert_eq!(server.get_ref().output, b"\x88\x02\x03\xea");
+
+        // over the message size
+        let mut server = socket(Role::Server, client_frames(vec![Message::Binary(vec![1; 100])], None));
+        server.set_max_message_size(10);
+        assert!(server.read().is_err());
+        assert_eq!(server.get_ref().output, b"\x88\x02\x03\xf1");
+
+        // invalid utf-8
+        let mut server = socket(Role::Server, client_frames(vec![Message::Binary(vec![0xff])], None));
+        let mut input = server.get_ref().input.get_ref().clone();
+        input[0] = 0x81;
+        server = socket(Role::Server, input);
+        assert!(server.read().is_err());
+        assert_eq!(server.get_ref().output, b"\x88\x02\x03\xef");
+
+        // a continuation without a message
+        let mut input = client_frames(vec![Message::Text("x".to_owned())], None);
+        input[0] = 0x80;
+        let mut server = socket(Role::Server, input);
+        assert!(server.read().is_err());
+    }
+
+    fn upgrade(req: Request, res: Response) {
+        let mut ws = match res.upgrade_websocket(req) {
+            Ok(ws) => ws,
+            Err(_) => return,
+        };
+        while let Ok(msg) = ws.read() {
+            if let Message::Text(text) = msg {
+                ws.send(Message::Text(text.to_uppercase())).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_upgrade() {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let mut listening = server.handle_threads(|req: Request, res: Response| {
+            let mut ws = match res.upgrade_websocket(req) {
+                Ok(ws) => ws,
+                Err(_) => return,
+            };
+            // the WebSocket outlives the handler
+            thread::spawn(move || {
+                while let Ok(msg) = ws.read() {
+                    if let Message::Text(text) = msg {
+                        ws.send(Message::Text(text.to_uppercase())).unwrap();
+                    }
+                }
+            });
+        }, 2).unwrap();
+
+        // the client checks the 101 and its Sec-WebSocket-Accept
+        let mut ws = Client::new().websocket(&format!("ws://{}/ws", listening.socket)).unwrap();
+        ws.send(Message::Text("hi".to_owned())).unwrap();
+        assert_eq!(ws.read().unwrap(), Message::Text("HI".to_owned()));
+        ws.send(Message::Text("again".to_owned())).unwrap();
+        assert_eq!(ws.read().unwrap(), Message::Text("AGAIN".to_owned()));
+        ws.close(None).unwrap();
+        assert_eq!(ws.read().unwrap(), Message::Close(None));
+        listening.close().unwrap();
+    }
+
+    #[test]
+    fn test_upgrade_refused() {
+        let s = serve(upgrade, b"GET /ws HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(s.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", s);
+
+        let s = serve(upgrade, b"GET /ws HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+            Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 8\r\n\r\n");
+        assert!(s.starts_with("HTTP/1.1 426 Upgrade Required\r\n"), "{}", s);
+        assert!(s.contains("Sec-WebSocket-Version: 13\r\n"), "{}", s);
+
+        let s = serve(upgrade, b"GET /ws HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+            Sec-WebSocket-Key: short\r\nSec-WebSocket-Version: 13\r\n\r\n");
+        assert!(s.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", s);
+    }
+}